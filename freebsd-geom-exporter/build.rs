@@ -0,0 +1,13 @@
+// vim: tw=80
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/geom_exporter.proto");
+        tonic_build::configure()
+            .build_client(false)
+            .compile(&["proto/geom_exporter.proto"], &["proto"])
+            .expect("compiling protocol buffers; is protoc installed?");
+    }
+}
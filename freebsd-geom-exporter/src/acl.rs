@@ -0,0 +1,189 @@
+//! Restricting which clients may fetch metrics
+//!
+//! Where no firewall protects the exporter, `--allow-cidr` and `--deny-cidr`
+//! limit which peer addresses it will answer.  A client is rejected if it
+//! matches any denied network, or if allowed networks are given and it
+//! matches none of them.
+use std::{fmt, net::IpAddr, str::FromStr};
+
+/// A network, written like `192.0.2.0/24` or `2001:db8::/32`.  A bare address
+/// is a network of just that address.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Cidr {
+    addr: IpAddr,
+    /// Length of the network prefix, in bits
+    len:  u8,
+}
+
+impl Cidr {
+    /// Does this network include `addr`?
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.addr, canonical(addr)) {
+            (IpAddr::V4(net), IpAddr::V4(a)) => {
+                prefix(u32::from(net) ^ u32::from(a), 32, self.len)
+            }
+            (IpAddr::V6(net), IpAddr::V6(a)) => {
+                prefix(u128::from(net) ^ u128::from(a), 128, self.len)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, len) =
+            s.split_once('/').map_or((s, None), |(a, l)| (a, Some(l)));
+        let addr = addr
+            .parse::<IpAddr>()
+            .map_err(|e| format!("{addr:?}: {e}"))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let len = match len {
+            None => max,
+            Some(l) => l
+                .parse::<u8>()
+                .ok()
+                .filter(|l| *l <= max)
+                .ok_or_else(|| format!("invalid prefix length {l:?}"))?,
+        };
+        Ok(Cidr { addr, len })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.len)
+    }
+}
+
+/// Are the first `len` of an address's `bits` all zero in `diff`, the XOR of
+/// two addresses?
+fn prefix<T>(diff: T, bits: u32, len: u8) -> bool
+where
+    T: Into<u128>,
+{
+    let len = u32::from(len);
+    len == 0 || diff.into() >> (bits - len) == 0
+}
+
+/// Treat IPv4-mapped IPv6 addresses, like `::ffff:192.0.2.1`, as IPv4.  A
+/// server bound to `::` sees IPv4 clients that way.
+fn canonical(addr: IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(addr, IpAddr::V4),
+        IpAddr::V4(_) => addr,
+    }
+}
+
+/// The `--allow-cidr` and `--deny-cidr` networks
+#[derive(Clone, Debug, Default)]
+pub struct Acl {
+    pub allow: Vec<Cidr>,
+    pub deny:  Vec<Cidr>,
+}
+
+impl Acl {
+    /// May this peer fetch metrics?  A peer whose address is unknown is only
+    /// permitted if no networks were given at all.
+    pub fn permits(&self, peer: Option<IpAddr>) -> bool {
+        let Some(peer) = peer else {
+            return self.allow.is_empty() && self.deny.is_empty();
+        };
+        !self.deny.iter().any(|net| net.contains(peer))
+            && (self.allow.is_empty()
+                || self.allow.iter().any(|net| net.contains(peer)))
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn nets(nets: &[&str]) -> Vec<Cidr> {
+        nets.iter().map(|n| n.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn contains_v4() {
+        let net = "192.0.2.0/24".parse::<Cidr>().unwrap();
+        assert!(net.contains(ip("192.0.2.0")));
+        assert!(net.contains(ip("192.0.2.255")));
+        assert!(!net.contains(ip("192.0.3.0")));
+        assert!(!net.contains(ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn contains_v6() {
+        let net = "2001:db8::/32".parse::<Cidr>().unwrap();
+        assert!(net.contains(ip("2001:db8:ffff::1")));
+        assert!(!net.contains(ip("2001:db9::1")));
+        assert!(!net.contains(ip("192.0.2.1")));
+    }
+
+    #[test]
+    fn contains_mapped() {
+        let net = "192.0.2.0/24".parse::<Cidr>().unwrap();
+        assert!(net.contains(ip("::ffff:192.0.2.7")));
+    }
+
+    #[test]
+    fn contains_everything() {
+        let net = "0.0.0.0/0".parse::<Cidr>().unwrap();
+        assert!(net.contains(ip("198.51.100.1")));
+        assert!(!net.contains(ip("::1")));
+    }
+
+    #[test]
+    fn parse() {
+        let net = "10.1.2.3".parse::<Cidr>().unwrap();
+        assert_eq!(net.to_string(), "10.1.2.3/32");
+        assert!(net.contains(ip("10.1.2.3")));
+        assert!(!net.contains(ip("10.1.2.4")));
+        assert_eq!("::1".parse::<Cidr>().unwrap().to_string(), "::1/128");
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("10.0.0.0/x".parse::<Cidr>().is_err());
+        assert!("localhost/8".parse::<Cidr>().is_err());
+        assert!("2001:db8::/129".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn permits_by_default() {
+        let acl = Acl::default();
+        assert!(acl.permits(Some(ip("198.51.100.1"))));
+        assert!(acl.permits(None));
+    }
+
+    #[test]
+    fn permits_allowed() {
+        let acl = Acl {
+            allow: nets(&["10.0.0.0/8", "::1"]),
+            deny:  vec![],
+        };
+        assert!(acl.permits(Some(ip("10.9.8.7"))));
+        assert!(acl.permits(Some(ip("::1"))));
+        assert!(!acl.permits(Some(ip("192.0.2.1"))));
+        assert!(!acl.permits(None));
+    }
+
+    #[test]
+    fn deny_wins() {
+        let acl = Acl {
+            allow: nets(&["10.0.0.0/8"]),
+            deny:  nets(&["10.0.0.0/24"]),
+        };
+        assert!(acl.permits(Some(ip("10.0.1.1"))));
+        assert!(!acl.permits(Some(ip("10.0.0.1"))));
+        let acl = Acl {
+            allow: vec![],
+            deny:  nets(&["192.0.2.0/24"]),
+        };
+        assert!(acl.permits(Some(ip("198.51.100.1"))));
+        assert!(!acl.permits(Some(ip("192.0.2.1"))));
+    }
+}
@@ -0,0 +1,185 @@
+//! Bounding the number of devices with published series
+//!
+//! Every device name becomes a label value on several metrics, and each label
+//! set costs memory both here and in Prometheus.  On hosts where devices like
+//! zvols are constantly created and destroyed, that's unbounded.  So a device's
+//! series are evicted once it has been absent for `--device-ttl`, and at most
+//! `--device-limit` devices are published at once.
+use std::{
+    collections::{BTreeSet, HashMap},
+    time::{Duration, Instant},
+};
+
+use freebsd_libgeom::{DeviceEvent, DeviceWatcher};
+
+/// Decides which devices' series to publish, and which to evict
+#[derive(Debug)]
+pub struct Guard {
+    ttl:       Duration,
+    limit:     Option<usize>,
+    /// Notices devices departing and returning
+    watcher:   DeviceWatcher,
+    /// Each published device, and if it's absent, when it was last present
+    published: HashMap<String, Option<Instant>>,
+    /// The time of the previous update
+    last:      Option<Instant>,
+}
+
+/// The outcome of [`Guard::update`]
+#[derive(Debug, Default, PartialEq)]
+pub struct Update {
+    /// Devices whose series should be removed, sorted
+    pub evicted: Vec<String>,
+    /// Present devices that must not be published, to stay within the limit,
+    /// sorted
+    pub refused: Vec<String>,
+}
+
+impl Guard {
+    pub fn new(ttl: Duration, limit: Option<usize>) -> Self {
+        Guard {
+            ttl,
+            limit,
+            watcher: DeviceWatcher::default(),
+            published: HashMap::new(),
+            last: None,
+        }
+    }
+
+    /// Forget every device, as if none had ever been published.
+    pub fn clear(&mut self) {
+        *self = Guard::new(self.ttl, self.limit);
+    }
+
+    /// Note which devices are `present` at time `now`.
+    ///
+    /// Devices that have been absent for at least the TTL are evicted.  New
+    /// devices are admitted in name order, evicting the longest-absent
+    /// devices to make room if necessary.  If every published device is still
+    /// present, the remaining new devices are refused.
+    pub fn update<'a, I>(&mut self, present: I, now: Instant) -> Update
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let present = present.into_iter().collect::<BTreeSet<_>>();
+        let mut update = Update::default();
+        let last = self.last.replace(now).unwrap_or(now);
+        let events = self
+            .watcher
+            .update_names(present.iter().map(|name| (*name).to_owned()));
+        for event in events {
+            if let Some(absent) = self.published.get_mut(event.name()) {
+                *absent = match event {
+                    DeviceEvent::Departed(_) => Some(last),
+                    DeviceEvent::Arrived(_) => None,
+                };
+            }
+        }
+        let ttl = self.ttl;
+        self.published.retain(|name, absent| {
+            let keep =
+                absent.map_or(true, |since| now.duration_since(since) < ttl);
+            if !keep {
+                update.evicted.push(name.clone());
+            }
+            keep
+        });
+        for name in present {
+            if self.published.contains_key(name) {
+                continue;
+            }
+            if self
+                .limit
+                .is_some_and(|limit| self.published.len() >= limit)
+            {
+                let oldest = self
+                    .published
+                    .iter()
+                    .filter_map(|(name, absent)| {
+                        absent.map(|since| (since, name))
+                    })
+                    .min()
+                    .map(|(_, name)| name.clone());
+                let Some(oldest) = oldest else {
+                    update.refused.push(name.to_owned());
+                    continue;
+                };
+                self.published.remove(&oldest);
+                update.evicted.push(oldest);
+            }
+            self.published.insert(name.to_owned(), None);
+        }
+        update.evicted.sort();
+        update
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    const SEC: Duration = Duration::from_secs(1);
+
+    /// With no TTL, a device is evicted as soon as it departs.
+    #[test]
+    fn departed() {
+        let mut guard = Guard::new(Duration::ZERO, None);
+        let t0 = Instant::now();
+        assert_eq!(guard.update(["da0", "da1", "da2"], t0), Update::default());
+        let update = guard.update(["da1", "da3"], t0 + SEC);
+        assert_eq!(update.evicted, ["da0", "da2"]);
+        assert!(update.refused.is_empty());
+    }
+
+    /// A departed device lingers until the TTL expires.
+    #[test]
+    fn ttl() {
+        let mut guard = Guard::new(10 * SEC, None);
+        let t0 = Instant::now();
+        guard.update(["da0", "da1"], t0);
+        assert!(guard.update(["da1"], t0 + 5 * SEC).evicted.is_empty());
+        // Returning resets the clock
+        guard.update(["da0", "da1"], t0 + 6 * SEC);
+        assert!(guard.update(["da1"], t0 + 15 * SEC).evicted.is_empty());
+        assert_eq!(guard.update(["da1"], t0 + 16 * SEC).evicted, ["da0"]);
+    }
+
+    /// Beyond the limit, new devices are refused until some depart.
+    #[test]
+    fn limit() {
+        let mut guard = Guard::new(60 * SEC, Some(2));
+        let t0 = Instant::now();
+        let update = guard.update(["da2", "da0", "da1"], t0);
+        assert!(update.evicted.is_empty());
+        assert_eq!(update.refused, ["da2"]);
+        // Still refused on the next scrape, rather than displacing da0
+        let update = guard.update(["da0", "da1", "da2"], t0 + SEC);
+        assert_eq!(update.refused, ["da2"]);
+        // Once da0 departs, it makes room for da2, even before its TTL
+        let update = guard.update(["da1", "da2"], t0 + 2 * SEC);
+        assert_eq!(update.evicted, ["da0"]);
+        assert!(update.refused.is_empty());
+    }
+
+    /// The longest-absent device is evicted first to make room.
+    #[test]
+    fn limit_evicts_oldest() {
+        let mut guard = Guard::new(60 * SEC, Some(3));
+        let t0 = Instant::now();
+        guard.update(["da0", "da1", "da2"], t0);
+        guard.update(["da1", "da2"], t0 + SEC);
+        guard.update(["da2"], t0 + 2 * SEC);
+        let update = guard.update(["da2", "da3", "da4"], t0 + 3 * SEC);
+        assert_eq!(update.evicted, ["da0", "da1"]);
+        assert!(update.refused.is_empty());
+    }
+
+    #[test]
+    fn clear() {
+        let mut guard = Guard::new(60 * SEC, Some(1));
+        let t0 = Instant::now();
+        guard.update(["da0"], t0);
+        guard.clear();
+        assert_eq!(guard.update(["da1"], t0), Update::default());
+    }
+}
@@ -0,0 +1,326 @@
+//! Streaming statistics over gRPC
+//!
+//! Scraping suits Prometheus, but some consumers, like autoscalers, would
+//! rather be told about each interval as soon as it ends.  With `--grpc-port`,
+//! the `GeomStatistics.Watch` method streams every device's statistics to
+//! each client once per `--grpc-interval`.  They're sampled by a background
+//! thread, which is started by the first client, and idles whenever no client
+//! is watching.  The messages are defined in `proto/geom_exporter.proto`.
+//!
+//! This is experimental, and only built with the `grpc` feature.
+use std::{
+    collections::HashSet,
+    error::Error,
+    io,
+    net::{SocketAddr, TcpListener},
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    sync::{Arc, Mutex, PoisonError},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use freebsd_libgeom::{name_cmp, DeltaSnapshot};
+use prometheus::IntCounter;
+use tokio::sync::broadcast;
+use tokio_stream::{
+    wrappers::{BroadcastStream, TcpListenerStream},
+    Stream,
+    StreamExt,
+};
+use tonic::{transport::Server, Request, Response, Status};
+
+use self::proto::{
+    geom_statistics_server::{GeomStatistics, GeomStatisticsServer},
+    DeviceStatistics,
+    Interval,
+    Operations,
+    WatchRequest,
+};
+use crate::{acl::Acl, Filter};
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+pub mod proto {
+    tonic::include_proto!("geom_exporter.v1");
+}
+
+/// How many intervals a client may fall behind before it starts missing some
+const BACKLOG: usize = 4;
+
+const MIB: f64 = (1 << 20) as f64;
+
+/// Owned by the sampling thread
+struct Worker {
+    filter: Filter,
+    /// Created by the first sample, so that failure is reported like any
+    /// other sampling error
+    deltas: Option<DeltaSnapshot>,
+}
+
+impl Worker {
+    fn new(filter: Filter) -> Self {
+        Worker {
+            filter,
+            deltas: None,
+        }
+    }
+
+    /// Capture every device's statistics, and compute them over the interval
+    /// since the previous capture, if any.
+    fn sample(&mut self) -> io::Result<Option<Interval>> {
+        let deltas = match self.deltas.as_mut() {
+            Some(deltas) => deltas,
+            None => self.deltas.insert(DeltaSnapshot::new()?),
+        };
+        let deltas = deltas.refresh()?;
+        if deltas.is_first() {
+            // There's nothing to compare it to.
+            return Ok(None);
+        }
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0.0, |d| d.as_secs_f64());
+        let etime = deltas.etime();
+        let mut seen = HashSet::new();
+        let mut devices = Vec::new();
+        for delta in deltas {
+            let Some(name) = self.filter.select(delta.gident, &delta.devstat)
+            else {
+                continue;
+            };
+            let name = self.filter.normalize(&name).into_owned();
+            // As at /json, the first of several devices with the same
+            // normalized name wins.
+            if !seen.insert(name.clone()) {
+                continue;
+            }
+            let stats = delta.stats.to_record();
+            devices.push(DeviceStatistics {
+                name,
+                busy_pct: stats.busy_pct,
+                queue_length: stats.queue_length,
+                avg_queue_depth: stats.avg_queue_depth,
+                read: Some(Operations {
+                    ops_per_second:   stats.transfers_per_second_read,
+                    bytes_per_second: stats.mb_per_second_read * MIB,
+                    ms_per_op:        stats.ms_per_transaction_read,
+                }),
+                write: Some(Operations {
+                    ops_per_second:   stats.transfers_per_second_write,
+                    bytes_per_second: stats.mb_per_second_write * MIB,
+                    ms_per_op:        stats.ms_per_transaction_write,
+                }),
+                free: Some(Operations {
+                    ops_per_second:   stats.transfers_per_second_free,
+                    bytes_per_second: stats.mb_per_second_free * MIB,
+                    ms_per_op:        stats.ms_per_transaction_free,
+                }),
+                other: Some(Operations {
+                    ops_per_second:   stats.transfers_per_second_other,
+                    bytes_per_second: 0.0,
+                    ms_per_op:        stats.ms_per_transaction_other,
+                }),
+            });
+        }
+        devices.sort_by(|l, r| name_cmp(&l.name, &r.name));
+        Ok(Some(Interval {
+            time,
+            duration: etime,
+            devices,
+        }))
+    }
+}
+
+/// The sampling thread's settings, until it's started
+struct Sampler {
+    filter:   Filter,
+    interval: Duration,
+    panics:   IntCounter,
+}
+
+impl Sampler {
+    /// Start sampling every `interval`.  Each [`Interval`] is sent to every
+    /// receiver subscribed to `tx`.  While there are none, nothing is
+    /// sampled.
+    ///
+    /// If sampling panics, `panics` is incremented and the thread starts over.
+    fn spawn(&self, tx: broadcast::Sender<Arc<Interval>>) -> io::Result<()> {
+        let filter = self.filter.clone();
+        let interval = self.interval;
+        let panics = self.panics.clone();
+        thread::Builder::new()
+            .name("grpc-sampler".to_owned())
+            .spawn(move || {
+                let mut worker = Worker::new(filter.clone());
+                loop {
+                    if tx.receiver_count() == 0 {
+                        // Whoever watches next should get a full interval,
+                        // not one spanning the idle time.
+                        worker = Worker::new(filter.clone());
+                        thread::sleep(interval);
+                        continue;
+                    }
+                    let r = panic::catch_unwind(AssertUnwindSafe(|| {
+                        worker.sample()
+                    }));
+                    match r {
+                        Ok(Ok(Some(interval))) => {
+                            // Fails only if every client has gone
+                            let _ = tx.send(Arc::new(interval));
+                        }
+                        Ok(Ok(None)) => (),
+                        Ok(Err(e)) => {
+                            eprintln!("Error sampling devices for gRPC: {e}")
+                        }
+                        Err(_) => {
+                            // The panic hook has already logged it
+                            panics.inc();
+                            worker = Worker::new(filter.clone());
+                        }
+                    }
+                    thread::sleep(interval);
+                }
+            })?;
+        Ok(())
+    }
+}
+
+/// Copy `interval`, keeping only the `wanted` devices, or every device if
+/// `wanted` is empty.
+fn select(interval: &Interval, wanted: &HashSet<String>) -> Interval {
+    Interval {
+        time:     interval.time,
+        duration: interval.duration,
+        devices:  interval
+            .devices
+            .iter()
+            .filter(|dev| wanted.is_empty() || wanted.contains(&dev.name))
+            .cloned()
+            .collect(),
+    }
+}
+
+struct Service {
+    intervals: broadcast::Sender<Arc<Interval>>,
+    /// Taken when the first client subscribes, and the sampler is started
+    sampler:   Mutex<Option<Sampler>>,
+    acl:       Acl,
+    rejected:  IntCounter,
+}
+
+#[tonic::async_trait]
+impl GeomStatistics for Service {
+    type WatchStream =
+        Pin<Box<dyn Stream<Item = Result<Interval, Status>> + Send>>;
+
+    async fn watch(
+        &self,
+        request: Request<WatchRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        if !self.acl.permits(request.remote_addr().map(|a| a.ip())) {
+            self.rejected.inc();
+            return Err(Status::permission_denied("Forbidden"));
+        }
+        let wanted = request
+            .into_inner()
+            .devices
+            .into_iter()
+            .collect::<HashSet<_>>();
+        let rx = self.intervals.subscribe();
+        let mut sampler =
+            self.sampler.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(s) = sampler.as_ref() {
+            s.spawn(self.intervals.clone()).map_err(|e| {
+                Status::internal(format!("starting the sampler: {e}"))
+            })?;
+            *sampler = None;
+        }
+        drop(sampler);
+        let stream = BroadcastStream::new(rx).filter_map(move |r| {
+            // A client that falls behind just misses some intervals
+            let interval = r.ok()?;
+            Some(Ok(select(&interval, &wanted)))
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+/// Serve the `GeomStatistics` service on `addr`, from a background thread.
+/// Clients are subject to the same `acl` as HTTP requests.
+pub fn serve(
+    addr: SocketAddr,
+    filter: Filter,
+    interval: Duration,
+    acl: Acl,
+    rejected: IntCounter,
+    panics: IntCounter,
+) -> Result<(), Box<dyn Error>> {
+    // Bind now, so that failure is reported at startup
+    let listener = TcpListener::bind(addr)?;
+    listener.set_nonblocking(true)?;
+    let sampler = Sampler {
+        filter,
+        interval,
+        panics,
+    };
+    let service = GeomStatisticsServer::new(Service {
+        intervals: broadcast::channel(BACKLOG).0,
+        sampler: Mutex::new(Some(sampler)),
+        acl,
+        rejected,
+    });
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    thread::Builder::new()
+        .name("grpc".to_owned())
+        .spawn(move || {
+            let r = runtime.block_on(async {
+                let listener = tokio::net::TcpListener::from_std(listener)?;
+                Server::builder()
+                    .add_service(service)
+                    .serve_with_incoming(TcpListenerStream::new(listener))
+                    .await?;
+                Ok::<_, Box<dyn Error>>(())
+            });
+            if let Err(e) = r {
+                eprintln!("gRPC server failed: {e}");
+            }
+        })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    fn interval() -> Interval {
+        let dev = |name: &str| DeviceStatistics {
+            name: name.to_owned(),
+            ..Default::default()
+        };
+        Interval {
+            time:     1.0,
+            duration: 2.0,
+            devices:  vec![dev("ada0"), dev("ada1"), dev("da0")],
+        }
+    }
+
+    fn names(interval: &Interval) -> Vec<&str> {
+        interval.devices.iter().map(|d| d.name.as_str()).collect()
+    }
+
+    #[test]
+    fn select_all() {
+        let selected = select(&interval(), &HashSet::new());
+        assert_eq!(selected, interval());
+    }
+
+    #[test]
+    fn select_some() {
+        let wanted = ["da0", "ada0", "nvd0"].map(str::to_owned).into();
+        let selected = select(&interval(), &wanted);
+        assert_eq!(names(&selected), ["ada0", "da0"]);
+        assert_eq!(selected.duration, 2.0);
+    }
+}
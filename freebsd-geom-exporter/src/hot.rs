@@ -0,0 +1,228 @@
+//! High-frequency sampling of the busiest devices
+//!
+//! With `--hot-devices`, a background thread captures every device's
+//! statistics each `--hot-interval`, but only computes latencies for the few
+//! devices that were busiest as of the previous scrape.  That gives finer
+//! resolution where it matters, without multiplying the number of series.
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    io,
+    mem,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Mutex, PoisonError},
+    thread,
+    time::Duration,
+};
+
+use freebsd_libgeom::{name_cmp, DeltaSnapshot};
+use prometheus::IntCounter;
+
+use crate::Device;
+
+/// Average latency of each kind of operation during one sampling interval, in
+/// seconds
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Latency {
+    pub read:  f64,
+    pub write: f64,
+}
+
+impl Latency {
+    /// The worse of each field
+    fn max(self, other: Self) -> Self {
+        Latency {
+            read:  self.read.max(other.read),
+            write: self.write.max(other.write),
+        }
+    }
+}
+
+/// State shared between the sampling thread and the collector
+#[derive(Debug, Default)]
+struct Shared {
+    /// Names of the devices to sample
+    devices: Vec<String>,
+    /// Each device's latency during the most recent interval
+    latest:  HashMap<String, Latency>,
+    /// Each device's worst interval since the previous scrape
+    peak:    HashMap<String, Latency>,
+}
+
+/// Owned by the sampling thread
+#[derive(Default)]
+struct Worker {
+    /// Created by the first sample, so that failure is reported like any
+    /// other sampling error
+    deltas: Option<DeltaSnapshot>,
+}
+
+impl Worker {
+    fn sample(&mut self, shared: &Mutex<Shared>) -> io::Result<()> {
+        let deltas = match self.deltas.as_mut() {
+            Some(deltas) => deltas,
+            None => self.deltas.insert(DeltaSnapshot::new()?),
+        };
+        let deltas = deltas.refresh()?;
+        if deltas.is_first() {
+            // There's nothing to compare it to.
+            return Ok(());
+        }
+        let mut shared = shared.lock().unwrap_or_else(PoisonError::into_inner);
+        let Shared {
+            devices,
+            latest,
+            peak,
+        } = &mut *shared;
+        for (name, _, stats) in deltas.providers() {
+            let name = name.to_string_lossy();
+            if !devices.iter().any(|d| *d == name) {
+                continue;
+            }
+            let latency = Latency {
+                read:  stats.ms_per_transaction_read() / 1000.0,
+                write: stats.ms_per_transaction_write() / 1000.0,
+            };
+            peak.entry(name.to_string())
+                .and_modify(|p| *p = p.max(latency))
+                .or_insert(latency);
+            latest.insert(name.into_owned(), latency);
+        }
+        Ok(())
+    }
+}
+
+/// Handle to the background sampling thread
+pub struct Sampler {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl Sampler {
+    /// Start sampling every `interval`.  No devices are sampled until the
+    /// first call to [`Sampler::select`].
+    ///
+    /// If sampling panics, `panics` is incremented and the thread starts over.
+    pub fn spawn(interval: Duration, panics: IntCounter) -> io::Result<Self> {
+        let shared = Arc::new(Mutex::new(Shared::default()));
+        let theirs = shared.clone();
+        thread::Builder::new()
+            .name("hot-sampler".to_owned())
+            .spawn(move || {
+                let mut worker = Worker::default();
+                loop {
+                    let r = panic::catch_unwind(AssertUnwindSafe(|| {
+                        worker.sample(&theirs)
+                    }));
+                    match r {
+                        Ok(Ok(())) => (),
+                        Ok(Err(e)) => {
+                            eprintln!("Error sampling hot devices: {e}")
+                        }
+                        Err(_) => {
+                            // The panic hook has already logged it
+                            panics.inc();
+                            worker = Worker::default();
+                        }
+                    }
+                    thread::sleep(interval);
+                }
+            })?;
+        Ok(Sampler { shared })
+    }
+
+    /// Sample `devices` from now on.
+    ///
+    /// Returns the latest and the peak latency of each previously selected
+    /// device.  The peaks start over afterwards.
+    pub fn select(
+        &self,
+        devices: Vec<String>,
+    ) -> (HashMap<String, Latency>, HashMap<String, Latency>) {
+        let mut shared =
+            self.shared.lock().unwrap_or_else(PoisonError::into_inner);
+        shared.devices = devices;
+        let latest = mem::take(&mut shared.latest);
+        let peak = mem::take(&mut shared.peak);
+        (latest, peak)
+    }
+}
+
+/// The names of the `k` devices with the most busy time since `prev`, which
+/// maps each device's name to its busy time at the previous scrape.
+///
+/// Ties, such as among idle devices, are broken by name.
+pub fn busiest(
+    prev: &HashMap<String, f64>,
+    devices: &[Device],
+    k: usize,
+) -> Vec<String> {
+    let mut deltas = devices
+        .iter()
+        .map(|dev| {
+            let busy = prev
+                .get(&dev.name)
+                .map(|p| dev.busy_time - p)
+                .unwrap_or(0.0);
+            (dev.name.as_str(), busy)
+        })
+        .collect::<Vec<_>>();
+    deltas.sort_by(|(ln, lb), (rn, rb)| {
+        rb.partial_cmp(lb)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| name_cmp(ln, rn))
+    });
+    deltas
+        .into_iter()
+        .take(k)
+        .map(|(name, _)| name.to_owned())
+        .collect()
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    fn dev(name: &str, busy_time: f64) -> Device {
+        Device {
+            name: name.to_owned(),
+            busy_time,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn busiest_since_prev() {
+        let prev = [("da0", 100.0), ("da1", 5.0), ("da2", 50.0)]
+            .into_iter()
+            .map(|(n, b)| (n.to_owned(), b))
+            .collect();
+        // da0 has the most busy time overall, but not recently
+        let cur = [dev("da0", 100.5), dev("da1", 6.0), dev("da2", 50.75)];
+        assert_eq!(busiest(&prev, &cur, 2), ["da1", "da2"]);
+        assert_eq!(busiest(&prev, &cur, 5), ["da1", "da2", "da0"]);
+    }
+
+    /// On the first scrape, nothing has been busy yet
+    #[test]
+    fn busiest_first() {
+        let cur = [dev("da10", 9.0), dev("da2", 1.0), dev("da1", 5.0)];
+        assert_eq!(busiest(&HashMap::new(), &cur, 2), ["da1", "da2"]);
+    }
+
+    #[test]
+    fn latency_max() {
+        let a = Latency {
+            read:  0.5,
+            write: 0.1,
+        };
+        let b = Latency {
+            read:  0.2,
+            write: 0.3,
+        };
+        let expected = Latency {
+            read:  0.5,
+            write: 0.3,
+        };
+        assert_eq!(a.max(b), expected);
+    }
+}
@@ -0,0 +1,248 @@
+//! Compatibility with the Python gstat_exporter
+//!
+//! With `--legacy`, the metrics of the unmaintained Python `gstat_exporter` are
+//! published alongside the native `geom_*` ones, with the same names and
+//! labels, so dashboards can be migrated gradually.  Like that exporter, only
+//! physical providers are reported, and rates are computed over an interval:
+//! here, the one between consecutive scrapes.
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    error::Error,
+};
+
+use freebsd_libgeom::{record::Record, DeltaSnapshot, Tree};
+use prometheus::{register_gauge, register_gauge_vec, GaugeVec};
+
+use crate::Device;
+
+/// Labels of every legacy metric.  All but `name` are copied from the GEOM
+/// DISK configuration, and are empty for other classes.
+const LABELS: [&str; 9] = [
+    "name",
+    "descr",
+    "mediasize",
+    "sectorsize",
+    "lunid",
+    "ident",
+    "rotationrate",
+    "fwsectors",
+    "fwheads",
+];
+
+/// Each legacy metric's name, help, and value
+#[allow(clippy::type_complexity)]
+const METRICS: [(&str, &str, fn(&Record) -> f64); 17] = [
+    ("gstat_queue_depth", "The queue depth for this GEOM", |s| {
+        f64::from(s.queue_length)
+    }),
+    (
+        "gstat_total_operations_per_second",
+        "The total number of operations/second for this GEOM",
+        |s| s.transfers_per_second,
+    ),
+    (
+        "gstat_read_operations_per_second",
+        "The number of read operations/second for this GEOM",
+        |s| s.transfers_per_second_read,
+    ),
+    (
+        "gstat_read_size_kilobytes",
+        "The size in kilobytes of read operations for this GEOM",
+        |s| s.kb_per_transfer_read,
+    ),
+    (
+        "gstat_read_kilobytes_per_second",
+        "The speed in kilobytes/second of read operations for this GEOM",
+        |s| s.mb_per_second_read * 1024.0,
+    ),
+    (
+        "gstat_miliseconds_per_read",
+        "The speed in miliseconds/read operation for this GEOM",
+        |s| s.ms_per_transaction_read,
+    ),
+    (
+        "gstat_write_operations_per_second",
+        "The number of write operations/second for this GEOM",
+        |s| s.transfers_per_second_write,
+    ),
+    (
+        "gstat_write_size_kilobytes",
+        "The size in kilobytes of write operations for this GEOM",
+        |s| s.kb_per_transfer_write,
+    ),
+    (
+        "gstat_write_kilobytes_per_second",
+        "The speed in kilobytes/second of write operations for this GEOM",
+        |s| s.mb_per_second_write * 1024.0,
+    ),
+    (
+        "gstat_miliseconds_per_write",
+        "The speed in miliseconds/write operation for this GEOM",
+        |s| s.ms_per_transaction_write,
+    ),
+    (
+        "gstat_delete_operations_per_second",
+        "The number of delete operations/second for this GEOM",
+        |s| s.transfers_per_second_free,
+    ),
+    (
+        "gstat_delete_size_kilobytes",
+        "The size in kilobytes of delete operations for this GEOM",
+        |s| s.kb_per_transfer_free,
+    ),
+    (
+        "gstat_delete_kilobytes_per_second",
+        "The speed in kilobytes/second of delete operations for this GEOM",
+        |s| s.mb_per_second_free * 1024.0,
+    ),
+    (
+        "gstat_miliseconds_per_delete",
+        "The speed in miliseconds/delete operation for this GEOM",
+        |s| s.ms_per_transaction_free,
+    ),
+    (
+        "gstat_other_operations_per_second",
+        "The number of other operations (BIO_FLUSH)/second for this GEOM",
+        |s| s.transfers_per_second_other,
+    ),
+    (
+        "gstat_miliseconds_per_other",
+        "The speed in miliseconds/other operation (BIO_FLUSH) for this GEOM",
+        |s| s.ms_per_transaction_other,
+    ),
+    (
+        "gstat_percent_busy",
+        "The percent of the time this GEOM is busy",
+        |s| s.busy_pct.min(100.0),
+    ),
+];
+
+/// Map the name of each physical provider to the values of its [`LABELS`].
+fn labels(tree: &Tree) -> HashMap<String, Vec<String>> {
+    let mut labels = HashMap::new();
+    for geom in tree.classes().flat_map(|class| class.geoms()) {
+        if geom.rank() != 1 {
+            continue;
+        }
+        for provider in geom.providers() {
+            let name = provider.name().to_string_lossy().into_owned();
+            let attr = |attr| {
+                provider
+                    .config()
+                    .get(attr)
+                    .map(|v| v.to_string_lossy().into_owned())
+                    .unwrap_or_default()
+            };
+            let values = vec![
+                name.clone(),
+                attr("descr"),
+                provider.mediasize().to_string(),
+                provider.sectorsize().to_string(),
+                attr("lunid"),
+                attr("ident"),
+                attr("rotationrate"),
+                attr("fwsectors"),
+                attr("fwheads"),
+            ];
+            labels.insert(name, values);
+        }
+    }
+    labels
+}
+
+/// Publishes the Python gstat_exporter's metrics
+pub struct Legacy {
+    #[allow(clippy::type_complexity)]
+    gauges: Vec<(GaugeVec, fn(&Record) -> f64)>,
+    /// The previous scrape's snapshot, once there's been one
+    deltas: RefCell<Option<DeltaSnapshot>>,
+}
+
+impl Legacy {
+    pub fn new() -> Result<Self, Box<dyn Error>> {
+        // It's always 1, and need never be updated
+        register_gauge!(
+            "gstat_up",
+            "The value of this Gauge is always 1 when the gstat_exporter is up"
+        )?
+        .set(1.0);
+        let gauges = METRICS
+            .iter()
+            .map(|(name, help, f)| {
+                Ok((register_gauge_vec!(*name, *help, &LABELS)?, *f))
+            })
+            .collect::<Result<Vec<_>, prometheus::Error>>()?;
+        Ok(Legacy {
+            gauges,
+            deltas: RefCell::default(),
+        })
+    }
+
+    /// Forget the previous scrape's snapshot.
+    pub fn restart(&self) {
+        self.deltas.borrow_mut().take();
+    }
+
+    /// Publish the statistics since the previous scrape of every physical
+    /// provider among `devices`.
+    ///
+    /// Nothing is published at the first scrape.  Devices that arrived since
+    /// the previous one report their statistics since they were created.
+    pub fn collect(
+        &self,
+        devices: &[Device],
+        tree: &Tree,
+    ) -> Result<(), Box<dyn Error>> {
+        for (gauge, _) in self.gauges.iter() {
+            gauge.reset();
+        }
+        let mut deltas = self.deltas.borrow_mut();
+        let deltas = match deltas.as_mut() {
+            Some(deltas) => deltas,
+            None => deltas.insert(DeltaSnapshot::new()?),
+        };
+        let deltas = deltas.refresh()?;
+        if deltas.is_first() {
+            return Ok(());
+        }
+        let labels = labels(tree);
+        // Like the Python exporter, ignore --normalize
+        let wanted =
+            devices.iter().map(Device::raw_name).collect::<HashSet<_>>();
+        for (name, _, stats) in deltas.providers() {
+            let name = name.to_string_lossy();
+            if !wanted.contains(&*name) {
+                continue;
+            }
+            let Some(values) = labels.get(&*name) else {
+                continue;
+            };
+            let values = values.iter().map(String::as_str).collect::<Vec<_>>();
+            let record = stats.to_record();
+            for (gauge, f) in self.gauges.iter() {
+                gauge.with_label_values(&values).set(f(&record));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    /// Every metric of every provider should be a sensible number
+    #[test]
+    fn metrics() {
+        let mut deltas = DeltaSnapshot::new().unwrap();
+        deltas.refresh().unwrap();
+        for (name, _, stats) in deltas.refresh().unwrap().providers() {
+            let record = stats.to_record();
+            for (metric, _, f) in METRICS.iter() {
+                let v = f(&record);
+                assert!(v.is_finite() && v >= 0.0, "{name:?} {metric} = {v}");
+            }
+        }
+    }
+}
@@ -1,150 +1,1105 @@
 // vim: tw=80
+mod acl;
+mod cardinality;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod hot;
+mod legacy;
+mod normalize;
+#[cfg(feature = "opentelemetry")]
+mod otel;
+mod query;
+
 use std::{
+    any::Any,
+    borrow::Cow,
+    cell::RefCell,
+    collections::HashMap,
     error::Error,
+    ffi::CStr,
+    io::Cursor,
     net::{IpAddr, SocketAddr},
+    panic::{self, AssertUnwindSafe},
+    time::{Duration, Instant},
 };
 
 use clap::Parser;
-use freebsd_libgeom::{Snapshot, Statistics, Tree};
-use prometheus_exporter::prometheus::register_gauge_vec;
+use freebsd_libgeom::{
+    record::Record,
+    DeviceEvent,
+    DeviceWatcher,
+    Devstat,
+    GconfigIter,
+    Gident,
+    Snapshot,
+    Statistics,
+    StatsHandle,
+    Tree,
+};
+use prometheus::{
+    register_gauge_vec,
+    register_int_counter,
+    Encoder,
+    GaugeVec,
+    IntCounter,
+    TextEncoder,
+};
 use regex::Regex;
+use serde_derive::{Deserialize, Serialize};
+use tiny_http::{Header, Response, Server};
+
+use crate::legacy::Legacy;
 
 /// Export GEOM device metrics to Prometheus
 #[derive(Debug, Default, clap::Parser)]
 struct Cli {
     /// Bind to this local address
     #[clap(short = 'b', default_value = "0.0.0.0")]
-    addr:     String,
+    addr:          String,
+    /// Only answer clients within this network, like "192.0.2.0/24".  May be
+    /// repeated.
+    #[clap(long = "allow-cidr", value_parser = str::parse::<acl::Cidr>)]
+    allow_cidr:    Vec<acl::Cidr>,
+    /// Never answer clients within this network.  May be repeated.
+    #[clap(long = "deny-cidr", value_parser = str::parse::<acl::Cidr>)]
+    deny_cidr:     Vec<acl::Cidr>,
     /// Only report physical providers (those with rank of 1).
     #[clap(short = 'P', long = "physical")]
-    physical: bool,
+    physical:      bool,
+    /// Also report devices that aren't GEOM providers, like pass(4).
+    #[clap(long = "all-devstat")]
+    all_devstat:   bool,
     /// Only report devices with names matching this regex.
     #[clap(short = 'f', long = "include")]
-    include:  Option<String>,
+    include:       Option<String>,
+    /// Only report devices belonging to these GEOM classes, like DISK,PART.
+    #[clap(long = "class", value_delimiter = ',')]
+    class:         Option<Vec<String>>,
     /// Do not report devices with names matching this regex
     #[clap(short = 'F', long = "exclude")]
-    exclude:  Option<String>,
+    exclude:       Option<String>,
     /// TCP port
     #[clap(short = 'p', default_value = "9248")]
-    port:     u16,
+    port:          u16,
+    /// Also publish geom_topology_info, describing which providers are built on
+    /// which.
+    #[clap(long = "topology")]
+    topology:      bool,
+    /// Also publish geom_mediasize_bytes and geom_partition_size_bytes.
+    #[clap(long = "capacity")]
+    capacity:      bool,
+    /// Don't serve metrics until at least this many devices are found
+    #[clap(long = "min-devices", default_value = "1")]
+    min_devices:   usize,
+    /// Serve metrics anyway if --min-devices is still unmet after this many
+    /// seconds.
+    #[clap(long = "ready-timeout", default_value = "300")]
+    ready_timeout: u64,
+    /// Also sample this many of the busiest devices every --hot-interval, and
+    /// publish their recent latency.
+    #[clap(long = "hot-devices")]
+    hot_devices:   Option<usize>,
+    /// Milliseconds between samples of the --hot-devices
+    #[clap(
+        long = "hot-interval",
+        default_value = "100",
+        requires = "hot_devices",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    hot_interval:  u64,
+    /// Also publish the metrics of the Python gstat_exporter, like
+    /// gstat_queue_depth, to ease migrating dashboards.
+    #[clap(long = "legacy")]
+    legacy:        bool,
+    /// Rewrite device names before exporting them, like
+    /// "^diskid/DISK-(.*)=$1".  Each rule is a regex and its replacement,
+    /// applied in order.  May be repeated.
+    #[clap(long = "normalize", value_parser = str::parse::<normalize::Rule>)]
+    normalize:     Vec<normalize::Rule>,
+    /// Publish the I/O statistics of at most this many devices at once.
+    /// Additional devices are left out until others depart.
+    #[clap(long = "device-limit")]
+    device_limit:  Option<usize>,
+    /// Keep publishing a departed device's last I/O statistics for this many
+    /// seconds, in case it returns.
+    #[clap(long = "device-ttl", default_value = "0")]
+    device_ttl:    u64,
+    /// Also stream each device's statistics over gRPC, on this TCP port.
+    /// Experimental.
+    #[cfg(feature = "grpc")]
+    #[clap(long = "grpc-port")]
+    grpc_port:     Option<u16>,
+    /// Milliseconds between the intervals streamed over gRPC
+    #[cfg(feature = "grpc")]
+    #[clap(
+        long = "grpc-interval",
+        default_value = "1000",
+        requires = "grpc_port",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    grpc_interval: u64,
+    /// Export a trace span for every request to this OTLP collector, like
+    /// "http://localhost:4317".
+    #[cfg(feature = "opentelemetry")]
+    #[clap(long = "otlp-endpoint")]
+    otlp_endpoint: Option<String>,
+    #[clap(subcommand)]
+    command:       Option<Command>,
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let cli: Cli = Cli::parse();
+#[derive(Debug, clap::Subcommand)]
+enum Command {
+    /// Print statistics from a running exporter, then exit.
+    Query(query::Args),
+}
 
-    // Parse address used to bind exporter to.
-    let ia: IpAddr = cli.addr.parse().unwrap();
-    let sa = SocketAddr::new(ia, cli.port);
+/// Cumulative statistics for one kind of operation
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+struct Totals {
+    bytes:      u64,
+    operations: u64,
+    /// Total time spent processing operations, in seconds
+    duration:   f64,
+}
+
+/// One device's cumulative statistics, as published at `/json`
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct Device {
+    name:         String,
+    /// The devices' names before `--normalize`, if it changed them.  If
+    /// several devices were normalized to the same name, the statistics are
+    /// those of the first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    raw:          Vec<String>,
+    /// Cumulative time in seconds that the device had at least one
+    /// outstanding operation
+    busy_time:    f64,
+    queue_length: u32,
+    read:         Totals,
+    write:        Totals,
+    free:         Totals,
+    other:        Totals,
+}
+
+/// `record` must be cumulative, as computed without a previous snapshot.
+impl From<Record> for Device {
+    fn from(record: Record) -> Self {
+        Device {
+            name:         record.name,
+            raw:          Vec::new(),
+            busy_time:    record.busy_time,
+            queue_length: record.queue_length,
+            read:         Totals {
+                bytes:      record.total_bytes_read,
+                operations: record.total_transfers_read,
+                duration:   record.total_duration_read,
+            },
+            write:        Totals {
+                bytes:      record.total_bytes_write,
+                operations: record.total_transfers_write,
+                duration:   record.total_duration_write,
+            },
+            free:         Totals {
+                bytes:      record.total_bytes_free,
+                operations: record.total_transfers_free,
+                duration:   record.total_duration_free,
+            },
+            // devstat doesn't count the bytes of other operations
+            other:        Totals {
+                bytes:      0,
+                operations: record.total_transfers_other,
+                duration:   record.total_duration_other,
+            },
+        }
+    }
+}
+
+impl Device {
+    /// The device's name as GEOM knows it
+    fn raw_name(&self) -> &str {
+        self.raw.first().unwrap_or(&self.name)
+    }
+}
+
+/// Gauges published with --capacity
+struct Capacity {
+    mediasize:      GaugeVec,
+    partition_size: GaugeVec,
+}
 
-    let include = cli.include.as_ref().map(|s| Regex::new(s).unwrap());
-    let exclude = cli.exclude.as_ref().map(|s| Regex::new(s).unwrap());
-
-    let exporter = prometheus_exporter::start(sa).unwrap();
-
-    let duration = register_gauge_vec!(
-        "geom_duration",
-        "Total time spent processing commands in seconds",
-        &["device", "method"]
-    )
-    .expect("cannot create gauge");
-    let bytes = register_gauge_vec!(
-        "geom_bytes",
-        "Total bytes processed",
-        &["device", "method"]
-    )
-    .expect("cannot create gauge");
-    let ops = register_gauge_vec!(
-        "geom_operations",
-        "Total operations processed",
-        &["device", "method"]
-    )
-    .expect("cannot create gauge");
-    let busy_time = register_gauge_vec!(
-        "geom_busy_time",
-        "Cumulative time in seconds that the device had at least one \
-         outstanding operation",
-        &["device"]
-    )
-    .expect("cannot create gauge");
-    let queue_length = register_gauge_vec!(
-        "geom_queue_length",
-        "Number of incomplete transactions at the sampling instant",
-        &["device"]
-    )
-    .expect("cannot create gauge");
-
-    loop {
-        let _guard = exporter.wait_request();
+/// Gauges published with --hot-devices
+struct Hot {
+    sampler: hot::Sampler,
+    /// How many devices to sample
+    count:   usize,
+    /// Each device's busy time at the previous scrape
+    busy:    RefCell<HashMap<String, f64>>,
+    latency: GaugeVec,
+    peak:    GaugeVec,
+}
+
+impl Hot {
+    /// Publish the latency of the busiest devices, and choose which devices to
+    /// sample until the next scrape.
+    fn collect(&self, devices: &[Device]) {
+        let mut busy = self.busy.borrow_mut();
+        let busiest = hot::busiest(&busy, devices, self.count);
+        *busy = devices
+            .iter()
+            .map(|dev| (dev.name.clone(), dev.busy_time))
+            .collect();
+        // The sampler knows devices by their raw names
+        let raw = devices
+            .iter()
+            .map(|dev| (dev.name.as_str(), dev.raw_name()))
+            .collect::<HashMap<_, _>>();
+        let normalized = devices
+            .iter()
+            .map(|dev| (dev.raw_name(), dev.name.as_str()))
+            .collect::<HashMap<_, _>>();
+        let busiest = busiest
+            .iter()
+            .filter_map(|name| raw.get(name.as_str()))
+            .map(|name| (*name).to_owned())
+            .collect();
+        let (latest, peak) = self.sampler.select(busiest);
+        for (gauge, latencies) in [(&self.latency, latest), (&self.peak, peak)]
+        {
+            for (device, latency) in latencies {
+                let Some(&device) = normalized.get(device.as_str()) else {
+                    continue;
+                };
+                gauge.with_label_values(&[device, "read"]).set(latency.read);
+                gauge
+                    .with_label_values(&[device, "write"])
+                    .set(latency.write);
+            }
+        }
+    }
+}
+
+/// Which devices to report, and what to call them
+#[derive(Clone)]
+struct Filter {
+    physical:    bool,
+    all_devstat: bool,
+    class:       Option<Vec<String>>,
+    include:     Option<Regex>,
+    exclude:     Option<Regex>,
+    normalize:   Vec<normalize::Rule>,
+}
+
+impl Filter {
+    fn new(cli: &Cli) -> Result<Self, Box<dyn Error>> {
+        Ok(Filter {
+            physical:    cli.physical,
+            all_devstat: cli.all_devstat,
+            class:       cli.class.clone(),
+            include:     cli.include.as_deref().map(Regex::new).transpose()?,
+            exclude:     cli.exclude.as_deref().map(Regex::new).transpose()?,
+            normalize:   cli.normalize.clone(),
+        })
+    }
+
+    /// Should this device be reported, based on the command line filters?
+    fn wanted(&self, name: &str, class: &str) -> bool {
+        self.class
+            .as_ref()
+            .map(|c| c.iter().any(|c| c.eq_ignore_ascii_case(class)))
+            .unwrap_or(true)
+            && self
+                .include
+                .as_ref()
+                .map(|f| f.is_match(name))
+                .unwrap_or(true)
+            && !self
+                .exclude
+                .as_ref()
+                .map(|f| f.is_match(name))
+                .unwrap_or(false)
+    }
+
+    /// A device's name as it should be exported
+    fn normalize<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        normalize::apply(&self.normalize, name)
+    }
+
+    /// The name, before normalization, of a devstat device that should be
+    /// reported, or `None` if it shouldn't be.  `gident` is the device's place
+    /// in the GEOM tree, if any.
+    fn select<'a>(
+        &self,
+        gident: Option<Gident<'a>>,
+        item: &Devstat<'a>,
+    ) -> Option<Cow<'a, str>> {
+        let (name, class) = match gident {
+            Some(gident) => match (gident.rank(), gident.name()) {
+                (Some(rank), Ok(name)) if rank == 1 || !self.physical => (
+                    name.to_string_lossy(),
+                    gident
+                        .class()
+                        .map(CStr::to_string_lossy)
+                        .unwrap_or_default(),
+                ),
+                _ => return None,
+            },
+            None if self.all_devstat && !self.physical => (
+                Cow::Owned(item.devname()),
+                item.device_name().to_string_lossy(),
+            ),
+            None => return None,
+        };
+        self.wanted(&name, &class).then_some(name)
+    }
+}
+
+/// Publishes GEOM statistics as Prometheus gauges
+struct Collector {
+    filter:       Filter,
+    duration:     GaugeVec,
+    bytes:        GaugeVec,
+    ops:          GaugeVec,
+    busy_time:    GaugeVec,
+    queue_length: GaugeVec,
+    topology:     Option<GaugeVec>,
+    capacity:     Option<Capacity>,
+    hot:          Option<Hot>,
+    legacy:       Option<Legacy>,
+    mirror_state: GaugeVec,
+    component:    GaugeVec,
+    eli_attached: GaugeVec,
+    /// Logs devices arriving and departing between scrapes
+    watcher:      RefCell<DeviceWatcher>,
+    /// Decides which devices' I/O statistics are published
+    guard:        RefCell<cardinality::Guard>,
+    /// Counts devices whose I/O statistics were removed
+    evictions:    IntCounter,
+    /// Maps normalized names to raw ones, with --normalize
+    name_info:    Option<GaugeVec>,
+    /// Keeps /dev/devstat open, rather than reopening it for every scrape
+    _stats:       StatsHandle,
+}
+
+/// Look up a configuration attribute, lossily converted to UTF-8.
+fn config_value<'a>(config: GconfigIter<'a>, name: &str) -> Cow<'a, str> {
+    config
+        .get(name)
+        .map(CStr::to_string_lossy)
+        .unwrap_or_default()
+}
+
+impl Collector {
+    /// `panics` counts panics in the collection subsystem, including the
+    /// --hot-devices sampling thread.
+    fn new(cli: &Cli, panics: &IntCounter) -> Result<Self, Box<dyn Error>> {
+        let filter = Filter::new(cli)?;
+        let duration = register_gauge_vec!(
+            "geom_duration",
+            "Total time spent processing commands in seconds",
+            &["device", "method"]
+        )?;
+        let bytes = register_gauge_vec!(
+            "geom_bytes",
+            "Total bytes processed",
+            &["device", "method"]
+        )?;
+        let ops = register_gauge_vec!(
+            "geom_operations",
+            "Total operations processed",
+            &["device", "method"]
+        )?;
+        let busy_time = register_gauge_vec!(
+            "geom_busy_time",
+            "Cumulative time in seconds that the device had at least one \
+             outstanding operation",
+            &["device"]
+        )?;
+        let queue_length = register_gauge_vec!(
+            "geom_queue_length",
+            "Number of incomplete transactions at the sampling instant",
+            &["device"]
+        )?;
+        let topology = if cli.topology {
+            Some(register_gauge_vec!(
+                "geom_topology_info",
+                "Always 1.  Each provider has one series for every provider \
+                 that it is built upon, or one with an empty parent if none.",
+                &["device", "class", "parent"]
+            )?)
+        } else {
+            None
+        };
+        let capacity = if cli.capacity {
+            Some(Capacity {
+                mediasize:      register_gauge_vec!(
+                    "geom_mediasize_bytes",
+                    "Size of each provider in bytes",
+                    &["device", "class"]
+                )?,
+                partition_size: register_gauge_vec!(
+                    "geom_partition_size_bytes",
+                    "Size of each partition in bytes",
+                    &["device", "disk", "type"]
+                )?,
+            })
+        } else {
+            None
+        };
+        let hot = if let Some(count) = cli.hot_devices {
+            Some(Hot {
+                sampler: hot::Sampler::spawn(
+                    Duration::from_millis(cli.hot_interval),
+                    panics.clone(),
+                )?,
+                count,
+                busy: RefCell::default(),
+                latency: register_gauge_vec!(
+                    "geom_hot_latency_seconds",
+                    "Average latency per operation of the busiest devices, \
+                     during the most recent --hot-interval",
+                    &["device", "method"]
+                )?,
+                peak: register_gauge_vec!(
+                    "geom_hot_latency_peak_seconds",
+                    "Highest average latency per operation of the busiest \
+                     devices, during any --hot-interval since the previous \
+                     scrape",
+                    &["device", "method"]
+                )?,
+            })
+        } else {
+            None
+        };
+        let legacy = if cli.legacy {
+            Some(Legacy::new()?)
+        } else {
+            None
+        };
+        let mirror_state = register_gauge_vec!(
+            "geom_mirror_state",
+            "Always 1.  The state of each gmirror(8) mirror, like COMPLETE or \
+             DEGRADED.",
+            &["mirror", "state"]
+        )?;
+        let component = register_gauge_vec!(
+            "geom_mirror_component_state",
+            "Always 1.  The state of each gmirror(8) component, like ACTIVE \
+             or SYNCHRONIZING.",
+            &["mirror", "component", "state"]
+        )?;
+        let eli_attached = register_gauge_vec!(
+            "geom_eli_attached",
+            "Always 1.  Present for each provider that geli(8) is attached \
+             to, labeled with the geli device's state.",
+            &["device", "state"]
+        )?;
+        let evictions = register_int_counter!(
+            "geom_exporter_evicted_devices_total",
+            "Number of devices whose I/O statistics were removed, after being \
+             absent for --device-ttl or to stay within --device-limit"
+        )?;
+        let name_info = if cli.normalize.is_empty() {
+            None
+        } else {
+            Some(register_gauge_vec!(
+                "geom_device_name_info",
+                "Always 1.  Maps each device name that --normalize changed to \
+                 the names of the devices that GEOM knows by it.",
+                &["device", "raw"]
+            )?)
+        };
+        Ok(Collector {
+            filter,
+            duration,
+            bytes,
+            ops,
+            busy_time,
+            queue_length,
+            topology,
+            capacity,
+            hot,
+            legacy,
+            mirror_state,
+            component,
+            eli_attached,
+            watcher: RefCell::default(),
+            guard: RefCell::new(cardinality::Guard::new(
+                Duration::from_secs(cli.device_ttl),
+                cli.device_limit,
+            )),
+            evictions,
+            name_info,
+            _stats: StatsHandle::new()?,
+        })
+    }
+
+    /// Forget the state carried between scrapes, which a panic may have left
+    /// inconsistent.  The next scrape starts afresh, as if it were the first.
+    fn restart(&self) {
+        *self.watcher.borrow_mut() = DeviceWatcher::default();
+        self.guard.borrow_mut().clear();
+        for gauge in [
+            &self.duration,
+            &self.bytes,
+            &self.ops,
+            &self.busy_time,
+            &self.queue_length,
+        ] {
+            gauge.reset();
+        }
+        if let Some(hot) = self.hot.as_ref() {
+            hot.busy.borrow_mut().clear();
+        }
+        if let Some(legacy) = self.legacy.as_ref() {
+            legacy.restart();
+        }
+    }
+
+    /// Read the current statistics of every device that should be reported.
+    fn sample(&self, tree: &mut Tree) -> Result<Vec<Device>, Box<dyn Error>> {
+        let mut current = Snapshot::new()?;
+        let mut devices: Vec<Device> = Vec::with_capacity(current.len());
+        // Index of each normalized name in `devices`
+        let mut index = HashMap::with_capacity(current.len());
+        for item in current.iter() {
+            let gident = tree.lookup(item.id());
+            let Some(name) = self.filter.select(gident, &item) else {
+                continue;
+            };
+            let normalized = self.filter.normalize(&name).into_owned();
+            let raw = if normalized != name {
+                vec![name.into_owned()]
+            } else {
+                Vec::new()
+            };
+            if let Some(&i) = index.get(&normalized) {
+                let dev: &mut Device = &mut devices[i];
+                if !raw.is_empty() {
+                    if dev.raw.is_empty() {
+                        dev.raw.push(dev.name.clone());
+                    }
+                    dev.raw.extend(raw);
+                }
+                continue;
+            }
+            index.insert(normalized.clone(), devices.len());
+            let mut record = Statistics::compute(item, None, 0.0).to_record();
+            record.name = normalized;
+            devices.push(Device {
+                raw,
+                ..Device::from(record)
+            });
+        }
+        Ok(devices)
+    }
+
+    /// Update every gauge from a fresh snapshot.
+    ///
+    /// Returns the number of devices reported.
+    fn collect(&self) -> Result<usize, Box<dyn Error>> {
         // Note: it might be more efficient to only call Tree:new if we detect
         // that a device has arrived or departed.  But on a system with hundreds
         // of disks, it only takes 13ms.
         let mut tree = Tree::new()?;
-        let mut current = Snapshot::new()?;
-        busy_time.reset();
-        duration.reset();
-        bytes.reset();
-        ops.reset();
-        queue_length.reset();
-        for item in current.iter() {
-            if let Some(gident) = tree.lookup(item.id()) {
-                if let Some(rank) = gident.rank() {
-                    if rank > 1 && cli.physical {
+        for event in self.watcher.borrow_mut().update(&tree) {
+            match event {
+                DeviceEvent::Arrived(name) => {
+                    eprintln!("Device arrived: {name}")
+                }
+                DeviceEvent::Departed(name) => {
+                    eprintln!("Device departed: {name}")
+                }
+            }
+        }
+        let mut devices = self.sample(&mut tree)?;
+        let ndevices = devices.len();
+        let now = Instant::now();
+        let update = self
+            .guard
+            .borrow_mut()
+            .update(devices.iter().map(|dev| dev.name.as_str()), now);
+        devices.retain(|dev| update.refused.binary_search(&dev.name).is_err());
+        self.evict(&update.evicted);
+        if let Some(topology) = self.topology.as_ref() {
+            topology.reset();
+            self.collect_topology(topology, &tree);
+        }
+        if let Some(capacity) = self.capacity.as_ref() {
+            capacity.mediasize.reset();
+            capacity.partition_size.reset();
+            self.collect_capacity(capacity, &tree);
+        }
+        if let Some(hot) = self.hot.as_ref() {
+            hot.latency.reset();
+            hot.peak.reset();
+            hot.collect(&devices);
+        }
+        if let Some(legacy) = self.legacy.as_ref() {
+            legacy.collect(&devices, &tree)?;
+        }
+        self.mirror_state.reset();
+        self.component.reset();
+        self.eli_attached.reset();
+        self.collect_state(&tree);
+        if let Some(name_info) = self.name_info.as_ref() {
+            name_info.reset();
+            for dev in devices.iter() {
+                for raw in dev.raw.iter() {
+                    name_info.with_label_values(&[&dev.name, raw]).set(1.0);
+                }
+            }
+        }
+        for dev in devices.iter() {
+            let device = dev.name.as_str();
+            self.busy_time
+                .with_label_values(&[device])
+                .set(dev.busy_time);
+            self.queue_length
+                .with_label_values(&[device])
+                .set(dev.queue_length as f64);
+            for (method, totals) in [
+                ("read", &dev.read),
+                ("write", &dev.write),
+                ("free", &dev.free),
+            ] {
+                self.bytes
+                    .with_label_values(&[device, method])
+                    .set(totals.bytes as f64);
+                self.duration
+                    .with_label_values(&[device, method])
+                    .set(totals.duration);
+                self.ops
+                    .with_label_values(&[device, method])
+                    .set(totals.operations as f64);
+            }
+            // devstat doesn't count the bytes of other operations
+            self.duration
+                .with_label_values(&[device, "other"])
+                .set(dev.other.duration);
+            self.ops
+                .with_label_values(&[device, "other"])
+                .set(dev.other.operations as f64);
+        }
+        Ok(ndevices)
+    }
+
+    /// Remove the I/O statistics of the `evicted` devices, so Prometheus stops
+    /// scraping them.
+    fn evict(&self, evicted: &[String]) {
+        for device in evicted {
+            self.evictions.inc();
+            // Errors just mean that the series didn't exist
+            let _ = self.busy_time.remove_label_values(&[device]);
+            let _ = self.queue_length.remove_label_values(&[device]);
+            for method in ["read", "write", "free", "other"] {
+                let _ = self.duration.remove_label_values(&[device, method]);
+                let _ = self.ops.remove_label_values(&[device, method]);
+                let _ = self.bytes.remove_label_values(&[device, method]);
+            }
+        }
+    }
+
+    /// Walk the whole GEOM mesh, recording each provider's parents.
+    fn collect_topology(&self, topology: &GaugeVec, tree: &Tree) {
+        for class in tree.classes() {
+            let class_name = class.name().to_string_lossy();
+            for geom in class.geoms() {
+                for provider in geom.providers() {
+                    let name = provider.name().to_string_lossy();
+                    if !self.filter.wanted(&name, &class_name) {
                         continue;
                     }
-                    let device = gident.name().unwrap().to_string_lossy();
-                    if !include
-                        .as_ref()
-                        .map(|f| f.is_match(&device))
-                        .unwrap_or(true)
+                    let name = self.filter.normalize(&name);
+                    let mut orphan = true;
+                    for parent in geom.consumers().filter_map(|c| c.provider())
                     {
-                        continue;
+                        let parent = parent.name().to_string_lossy();
+                        let parent = self.filter.normalize(&parent);
+                        topology
+                            .with_label_values(&[&name, &class_name, &parent])
+                            .set(1.0);
+                        orphan = false;
                     }
-                    if exclude
-                        .as_ref()
-                        .map(|f| f.is_match(&device))
-                        .unwrap_or(false)
-                    {
+                    if orphan {
+                        topology
+                            .with_label_values(&[&name, &class_name, ""])
+                            .set(1.0);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Record the size of every provider, and of partitions separately.
+    fn collect_capacity(&self, capacity: &Capacity, tree: &Tree) {
+        for class in tree.classes() {
+            let class_name = class.name().to_string_lossy();
+            let part = class_name == "PART";
+            for geom in class.geoms() {
+                for provider in geom.providers() {
+                    let name = provider.name().to_string_lossy();
+                    if !self.filter.wanted(&name, &class_name) {
                         continue;
                     }
-                    let stats = Statistics::compute(item, None, 0.0);
-
-                    busy_time
-                        .with_label_values(&[&device])
-                        .set(stats.busy_time());
-                    queue_length
-                        .with_label_values(&[&device])
-                        .set(stats.queue_length() as f64);
-                    bytes
-                        .with_label_values(&[&device, "read"])
-                        .set(stats.total_bytes_read() as f64);
-                    duration
-                        .with_label_values(&[&device, "read"])
-                        .set(stats.total_duration_read());
-                    ops.with_label_values(&[&device, "read"])
-                        .set(stats.total_transfers_read() as f64);
-                    bytes
-                        .with_label_values(&[&device, "write"])
-                        .set(stats.total_bytes_write() as f64);
-                    duration
-                        .with_label_values(&[&device, "write"])
-                        .set(stats.total_duration_write());
-                    ops.with_label_values(&[&device, "write"])
-                        .set(stats.total_transfers_write() as f64);
-                    bytes
-                        .with_label_values(&[&device, "free"])
-                        .set(stats.total_bytes_free() as f64);
-                    duration
-                        .with_label_values(&[&device, "free"])
-                        .set(stats.total_duration_free());
-                    ops.with_label_values(&[&device, "free"])
-                        .set(stats.total_transfers_free() as f64);
-                    duration
-                        .with_label_values(&[&device, "other"])
-                        .set(stats.total_duration_other());
-                    ops.with_label_values(&[&device, "other"])
-                        .set(stats.total_transfers_other() as f64);
+                    let name = self.filter.normalize(&name);
+                    let size = provider.mediasize() as f64;
+                    capacity
+                        .mediasize
+                        .with_label_values(&[&name, &class_name])
+                        .set(size);
+                    if part {
+                        let disk = geom.name().to_string_lossy();
+                        let disk = self.filter.normalize(&disk);
+                        let ptype = config_value(provider.config(), "type");
+                        capacity
+                            .partition_size
+                            .with_label_values(&[&name, &disk, &ptype])
+                            .set(size);
+                    }
                 }
             }
         }
     }
+
+    /// Record the operational state of mirrors and geli devices.
+    fn collect_state(&self, tree: &Tree) {
+        for class in tree.classes() {
+            let class_name = class.name().to_string_lossy();
+            match class_name.as_ref() {
+                "MIRROR" => {
+                    for geom in class.geoms() {
+                        let mirror = geom.name().to_string_lossy();
+                        if !self.filter.wanted(&mirror, &class_name) {
+                            continue;
+                        }
+                        let state = config_value(geom.config(), "State");
+                        self.mirror_state
+                            .with_label_values(&[&mirror, &state])
+                            .set(1.0);
+                        for consumer in geom.consumers() {
+                            let Some(provider) = consumer.provider() else {
+                                continue;
+                            };
+                            let name = provider.name().to_string_lossy();
+                            let state =
+                                config_value(consumer.config(), "State");
+                            self.component
+                                .with_label_values(&[&mirror, &name, &state])
+                                .set(1.0);
+                        }
+                    }
+                }
+                "ELI" => {
+                    for geom in class.geoms() {
+                        let state = config_value(geom.config(), "State");
+                        for provider in
+                            geom.consumers().filter_map(|c| c.provider())
+                        {
+                            let name = provider.name().to_string_lossy();
+                            if !self.filter.wanted(&name, &class_name) {
+                                continue;
+                            }
+                            let name = self.filter.normalize(&name);
+                            self.eli_attached
+                                .with_label_values(&[&name, &state])
+                                .set(1.0);
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+/// Tracks whether GEOM has settled enough to serve metrics.
+///
+/// At boot time, the exporter may start before all GEOM providers have been
+/// created.  Until at least `min_devices` are found, or until the deadline
+/// passes, metrics requests will fail, rather than return an incomplete list
+/// of devices.
+struct Readiness {
+    min_devices: usize,
+    deadline:    Instant,
+    ready:       bool,
+}
+
+impl Readiness {
+    fn new(min_devices: usize, timeout: Duration) -> Self {
+        Readiness {
+            min_devices,
+            deadline: Instant::now() + timeout,
+            ready: false,
+        }
+    }
+
+    /// Record the result of a collection, and return whether we're ready.
+    fn update(&mut self, ndevices: usize) -> bool {
+        self.ready |=
+            ndevices >= self.min_devices || Instant::now() >= self.deadline;
+        self.ready
+    }
+}
+
+type HttpResponse = Response<Cursor<Vec<u8>>>;
+
+fn not_ready() -> HttpResponse {
+    Response::from_string("Waiting for GEOM devices\n").with_status_code(503)
+}
+
+/// Update the gauges.  When tracing, note on the request's span how many
+/// devices were found and how long it took.
+fn collect(collector: &Collector) -> Result<usize, Box<dyn Error>> {
+    #[cfg(feature = "opentelemetry")]
+    let start = Instant::now();
+    let ndevices = collector.collect()?;
+    #[cfg(feature = "opentelemetry")]
+    otel::record_collection(ndevices, start.elapsed());
+    Ok(ndevices)
+}
+
+fn metrics(
+    collector: &Collector,
+    readiness: &mut Readiness,
+) -> Result<HttpResponse, Box<dyn Error>> {
+    let ndevices = collect(collector)?;
+    if !readiness.update(ndevices) {
+        return Ok(not_ready());
+    }
+    let encoder = TextEncoder::new();
+    let mut buf = Vec::new();
+    encoder.encode(&prometheus::gather(), &mut buf)?;
+    let ct = Header::from_bytes(&b"Content-Type"[..], encoder.format_type())
+        .unwrap();
+    Ok(Response::from_data(buf).with_header(ct))
+}
+
+fn json(
+    collector: &Collector,
+    readiness: &mut Readiness,
+) -> Result<HttpResponse, Box<dyn Error>> {
+    #[cfg(feature = "opentelemetry")]
+    let start = Instant::now();
+    let devices = collector.sample(&mut Tree::new()?)?;
+    #[cfg(feature = "opentelemetry")]
+    otel::record_collection(devices.len(), start.elapsed());
+    if !readiness.update(devices.len()) {
+        return Ok(not_ready());
+    }
+    let buf = serde_json::to_vec(&devices)?;
+    let ct = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
+        .unwrap();
+    Ok(Response::from_data(buf).with_header(ct))
+}
+
+/// If `degraded`, try collecting again, to see whether it has recovered.
+fn health(
+    collector: &Collector,
+    readiness: &mut Readiness,
+    degraded: bool,
+) -> Result<HttpResponse, Box<dyn Error>> {
+    if !readiness.ready || degraded {
+        let ndevices = collect(collector)?;
+        if !readiness.update(ndevices) {
+            return Ok(not_ready());
+        }
+    }
+    Ok(Response::from_string("OK\n"))
+}
+
+/// The message that a panic was started with, if any
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
+/// Isolates the HTTP server from panics in the collection subsystem, like
+/// those that unexpected kernel data might cause.  A panic fails only the
+/// request that caused it, and `/health` reports the exporter as degraded
+/// until a collection succeeds.
+struct Supervisor {
+    panics:   IntCounter,
+    /// The message of the most recent panic, until a request succeeds
+    degraded: Option<String>,
+}
+
+impl Supervisor {
+    /// Handle one request with `f`.  If it panics, call `restart` to clean up
+    /// after it.
+    fn run<F, R>(
+        &mut self,
+        f: F,
+        restart: R,
+    ) -> Result<HttpResponse, Box<dyn Error>>
+    where
+        F: FnOnce() -> Result<HttpResponse, Box<dyn Error>>,
+        R: FnOnce(),
+    {
+        // The panic hook has already logged the panic by the time it's caught.
+        match panic::catch_unwind(AssertUnwindSafe(f)) {
+            Ok(r) => {
+                if r.is_ok() {
+                    self.degraded = None;
+                }
+                r
+            }
+            Err(payload) => {
+                self.panics.inc();
+                restart();
+                let msg = panic_message(&*payload).to_owned();
+                let response = Response::from_string(format!(
+                    "Degraded: collection panicked: {msg}\n"
+                ))
+                .with_status_code(503);
+                self.degraded = Some(msg);
+                Ok(response)
+            }
+        }
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let cli: Cli = Cli::parse();
+    if let Some(Command::Query(args)) = &cli.command {
+        return query::run(args);
+    }
+
+    // Parse address used to bind exporter to.
+    let ia: IpAddr = cli.addr.parse()?;
+    let sa = SocketAddr::new(ia, cli.port);
+
+    let panics = register_int_counter!(
+        "geom_exporter_panics_total",
+        "Number of times that collecting statistics has panicked"
+    )?;
+    let rejected = register_int_counter!(
+        "geom_exporter_rejected_requests_total",
+        "Number of requests refused by --allow-cidr or --deny-cidr"
+    )?;
+    let acl = acl::Acl {
+        allow: cli.allow_cidr.clone(),
+        deny:  cli.deny_cidr.clone(),
+    };
+    let collector = Collector::new(&cli, &panics)?;
+    #[cfg(feature = "opentelemetry")]
+    let telemetry = cli.otlp_endpoint.as_deref().map(otel::init).transpose()?;
+    #[cfg(feature = "grpc")]
+    if let Some(port) = cli.grpc_port {
+        grpc::serve(
+            SocketAddr::new(ia, port),
+            Filter::new(&cli)?,
+            Duration::from_millis(cli.grpc_interval),
+            acl.clone(),
+            rejected.clone(),
+            panics.clone(),
+        )?;
+    }
+    let mut supervisor = Supervisor {
+        panics,
+        degraded: None,
+    };
+    let mut readiness =
+        Readiness::new(cli.min_devices, Duration::from_secs(cli.ready_timeout));
+
+    let server = Server::http(sa).map_err(|e| e as Box<dyn Error>)?;
+    for request in server.incoming_requests() {
+        if !acl.permits(request.remote_addr().map(SocketAddr::ip)) {
+            rejected.inc();
+            let response =
+                Response::from_string("Forbidden\n").with_status_code(403);
+            if let Err(e) = request.respond(response) {
+                eprintln!("Error sending response: {e}");
+            }
+            continue;
+        }
+        let path = request.url().split('?').next().unwrap_or_default();
+        #[cfg(feature = "opentelemetry")]
+        let _span = telemetry
+            .as_ref()
+            .map(|_| otel::request_span(&request, path).entered());
+        let degraded = supervisor.degraded.is_some();
+        let restart = || collector.restart();
+        let response = match path {
+            "/metrics" => {
+                supervisor.run(|| metrics(&collector, &mut readiness), restart)
+            }
+            "/health" => supervisor
+                .run(|| health(&collector, &mut readiness, degraded), restart),
+            "/json" => {
+                supervisor.run(|| json(&collector, &mut readiness), restart)
+            }
+            _ => Ok(Response::from_string("Not found\n").with_status_code(404)),
+        }
+        .unwrap_or_else(|e| {
+            Response::from_string(format!("{e}\n")).with_status_code(500)
+        });
+        if let Err(e) = request.respond(response) {
+            eprintln!("Error sending response: {e}");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    mod supervisor {
+        use super::*;
+
+        fn supervisor() -> Supervisor {
+            Supervisor {
+                panics:   IntCounter::new("panics", "panics").unwrap(),
+                degraded: None,
+            }
+        }
+
+        #[test]
+        fn panic() {
+            let mut s = supervisor();
+            let mut restarted = false;
+            let r = s
+                .run(|| panic!("unexpected kernel data"), || restarted = true)
+                .unwrap();
+            assert_eq!(r.status_code(), 503);
+            assert!(restarted);
+            assert_eq!(s.panics.get(), 1);
+            assert_eq!(s.degraded.as_deref(), Some("unexpected kernel data"));
+
+            // Recovery
+            let r = s.run(|| Ok(Response::from_string("OK\n")), || ()).unwrap();
+            assert_eq!(r.status_code(), 200);
+            assert_eq!(s.panics.get(), 1);
+            assert!(s.degraded.is_none());
+        }
+
+        #[test]
+        fn panic_message() {
+            let e = panic::catch_unwind(|| panic!("{}", 42)).unwrap_err();
+            assert_eq!(super::panic_message(&*e), "42");
+        }
+    }
+
+    mod readiness {
+        use super::*;
+
+        #[test]
+        fn enough_devices() {
+            let mut r = Readiness::new(2, Duration::from_secs(3600));
+            assert!(!r.update(0));
+            assert!(!r.update(1));
+            assert!(r.update(2));
+            // Once ready, always ready
+            assert!(r.update(0));
+        }
+
+        #[test]
+        fn timeout() {
+            let mut r = Readiness::new(2, Duration::ZERO);
+            assert!(r.update(0));
+        }
+    }
 }
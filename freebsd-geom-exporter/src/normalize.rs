@@ -0,0 +1,83 @@
+//! Rewriting device names before they're exported
+//!
+//! Dashboards may want the same disk to have the same label regardless of
+//! how it was discovered, like `da12` and `diskid/DISK-XYZ`.  Each `--normalize`
+//! rule is a regex and a replacement, applied in order to every device name.
+use std::{borrow::Cow, fmt, str::FromStr};
+
+use regex::Regex;
+
+/// One `--normalize` rule, written as `REGEX=REPLACEMENT`
+#[derive(Clone, Debug)]
+pub struct Rule {
+    re:          Regex,
+    /// May refer to capture groups, like `$1`
+    replacement: String,
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (re, replacement) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected REGEX=REPLACEMENT, got {s:?}"))?;
+        let re = Regex::new(re).map_err(|e| e.to_string())?;
+        Ok(Rule {
+            re,
+            replacement: replacement.to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.re, self.replacement)
+    }
+}
+
+/// Apply every rule, in order, to `name`.  Each rule replaces only its first
+/// match.
+pub fn apply<'a>(rules: &[Rule], name: &'a str) -> Cow<'a, str> {
+    let mut name = Cow::Borrowed(name);
+    for rule in rules {
+        if let Cow::Owned(s) = rule.re.replace(&name, rule.replacement.as_str())
+        {
+            name = Cow::Owned(s);
+        }
+    }
+    name
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    fn rules(rules: &[&str]) -> Vec<Rule> {
+        rules.iter().map(|r| r.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn apply_in_order() {
+        let rules =
+            rules(&["^diskid/DISK-(.*)$=$1", "^da12$=XYZ", "^XYZ$=disk-XYZ"]);
+        assert_eq!(apply(&rules, "diskid/DISK-XYZ"), "disk-XYZ");
+        assert_eq!(apply(&rules, "da12"), "disk-XYZ");
+        assert_eq!(apply(&rules, "da120"), "da120");
+    }
+
+    #[test]
+    fn unchanged_is_borrowed() {
+        let rules = rules(&["^ada=da"]);
+        assert!(matches!(apply(&rules, "nvd0"), Cow::Borrowed(_)));
+        assert!(matches!(apply(&rules, "ada0"), Cow::Owned(_)));
+    }
+
+    #[test]
+    fn parse() {
+        assert!("no-equals".parse::<Rule>().is_err());
+        assert!("(=x".parse::<Rule>().is_err());
+        let rule = "^gpt/(.*)=$1".parse::<Rule>().unwrap();
+        assert_eq!(rule.to_string(), "^gpt/(.*)=$1");
+    }
+}
@@ -0,0 +1,157 @@
+//! Tracing scrapes with OpenTelemetry
+//!
+//! With `--otlp-endpoint`, every request gets a span, exported over OTLP.  If
+//! the request carries a W3C `traceparent` header, the span joins the
+//! caller's trace, so a scrape pipeline can be traced end to end.  Spans that
+//! collect statistics also record how many devices were found and how long
+//! that took.
+//!
+//! This is only built with the `opentelemetry` feature.
+use std::{error::Error, time::Duration};
+
+use opentelemetry::{
+    global,
+    propagation::Extractor,
+    trace::TraceError,
+    KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{propagation::TraceContextPropagator, trace, Resource};
+use tiny_http::{Header, Request};
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::layer::SubscriberExt;
+
+/// Keeps the exporter's background tasks running.  Dropping it flushes any
+/// spans not yet exported.
+pub struct Telemetry {
+    _runtime: tokio::runtime::Runtime,
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        global::shutdown_tracer_provider();
+    }
+}
+
+/// Export spans to the OTLP collector at `endpoint`, like
+/// "http://localhost:4317".
+pub fn init(endpoint: &str) -> Result<Telemetry, Box<dyn Error>> {
+    // The batch exporter runs on its own threads, so the HTTP server needn't
+    // be async.
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .thread_name("otlp")
+        .enable_all()
+        .build()?;
+    let tracer = {
+        let _guard = runtime.enter();
+        opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(
+                opentelemetry_otlp::new_exporter()
+                    .tonic()
+                    .with_endpoint(endpoint),
+            )
+            .with_trace_config(trace::config().with_resource(Resource::new([
+                KeyValue::new("service.name", env!("CARGO_BIN_NAME")),
+                KeyValue::new("service.version", env!("CARGO_PKG_VERSION")),
+            ])))
+            .install_batch(opentelemetry_sdk::runtime::Tokio)?
+    };
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    global::set_error_handler(|e: opentelemetry::global::Error| {
+        eprintln!("Error exporting traces: {e}");
+    })
+    .map_err(|e| TraceError::Other(Box::new(e)))?;
+    let subscriber = tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(tracer));
+    tracing::subscriber::set_global_default(subscriber)?;
+    Ok(Telemetry { _runtime: runtime })
+}
+
+/// Adapts a request's headers for [`TraceContextPropagator`]
+struct Headers<'a>(&'a [Header]);
+
+impl Extractor for Headers<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|h| h.field.as_str().as_str().eq_ignore_ascii_case(key))
+            .map(|h| h.value.as_str())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.iter().map(|h| h.field.as_str().as_str()).collect()
+    }
+}
+
+/// A span for handling `request`, which continues the caller's trace if the
+/// request has a `traceparent` header.
+pub fn request_span(request: &Request, path: &str) -> Span {
+    let span = tracing::info_span!(
+        "scrape",
+        otel.kind = "server",
+        http.request.method = %request.method(),
+        url.path = path,
+        geom.devices = tracing::field::Empty,
+        geom.collection_duration_ms = tracing::field::Empty,
+    );
+    let cx = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&Headers(request.headers()))
+    });
+    span.set_parent(cx);
+    span
+}
+
+/// Note on the current request's span how many devices a collection found,
+/// and how long it took.
+pub fn record_collection(ndevices: usize, elapsed: Duration) {
+    let span = Span::current();
+    span.record("geom.devices", ndevices);
+    span.record(
+        "geom.collection_duration_ms",
+        elapsed.as_secs_f64() * 1000.0,
+    );
+}
+
+#[cfg(test)]
+mod t {
+    use opentelemetry::{
+        propagation::TextMapPropagator,
+        trace::TraceContextExt,
+    };
+
+    use super::*;
+
+    fn header(field: &str, value: &str) -> Header {
+        Header::from_bytes(field.as_bytes(), value.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn traceparent() {
+        let headers = [
+            header("Accept", "text/plain"),
+            header(
+                "Traceparent",
+                "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+            ),
+        ];
+        let cx = TraceContextPropagator::new().extract(&Headers(&headers));
+        let sc = cx.span().span_context().clone();
+        assert!(sc.is_remote());
+        assert!(sc.is_sampled());
+        assert_eq!(
+            sc.trace_id().to_string(),
+            "0af7651916cd43dd8448eb211c80319c"
+        );
+        assert_eq!(sc.span_id().to_string(), "b7ad6b7169203331");
+    }
+
+    #[test]
+    fn no_traceparent() {
+        let headers = [header("Accept", "text/plain")];
+        let cx = TraceContextPropagator::new().extract(&Headers(&headers));
+        assert!(!cx.span().span_context().is_valid());
+    }
+}
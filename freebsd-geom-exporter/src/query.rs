@@ -0,0 +1,251 @@
+//! A command-line client for a running exporter's `/json` endpoint
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    error::Error,
+    io::{Read, Write},
+    net::TcpStream,
+    thread,
+    time::{Duration, Instant},
+};
+
+use freebsd_libgeom::name_cmp;
+
+use crate::{Device, Totals};
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// Address of the exporter to query, as host:port
+    #[clap(long = "host", default_value = "localhost:9248")]
+    host:     String,
+    /// Only display the first this many devices, after sorting
+    #[clap(long = "top")]
+    top:      Option<usize>,
+    /// Sort by this statistic, largest first.  "name" sorts alphabetically.
+    #[clap(long = "sort", value_enum, default_value = "pct_busy")]
+    sort:     SortKey,
+    /// Seconds between the two samples used to compute rates
+    #[clap(short = 'I', long = "interval", default_value = "1")]
+    interval: u64,
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum SortKey {
+    #[value(name = "name")]
+    Name,
+    #[value(name = "qd")]
+    Qd,
+    #[value(name = "r_s")]
+    RS,
+    #[value(name = "kbs_r")]
+    KbsR,
+    #[value(name = "ms_r")]
+    MsR,
+    #[value(name = "w_s")]
+    WS,
+    #[value(name = "kbs_w")]
+    KbsW,
+    #[value(name = "ms_w")]
+    MsW,
+    #[value(name = "pct_busy")]
+    PctBusy,
+}
+
+/// Per-second statistics for one device, computed from two samples
+#[derive(Debug)]
+struct Row {
+    name:     String,
+    qd:       u32,
+    r_s:      f64,
+    kbs_r:    f64,
+    ms_r:     f64,
+    w_s:      f64,
+    kbs_w:    f64,
+    ms_w:     f64,
+    pct_busy: f64,
+}
+
+impl Row {
+    fn new(prev: &Device, cur: &Device, etime: f64) -> Self {
+        let (r_s, kbs_r, ms_r) = rates(&prev.read, &cur.read, etime);
+        let (w_s, kbs_w, ms_w) = rates(&prev.write, &cur.write, etime);
+        Row {
+            name: cur.name.clone(),
+            qd: cur.queue_length,
+            r_s,
+            kbs_r,
+            ms_r,
+            w_s,
+            kbs_w,
+            ms_w,
+            pct_busy: ((cur.busy_time - prev.busy_time) / etime * 100.0)
+                .max(0.0),
+        }
+    }
+
+    fn key(&self, k: SortKey) -> f64 {
+        match k {
+            SortKey::Name => 0.0,
+            SortKey::Qd => self.qd as f64,
+            SortKey::RS => self.r_s,
+            SortKey::KbsR => self.kbs_r,
+            SortKey::MsR => self.ms_r,
+            SortKey::WS => self.w_s,
+            SortKey::KbsW => self.kbs_w,
+            SortKey::MsW => self.ms_w,
+            SortKey::PctBusy => self.pct_busy,
+        }
+    }
+
+    fn cmp_by(&self, k: SortKey, other: &Self) -> Ordering {
+        match k {
+            SortKey::Name => name_cmp(&self.name, &other.name),
+            // Largest first
+            _ => other.key(k).total_cmp(&self.key(k)),
+        }
+    }
+}
+
+/// Operations per second, kB per second, and ms per operation
+fn rates(prev: &Totals, cur: &Totals, etime: f64) -> (f64, f64, f64) {
+    let ops = cur.operations.saturating_sub(prev.operations);
+    let bytes = cur.bytes.saturating_sub(prev.bytes);
+    let ms = if ops > 0 {
+        (cur.duration - prev.duration) * 1000.0 / ops as f64
+    } else {
+        0.0
+    };
+    (ops as f64 / etime, bytes as f64 / 1024.0 / etime, ms)
+}
+
+/// Fetch the devices published by the exporter at `host`
+fn fetch(host: &str) -> Result<Vec<Device>, Box<dyn Error>> {
+    let mut stream = TcpStream::connect(host)?;
+    write!(stream, "GET /json HTTP/1.0\r\nHost: {host}\r\n\r\n")?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or("Malformed HTTP response")?;
+    let status = head.lines().next().unwrap_or_default();
+    if status.split_whitespace().nth(1) != Some("200") {
+        return Err(format!("{host}: {status}").into());
+    }
+    Ok(serde_json::from_str(body)?)
+}
+
+/// Compute rates between two samples, then sort and truncate them.  Devices
+/// absent from the first sample are skipped, since their counters cover an
+/// unknown time.
+fn rows(
+    prev: &[Device],
+    cur: &[Device],
+    etime: f64,
+    sort: SortKey,
+    top: Option<usize>,
+) -> Vec<Row> {
+    let prev = prev
+        .iter()
+        .map(|dev| (dev.name.as_str(), dev))
+        .collect::<HashMap<_, _>>();
+    let mut rows = cur
+        .iter()
+        .filter_map(|dev| {
+            prev.get(dev.name.as_str()).map(|p| Row::new(p, dev, etime))
+        })
+        .collect::<Vec<_>>();
+    rows.sort_by(|l, r| l.cmp_by(sort, r));
+    rows.truncate(top.unwrap_or(usize::MAX));
+    rows
+}
+
+pub fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    let prev = fetch(&args.host)?;
+    let start = Instant::now();
+    thread::sleep(Duration::from_secs(args.interval));
+    let cur = fetch(&args.host)?;
+    let etime = start.elapsed().as_secs_f64();
+
+    println!(
+        "{:>5} {:>7} {:>7} {:>7} {:>7} {:>7} {:>7} {:>6} Name",
+        "L(q)", "r/s", "kBps", "ms/r", "w/s", "kBps", "ms/w", "%busy"
+    );
+    for row in rows(&prev, &cur, etime, args.sort, args.top) {
+        println!(
+            "{:>5} {:>7.0} {:>7.0} {:>7.1} {:>7.0} {:>7.0} {:>7.1} {:>6.1} {}",
+            row.qd,
+            row.r_s,
+            row.kbs_r,
+            row.ms_r,
+            row.w_s,
+            row.kbs_w,
+            row.ms_w,
+            row.pct_busy,
+            row.name
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    fn dev(name: &str, ops: u64, duration: f64, busy_time: f64) -> Device {
+        Device {
+            name: name.to_owned(),
+            busy_time,
+            write: Totals {
+                bytes: ops * 4096,
+                operations: ops,
+                duration,
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn rates() {
+        let prev = [dev("da0", 100, 1.0, 10.0)];
+        let cur = [dev("da0", 300, 2.0, 11.0)];
+        let rows = rows(&prev, &cur, 2.0, SortKey::Name, None);
+        assert_eq!(rows[0].w_s, 100.0);
+        assert_eq!(rows[0].kbs_w, 400.0);
+        assert_eq!(rows[0].ms_w, 5.0);
+        assert_eq!(rows[0].pct_busy, 50.0);
+        assert_eq!(rows[0].r_s, 0.0);
+        assert_eq!(rows[0].ms_r, 0.0);
+    }
+
+    #[test]
+    fn sort_and_top() {
+        let prev = [
+            dev("da10", 0, 0.0, 0.0),
+            dev("da2", 0, 0.0, 0.0),
+            dev("da1", 0, 0.0, 0.0),
+        ];
+        let cur = [
+            dev("da10", 10, 0.1, 0.0),
+            dev("da2", 10, 0.5, 0.0),
+            dev("da1", 10, 0.2, 0.0),
+        ];
+        let top = rows(&prev, &cur, 1.0, SortKey::MsW, Some(2));
+        let names = top.iter().map(|r| r.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["da2", "da1"]);
+
+        let all = rows(&prev, &cur, 1.0, SortKey::Name, None);
+        let names = all.iter().map(|r| r.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["da1", "da2", "da10"]);
+    }
+
+    /// A device that appeared between samples is skipped
+    #[test]
+    fn new_device() {
+        let prev = [dev("da0", 10, 0.0, 0.0)];
+        let cur = [dev("da0", 20, 0.0, 0.0), dev("da1", 1_000_000, 0.0, 1.0)];
+        let rows = rows(&prev, &cur, 1.0, SortKey::Name, None);
+        let names = rows.iter().map(|r| r.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["da0"]);
+        assert_eq!(rows[0].w_s, 10.0);
+    }
+}
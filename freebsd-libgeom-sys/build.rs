@@ -13,7 +13,9 @@ fn main() {
         .allowlist_function("geom_.*")
         .allowlist_function("gctl_.*")
         .allowlist_function("g_.*")
+        .allowlist_type("devstat_tag_type")
         .allowlist_type("devstat_trans_flags")
+        .allowlist_var("DEVSTAT_VERSION")
         .parse_callbacks(Box::new(bindgen::CargoCallbacks))
         .generate()
         .expect("Unable to generate bindings");
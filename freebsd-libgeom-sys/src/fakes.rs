@@ -3,9 +3,15 @@
 //! docs.rs does all of its builds on Linux, so the usual build script fails.
 //! As a workaround, we skip the usual build script when doing cross-builds, and
 //! define these stubs instead.
+#[derive(Debug)]
 pub struct devstat();
+pub struct gclass();
+pub struct gconfig();
+pub struct gconsumer();
+pub struct ggeom();
 pub struct gident();
 pub struct gmesh();
+pub struct gprovider();
 #[allow(dead_code)]
 #[derive(Copy, Clone)]
 pub struct timespec(i32);
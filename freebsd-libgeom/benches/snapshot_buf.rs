@@ -0,0 +1,67 @@
+//! Compare the cost of sampling with `Snapshot` and with `SnapshotBuf`.
+//!
+//! Run with "cargo bench --bench snapshot_buf".  Besides the time per sample,
+//! it reports the number of heap allocations per sample, which should be zero
+//! for `SnapshotBuf` once it has warmed up.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    hint::black_box,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+use freebsd_libgeom::{Snapshot, SnapshotBuf};
+
+/// Counts every allocation made by the process
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const ITERATIONS: usize = 10_000;
+
+/// Run `f` repeatedly, and print the time and allocations per iteration.
+fn bench<F: FnMut()>(name: &str, mut f: F) {
+    // Warm up
+    f();
+    let allocs = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    let elapsed = start.elapsed();
+    let allocs = ALLOCATIONS.load(Ordering::Relaxed) - allocs;
+    println!(
+        "{:12} {:>8.2} us/sample {:>6.2} allocations/sample",
+        name,
+        elapsed.as_secs_f64() * 1e6 / ITERATIONS as f64,
+        allocs as f64 / ITERATIONS as f64
+    );
+}
+
+fn main() {
+    bench("Snapshot", || {
+        let mut snap = Snapshot::new().unwrap();
+        black_box(snap.iter().map(|ds| ds.unit_number()).sum::<i32>());
+    });
+
+    let mut buf = SnapshotBuf::new();
+    bench("SnapshotBuf", || {
+        buf.capture().unwrap();
+        black_box(buf.iter().map(|ds| ds.unit_number()).sum::<i32>());
+    });
+}
@@ -0,0 +1,201 @@
+//! The recommended way to collect statistics within a long-running daemon.
+//!
+//! A [`Sampler`] thread captures a [`SnapshotBuf`] at a fixed interval and
+//! sends it, by value, over a channel.  On the daemon's own thread a
+//! [`Consumer`] computes each provider's statistics between consecutive
+//! samples, rebuilding its [`Tree`] whenever devices come or go.  Spent
+//! buffers are sent back to the sampler, so once it has warmed up, sampling
+//! doesn't allocate.  Shutting down the sampler, or just dropping it, stops
+//! the thread promptly, even in the middle of a long interval.
+//!
+//! The `Tree` stays on the consumer's thread, because it isn't `Send`.  Only
+//! the `SnapshotBuf`s cross threads.
+//!
+//! Similar to "gstat -p -b -c 5", printing each disk's %busy five times.
+
+use std::{
+    error::Error,
+    io,
+    os::raw::c_long,
+    sync::mpsc::{
+        self,
+        Receiver,
+        RecvTimeoutError,
+        Sender,
+        SyncSender,
+        TrySendError,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use freebsd_libgeom::{devstat_generation, SnapshotBuf, Statistics, Tree};
+
+/// One capture of every device's statistics
+pub struct Sample {
+    pub buf:        SnapshotBuf,
+    /// The devstat generation just before the capture.  It changes whenever
+    /// devices are added or removed.
+    pub generation: Option<c_long>,
+}
+
+/// A background thread that captures a [`Sample`] every interval
+pub struct Sampler {
+    samples: Receiver<io::Result<Sample>>,
+    recycle: Sender<SnapshotBuf>,
+    /// Dropping this tells the thread to stop
+    stop:    Option<Sender<()>>,
+    thread:  Option<JoinHandle<()>>,
+}
+
+impl Sampler {
+    pub fn spawn(interval: Duration) -> Self {
+        // Only one sample may wait in the channel.  If the consumer falls
+        // behind, newer samples are dropped rather than queued without bound.
+        let (tx, samples) = mpsc::sync_channel(1);
+        let (recycle, spares) = mpsc::channel();
+        let (stop, stopped) = mpsc::channel();
+        let thread =
+            thread::spawn(move || sample(interval, &tx, &spares, &stopped));
+        Sampler {
+            samples,
+            recycle,
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+
+    /// Wait for the next sample.  Returns `None` if the thread has exited.
+    pub fn recv(&self) -> Option<io::Result<Sample>> {
+        self.samples.recv().ok()
+    }
+
+    /// Return a spent buffer, for the thread to reuse.
+    pub fn recycle(&self, buf: SnapshotBuf) {
+        // If the thread already exited, the buffer is simply freed.
+        let _ = self.recycle.send(buf);
+    }
+
+    /// Stop the thread and wait for it to exit.
+    pub fn shutdown(mut self) -> thread::Result<()> {
+        self.stop.take();
+        self.thread.take().map_or(Ok(()), JoinHandle::join)
+    }
+}
+
+impl Drop for Sampler {
+    fn drop(&mut self) {
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// The body of the sampling thread
+fn sample(
+    interval: Duration,
+    tx: &SyncSender<io::Result<Sample>>,
+    spares: &Receiver<SnapshotBuf>,
+    stop: &Receiver<()>,
+) {
+    loop {
+        let mut buf = spares.try_recv().unwrap_or_default();
+        // Read the generation first, so a device that arrives during the
+        // capture will be noticed by the next sample.
+        let generation = devstat_generation().ok();
+        let sample = buf
+            .capture()
+            .map(|()| Sample { buf, generation })
+            .map_err(io::Error::from);
+        match tx.try_send(sample) {
+            Ok(()) | Err(TrySendError::Full(_)) => {}
+            Err(TrySendError::Disconnected(_)) => return,
+        }
+        match stop.recv_timeout(interval) {
+            Err(RecvTimeoutError::Timeout) => {}
+            // Either told to stop, or the Sampler was dropped
+            Ok(()) | Err(RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+/// Computes statistics from consecutive samples, on the daemon's own thread
+pub struct Consumer {
+    tree:       Tree,
+    /// The devstat generation when `tree` was built
+    generation: Option<c_long>,
+    prev:       Option<SnapshotBuf>,
+}
+
+impl Consumer {
+    pub fn new() -> io::Result<Self> {
+        let generation = devstat_generation().ok();
+        let tree = Tree::new()?;
+        Ok(Consumer {
+            tree,
+            generation,
+            prev: None,
+        })
+    }
+
+    /// Call `f` with the name, rank, and statistics of every GEOM provider,
+    /// computed between the previous sample and this one.  The first sample
+    /// only primes the consumer.
+    ///
+    /// Returns the previous sample's buffer, which should be recycled.
+    pub fn process<F>(
+        &mut self,
+        sample: Sample,
+        mut f: F,
+    ) -> io::Result<Option<SnapshotBuf>>
+    where
+        F: FnMut(&str, u32, &Statistics),
+    {
+        if sample.generation != self.generation {
+            self.tree = Tree::new()?;
+            self.generation = sample.generation;
+        }
+        if let Some(prev) = self.prev.as_ref() {
+            let etime = f64::from(sample.buf.timestamp() - prev.timestamp());
+            for (cur, prev) in sample.buf.iter_pair(Some(prev)) {
+                let Some(gident) = self.tree.lookup(cur.id()) else {
+                    continue;
+                };
+                let (Some(rank), Ok(name)) = (gident.rank(), gident.name())
+                else {
+                    continue;
+                };
+                let stats = Statistics::compute_or_since_creation(
+                    cur, prev, etime, None,
+                );
+                f(&name.to_string_lossy(), rank, &stats);
+            }
+        }
+        Ok(self.prev.replace(sample.buf))
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let sampler = Sampler::spawn(Duration::from_secs(1));
+    let mut consumer = Consumer::new()?;
+    // The first sample only primes the consumer
+    for i in 0..6 {
+        let Some(sample) = sampler.recv() else {
+            break;
+        };
+        let spent = consumer.process(sample?, |name, rank, stats| {
+            if rank == 1 {
+                println!("{name:8} {:>5.1}%", stats.busy_pct());
+            }
+        })?;
+        if let Some(buf) = spent {
+            sampler.recycle(buf);
+        }
+        if i > 0 {
+            println!();
+        }
+    }
+    sampler.shutdown().map_err(|_| "sampler thread panicked")?;
+    Ok(())
+}
@@ -0,0 +1,129 @@
+//! Owned copies of the GEOM configuration
+//!
+//! These contain the same information as `sysctl kern.geom.confxml`, but
+//! unlike [`Tree`] they don't borrow from libgeom.  With the `serde` feature,
+//! they can be serialized.
+use std::{collections::BTreeMap, ffi::CStr, io};
+
+#[cfg(feature = "serde")]
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{GconfigIter, Ggeom, Tree};
+
+/// A class-specific configuration dictionary, like a partition's `type` and
+/// `label`
+pub type Config = BTreeMap<String, String>;
+
+fn string(s: &CStr) -> String {
+    s.to_string_lossy().into_owned()
+}
+
+fn config(iter: GconfigIter<'_>) -> Config {
+    iter.map(|c| (string(c.name()), c.value().map(string).unwrap_or_default()))
+        .collect()
+}
+
+/// The entire GEOM configuration
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct Mesh {
+    pub classes: Vec<Class>,
+}
+
+impl Mesh {
+    /// Read the current configuration from the kernel.
+    pub fn new() -> io::Result<Self> {
+        Ok(Mesh::from(&Tree::new()?))
+    }
+}
+
+impl From<&Tree> for Mesh {
+    fn from(tree: &Tree) -> Self {
+        let classes = tree
+            .classes()
+            .map(|class| Class {
+                name:   string(class.name()),
+                config: config(class.config()),
+                geoms:  class.geoms().map(Geom::from).collect(),
+            })
+            .collect();
+        Mesh { classes }
+    }
+}
+
+/// A GEOM class, like `DISK` or `PART`
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct Class {
+    pub name:   String,
+    pub config: Config,
+    pub geoms:  Vec<Geom>,
+}
+
+/// A GEOM geom, like a disk's partition table
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct Geom {
+    pub name:      String,
+    pub rank:      u32,
+    pub config:    Config,
+    pub providers: Vec<Provider>,
+    pub consumers: Vec<Consumer>,
+}
+
+impl From<Ggeom<'_>> for Geom {
+    fn from(geom: Ggeom<'_>) -> Self {
+        let providers = geom
+            .providers()
+            .map(|p| Provider {
+                name:         string(p.name()),
+                mode:         string(p.mode()),
+                mediasize:    p.mediasize(),
+                sectorsize:   p.sectorsize(),
+                stripesize:   p.stripesize(),
+                stripeoffset: p.stripeoffset(),
+                config:       config(p.config()),
+            })
+            .collect();
+        let consumers = geom
+            .consumers()
+            .map(|c| Consumer {
+                provider: c.provider().map(|p| string(p.name())),
+                mode:     string(c.mode()),
+                config:   config(c.config()),
+            })
+            .collect();
+        Geom {
+            name: string(geom.name()),
+            rank: geom.rank(),
+            config: config(geom.config()),
+            providers,
+            consumers,
+        }
+    }
+}
+
+/// A GEOM provider, like `ada0p1`
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct Provider {
+    pub name:         String,
+    /// Access counts, like `r1w1e1`
+    pub mode:         String,
+    pub mediasize:    i64,
+    pub sectorsize:   u32,
+    pub stripesize:   i64,
+    pub stripeoffset: i64,
+    pub config:       Config,
+}
+
+/// A GEOM consumer
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct Consumer {
+    /// Name of the provider that this consumer is attached to, if any
+    pub provider: Option<String>,
+    /// Access counts, like `r1w1e1`
+    pub mode:     String,
+    pub config:   Config,
+}
@@ -9,26 +9,35 @@
 #![allow(clippy::redundant_closure_call)]
 
 use std::{
-    ffi::CStr,
+    borrow::Borrow,
+    cell::{Cell, RefCell},
+    cmp::Ordering,
+    collections::HashSet,
+    ffi::{CStr, CString},
     fmt,
-    io::{self, Error},
+    io,
     marker::PhantomData,
     mem::{self, MaybeUninit},
     ops::Sub,
-    os::raw::c_void,
+    os::raw::{c_char, c_int, c_long, c_void},
     pin::Pin,
-    ptr::NonNull,
+    ptr::{self, NonNull},
+    slice,
+    sync::{Mutex, MutexGuard, PoisonError},
 };
 
 use freebsd_libgeom_sys::*;
-use lazy_static::lazy_static;
+
+pub mod conf;
+pub mod record;
+mod wire;
 
 // BINTIME_SCALE is 1 / 2**64
 const BINTIME_SCALE: f64 = 5.421010862427522e-20;
 
-/// Used by [`Statistics::compute`]
+/// Used by [`Statistics`]'s accessors
 macro_rules! delta {
-    ($current: ident, $previous: ident, $field:ident, $index:expr) => {{
+    ($current: expr, $previous: expr, $field:ident, $index:expr) => {{
         let idx = $index as usize;
         let old = if let Some(prev) = $previous {
             unsafe { prev.devstat.as_ref() }.$field[idx]
@@ -36,10 +45,58 @@ macro_rules! delta {
             0
         };
         let new = unsafe { $current.devstat.as_ref() }.$field[idx];
-        new - old
+        new.saturating_sub(old)
     }};
 }
 
+/// Borrow a C string from the GEOM mesh, treating NULL as empty.
+///
+/// # Safety
+///
+/// `p` must be NULL or point to a NUL-terminated string that outlives `'a`.
+unsafe fn cstr<'a>(p: *const c_char) -> &'a CStr {
+    if p.is_null() {
+        Default::default()
+    } else {
+        CStr::from_ptr(p)
+    }
+}
+
+/// Defines an iterator over one of libgeom's intrusive linked lists
+macro_rules! list_iter {
+    ($(#[$attr:meta])* $iter:ident, $item:ident, $raw:ty, $ptr:ident,
+     $link:ident) => {
+        $(#[$attr])*
+        #[derive(Debug, Clone)]
+        pub struct $iter<'a> {
+            next:    *mut $raw,
+            phantom: PhantomData<&'a Tree>,
+        }
+
+        impl<'a> $iter<'a> {
+            fn new(first: *mut $raw) -> Self {
+                $iter {
+                    next:    first,
+                    phantom: PhantomData,
+                }
+            }
+        }
+
+        impl<'a> Iterator for $iter<'a> {
+            type Item = $item<'a>;
+
+            fn next(&mut self) -> Option<Self::Item> {
+                let $ptr = NonNull::new(self.next)?;
+                self.next = unsafe { $ptr.as_ref() }.$link.le_next;
+                Some($item {
+                    $ptr,
+                    phantom: PhantomData,
+                })
+            }
+        }
+    };
+}
+
 macro_rules! delta_t {
     ($cur: expr, $prev: expr, $bintime:expr) => {{
         let old: bintime = if let Some(prev) = $prev {
@@ -48,19 +105,36 @@ macro_rules! delta_t {
             bintime { sec: 0, frac: 0 }
         };
         let new: bintime = $bintime(unsafe { $cur.devstat.as_ref() });
-        let mut dsec = new.sec - old.sec;
+        let mut dsec = new.sec.saturating_sub(old.sec);
         let (dfrac, overflow) = new.frac.overflowing_sub(old.frac);
         if overflow {
-            dsec -= 1;
+            dsec = dsec.saturating_sub(1);
         }
         dsec as f64 + dfrac as f64 * BINTIME_SCALE
     }};
 }
 
 macro_rules! fields {
-    ($self: ident, $meth: ident, $field: ident) => {
+    ($self: ident, $meth: ident, $field: ident, $index: expr) => {
+        pub fn $meth(&$self) -> u64 {
+            delta!($self.current, &$self.previous, $field, $index)
+        }
+    }
+}
+
+macro_rules! blocks {
+    ($self: ident, $meth: ident, $bytes: ident) => {
         pub fn $meth(&$self) -> u64 {
-            $self.$field
+            $self.$bytes() / $self.block_size()
+        }
+    }
+}
+
+macro_rules! duration {
+    ($self: ident, $meth: ident, $flag: expr) => {
+        pub fn $meth(&$self) -> f64 {
+            delta_t!($self.current, &$self.previous, |ds: &devstat| ds.duration
+                [$flag.index()])
         }
     }
 }
@@ -69,7 +143,7 @@ macro_rules! fields_per_sec {
     ($self: ident, $meth: ident, $field: ident) => {
         pub fn $meth(&$self) -> f64 {
             if $self.etime > 0.0 {
-                $self.$field as f64 / $self.etime
+                $self.$field() as f64 / $self.etime
             } else {
                 0.0
             }
@@ -80,8 +154,9 @@ macro_rules! fields_per_sec {
 macro_rules! kb_per_xfer {
     ($self: ident, $meth: ident, $xfers: ident, $bytes: ident) => {
         pub fn $meth(&$self) -> f64 {
-            if $self.$xfers > 0 {
-                $self.$bytes as f64 / (1<<10) as f64 / $self.$xfers as f64
+            let xfers = $self.$xfers();
+            if xfers > 0 {
+                $self.$bytes() as f64 / (1<<10) as f64 / xfers as f64
             } else {
                 0.0
             }
@@ -93,7 +168,7 @@ macro_rules! mb_per_sec {
     ($self: ident, $meth: ident, $field: ident) => {
         pub fn $meth(&$self) -> f64 {
             if $self.etime > 0.0 {
-                $self.$field as f64 / (1<<20) as f64 / $self.etime
+                $self.$field() as f64 / (1<<20) as f64 / $self.etime
             } else {
                 0.0
             }
@@ -103,12 +178,10 @@ macro_rules! mb_per_sec {
 
 macro_rules! ms_per_xfer {
     ($self: ident, $meth: ident, $xfers: ident, $duration: ident) => {
-        pub fn $duration(&$self) -> f64 {
-            $self.$duration
-        }
         pub fn $meth(&$self) -> f64 {
-            if $self.$xfers > 0 {
-                $self.$duration * 1000.0 / $self.$xfers as f64
+            let xfers = $self.$xfers();
+            if xfers > 0 {
+                $self.$duration() * 1000.0 / xfers as f64
             } else {
                 0.0
             }
@@ -116,38 +189,301 @@ macro_rules! ms_per_xfer {
     }
 }
 
-lazy_static! {
-    static ref GEOM_STATS: io::Result<()> = {
-        let r = unsafe { geom_stats_open() };
-        if r != 0 {
-            Err(Error::last_os_error())
-        } else {
-            Ok(())
+/// libgeom's global statistics state.  libgeom itself isn't thread-safe, so
+/// every call that touches that state must hold this lock.
+struct Stats {
+    /// Number of live [`StatsHandle`]s
+    users:      usize,
+    /// The devstat generation as of the last [`stats_resync`]
+    generation: c_long,
+}
+
+static STATS: Mutex<Stats> = Mutex::new(Stats {
+    users:      0,
+    generation: 0,
+});
+
+fn stats() -> MutexGuard<'static, Stats> {
+    STATS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// A reference to libgeom's statistics state.  The first opens it, and the
+/// last to be dropped closes it, so independent users within one process
+/// don't interfere with each other.
+///
+/// Every [`Snapshot`] holds one.  A program that takes short-lived snapshots,
+/// like one per request, should hold one of its own too.  Otherwise each
+/// snapshot reopens `/dev/devstat`.
+#[derive(Debug)]
+pub struct StatsHandle(());
+
+impl StatsHandle {
+    /// Open libgeom's statistics state, unless it's already open.
+    pub fn new() -> Result<Self, Error> {
+        let mut stats = stats();
+        if stats.users == 0 {
+            if unsafe { geom_stats_open() } != 0 {
+                return Err(Error::StatsOpenFailed(io::Error::last_os_error()));
+            }
+            // Freshly opened, so it already covers every device
+            stats.generation = devstat_generation().unwrap_or(0);
+        }
+        stats.users += 1;
+        Ok(StatsHandle(()))
+    }
+}
+
+impl Drop for StatsHandle {
+    fn drop(&mut self) {
+        let mut stats = stats();
+        stats.users -= 1;
+        if stats.users == 0 {
+            unsafe { geom_stats_close() };
         }
+    }
+}
+
+/// The kernel's devstat generation, which changes whenever a device is added
+/// or removed.
+///
+/// It's a cheap way to decide whether a [`Tree`] must be rebuilt.
+pub fn devstat_generation() -> io::Result<c_long> {
+    const NAME: &[u8] = b"kern.devstat.generation\0";
+
+    let mut generation: c_long = 0;
+    let mut len = mem::size_of::<c_long>();
+    let r = unsafe {
+        libc::sysctlbyname(
+            NAME.as_ptr().cast(),
+            (&mut generation as *mut c_long).cast(),
+            &mut len,
+            ptr::null(),
+            0,
+        )
     };
+    if r == 0 {
+        Ok(generation)
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Extend libgeom's view of the kernel's statistics to cover any devices that
+/// have been created since it was opened.
+///
+/// Without this, a [`Snapshot`] may omit recently attached devices.
+/// [`Snapshot::new`] calls it automatically whenever devices have been added
+/// or removed, so there is rarely any need to call it directly.  If no
+/// `Snapshot` exists, then there is nothing to resync.
+pub fn stats_resync() {
+    resync(&mut stats());
+}
+
+fn resync(stats: &mut Stats) {
+    if stats.users == 0 {
+        return;
+    }
+    if let Ok(generation) = devstat_generation() {
+        stats.generation = generation;
+    }
+    unsafe { geom_stats_resync() };
+}
+
+/// The kinds of operations that devstat counts separately
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum TransferFlag {
+    /// Operations that transfer no data, like cache flushes
+    NoData,
+    Read,
+    Write,
+    /// Deletions, like TRIM or UNMAP
+    Free,
+}
+
+impl TransferFlag {
+    /// Every kind of operation
+    pub const ALL: [TransferFlag; 4] = [
+        TransferFlag::NoData,
+        TransferFlag::Read,
+        TransferFlag::Write,
+        TransferFlag::Free,
+    ];
+
+    /// Index into `struct devstat`'s per-flag arrays
+    fn index(self) -> usize {
+        devstat_trans_flags::from(self) as usize
+    }
 }
 
+impl From<TransferFlag> for devstat_trans_flags {
+    fn from(flag: TransferFlag) -> Self {
+        match flag {
+            TransferFlag::NoData => devstat_trans_flags_DEVSTAT_NO_DATA,
+            TransferFlag::Read => devstat_trans_flags_DEVSTAT_READ,
+            TransferFlag::Write => devstat_trans_flags_DEVSTAT_WRITE,
+            TransferFlag::Free => devstat_trans_flags_DEVSTAT_FREE,
+        }
+    }
+}
+
+impl TryFrom<devstat_trans_flags> for TransferFlag {
+    type Error = UnknownTransferFlag;
+
+    fn try_from(flag: devstat_trans_flags) -> Result<Self, Self::Error> {
+        TransferFlag::ALL
+            .into_iter()
+            .find(|f| devstat_trans_flags::from(*f) == flag)
+            .ok_or(UnknownTransferFlag(flag))
+    }
+}
+
+/// Returned when converting a value that isn't one of devstat's transfer
+/// flags into a [`TransferFlag`]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct UnknownTransferFlag(pub devstat_trans_flags);
+
+impl fmt::Display for UnknownTransferFlag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown devstat transfer flag {}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownTransferFlag {}
+
 /// Describes the stats of a single geom element as part of a [`Snapshot`].
-#[derive(Debug, Copy, Clone)]
+#[derive(Copy, Clone)]
 #[repr(transparent)]
 pub struct Devstat<'a> {
     devstat: NonNull<devstat>,
     phantom: PhantomData<&'a devstat>,
 }
 
+/// Shows the raw `struct devstat`, counters and all.
+impl fmt::Debug for Devstat<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        unsafe { self.devstat.as_ref() }.fmt(f)
+    }
+}
+
 impl<'a> Devstat<'a> {
+    /// The time at which the device was created, in seconds since boot.
+    ///
+    /// Its counters all started at zero then, so this is the beginning of the
+    /// interval covered by statistics computed from a single snapshot.
+    pub fn creation_time(&self) -> f64 {
+        let ct = unsafe { self.devstat.as_ref() }.creation_time;
+        ct.sec as f64 + ct.frac as f64 * BINTIME_SCALE
+    }
+
+    /// Total bytes transferred by operations of this kind since the device was
+    /// created.
+    ///
+    /// This is devstat's raw cumulative counter.  Use [`Statistics`] for the
+    /// difference between two snapshots.  Operations without data never
+    /// count any bytes.
+    pub fn bytes(&self, flag: TransferFlag) -> u64 {
+        unsafe { self.devstat.as_ref() }.bytes[flag.index()]
+    }
+
+    /// Total operations of this kind completed since the device was created.
+    pub fn operations(&self, flag: TransferFlag) -> u64 {
+        unsafe { self.devstat.as_ref() }.operations[flag.index()]
+    }
+
+    /// Total time spent processing completed operations of this kind since the
+    /// device was created, in seconds.
+    pub fn duration(&self, flag: TransferFlag) -> f64 {
+        let d = unsafe { self.devstat.as_ref() }.duration[flag.index()];
+        d.sec as f64 + d.frac as f64 * BINTIME_SCALE
+    }
+
+    /// Total time that the device had at least one outstanding operation since
+    /// it was created, in seconds.
+    pub fn busy_time(&self) -> f64 {
+        let bt = unsafe { self.devstat.as_ref() }.busy_time;
+        bt.sec as f64 + bt.frac as f64 * BINTIME_SCALE
+    }
+
+    /// The name of the device's driver, like "da" for `da0`.
+    ///
+    /// Devices that aren't GEOM providers, like `pass(4)` and `ses(4)`, can
+    /// only be identified this way.  For GEOM providers, use [`Gident::name`]
+    /// instead.
+    pub fn device_name(&self) -> &'a CStr {
+        let ds = unsafe { self.devstat.as_ref() };
+        let bytes = unsafe {
+            slice::from_raw_parts(
+                ds.device_name.as_ptr() as *const u8,
+                ds.device_name.len(),
+            )
+        };
+        CStr::from_bytes_until_nul(bytes).unwrap_or_default()
+    }
+
+    /// The device's name and unit number combined, like `da0`.
+    ///
+    /// This doesn't require a [`Tree`], but it is only meaningful for devices
+    /// that aren't GEOM providers.
+    pub fn devname(&self) -> String {
+        format!(
+            "{}{}",
+            self.device_name().to_string_lossy(),
+            self.unit_number()
+        )
+    }
+
     pub fn id(&'a self) -> Id<'a> {
         Id {
             id:      unsafe { self.devstat.as_ref() }.id,
             phantom: PhantomData,
         }
     }
+
+    /// How long ago the device was created, in seconds.
+    ///
+    /// For disks present at boot this is about the system's uptime, but it's
+    /// shorter for disks that were hot-plugged later.
+    pub fn uptime(&self) -> io::Result<f64> {
+        let now = f64::from(uptime()?);
+        Ok((now - self.creation_time()).max(0.0))
+    }
+
+    /// The device's unit number, like 0 for `da0`.
+    ///
+    /// GEOM providers and consumers all have a unit number of -1.
+    pub fn unit_number(&self) -> i32 {
+        unsafe { self.devstat.as_ref() }.unit_number
+    }
+
+    /// The devstat record's leading sequence number.
+    ///
+    /// The kernel increments `sequence1` before updating a device's counters,
+    /// and `sequence0` afterwards.
+    pub fn sequence0(&self) -> u32 {
+        unsafe { self.devstat.as_ref() }.sequence0
+    }
+
+    /// The devstat record's trailing sequence number.  See
+    /// [`sequence0`](Devstat::sequence0).
+    pub fn sequence1(&self) -> u32 {
+        unsafe { self.devstat.as_ref() }.sequence1
+    }
+
+    /// Was this record copied while the kernel wasn't updating it?
+    ///
+    /// If not, it's torn: some of its counters may have been updated and
+    /// others not, so statistics computed from it may be implausible.
+    pub fn is_consistent(&self) -> bool {
+        self.sequence0() == self.sequence1()
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
 #[non_exhaustive]
 pub enum GidentError {
     NotAProvider,
+    /// The GEOM mesh is missing a required pointer
+    Malformed,
 }
 
 impl fmt::Display for GidentError {
@@ -156,10 +492,77 @@ impl fmt::Display for GidentError {
             GidentError::NotAProvider => {
                 write!(f, "Not a GEOM provider")
             }
+            GidentError::Malformed => {
+                write!(f, "Malformed GEOM mesh")
+            }
+        }
+    }
+}
+
+impl std::error::Error for GidentError {}
+
+/// Errors from gathering information from the kernel.
+///
+/// Each variant wraps the underlying OS error.  It converts into an
+/// [`io::Error`] of the same kind, for callers that return those.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// libgeom's statistics state couldn't be opened.  Usually that means
+    /// that `/dev/devstat` couldn't be opened.
+    StatsOpenFailed(io::Error),
+    /// The GEOM tree couldn't be fetched from the kernel
+    TreeFailed(io::Error),
+    /// The devices' statistics couldn't be copied from the kernel
+    SnapshotFailed(io::Error),
+}
+
+impl Error {
+    /// The underlying OS error
+    pub fn io_error(&self) -> &io::Error {
+        match self {
+            Error::StatsOpenFailed(e)
+            | Error::TreeFailed(e)
+            | Error::SnapshotFailed(e) => e,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::StatsOpenFailed(e) => {
+                write!(f, "Cannot open devstat statistics: {e}")
+            }
+            Error::TreeFailed(e) => write!(f, "Cannot get GEOM tree: {e}"),
+            Error::SnapshotFailed(e) => {
+                write!(f, "Cannot take devstat snapshot: {e}")
+            }
         }
     }
 }
 
+impl std::error::Error for Error {}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> io::Error {
+        io::Error::new(e.io_error().kind(), e)
+    }
+}
+
+/// Returned by [`Statistics::compute_checked`] when a device's counters can't
+/// be compared between two snapshots.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct DeviceRestarted;
+
+impl fmt::Display for DeviceRestarted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Device was re-created between snapshots")
+    }
+}
+
+impl std::error::Error for DeviceRestarted {}
+
 /// Identifies an element in the Geom [`Tree`]
 #[derive(Debug, Copy, Clone)]
 pub struct Gident<'a> {
@@ -178,16 +581,53 @@ impl<'a> Gident<'a> {
 
     pub fn name(&self) -> Result<&'a CStr, GidentError> {
         if !self.is_provider() {
-            Err(GidentError::NotAProvider)
-        } else {
-            unsafe {
-                let gprovider = self.ident.as_ref().lg_ptr as *const gprovider;
-                assert!(!gprovider.is_null());
+            return Err(GidentError::NotAProvider);
+        }
+        unsafe {
+            let gprovider = self.ident.as_ref().lg_ptr as *const gprovider;
+            if gprovider.is_null() || (*gprovider).lg_name.is_null() {
+                Err(GidentError::Malformed)
+            } else {
                 Ok(CStr::from_ptr((*gprovider).lg_name))
             }
         }
     }
 
+    /// Return the name of the GEOM class, like `DISK` or `PART`, whose geom
+    /// owns this provider or consumer.
+    pub fn class(&self) -> Option<&'a CStr> {
+        unsafe {
+            let ident = self.ident.as_ref();
+            if ident.lg_ptr.is_null() {
+                return None;
+            }
+            let geom = if self.is_provider() {
+                (*(ident.lg_ptr as *const gprovider)).lg_geom
+            } else if self.is_consumer() {
+                (*(ident.lg_ptr as *const gconsumer)).lg_geom
+            } else {
+                return None;
+            };
+            if geom.is_null() || (*geom).lg_class.is_null() {
+                None
+            } else {
+                Some(cstr((*(*geom).lg_class).lg_name))
+            }
+        }
+    }
+
+    /// Return the consumer that this identifies, if it is one.
+    pub fn consumer(&self) -> Option<Gconsumer<'a>> {
+        if !self.is_consumer() {
+            return None;
+        }
+        let consumer = unsafe { self.ident.as_ref() }.lg_ptr as *mut gconsumer;
+        NonNull::new(consumer).map(|consumer| Gconsumer {
+            consumer,
+            phantom: PhantomData,
+        })
+    }
+
     /// Return the GEOM provider rank of this device, if it is a provider.
     pub fn rank(&self) -> Option<u32> {
         if !self.is_provider() {
@@ -195,7 +635,9 @@ impl<'a> Gident<'a> {
         } else {
             unsafe {
                 let gprovider = self.ident.as_ref().lg_ptr as *const gprovider;
-                assert!(!gprovider.is_null());
+                if gprovider.is_null() {
+                    return None;
+                }
                 let geom = (*gprovider).lg_geom;
                 if geom.is_null() {
                     None
@@ -216,16 +658,50 @@ pub struct Id<'a> {
     phantom: PhantomData<&'a Devstat<'a>>,
 }
 
-/// Iterates through a pair of [`Snapshot`]s in lockstep, where one snapshot is
-/// optional.
+/// Find the entry of `prevs` for the same device as `cur`, which is the `i`th
+/// entry of its own snapshot.
+fn find_prev<'p, D: Borrow<devstat>>(
+    prevs: &'p [D],
+    i: usize,
+    cur: &devstat,
+) -> Option<&'p D> {
+    let same = |p: &&D| <D as Borrow<devstat>>::borrow(p).id == cur.id;
+    // Devices are usually in the same order in both snapshots.  But if one
+    // arrived or departed, we must search.
+    prevs
+        .get(i)
+        .filter(same)
+        .or_else(|| prevs.iter().find(same))
+}
+
+/// Iterates through a [`Snapshot`]'s devices, each paired with the same device
+/// in an optional previous snapshot.
 pub struct SnapshotPairIter<'a> {
-    cur:  &'a mut Snapshot,
-    prev: Option<&'a mut Snapshot>,
+    cur:   &'a mut Snapshot,
+    /// Every device in the previous snapshot
+    prevs: Vec<&'a devstat>,
+    /// The index of the next device in `cur`
+    i:     usize,
 }
 
 impl<'a> SnapshotPairIter<'a> {
     fn new(cur: &'a mut Snapshot, prev: Option<&'a mut Snapshot>) -> Self {
-        SnapshotPairIter { cur, prev }
+        let prevs = if let Some(prev) = prev {
+            let mut prevs = Vec::with_capacity(prev.len());
+            loop {
+                let praw = unsafe { geom_stats_snapshot_next(prev.0.as_mut()) };
+                // The devstats live as long as the snapshot does
+                match unsafe { praw.as_ref() } {
+                    Some(p) => prevs.push(p),
+                    None => break,
+                }
+            }
+            prev.reset();
+            prevs
+        } else {
+            Vec::new()
+        };
+        SnapshotPairIter { cur, prevs, i: 0 }
     }
 }
 
@@ -233,42 +709,41 @@ impl<'a> Iterator for SnapshotPairIter<'a> {
     type Item = (Devstat<'a>, Option<Devstat<'a>>);
 
     fn next(&mut self) -> Option<Self::Item> {
-        let ps = if let Some(prev) = self.prev.as_mut() {
-            let praw = unsafe { geom_stats_snapshot_next(prev.0.as_mut()) };
-            NonNull::new(praw).map(|devstat| Devstat {
+        let craw = unsafe { geom_stats_snapshot_next(self.cur.0.as_mut()) };
+        let devstat = NonNull::new(craw)?;
+        let ps = find_prev(&self.prevs, self.i, unsafe { devstat.as_ref() })
+            .map(|p| Devstat {
+                devstat: NonNull::from(*p),
+                phantom: PhantomData,
+            });
+        self.i += 1;
+        Some((
+            Devstat {
                 devstat,
                 phantom: PhantomData,
-            })
-        } else {
-            None
-        };
-        let craw = unsafe { geom_stats_snapshot_next(self.cur.0.as_mut()) };
-        NonNull::new(craw).map(|devstat| {
-            (
-                Devstat {
-                    devstat,
-                    phantom: PhantomData,
-                },
-                ps,
-            )
-        })
+            },
+            ps,
+        ))
     }
 }
 
 impl Drop for SnapshotPairIter<'_> {
     fn drop(&mut self) {
         self.cur.reset();
-        if let Some(prev) = self.prev.as_mut() {
-            prev.reset()
-        }
     }
 }
 
 /// A geom statistics snapshot.
 ///
+/// Snapshots may be created from multiple threads.  libgeom's statistics state
+/// is opened along with the first snapshot, and closed along with the last.
 // FreeBSD BUG: geom_stats_snapshot_get should return an opaque pointer instead
 // of a void*, for better type safety.
-pub struct Snapshot(NonNull<c_void>);
+// FreeBSD BUG: geom_stats_snapshot_timestamp should take a const pointer, not a
+// mut one.  So the timestamp is cached when the snapshot is taken.
+// FreeBSD BUG: libgeom has no way to count a snapshot's devices, short of
+// iterating through them.  So they're counted when the snapshot is taken.
+pub struct Snapshot(NonNull<c_void>, StatsHandle, Timespec, usize);
 
 impl Snapshot {
     /// Iterate through all devices described by the snapshot
@@ -276,8 +751,11 @@ impl Snapshot {
         SnapshotIter(self)
     }
 
-    /// Iterates through a pair of [`Snapshot`]s in lockstep, where one snapshot
-    /// is optional.
+    /// Iterate through this snapshot's devices, each paired with the same
+    /// device in `prev`, if it was present there.
+    ///
+    /// Devices are matched by their devstat id, so statistics remain correct
+    /// even if devices arrive or depart between the two snapshots.
     pub fn iter_pair<'a>(
         &'a mut self,
         prev: Option<&'a mut Snapshot>,
@@ -287,13 +765,64 @@ impl Snapshot {
 
     /// Acquires a new snapshot of the raw data from the kernel.
     ///
-    /// Is not guaranteed to be completely atomic and consistent.
-    pub fn new() -> io::Result<Self> {
-        GEOM_STATS.as_ref().unwrap();
+    /// Is not guaranteed to be completely atomic and consistent.  Check
+    /// [`is_consistent`](Snapshot::is_consistent), or use
+    /// [`with_retries`](Snapshot::with_retries) instead.  Devices attached
+    /// since the previous snapshot are included; see [`stats_resync`].
+    pub fn new() -> Result<Self, Error> {
+        let handle = StatsHandle::new()?;
+        let mut stats = stats();
+        let changed = devstat_generation()
+            .map_or(true, |generation| stats.generation != generation);
+        if changed {
+            resync(&mut stats);
+        }
         let raw = unsafe { geom_stats_snapshot_get() };
-        NonNull::new(raw)
-            .map(Snapshot)
-            .ok_or_else(Error::last_os_error)
+        // Read errno before unlocking, lest StatsHandle::drop clobber it
+        let e = io::Error::last_os_error();
+        drop(stats);
+        let mut raw = NonNull::new(raw).ok_or(Error::SnapshotFailed(e))?;
+        let timestamp = unsafe {
+            let mut ts = MaybeUninit::uninit();
+            geom_stats_snapshot_timestamp(raw.as_mut(), ts.as_mut_ptr());
+            Timespec(ts.assume_init())
+        };
+        let mut snapshot = Snapshot(raw, handle, timestamp, 0);
+        snapshot.3 = snapshot.iter().count();
+        Ok(snapshot)
+    }
+
+    /// Like [`Snapshot::new`], but if any device's record was torn by a
+    /// concurrent update, take the snapshot again, up to `retries` more times.
+    ///
+    /// With `retries` of 0, it's the same as [`Snapshot::new`].  Check
+    /// [`is_consistent`](Snapshot::is_consistent) to find out whether a
+    /// consistent snapshot was eventually taken.
+    pub fn with_retries(retries: usize) -> Result<Self, Error> {
+        let mut snapshot = Self::new()?;
+        for _ in 0..retries {
+            if snapshot.is_consistent() {
+                break;
+            }
+            snapshot = Self::new()?;
+        }
+        Ok(snapshot)
+    }
+
+    /// Were none of the snapshot's records torn?  See
+    /// [`Devstat::is_consistent`].
+    pub fn is_consistent(&mut self) -> bool {
+        self.iter().all(|ds| ds.is_consistent())
+    }
+
+    /// The number of devices described by the snapshot
+    pub fn len(&self) -> usize {
+        self.3
+    }
+
+    /// Does the snapshot describe no devices at all?
+    pub fn is_empty(&self) -> bool {
+        self.3 == 0
     }
 
     /// Reset the state of the internal iterator back to the beginning
@@ -302,15 +831,8 @@ impl Snapshot {
     }
 
     /// Accessor for the embedded timestamp generated by [`Snapshot::new`].
-    // FreeBSD BUG: geom_stats_snapshot_timestamp should take a const pointer,
-    // not a mut one.
-    pub fn timestamp(&mut self) -> Timespec {
-        let inner = unsafe {
-            let mut ts = MaybeUninit::uninit();
-            geom_stats_snapshot_timestamp(self.0.as_mut(), ts.as_mut_ptr());
-            ts.assume_init()
-        };
-        Timespec(inner)
+    pub fn timestamp(&self) -> Timespec {
+        self.2
     }
 }
 
@@ -341,64 +863,402 @@ impl Drop for SnapshotIter<'_> {
     }
 }
 
-/// Computes statistics between two [`Snapshot`]s for the same device.
+/// A reusable buffer holding a snapshot of every device's statistics.
 ///
-/// This is equivalent to libgeom's
-/// [`devstat_compute_statistics`](https://www.freebsd.org/cgi/man.cgi?query=devstat&sektion=3)
-/// function.
-// Note that Rust cannot bind to devstat_compute_statistics because its API
-// includes "long double", which has no Rust equivalent.  So we reimplement the
-// logic here.
-pub struct Statistics<'a> {
-    current:               Devstat<'a>,
-    previous:              Option<Devstat<'a>>,
-    etime:                 f64,
-    total_bytes:           u64,
-    total_bytes_free:      u64,
-    total_bytes_read:      u64,
-    total_bytes_write:     u64,
-    total_blocks:          u64,
-    total_blocks_free:     u64,
-    total_blocks_read:     u64,
-    total_blocks_write:    u64,
-    total_duration:        f64,
-    total_duration_free:   f64,
-    total_duration_other:  f64,
-    total_duration_read:   f64,
-    total_duration_write:  f64,
-    total_transfers:       u64,
-    total_transfers_free:  u64,
-    total_transfers_other: u64,
-    total_transfers_read:  u64,
-    total_transfers_write: u64,
+/// Unlike [`Snapshot`], which allocates new memory every time, a `SnapshotBuf`
+/// reuses its storage for each capture.  Once the buffer has grown large enough
+/// for every device on the system, capturing doesn't allocate at all.  That
+/// makes it suitable for collectors that sample at a high frequency.
+pub struct SnapshotBuf {
+    /// Raw output of the `kern.devstat.all` sysctl: a generation number
+    /// followed by an array of `struct devstat`.  It's stored as `u64` for
+    /// the sake of alignment.
+    buf:       Vec<u64>,
+    /// Number of valid bytes in `buf`
+    len:       usize,
+    timestamp: Timespec,
 }
 
-impl<'a> Statistics<'a> {
-    fields! {self, total_bytes, total_bytes}
-
-    fields! {self, total_bytes_free, total_bytes_free}
+impl SnapshotBuf {
+    /// Create an empty buffer.  It won't allocate until the first
+    /// [`capture`](SnapshotBuf::capture).
+    pub fn new() -> Self {
+        SnapshotBuf {
+            buf:       Vec::new(),
+            len:       0,
+            timestamp: Timespec(freebsd_libgeom_sys::timespec {
+                tv_sec:  0,
+                tv_nsec: 0,
+            }),
+        }
+    }
 
-    fields! {self, total_bytes_read, total_bytes_read}
+    /// Replace the buffer's contents with fresh data from the kernel.
+    ///
+    /// Like [`Snapshot::new`], it is not guaranteed to be completely atomic.
+    /// Check [`is_consistent`](SnapshotBuf::is_consistent), or use
+    /// [`capture_with_retries`](SnapshotBuf::capture_with_retries) instead.
+    pub fn capture(&mut self) -> Result<(), Error> {
+        self.capture_with_retries(0)
+    }
 
-    fields! {self, total_bytes_write, total_bytes_write}
+    /// Like [`capture`](SnapshotBuf::capture), but if any device's record was
+    /// torn by a concurrent update, capture again, up to `retries` more times.
+    ///
+    /// Check [`is_consistent`](SnapshotBuf::is_consistent) to find out
+    /// whether a consistent capture was eventually made.
+    pub fn capture_with_retries(
+        &mut self,
+        retries: usize,
+    ) -> Result<(), Error> {
+        self.capture_once().map_err(Error::SnapshotFailed)?;
+        for _ in 0..retries {
+            if self.is_consistent() {
+                break;
+            }
+            self.capture_once().map_err(Error::SnapshotFailed)?;
+        }
+        self.timestamp = uptime().map_err(Error::SnapshotFailed)?;
+        Ok(())
+    }
 
-    fields! {self, total_blocks, total_blocks}
+    fn capture_once(&mut self) -> io::Result<()> {
+        const NAME: &[u8] = b"kern.devstat.all\0";
+
+        loop {
+            let mut len = self.buf.len() * mem::size_of::<u64>();
+            let r = unsafe {
+                libc::sysctlbyname(
+                    NAME.as_ptr().cast(),
+                    self.buf.as_mut_ptr().cast(),
+                    &mut len,
+                    ptr::null(),
+                    0,
+                )
+            };
+            if r == 0 {
+                self.len = len;
+                break;
+            }
+            let e = io::Error::last_os_error();
+            if e.raw_os_error() != Some(libc::ENOMEM) {
+                return Err(e);
+            }
+            // The buffer is too small.  Find out how large it must be, and
+            // leave room for a few more devices to arrive.
+            let mut needed = 0;
+            let r = unsafe {
+                libc::sysctlbyname(
+                    NAME.as_ptr().cast(),
+                    ptr::null_mut(),
+                    &mut needed,
+                    ptr::null(),
+                    0,
+                )
+            };
+            if r != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            needed += 4 * mem::size_of::<devstat>();
+            let words =
+                (needed + mem::size_of::<u64>() - 1) / mem::size_of::<u64>();
+            self.buf.resize(words, 0);
+        }
+        Ok(())
+    }
 
-    fields! {self, total_blocks_free, total_blocks_free}
+    /// Were none of the most recent capture's records torn?  See
+    /// [`Devstat::is_consistent`].
+    pub fn is_consistent(&self) -> bool {
+        self.iter().all(|ds| ds.is_consistent())
+    }
 
-    fields! {self, total_blocks_read, total_blocks_read}
+    /// The devstat generation at the time of the most recent capture, or
+    /// `None` if nothing has been captured yet.
+    ///
+    /// If it differs from [`devstat_generation`], then devices have been
+    /// added or removed since.
+    pub fn generation(&self) -> Option<c_long> {
+        if self.len < mem::size_of::<c_long>() {
+            None
+        } else {
+            Some(unsafe { self.buf.as_ptr().cast::<c_long>().read() })
+        }
+    }
 
-    fields! {self, total_blocks_write, total_blocks_write}
+    fn devstats(&self) -> &[devstat] {
+        let hdr = mem::size_of::<c_long>();
+        let n = self.len.saturating_sub(hdr) / mem::size_of::<devstat>();
+        unsafe {
+            let first = self.buf.as_ptr().cast::<u8>().add(hdr);
+            slice::from_raw_parts(first.cast::<devstat>(), n)
+        }
+    }
 
-    fields! {self, total_transfers, total_transfers}
+    /// Iterate through all devices described by the most recent capture
+    pub fn iter(&self) -> impl Iterator<Item = Devstat<'_>> + '_ {
+        self.devstats().iter().map(|devstat| Devstat {
+            devstat: NonNull::from(devstat),
+            phantom: PhantomData,
+        })
+    }
 
-    fields! {self, total_transfers_free, total_transfers_free}
+    /// Iterate through this capture's devices, each paired with the same
+    /// device in `prev`, if it was present there.
+    pub fn iter_pair<'a>(
+        &'a self,
+        prev: Option<&'a SnapshotBuf>,
+    ) -> impl Iterator<Item = (Devstat<'a>, Option<Devstat<'a>>)> + 'a {
+        let prevs = prev.map(SnapshotBuf::devstats).unwrap_or_default();
+        self.devstats().iter().enumerate().map(move |(i, cur)| {
+            let p = find_prev(prevs, i, cur);
+            (
+                Devstat {
+                    devstat: NonNull::from(cur),
+                    phantom: PhantomData,
+                },
+                p.map(|p| Devstat {
+                    devstat: NonNull::from(p),
+                    phantom: PhantomData,
+                }),
+            )
+        })
+    }
 
-    fields! {self, total_transfers_read, total_transfers_read}
+    /// The time at which the most recent capture was made
+    pub fn timestamp(&self) -> Timespec {
+        self.timestamp
+    }
+}
 
-    fields! {self, total_transfers_other, total_transfers_other}
+impl Default for SnapshotBuf {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-    fields! {self, total_transfers_write, total_transfers_write}
+/// Computes every device's statistics between successive snapshots.
+///
+/// It keeps the previous snapshot and a [`TreeCache`] for identifying the
+/// devices, so callers needn't pair up the snapshots themselves.  Both
+/// snapshots are [`SnapshotBuf`]s, so once they've grown large enough,
+/// refreshing doesn't allocate.  The first [`refresh`](DeltaSnapshot::refresh)
+/// yields statistics since each device was created, like `iostat`.
+///
+/// # Examples
+/// ```no_run
+/// use std::{thread::sleep, time::Duration};
+///
+/// use freebsd_libgeom::DeltaSnapshot;
+///
+/// let mut ds = DeltaSnapshot::new().unwrap();
+/// loop {
+///     for (name, rank, stats) in ds.refresh().unwrap().providers() {
+///         if rank == 1 {
+///             println!("{:?}: {:.0}%", name, stats.busy_pct());
+///         }
+///     }
+///     sleep(Duration::from_secs(1));
+/// }
+/// ```
+pub struct DeltaSnapshot {
+    prev: SnapshotBuf,
+    cur:  SnapshotBuf,
+    tree: TreeCache,
+}
+
+impl DeltaSnapshot {
+    /// Build the GEOM tree.  No snapshot is taken until the first
+    /// [`refresh`](DeltaSnapshot::refresh).
+    pub fn new() -> Result<Self, Error> {
+        Ok(DeltaSnapshot {
+            prev: SnapshotBuf::new(),
+            cur:  SnapshotBuf::new(),
+            tree: TreeCache::new()?,
+        })
+    }
+
+    /// Take a new snapshot, and iterate through every device's statistics
+    /// since the previous one.
+    pub fn refresh(&mut self) -> Result<DeltaSnapshotIter<'_>, Error> {
+        self.tree.refresh()?;
+        mem::swap(&mut self.cur, &mut self.prev);
+        self.cur.capture()?;
+        let first = self.prev.generation().is_none();
+        let now = f64::from(self.cur.timestamp());
+        let etime = if first {
+            now
+        } else {
+            f64::from(self.cur.timestamp() - self.prev.timestamp())
+        };
+        Ok(DeltaSnapshotIter {
+            cur: self.cur.devstats(),
+            prevs: self.prev.devstats(),
+            i: 0,
+            tree: &self.tree,
+            first,
+            etime,
+            now,
+        })
+    }
+
+    /// The most recently built GEOM tree
+    pub fn tree(&self) -> &Tree {
+        self.tree.tree()
+    }
+}
+
+/// One device's statistics, as yielded by [`DeltaSnapshotIter`]
+pub struct Delta<'a> {
+    /// The device's record in the latest snapshot
+    pub devstat: Devstat<'a>,
+    /// The device's place in the GEOM tree.  It's `None` for devices that
+    /// aren't part of GEOM, like pass(4) devices.
+    pub gident:  Option<Gident<'a>>,
+    pub stats:   Statistics<'a>,
+}
+
+/// Return type of [`DeltaSnapshot::refresh`].  Yields every device's
+/// statistics, GEOM or not.
+pub struct DeltaSnapshotIter<'a> {
+    cur:   &'a [devstat],
+    prevs: &'a [devstat],
+    /// The index of the next device in `cur`
+    i:     usize,
+    tree:  &'a TreeCache,
+    first: bool,
+    etime: f64,
+    /// The system's uptime when the snapshot was taken
+    now:   f64,
+}
+
+impl<'a> DeltaSnapshotIter<'a> {
+    /// The time, in seconds, since the previous snapshot, or since boot if
+    /// there was none.
+    pub fn etime(&self) -> f64 {
+        self.etime
+    }
+
+    /// Is this the first refresh?  If so, the statistics cover the time since
+    /// each device's creation.
+    pub fn is_first(&self) -> bool {
+        self.first
+    }
+
+    /// Yield just the GEOM providers' names, ranks, and statistics.
+    pub fn providers(
+        self,
+    ) -> impl Iterator<Item = (&'a CStr, u32, Statistics<'a>)> {
+        self.filter_map(|delta| {
+            let gident = delta.gident?;
+            Some((gident.name().ok()?, gident.rank()?, delta.stats))
+        })
+    }
+}
+
+impl<'a> Iterator for DeltaSnapshotIter<'a> {
+    type Item = Delta<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let cur = self.cur.get(self.i)?;
+        let prev = find_prev(self.prevs, self.i, cur);
+        self.i += 1;
+        let wrap = |devstat| Devstat {
+            devstat: NonNull::from(devstat),
+            phantom: PhantomData,
+        };
+        let devstat = wrap(cur);
+        let stats = Statistics::compute_or_since_creation(
+            devstat,
+            prev.map(wrap),
+            self.etime,
+            Some(self.now),
+        );
+        Some(Delta {
+            devstat,
+            gident: self.tree.lookup(devstat.id()),
+            stats,
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let n = self.cur.len() - self.i;
+        (n, Some(n))
+    }
+}
+
+/// The time since boot, which is the interval covered by statistics computed
+/// from a single snapshot.
+fn uptime() -> io::Result<Timespec> {
+    let ts = unsafe {
+        let mut ts = MaybeUninit::uninit();
+        if libc::clock_gettime(libc::CLOCK_UPTIME, ts.as_mut_ptr()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        ts.assume_init()
+    };
+    Ok(Timespec(freebsd_libgeom_sys::timespec {
+        tv_sec:  ts.tv_sec as _,
+        tv_nsec: ts.tv_nsec as _,
+    }))
+}
+
+/// Computes statistics between two [`Snapshot`]s for the same device.
+///
+/// This is equivalent to libgeom's
+/// [`devstat_compute_statistics`](https://www.freebsd.org/cgi/man.cgi?query=devstat&sektion=3)
+/// function.
+// Note that Rust cannot bind to devstat_compute_statistics because its API
+// includes "long double", which has no Rust equivalent.  So we reimplement the
+// logic here.
+pub struct Statistics<'a> {
+    current:  Devstat<'a>,
+    previous: Option<Devstat<'a>>,
+    etime:    f64,
+}
+
+impl<'a> Statistics<'a> {
+    fields! {self, total_bytes_free, bytes, TransferFlag::Free.index()}
+
+    fields! {self, total_bytes_read, bytes, TransferFlag::Read.index()}
+
+    fields! {self, total_bytes_write, bytes, TransferFlag::Write.index()}
+
+    blocks! {self, total_blocks, total_bytes}
+
+    blocks! {self, total_blocks_free, total_bytes_free}
+
+    blocks! {self, total_blocks_read, total_bytes_read}
+
+    blocks! {self, total_blocks_write, total_bytes_write}
+
+    duration! {self, total_duration_free, TransferFlag::Free}
+
+    duration! {self, total_duration_other, TransferFlag::NoData}
+
+    duration! {self, total_duration_read, TransferFlag::Read}
+
+    duration! {self, total_duration_write, TransferFlag::Write}
+
+    // Each transaction is counted by the SCSI tag type it was queued with,
+    // regardless of whether it was a read, write, or something else.
+    fields! {self, total_tags_head, tag_types, devstat_tag_type_DEVSTAT_TAG_HEAD}
+
+    fields! {self, total_tags_ordered, tag_types,
+    devstat_tag_type_DEVSTAT_TAG_ORDERED}
+
+    fields! {self, total_tags_simple, tag_types,
+    devstat_tag_type_DEVSTAT_TAG_SIMPLE}
+
+    fields! {self, total_transfers_free, operations, TransferFlag::Free.index()}
+
+    fields! {self, total_transfers_read, operations, TransferFlag::Read.index()}
+
+    fields! {self, total_transfers_other, operations,
+    TransferFlag::NoData.index()}
+
+    fields! {self, total_transfers_write, operations,
+    TransferFlag::Write.index()}
 
     fields_per_sec! {self, blocks_per_second, total_blocks}
 
@@ -410,12 +1270,14 @@ impl<'a> Statistics<'a> {
 
     kb_per_xfer! {self, kb_per_transfer, total_transfers, total_bytes}
 
-    kb_per_xfer! {self, kb_per_transfer_free, total_transfers_free, total_bytes}
+    kb_per_xfer! {self, kb_per_transfer_free, total_transfers_free,
+    total_bytes_free}
 
-    kb_per_xfer! {self, kb_per_transfer_read, total_transfers_read, total_bytes}
+    kb_per_xfer! {self, kb_per_transfer_read, total_transfers_read,
+    total_bytes_read}
 
     kb_per_xfer! {self, kb_per_transfer_write, total_transfers_write,
-    total_bytes}
+    total_bytes_write}
 
     ms_per_xfer! {self, ms_per_transaction, total_transfers, total_duration}
 
@@ -439,6 +1301,12 @@ impl<'a> Statistics<'a> {
 
     mb_per_sec! {self, mb_per_second_write, total_bytes_write}
 
+    fields_per_sec! {self, tags_per_second_head, total_tags_head}
+
+    fields_per_sec! {self, tags_per_second_ordered, total_tags_ordered}
+
+    fields_per_sec! {self, tags_per_second_simple, total_tags_simple}
+
     fields_per_sec! {self, transfers_per_second, total_transfers}
 
     fields_per_sec! {self, transfers_per_second_free, total_transfers_free}
@@ -449,112 +1317,134 @@ impl<'a> Statistics<'a> {
 
     fields_per_sec! {self, transfers_per_second_write, total_transfers_write}
 
+    pub fn total_bytes(&self) -> u64 {
+        self.total_bytes_read()
+            .saturating_add(self.total_bytes_write())
+            .saturating_add(self.total_bytes_free())
+    }
+
+    pub fn total_duration(&self) -> f64 {
+        self.total_duration_read()
+            + self.total_duration_write()
+            + self.total_duration_other()
+            + self.total_duration_free()
+    }
+
+    pub fn total_transfers(&self) -> u64 {
+        self.total_transfers_read()
+            .saturating_add(self.total_transfers_write())
+            .saturating_add(self.total_transfers_other())
+            .saturating_add(self.total_transfers_free())
+    }
+
     /// Compute statistics between two [`Devstat`] objects, which must
     /// correspond to the same device, and should come from two separate
     /// snapshots
     ///
     /// If `prev` is `None`, then statistics since boot will be returned.
     /// `etime` should be the elapsed time in seconds between the two snapshots.
+    ///
+    /// Nothing is computed up front.  Each accessor computes its statistic
+    /// from the two records when it's called, so a caller that needs only a
+    /// few statistics pays only for those.
     pub fn compute(
         current: Devstat<'a>,
         previous: Option<Devstat<'a>>,
         etime: f64,
     ) -> Self {
-        let cur = unsafe { current.devstat.as_ref() };
-
-        let total_transfers_read = delta!(
-            current,
-            previous,
-            operations,
-            devstat_trans_flags_DEVSTAT_READ
-        );
-        let total_transfers_write = delta!(
-            current,
-            previous,
-            operations,
-            devstat_trans_flags_DEVSTAT_WRITE
-        );
-        let total_transfers_other = delta!(
-            current,
-            previous,
-            operations,
-            devstat_trans_flags_DEVSTAT_NO_DATA
-        );
-        let total_transfers_free = delta!(
+        Self {
             current,
             previous,
-            operations,
-            devstat_trans_flags_DEVSTAT_FREE
-        );
-        let total_transfers = total_transfers_read
-            + total_transfers_write
-            + total_transfers_other
-            + total_transfers_free;
-
-        let total_bytes_free =
-            delta!(current, previous, bytes, devstat_trans_flags_DEVSTAT_FREE);
-        let total_bytes_read =
-            delta!(current, previous, bytes, devstat_trans_flags_DEVSTAT_READ);
-        let total_bytes_write =
-            delta!(current, previous, bytes, devstat_trans_flags_DEVSTAT_WRITE);
-        let total_bytes =
-            total_bytes_read + total_bytes_write + total_bytes_free;
-
-        let block_denominator = if cur.block_size > 0 {
+            etime,
+        }
+    }
+
+    /// The unit of the `total_blocks*` statistics
+    fn block_size(&self) -> u64 {
+        let cur = unsafe { self.current.devstat.as_ref() };
+        if cur.block_size > 0 {
             cur.block_size as u64
         } else {
             512u64
-        };
-        let total_blocks = total_bytes / block_denominator;
-        let total_blocks_free = total_bytes_free / block_denominator;
-        let total_blocks_read = total_bytes_read / block_denominator;
-        let total_blocks_write = total_bytes_write / block_denominator;
-
-        let total_duration_free =
-            delta_t!(current, previous, |ds: &devstat| ds.duration
-                [devstat_trans_flags_DEVSTAT_FREE as usize]);
-        let total_duration_read =
-            delta_t!(current, previous, |ds: &devstat| ds.duration
-                [devstat_trans_flags_DEVSTAT_READ as usize]);
-        let total_duration_write =
-            delta_t!(current, previous, |ds: &devstat| ds.duration
-                [devstat_trans_flags_DEVSTAT_WRITE as usize]);
-        let total_duration_other =
-            delta_t!(current, previous, |ds: &devstat| ds.duration
-                [devstat_trans_flags_DEVSTAT_NO_DATA as usize]);
-        let total_duration = total_duration_read
-            + total_duration_write
-            + total_duration_other
-            + total_duration_free;
+        }
+    }
 
-        Self {
-            current,
-            previous,
-            etime,
-            total_bytes,
-            total_bytes_free,
-            total_bytes_read,
-            total_bytes_write,
-            total_blocks,
-            total_blocks_free,
-            total_blocks_read,
-            total_blocks_write,
-            total_duration,
-            total_duration_free,
-            total_duration_other,
-            total_duration_read,
-            total_duration_write,
-            total_transfers,
-            total_transfers_free,
-            total_transfers_other,
-            total_transfers_read,
-            total_transfers_write,
+    /// Like [`Statistics::compute`], but first verify that `previous` can be
+    /// compared with `current`.
+    ///
+    /// If the device was destroyed and re-created between the snapshots, its
+    /// devstat id may be reused, but its counters start over.  Then
+    /// [`compute`](Statistics::compute) would saturate each difference at
+    /// zero.  Instead, this method returns [`DeviceRestarted`] if the
+    /// device's creation time changed, or if any counter went backwards.
+    /// Since every counter started within the interval, callers will usually
+    /// want to retry with no `previous` at all, as
+    /// [`compute_or_since_creation`](Statistics::compute_or_since_creation)
+    /// does.
+    pub fn compute_checked(
+        current: Devstat<'a>,
+        previous: Option<Devstat<'a>>,
+        etime: f64,
+    ) -> Result<Self, DeviceRestarted> {
+        if let Some(prev) = previous {
+            let cur = unsafe { current.devstat.as_ref() };
+            let prev = unsafe { prev.devstat.as_ref() };
+            let bt = |t: &bintime| (t.sec, t.frac);
+            let restarted = bt(&cur.creation_time) != bt(&prev.creation_time)
+                || cur
+                    .operations
+                    .iter()
+                    .zip(prev.operations)
+                    .any(|(c, p)| *c < p)
+                || cur.bytes.iter().zip(prev.bytes).any(|(c, p)| *c < p)
+                || cur
+                    .tag_types
+                    .iter()
+                    .zip(prev.tag_types)
+                    .any(|(c, p)| *c < p)
+                || cur
+                    .duration
+                    .iter()
+                    .zip(prev.duration.iter())
+                    .any(|(c, p)| bt(c) < bt(p))
+                || bt(&cur.busy_time) < bt(&prev.busy_time);
+            if restarted {
+                return Err(DeviceRestarted);
+            }
+        }
+        Ok(Self::compute(current, previous, etime))
+    }
+
+    /// Like [`Statistics::compute_checked`], but fall back to the device's
+    /// entire counters if it was re-created between the snapshots.
+    ///
+    /// Such a device's counters all started within the interval, as did those
+    /// of a device with no `previous` record.  If `uptime`, the system's
+    /// uptime in seconds when `current` was taken, is known, then those
+    /// statistics cover the time since the device's creation.  Otherwise they
+    /// cover `etime`.
+    pub fn compute_or_since_creation(
+        current: Devstat<'a>,
+        previous: Option<Devstat<'a>>,
+        etime: f64,
+        uptime: Option<f64>,
+    ) -> Self {
+        let since_creation = || {
+            let age = uptime
+                .map(|u| u - current.creation_time())
+                .unwrap_or_default();
+            let etime = if age > 0.0 { age } else { etime };
+            Self::compute(current, None, etime)
+        };
+        match previous {
+            Some(_) => Self::compute_checked(current, previous, etime)
+                .unwrap_or_else(|_| since_creation()),
+            None => since_creation(),
         }
     }
 
     pub fn busy_time(&self) -> f64 {
-        let bt = unsafe { self.current.devstat.as_ref() };
-        bt.busy_time.sec as f64 + bt.busy_time.frac as f64 * BINTIME_SCALE
+        self.current.busy_time()
     }
 
     /// The percentage of time the device had one or more transactions
@@ -565,11 +1455,37 @@ impl<'a> Statistics<'a> {
         (delta / self.etime * 100.0).max(0.0)
     }
 
+    /// The average number of transactions outstanding between the
+    /// acquisition of the two snapshots.
+    ///
+    /// Unlike [`busy_pct`](Self::busy_pct), which can't exceed 100% no matter
+    /// how many transactions are outstanding at once, this keeps growing with
+    /// the device's parallelism, so it better describes the load on devices
+    /// like NVMe drives.  By Little's law, it's the transactions per second
+    /// multiplied by their average latency in seconds.  Only transactions that
+    /// completed during the interval are counted.
+    pub fn avg_queue_depth(&self) -> f64 {
+        if self.etime > 0.0 {
+            (self.total_duration() / self.etime).max(0.0)
+        } else {
+            0.0
+        }
+    }
+
     /// Returns the number of incomplete transactions at the time `cur` was
     /// acquired.
     pub fn queue_length(&self) -> u32 {
         let cur = unsafe { self.current.devstat.as_ref() };
-        cur.start_count - cur.end_count
+        // Both counters wrap, so start_count may be numerically smaller
+        cur.start_count.wrapping_sub(cur.end_count)
+    }
+
+    /// Compute every statistic at once, into an owned [`Record`].  Its
+    /// `name` is left empty, for the caller to fill in.
+    ///
+    /// [`Record`]: record::Record
+    pub fn to_record(&self) -> record::Record {
+        record::Record::from(self)
     }
 }
 
@@ -599,6 +1515,20 @@ impl Sub for Timespec {
     }
 }
 
+/// Fetch the kernel's description of the GEOM hierarchy, as XML.
+///
+/// This is the same as `sysctl kern.geom.confxml`.  For a parsed version, see
+/// [`Tree`] or [`conf::Mesh`].
+pub fn getxml() -> io::Result<String> {
+    let p = unsafe { geom_getxml() };
+    if p.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+    let xml = unsafe { CStr::from_ptr(p) }.to_string_lossy().into_owned();
+    unsafe { libc::free(p.cast()) };
+    Ok(xml)
+}
+
 /// Describes the entire Geom heirarchy.
 #[derive(Debug)]
 #[repr(transparent)]
@@ -607,22 +1537,54 @@ pub struct Tree(Pin<Box<gmesh>>);
 impl Tree {
     // FreeBSD BUG: geom_lookupid takes a mutable pointer when it could be const
     pub fn lookup<'a>(&'a mut self, id: Id) -> Option<Gident<'a>> {
-        let raw = unsafe { geom_lookupid(&mut *self.0, id.id) };
+        self.lookup_shared(id)
+    }
+
+    /// Like [`Tree::lookup`], but usable while other elements of the tree are
+    /// borrowed.
+    fn lookup_shared<'a>(&'a self, id: Id) -> Option<Gident<'a>> {
+        // geom_lookupid doesn't really modify the mesh
+        let mesh = &*self.0 as *const gmesh as *mut gmesh;
+        let raw = unsafe { geom_lookupid(mesh, id.id) };
         NonNull::new(raw).map(|ident| Gident {
             ident,
             phantom: PhantomData,
         })
     }
 
+    /// Iterate through every GEOM class in the tree
+    pub fn classes(&self) -> GclassIter<'_> {
+        GclassIter::new(self.0.lg_class.lh_first)
+    }
+
+    /// Construct a `Tree` from XML previously obtained from [`getxml`].
+    pub fn from_xml(xml: &str) -> io::Result<Self> {
+        let mut buf = CString::new(xml)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?
+            .into_bytes_with_nul();
+        let (inner, r) = unsafe {
+            let mut inner = Box::pin(mem::zeroed());
+            let r = geom_xml2tree(&mut *inner, buf.as_mut_ptr().cast());
+            (inner, r)
+        };
+        match r {
+            0 => Ok(Tree(inner)),
+            -1 => {
+                Err(io::Error::new(io::ErrorKind::InvalidData, "malformed XML"))
+            }
+            e => Err(io::Error::from_raw_os_error(e)),
+        }
+    }
+
     /// Construct a new `Tree` representing all available geom providers
-    pub fn new() -> io::Result<Self> {
+    pub fn new() -> Result<Self, Error> {
         let (inner, r) = unsafe {
             let mut inner = Box::pin(mem::zeroed());
             let r = geom_gettree(&mut *inner);
             (inner, r)
         };
         if r != 0 {
-            Err(Error::last_os_error())
+            Err(Error::TreeFailed(io::Error::last_os_error()))
         } else {
             Ok(Tree(inner))
         }
@@ -635,11 +1597,1536 @@ impl Drop for Tree {
     }
 }
 
-#[cfg(test)]
-mod t {
-    use approx::*;
+/// A [`Tree`] that is only rebuilt when the GEOM topology changes
+///
+/// Building a `Tree` takes several milliseconds on systems with hundreds of
+/// disks, too long to do at every refresh.  But a stale one lacks any devices
+/// attached since it was built.  So `TreeCache` rebuilds it when the devstat
+/// generation changes, or when a lookup misses a device that might be new.
+#[derive(Debug)]
+pub struct TreeCache {
+    tree:       Tree,
+    /// The devstat generation when `tree` was built
+    generation: Option<c_long>,
+    /// Whether `tree` was built by the most recent refresh
+    fresh:      bool,
+    /// Ids that `tree` lacks, like those of pass(4) devices, which aren't
+    /// GEOM providers
+    missing:    RefCell<HashSet<usize>>,
+    /// Whether a lookup has missed a device that `tree` might be too old to
+    /// know
+    stale:      Cell<bool>,
+}
 
-    use super::*;
+impl TreeCache {
+    pub fn new() -> Result<Self, Error> {
+        let generation = devstat_generation().ok();
+        Ok(TreeCache {
+            tree: Tree::new()?,
+            generation,
+            fresh: true,
+            missing: RefCell::default(),
+            stale: Cell::new(false),
+        })
+    }
+
+    /// Should the tree be rebuilt, given the current devstat `generation`?
+    fn needs_rebuild(&self, generation: Option<c_long>) -> bool {
+        self.stale.get() || generation != self.generation
+    }
+
+    /// Rebuild the tree if devices have been added or removed since it was
+    /// built.  Returns whether it was rebuilt.
+    pub fn refresh(&mut self) -> Result<bool, Error> {
+        let generation = devstat_generation().ok();
+        self.fresh = self.needs_rebuild(generation);
+        if self.fresh {
+            self.tree = Tree::new()?;
+            self.generation = generation;
+            self.missing.get_mut().clear();
+            self.stale.set(false);
+        }
+        Ok(self.fresh)
+    }
+
+    /// Like [`Tree::lookup`].  A miss will cause the next
+    /// [`refresh`](Self::refresh) to rebuild the tree, unless it was built by
+    /// the most recent one, or the same device has already missed.
+    pub fn lookup(&self, id: Id) -> Option<Gident<'_>> {
+        let gident = self.tree.lookup_shared(id);
+        if gident.is_none() {
+            self.note_miss(id);
+        }
+        gident
+    }
+
+    fn note_miss(&self, id: Id) {
+        if self.missing.borrow_mut().insert(id.id as usize) && !self.fresh {
+            self.stale.set(true);
+        }
+    }
+
+    /// The most recently built tree
+    pub fn tree(&self) -> &Tree {
+        &self.tree
+    }
+}
+
+/// A GEOM provider appearing or disappearing, as reported by [`DeviceWatcher`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DeviceEvent {
+    Arrived(String),
+    Departed(String),
+}
+
+impl DeviceEvent {
+    /// The name of the provider that arrived or departed
+    pub fn name(&self) -> &str {
+        match self {
+            DeviceEvent::Arrived(name) | DeviceEvent::Departed(name) => name,
+        }
+    }
+}
+
+/// Detects GEOM providers arriving and departing, by comparing successive
+/// [`Tree`]s.
+///
+/// # Examples
+/// ```no_run
+/// use freebsd_libgeom::{DeviceWatcher, Tree};
+///
+/// let mut watcher = DeviceWatcher::default();
+/// watcher.update(&Tree::new().unwrap());
+/// // ... later ...
+/// for event in watcher.update(&Tree::new().unwrap()) {
+///     println!("{:?}", event);
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct DeviceWatcher {
+    /// Every provider in the previous tree, or `None` before the first update
+    names: Option<HashSet<String>>,
+}
+
+impl DeviceWatcher {
+    /// Compare `tree`'s providers with those of the previous update.
+    ///
+    /// The first update only records a baseline, so it reports nothing.
+    /// Departures are reported before arrivals, each in [`name_cmp`] order.
+    pub fn update(&mut self, tree: &Tree) -> Vec<DeviceEvent> {
+        self.update_names(
+            tree.classes()
+                .flat_map(|class| class.geoms())
+                .flat_map(|geom| geom.providers())
+                .map(|provider| provider.name().to_string_lossy().into_owned()),
+        )
+    }
+
+    /// Like [`update`](Self::update), but comparing arbitrary device names,
+    /// like only those that pass a filter.
+    pub fn update_names<I>(&mut self, names: I) -> Vec<DeviceEvent>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let names = names.into_iter().collect::<HashSet<_>>();
+        let mut events = Vec::new();
+        if let Some(prev) = self.names.as_ref() {
+            let mut departed = prev.difference(&names).collect::<Vec<_>>();
+            let mut arrived = names.difference(prev).collect::<Vec<_>>();
+            departed.sort_by(|a, b| name_cmp(a, b));
+            arrived.sort_by(|a, b| name_cmp(a, b));
+            events.extend(
+                departed
+                    .into_iter()
+                    .map(|name| DeviceEvent::Departed(name.clone())),
+            );
+            events.extend(
+                arrived
+                    .into_iter()
+                    .map(|name| DeviceEvent::Arrived(name.clone())),
+            );
+        }
+        self.names = Some(names);
+        events
+    }
+}
+
+/// A GEOM class, like `DISK` or `PART`.
+#[derive(Debug, Copy, Clone)]
+pub struct Gclass<'a> {
+    class:   NonNull<gclass>,
+    phantom: PhantomData<&'a Tree>,
+}
+
+impl<'a> Gclass<'a> {
+    pub fn name(&self) -> &'a CStr {
+        unsafe { cstr(self.class.as_ref().lg_name) }
+    }
+
+    /// Iterate through all of this class's geoms
+    pub fn geoms(&self) -> GgeomIter<'a> {
+        GgeomIter::new(unsafe { self.class.as_ref() }.lg_geom.lh_first)
+    }
+
+    /// Iterate through the class's configuration.
+    pub fn config(&self) -> GconfigIter<'a> {
+        GconfigIter::new(unsafe { self.class.as_ref() }.lg_config.lh_first)
+    }
+}
+
+list_iter! {
+    /// Iterates through the classes in a [`Tree`].  Returned by
+    /// [`Tree::classes`].
+    GclassIter, Gclass, gclass, class, lg_class
+}
+
+/// A single geom: an instance of a [`Gclass`], like one partition table.
+#[derive(Debug, Copy, Clone)]
+pub struct Ggeom<'a> {
+    geom:    NonNull<ggeom>,
+    phantom: PhantomData<&'a Tree>,
+}
+
+impl<'a> Ggeom<'a> {
+    pub fn name(&self) -> &'a CStr {
+        unsafe { cstr(self.geom.as_ref().lg_name) }
+    }
+
+    /// The class of which this geom is an instance.  It should only be
+    /// missing if the mesh is malformed.
+    pub fn class(&self) -> Option<Gclass<'a>> {
+        let class = unsafe { self.geom.as_ref() }.lg_class;
+        NonNull::new(class).map(|class| Gclass {
+            class,
+            phantom: PhantomData,
+        })
+    }
+
+    /// The geom's rank.  Geoms with no consumers have rank 1.
+    pub fn rank(&self) -> u32 {
+        unsafe { self.geom.as_ref() }.lg_rank
+    }
+
+    /// Iterate through the consumers that this geom uses to attach to the
+    /// providers beneath it.
+    pub fn consumers(&self) -> GconsumerIter<'a> {
+        GconsumerIter::new(unsafe { self.geom.as_ref() }.lg_consumer.lh_first)
+    }
+
+    /// Iterate through the providers that this geom offers to the geoms above
+    /// it.
+    pub fn providers(&self) -> GproviderIter<'a> {
+        GproviderIter::new(unsafe { self.geom.as_ref() }.lg_provider.lh_first)
+    }
+
+    /// Iterate through the geom's class-specific configuration, like a
+    /// mirror's `State`.
+    pub fn config(&self) -> GconfigIter<'a> {
+        GconfigIter::new(unsafe { self.geom.as_ref() }.lg_config.lh_first)
+    }
+}
+
+list_iter! {
+    /// Iterates through a [`Gclass`]'s geoms.  Returned by [`Gclass::geoms`].
+    GgeomIter, Ggeom, ggeom, geom, lg_geom
+}
+
+/// A GEOM provider, like `ada0p1`.
+#[derive(Debug, Copy, Clone)]
+pub struct Gprovider<'a> {
+    provider: NonNull<gprovider>,
+    phantom:  PhantomData<&'a Tree>,
+}
+
+impl<'a> Gprovider<'a> {
+    pub fn name(&self) -> &'a CStr {
+        unsafe { cstr(self.provider.as_ref().lg_name) }
+    }
+
+    /// The geom that offers this provider.  It should only be missing if the
+    /// mesh is malformed.
+    pub fn geom(&self) -> Option<Ggeom<'a>> {
+        let geom = unsafe { self.provider.as_ref() }.lg_geom;
+        NonNull::new(geom).map(|geom| Ggeom {
+            geom,
+            phantom: PhantomData,
+        })
+    }
+
+    /// The provider's size in bytes
+    pub fn mediasize(&self) -> i64 {
+        unsafe { self.provider.as_ref() }.lg_mediasize
+    }
+
+    /// The provider's access counts, like `r1w1e1`
+    pub fn mode(&self) -> &'a CStr {
+        unsafe { cstr(self.provider.as_ref().lg_mode) }
+    }
+
+    /// The provider's sector size in bytes
+    pub fn sectorsize(&self) -> u32 {
+        unsafe { self.provider.as_ref() }.lg_sectorsize
+    }
+
+    /// The provider's stripe size in bytes, or 0 if unknown
+    pub fn stripesize(&self) -> i64 {
+        unsafe { self.provider.as_ref() }.lg_stripesize
+    }
+
+    /// The offset of the provider's first stripe boundary, in bytes
+    pub fn stripeoffset(&self) -> i64 {
+        unsafe { self.provider.as_ref() }.lg_stripeoffset
+    }
+
+    /// Iterate through the provider's class-specific configuration.
+    pub fn config(&self) -> GconfigIter<'a> {
+        GconfigIter::new(unsafe { self.provider.as_ref() }.lg_config.lh_first)
+    }
+}
+
+list_iter! {
+    /// Iterates through a [`Ggeom`]'s providers.  Returned by
+    /// [`Ggeom::providers`].
+    GproviderIter, Gprovider, gprovider, provider, lg_provider
+}
+
+/// A GEOM consumer: the attachment of a [`Ggeom`] to a [`Gprovider`] beneath
+/// it.
+#[derive(Debug, Copy, Clone)]
+pub struct Gconsumer<'a> {
+    consumer: NonNull<gconsumer>,
+    phantom:  PhantomData<&'a Tree>,
+}
+
+impl<'a> Gconsumer<'a> {
+    /// The geom that owns this consumer.  It should only be missing if the
+    /// mesh is malformed.
+    pub fn geom(&self) -> Option<Ggeom<'a>> {
+        let geom = unsafe { self.consumer.as_ref() }.lg_geom;
+        NonNull::new(geom).map(|geom| Ggeom {
+            geom,
+            phantom: PhantomData,
+        })
+    }
+
+    /// The provider that this consumer is attached to, if any.
+    pub fn provider(&self) -> Option<Gprovider<'a>> {
+        let provider = unsafe { self.consumer.as_ref() }.lg_provider;
+        NonNull::new(provider).map(|provider| Gprovider {
+            provider,
+            phantom: PhantomData,
+        })
+    }
+
+    /// The consumer's access counts, like `r1w1e1`
+    pub fn mode(&self) -> &'a CStr {
+        unsafe { cstr(self.consumer.as_ref().lg_mode) }
+    }
+
+    /// Iterate through the consumer's class-specific configuration, like a
+    /// mirror component's `State`.
+    pub fn config(&self) -> GconfigIter<'a> {
+        GconfigIter::new(unsafe { self.consumer.as_ref() }.lg_config.lh_first)
+    }
+}
+
+list_iter! {
+    /// Iterates through a [`Ggeom`]'s consumers.  Returned by
+    /// [`Ggeom::consumers`].
+    GconsumerIter, Gconsumer, gconsumer, consumer, lg_consumer
+}
+
+/// One class-specific configuration attribute of a [`Ggeom`], [`Gprovider`],
+/// or [`Gconsumer`].
+#[derive(Debug, Copy, Clone)]
+pub struct Gconfig<'a> {
+    config:  NonNull<gconfig>,
+    phantom: PhantomData<&'a Tree>,
+}
+
+impl<'a> Gconfig<'a> {
+    pub fn name(&self) -> &'a CStr {
+        unsafe { cstr(self.config.as_ref().lg_name) }
+    }
+
+    /// The attribute's value, if it has one
+    pub fn value(&self) -> Option<&'a CStr> {
+        let val = unsafe { self.config.as_ref() }.lg_val;
+        if val.is_null() {
+            None
+        } else {
+            Some(unsafe { CStr::from_ptr(val) })
+        }
+    }
+}
+
+list_iter! {
+    /// Iterates through an object's configuration attributes.
+    GconfigIter, Gconfig, gconfig, config, lg_config
+}
+
+impl<'a> GconfigIter<'a> {
+    /// Find the value of the named attribute
+    pub fn get(mut self, name: &str) -> Option<&'a CStr> {
+        self.find(|c| c.name().to_bytes() == name.as_bytes())
+            .and_then(|c| c.value())
+    }
+}
+
+/// A GEOM control request, like those issued by `geom(8)`.
+///
+/// The request is built up in Rust and only handed to libgeom by
+/// [`GctlRequest::issue`], so the parameters are guaranteed to outlive it.
+///
+/// # Examples
+/// ```no_run
+/// use freebsd_libgeom::GctlRequest;
+///
+/// // Equivalent to "gpart commit ada0"
+/// GctlRequest::new("PART")
+///     .verb("commit")
+///     .arg("ada0")
+///     .issue()
+///     .unwrap();
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct GctlRequest {
+    params: Vec<(String, GctlParam)>,
+    args:   Vec<String>,
+}
+
+#[derive(Clone, Debug)]
+enum GctlParam {
+    Str(String),
+    Bytes(Vec<u8>),
+}
+
+impl GctlParam {
+    /// The parameter's value as libgeom expects it, and the length to pass
+    /// with it.  Strings are NUL-terminated and passed with a length of -1,
+    /// which tells libgeom to flag them as ASCII, as GEOM classes require.
+    fn encode(&self) -> io::Result<(Vec<u8>, c_int)> {
+        match self {
+            GctlParam::Str(s) => CString::new(s.as_str())
+                .map(|s| (s.into_bytes_with_nul(), -1))
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e)),
+            GctlParam::Bytes(b) => c_int::try_from(b.len())
+                .map(|len| (b.clone(), len))
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e)),
+        }
+    }
+}
+
+impl GctlRequest {
+    /// Begin a request to the named GEOM class, like `"PART"` or `"MIRROR"`.
+    pub fn new(class: &str) -> Self {
+        GctlRequest::default().param_str("class", class)
+    }
+
+    /// Set the request's verb, like `"destroy"`.
+    pub fn verb(self, verb: &str) -> Self {
+        self.param_str("verb", verb)
+    }
+
+    /// Append a positional argument, usually the name of a geom or provider.
+    pub fn arg(mut self, arg: &str) -> Self {
+        self.args.push(arg.to_owned());
+        self
+    }
+
+    /// Add a string-valued parameter.
+    pub fn param_str(self, name: &str, value: &str) -> Self {
+        self.param(name, GctlParam::Str(value.to_owned()))
+    }
+
+    /// Add a numeric parameter, which GEOM classes read as an `intmax_t`.
+    pub fn param_number(self, name: &str, value: i64) -> Self {
+        self.param(name, GctlParam::Bytes(value.to_ne_bytes().to_vec()))
+    }
+
+    /// Add a boolean parameter, like a command-line flag, which GEOM classes
+    /// read as an `int`.
+    pub fn param_bool(self, name: &str, value: bool) -> Self {
+        let value = c_int::from(value).to_ne_bytes().to_vec();
+        self.param(name, GctlParam::Bytes(value))
+    }
+
+    fn param(mut self, name: &str, value: GctlParam) -> Self {
+        self.params.push((name.to_owned(), value));
+        self
+    }
+
+    /// Every parameter as it will be passed to libgeom, including `nargs`
+    /// and `arg0`, `arg1`, etc., with its value and length.
+    fn encode(&self) -> io::Result<Vec<(CString, Vec<u8>, c_int)>> {
+        let nargs = c_int::try_from(self.args.len())
+            .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        let nargs = GctlParam::Bytes(nargs.to_ne_bytes().to_vec());
+        let args =
+            self.args.iter().enumerate().map(|(i, arg)| {
+                (format!("arg{i}"), GctlParam::Str(arg.clone()))
+            });
+        self.params
+            .iter()
+            .cloned()
+            .chain([("nargs".to_owned(), nargs)])
+            .chain(args)
+            .map(|(name, value)| {
+                let name = CString::new(name).map_err(|e| {
+                    io::Error::new(io::ErrorKind::InvalidInput, e)
+                })?;
+                let (value, len) = value.encode()?;
+                Ok((name, value, len))
+            })
+            .collect()
+    }
+
+    /// Send the request to the kernel.
+    ///
+    /// On failure, the error's message is the one reported by the GEOM class,
+    /// just as `geom(8)` would print it.
+    pub fn issue(&self) -> io::Result<()> {
+        self.with_handle(|req| {
+            let errstr = unsafe { gctl_issue(req) };
+            // The error string belongs to the request, so copy it before it's
+            // freed.
+            if errstr.is_null() {
+                Ok(())
+            } else {
+                let msg = unsafe { CStr::from_ptr(errstr) }.to_string_lossy();
+                Err(io::Error::new(io::ErrorKind::Other, msg.into_owned()))
+            }
+        })?
+    }
+
+    /// Build the request in libgeom and pass it to `f`, freeing it afterwards.
+    fn with_handle<F, R>(&self, f: F) -> io::Result<R>
+    where
+        F: FnOnce(*mut gctl_req) -> R,
+    {
+        let params = self.encode()?;
+        let req = unsafe { gctl_get_handle() };
+        if req.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        // libgeom stores these pointers until gctl_free, but `params` outlives
+        // that.
+        for (name, value, len) in params.iter() {
+            unsafe {
+                gctl_ro_param(req, name.as_ptr(), *len, value.as_ptr().cast())
+            };
+        }
+        let r = f(req);
+        unsafe { gctl_free(req) };
+        Ok(r)
+    }
+}
+
+/// Compare two device names in natural order.
+///
+/// Runs of digits are compared by their numeric value, so `da2` sorts before
+/// `da10`, and `ada0p2` sorts before `ada0p10`.  Everything else is compared
+/// character by character.  Digit runs that differ only in leading zeros are
+/// ordered shortest first, so the ordering is total.
+pub fn name_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a;
+    let mut b = b;
+    loop {
+        let (Some(ca), Some(cb)) = (a.chars().next(), b.chars().next()) else {
+            return a.len().cmp(&b.len());
+        };
+        if ca.is_ascii_digit() && cb.is_ascii_digit() {
+            let la = a.find(|c: char| !c.is_ascii_digit()).unwrap_or(a.len());
+            let lb = b.find(|c: char| !c.is_ascii_digit()).unwrap_or(b.len());
+            let (na, ra) = a.split_at(la);
+            let (nb, rb) = b.split_at(lb);
+            let ta = na.trim_start_matches('0');
+            let tb = nb.trim_start_matches('0');
+            let ord = ta
+                .len()
+                .cmp(&tb.len())
+                .then_with(|| ta.cmp(tb))
+                .then_with(|| na.len().cmp(&nb.len()));
+            if ord != Ordering::Equal {
+                return ord;
+            }
+            a = ra;
+            b = rb;
+        } else {
+            let ord = ca.cmp(&cb);
+            if ord != Ordering::Equal {
+                return ord;
+            }
+            a = &a[ca.len_utf8()..];
+            b = &b[cb.len_utf8()..];
+        }
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use approx::*;
+
+    use super::*;
+
+    /// Serializes the tests that open libgeom's statistics, so that each can
+    /// tell which [`StatsHandle`]s are its own.
+    fn serial() -> MutexGuard<'static, ()> {
+        static SERIAL: Mutex<()> = Mutex::new(());
+        SERIAL.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    mod name_cmp {
+        use super::*;
+
+        #[test]
+        fn equal() {
+            assert_eq!(name_cmp("da0", "da0"), Ordering::Equal);
+            assert_eq!(name_cmp("", ""), Ordering::Equal);
+        }
+
+        #[test]
+        fn leading_zeros() {
+            assert_eq!(name_cmp("da1", "da01"), Ordering::Less);
+            assert_eq!(name_cmp("da01", "da2"), Ordering::Less);
+        }
+
+        #[test]
+        fn natural() {
+            let mut names = vec![
+                "da10", "ada0p10", "da2", "ada0", "ada0p2", "cd0", "da1",
+                "ada0p1", "gpt/data",
+            ];
+            names.sort_by(|a, b| name_cmp(a, b));
+            assert_eq!(
+                names,
+                [
+                    "ada0", "ada0p1", "ada0p2", "ada0p10", "cd0", "da1", "da2",
+                    "da10", "gpt/data"
+                ]
+            );
+        }
+
+        #[test]
+        fn prefix() {
+            assert_eq!(name_cmp("da", "da0"), Ordering::Less);
+            assert_eq!(name_cmp("da0", "da0p1"), Ordering::Less);
+        }
+    }
+
+    mod find_prev {
+        use super::*;
+
+        fn mkdevstat(id: usize) -> devstat {
+            let mut ds: devstat = unsafe { mem::zeroed() };
+            ds.id = id as *const c_void;
+            ds
+        }
+
+        #[test]
+        fn lockstep() {
+            let prevs = [mkdevstat(1), mkdevstat(2)];
+            let p = find_prev(&prevs, 1, &mkdevstat(2)).unwrap();
+            assert!(ptr::eq(p, &prevs[1]));
+        }
+
+        /// After a device is inserted, later devices are shifted by one
+        #[test]
+        fn shifted() {
+            let prevs = [mkdevstat(1), mkdevstat(3)];
+            let cur = [mkdevstat(1), mkdevstat(2), mkdevstat(3)];
+            let refs = prevs.iter().collect::<Vec<_>>();
+            assert!(find_prev(&refs, 1, &cur[1]).is_none());
+            let p = find_prev(&refs, 2, &cur[2]).unwrap();
+            assert!(ptr::eq(*p, &prevs[1]));
+        }
+
+        #[test]
+        fn departed() {
+            let prevs = [mkdevstat(1), mkdevstat(2), mkdevstat(3)];
+            let p = find_prev(&prevs, 1, &mkdevstat(3)).unwrap();
+            assert!(ptr::eq(p, &prevs[2]));
+        }
+    }
+
+    mod snapshot {
+        use super::*;
+
+        /// libgeom's statistics state is closed along with the last Snapshot,
+        /// even if they're created on different threads.
+        #[test]
+        fn refcount() {
+            let _serial = serial();
+            let users = stats().users;
+            let a = Snapshot::new().unwrap();
+            assert_eq!(stats().users, users + 1);
+            std::thread::spawn(move || {
+                let _b = Snapshot::new().unwrap();
+                assert_eq!(stats().users, users + 2);
+            })
+            .join()
+            .unwrap();
+            assert_eq!(stats().users, users + 1);
+            drop(a);
+            assert_eq!(stats().users, users);
+        }
+
+        /// Counting the devices mustn't disturb iteration.
+        #[test]
+        fn len() {
+            let _serial = serial();
+            let mut snapshot = Snapshot::new().unwrap();
+            let n = snapshot.len();
+            assert_eq!(snapshot.is_empty(), n == 0);
+            assert_eq!(snapshot.iter().count(), n);
+            assert_eq!(snapshot.iter_pair(None).count(), n);
+        }
+
+        /// Retrying must still yield a usable snapshot
+        #[test]
+        fn with_retries() {
+            let _serial = serial();
+            let mut snapshot = Snapshot::with_retries(3).unwrap();
+            assert_eq!(snapshot.iter().count(), snapshot.len());
+        }
+    }
+
+    mod delta_snapshot {
+        use super::*;
+
+        /// Every provider yielded must be named and ranked
+        #[test]
+        fn refresh() {
+            let mut ds = DeltaSnapshot::new().unwrap();
+            for i in 0..2 {
+                let deltas = ds.refresh().unwrap();
+                assert_eq!(deltas.is_first(), i == 0);
+                assert!(deltas.etime() > 0.0);
+                for (name, rank, stats) in deltas.providers() {
+                    assert!(!name.to_bytes().is_empty());
+                    assert!(rank >= 1);
+                    assert!(stats.busy_pct() >= 0.0);
+                }
+            }
+        }
+    }
+
+    mod snapshot_buf {
+        use super::*;
+
+        /// Build a SnapshotBuf as if the kernel had returned `devstats`
+        fn mkbuf(devstats: &[devstat]) -> SnapshotBuf {
+            let hdr = mem::size_of::<c_long>();
+            let len = hdr + mem::size_of_val(devstats);
+            let mut sb = SnapshotBuf::new();
+            sb.buf.resize((len + 7) / 8, 0);
+            sb.len = len;
+            unsafe {
+                let first = sb.buf.as_mut_ptr().cast::<u8>().add(hdr);
+                ptr::copy_nonoverlapping(
+                    devstats.as_ptr(),
+                    first.cast(),
+                    devstats.len(),
+                );
+            }
+            sb
+        }
+
+        fn mkdevstat(id: usize, unit_number: i32) -> devstat {
+            let mut ds: devstat = unsafe { mem::zeroed() };
+            ds.id = id as *const c_void;
+            ds.unit_number = unit_number;
+            ds
+        }
+
+        #[test]
+        fn empty() {
+            let sb = SnapshotBuf::new();
+            assert_eq!(sb.iter().count(), 0);
+            assert_eq!(sb.generation(), None);
+        }
+
+        #[test]
+        fn iter() {
+            let sb = mkbuf(&[mkdevstat(1, 0), mkdevstat(2, 1)]);
+            let units =
+                sb.iter().map(|ds| ds.unit_number()).collect::<Vec<_>>();
+            assert_eq!(units, [0, 1]);
+        }
+
+        /// Devices should be paired by id, even if one has arrived or departed
+        #[test]
+        fn iter_pair() {
+            let prev = mkbuf(&[mkdevstat(1, 10), mkdevstat(3, 30)]);
+            let cur =
+                mkbuf(&[mkdevstat(1, 11), mkdevstat(2, 21), mkdevstat(3, 31)]);
+            let pairs = cur
+                .iter_pair(Some(&prev))
+                .map(|(c, p)| (c.unit_number(), p.map(|p| p.unit_number())))
+                .collect::<Vec<_>>();
+            assert_eq!(pairs, [(11, Some(10)), (21, None), (31, Some(30))]);
+        }
+
+        /// A record is torn if the kernel began updating it, but hadn't
+        /// finished, when it was copied.
+        #[test]
+        fn torn() {
+            let mut torn = mkdevstat(2, 1);
+            torn.sequence0 = 41;
+            torn.sequence1 = 42;
+            let mut whole = mkdevstat(1, 0);
+            whole.sequence0 = 42;
+            whole.sequence1 = 42;
+            let sb = mkbuf(&[whole, torn]);
+            let consistent =
+                sb.iter().map(|ds| ds.is_consistent()).collect::<Vec<_>>();
+            assert_eq!(consistent, [true, false]);
+            assert!(!sb.is_consistent());
+            assert!(mkbuf(&[whole]).is_consistent());
+        }
+
+        /// Retrying must still yield a usable capture
+        #[test]
+        fn capture_with_retries() {
+            let mut sb = SnapshotBuf::new();
+            sb.capture_with_retries(3).unwrap();
+            assert!(sb.generation().is_some());
+        }
+    }
+
+    mod config {
+        use super::*;
+
+        #[test]
+        fn get() {
+            let mut state: gconfig = unsafe { mem::zeroed() };
+            let mut flags: gconfig = unsafe { mem::zeroed() };
+            state.lg_name = b"State\0".as_ptr() as *mut _;
+            state.lg_val = b"DEGRADED\0".as_ptr() as *mut _;
+            flags.lg_name = b"Flags\0".as_ptr() as *mut _;
+            state.lg_config.le_next = &mut flags;
+
+            let config = GconfigIter::new(&mut state);
+            let names = config
+                .clone()
+                .map(|c| c.name().to_bytes())
+                .collect::<Vec<_>>();
+            assert_eq!(names, [&b"State"[..], &b"Flags"[..]]);
+            assert_eq!(
+                config.clone().get("State").unwrap().to_bytes(),
+                b"DEGRADED"
+            );
+            assert!(config.clone().get("Flags").is_none());
+            assert!(config.get("Missing").is_none());
+        }
+    }
+
+    mod gctl {
+        use super::*;
+
+        fn names(params: &[(CString, Vec<u8>, c_int)]) -> Vec<&str> {
+            params.iter().map(|(n, ..)| n.to_str().unwrap()).collect()
+        }
+
+        #[test]
+        fn encode() {
+            let req = GctlRequest::new("PART")
+                .verb("destroy")
+                .param_bool("force", true)
+                .param_number("index", 3)
+                .arg("ada0");
+            let params = req.encode().unwrap();
+            assert_eq!(
+                names(&params),
+                ["class", "verb", "force", "index", "nargs", "arg0"]
+            );
+            assert_eq!(params[0].1, b"PART\0");
+            assert_eq!(params[1].1, b"destroy\0");
+            assert_eq!(params[2].1, 1i32.to_ne_bytes());
+            assert_eq!(params[3].1, 3i64.to_ne_bytes());
+            assert_eq!(params[4].1, 1i32.to_ne_bytes());
+            assert_eq!(params[5].1, b"ada0\0");
+            let lens = params.iter().map(|p| p.2).collect::<Vec<_>>();
+            assert_eq!(lens, [-1, -1, 4, 8, 4, -1]);
+        }
+
+        /// libgeom flags the string parameters, but not the others, as ASCII
+        #[test]
+        fn ascii() {
+            // From <geom/geom_ctl.h>
+            const GCTL_PARAM_ASCII: c_int = 4;
+
+            let req = GctlRequest::new("PART")
+                .verb("destroy")
+                .param_number("index", 3)
+                .arg("ada0");
+            let args = req
+                .with_handle(|req| {
+                    let req = unsafe { &*req };
+                    let args = unsafe {
+                        slice::from_raw_parts(req.arg, req.narg as usize)
+                    };
+                    args.iter()
+                        .map(|arg| {
+                            let name = unsafe { CStr::from_ptr(arg.name) };
+                            let ascii = arg.flag & GCTL_PARAM_ASCII != 0;
+                            (name.to_str().unwrap().to_owned(), ascii, arg.len)
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .unwrap();
+            assert_eq!(
+                args,
+                [
+                    ("class".to_owned(), true, 5),
+                    ("verb".to_owned(), true, 8),
+                    ("index".to_owned(), false, 8),
+                    ("nargs".to_owned(), false, 4),
+                    ("arg0".to_owned(), true, 5),
+                ]
+            );
+        }
+
+        #[test]
+        fn no_args() {
+            let params = GctlRequest::new("MIRROR").encode().unwrap();
+            assert_eq!(names(&params), ["class", "nargs"]);
+            assert_eq!(params[1].1, 0i32.to_ne_bytes());
+        }
+
+        #[test]
+        fn interior_nul() {
+            let req = GctlRequest::new("PART").arg("ada\0");
+            let e = req.encode().unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+            let req = GctlRequest::new("PART").param_str("bad\0name", "x");
+            let e = req.encode().unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    mod topology {
+        use std::{mem::ManuallyDrop, ptr};
+
+        use super::*;
+
+        /// Allocate a zeroed C structure.  The tests leak them.
+        fn zalloc<T>() -> *mut T {
+            Box::into_raw(Box::new(unsafe { mem::zeroed() }))
+        }
+
+        /// Build a mesh with one disk and one partition on it.
+        fn fake_tree() -> ManuallyDrop<Tree> {
+            let disk_class = zalloc::<gclass>();
+            let part_class = zalloc::<gclass>();
+            let disk_geom = zalloc::<ggeom>();
+            let part_geom = zalloc::<ggeom>();
+            let ada0 = zalloc::<gprovider>();
+            let ada0p1 = zalloc::<gprovider>();
+            let cons = zalloc::<gconsumer>();
+            let ptype = zalloc::<gconfig>();
+            let mut mesh: gmesh = unsafe { mem::zeroed() };
+
+            unsafe {
+                (*disk_class).lg_name = b"DISK\0".as_ptr() as *mut _;
+                (*disk_class).lg_class.le_next = part_class;
+                (*disk_class).lg_geom.lh_first = disk_geom;
+                (*part_class).lg_name = b"PART\0".as_ptr() as *mut _;
+                (*part_class).lg_geom.lh_first = part_geom;
+
+                (*disk_geom).lg_name = b"ada0\0".as_ptr() as *mut _;
+                (*disk_geom).lg_class = disk_class;
+                (*disk_geom).lg_rank = 1;
+                (*disk_geom).lg_provider.lh_first = ada0;
+                (*part_geom).lg_name = b"ada0\0".as_ptr() as *mut _;
+                (*part_geom).lg_class = part_class;
+                (*part_geom).lg_rank = 2;
+                (*part_geom).lg_consumer.lh_first = cons;
+                (*part_geom).lg_provider.lh_first = ada0p1;
+
+                (*ada0).lg_name = b"ada0\0".as_ptr() as *mut _;
+                (*ada0).lg_geom = disk_geom;
+                (*ada0).lg_mode = b"r1w0e0\0".as_ptr() as *mut _;
+                (*ada0).lg_mediasize = 1 << 30;
+                (*ada0).lg_sectorsize = 512;
+                (*ada0p1).lg_name = b"ada0p1\0".as_ptr() as *mut _;
+                (*ada0p1).lg_geom = part_geom;
+                (*ada0p1).lg_mode = b"r0w0e0\0".as_ptr() as *mut _;
+                (*ada0p1).lg_mediasize = 1 << 29;
+                (*ada0p1).lg_sectorsize = 512;
+                (*ada0p1).lg_config.lh_first = ptype;
+                (*ptype).lg_name = b"type\0".as_ptr() as *mut _;
+                (*ptype).lg_val = b"freebsd-zfs\0".as_ptr() as *mut _;
+                (*cons).lg_geom = part_geom;
+                (*cons).lg_provider = ada0;
+                (*cons).lg_mode = b"r1w0e0\0".as_ptr() as *mut _;
+            }
+            mesh.lg_class.lh_first = disk_class;
+            mesh.lg_ident = ptr::null_mut();
+            // Don't let geom_deletetree try to free our fake mesh
+            ManuallyDrop::new(Tree(Box::pin(mesh)))
+        }
+
+        /// A consumer's devstat record identifies the consumer
+        #[test]
+        fn gident_consumer() {
+            let tree = fake_tree();
+            let part = tree.classes().nth(1).unwrap().geoms().next().unwrap();
+            let cons = part.consumers().next().unwrap();
+            let mut ident: gident = unsafe { mem::zeroed() };
+            ident.lg_what = gident_ISCONSUMER;
+            ident.lg_ptr = cons.consumer.as_ptr().cast();
+            let gident = Gident {
+                ident:   NonNull::from(&ident),
+                phantom: PhantomData,
+            };
+            let consumer = gident.consumer().unwrap();
+            assert_eq!(consumer.provider().unwrap().name().to_bytes(), b"ada0");
+            assert_eq!(gident.class().unwrap().to_bytes(), b"PART");
+            assert_eq!(gident.rank(), None);
+        }
+
+        /// Walk a hand-built mesh.
+        #[test]
+        fn walk() {
+            let tree = fake_tree();
+
+            let classes = tree.classes().collect::<Vec<_>>();
+            assert_eq!(classes.len(), 2);
+            assert_eq!(classes[0].name().to_bytes(), b"DISK");
+            assert_eq!(classes[1].name().to_bytes(), b"PART");
+
+            let disk = classes[0].geoms().next().unwrap();
+            assert_eq!(disk.rank(), 1);
+            assert_eq!(disk.consumers().count(), 0);
+            assert_eq!(disk.class().unwrap().name().to_bytes(), b"DISK");
+
+            let part = classes[1].geoms().next().unwrap();
+            assert_eq!(part.rank(), 2);
+            let provider = part.providers().next().unwrap();
+            assert_eq!(provider.name().to_bytes(), b"ada0p1");
+            assert_eq!(provider.geom().unwrap().name().to_bytes(), b"ada0");
+            let parent = part.consumers().next().unwrap().provider().unwrap();
+            assert_eq!(parent.name().to_bytes(), b"ada0");
+            let parent_class = parent.geom().unwrap().class().unwrap();
+            assert_eq!(parent_class.name().to_bytes(), b"DISK");
+        }
+
+        /// Copy a hand-built mesh into a conf::Mesh
+        #[test]
+        fn conf() {
+            let tree = fake_tree();
+            let mesh = conf::Mesh::from(&*tree);
+            let names = mesh
+                .classes
+                .iter()
+                .map(|c| c.name.as_str())
+                .collect::<Vec<_>>();
+            assert_eq!(names, ["DISK", "PART"]);
+            let part = &mesh.classes[1].geoms[0];
+            assert_eq!(part.name, "ada0");
+            assert_eq!(part.rank, 2);
+            assert_eq!(
+                part.providers,
+                [conf::Provider {
+                    name:         "ada0p1".to_owned(),
+                    mode:         "r0w0e0".to_owned(),
+                    mediasize:    1 << 29,
+                    sectorsize:   512,
+                    stripesize:   0,
+                    stripeoffset: 0,
+                    config:       [(
+                        "type".to_owned(),
+                        "freebsd-zfs".to_owned()
+                    )]
+                    .into(),
+                }]
+            );
+            assert_eq!(part.consumers.len(), 1);
+            assert_eq!(part.consumers[0].provider.as_deref(), Some("ada0"));
+            assert_eq!(part.consumers[0].mode, "r1w0e0");
+            assert!(mesh.classes[0].geoms[0].consumers.is_empty());
+        }
+
+        /// Build a mesh with a single disk
+        fn disk_tree(name: &'static [u8]) -> ManuallyDrop<Tree> {
+            let class = zalloc::<gclass>();
+            let geom = zalloc::<ggeom>();
+            let provider = zalloc::<gprovider>();
+            let mut mesh: gmesh = unsafe { mem::zeroed() };
+            unsafe {
+                (*class).lg_name = b"DISK\0".as_ptr() as *mut _;
+                (*class).lg_geom.lh_first = geom;
+                (*geom).lg_class = class;
+                (*geom).lg_name = name.as_ptr() as *mut _;
+                (*geom).lg_provider.lh_first = provider;
+                (*provider).lg_name = name.as_ptr() as *mut _;
+                (*provider).lg_geom = geom;
+            }
+            mesh.lg_class.lh_first = class;
+            mesh.lg_ident = ptr::null_mut();
+            ManuallyDrop::new(Tree(Box::pin(mesh)))
+        }
+
+        /// Only a miss on a tree older than the most recent refresh should
+        /// cause a rebuild, and only once per device.
+        #[test]
+        fn tree_cache() {
+            let mut cache = ManuallyDrop::new(TreeCache {
+                tree:       ManuallyDrop::into_inner(fake_tree()),
+                generation: Some(5),
+                fresh:      true,
+                missing:    RefCell::default(),
+                stale:      Cell::new(false),
+            });
+            let id = |id: usize| Id {
+                id:      id as *const c_void,
+                phantom: PhantomData,
+            };
+            assert!(!cache.needs_rebuild(Some(5)));
+            assert!(cache.needs_rebuild(Some(6)));
+            // Devices that miss on a fresh tree just aren't GEOM providers
+            cache.note_miss(id(1));
+            assert!(!cache.needs_rebuild(Some(5)));
+            cache.fresh = false;
+            cache.note_miss(id(1));
+            assert!(!cache.needs_rebuild(Some(5)));
+            // But a new miss might be a device that arrived since
+            cache.note_miss(id(2));
+            assert!(cache.needs_rebuild(Some(5)));
+        }
+
+        #[test]
+        fn watcher() {
+            let mut watcher = DeviceWatcher::default();
+            // The first update is just a baseline
+            assert!(watcher.update(&fake_tree()).is_empty());
+            assert!(watcher.update(&fake_tree()).is_empty());
+            assert_eq!(
+                watcher.update(&disk_tree(b"ada0\0")),
+                [DeviceEvent::Departed("ada0p1".to_owned())]
+            );
+            assert_eq!(
+                watcher.update(&disk_tree(b"da1\0")),
+                [
+                    DeviceEvent::Departed("ada0".to_owned()),
+                    DeviceEvent::Arrived("da1".to_owned())
+                ]
+            );
+            let events = watcher.update(&fake_tree());
+            let names =
+                events.iter().map(DeviceEvent::name).collect::<Vec<_>>();
+            assert_eq!(names, ["da1", "ada0", "ada0p1"]);
+            assert!(matches!(events[0], DeviceEvent::Departed(_)));
+        }
+
+        /// A mesh full of NULL pointers must not cause a panic or a crash.
+        #[test]
+        fn malformed() {
+            let class = zalloc::<gclass>();
+            let geom = zalloc::<ggeom>();
+            let provider = zalloc::<gprovider>();
+            let consumer = zalloc::<gconsumer>();
+            let config = zalloc::<gconfig>();
+            let ident = zalloc::<gident>();
+            let mut mesh: gmesh = unsafe { mem::zeroed() };
+            unsafe {
+                (*class).lg_geom.lh_first = geom;
+                (*class).lg_config.lh_first = config;
+                (*geom).lg_provider.lh_first = provider;
+                (*geom).lg_consumer.lh_first = consumer;
+                (*provider).lg_config.lh_first = config;
+                (*ident).lg_what = gident_ISPROVIDER;
+            }
+            mesh.lg_class.lh_first = class;
+            mesh.lg_ident = ptr::null_mut();
+            let tree = ManuallyDrop::new(Tree(Box::pin(mesh)));
+
+            for geom in tree.classes().flat_map(|c| c.geoms()) {
+                assert!(geom.class().is_none());
+                assert!(geom.providers().all(|p| p.geom().is_none()));
+                assert!(geom.consumers().all(|c| c.geom().is_none()));
+            }
+            let mesh = conf::Mesh::from(&*tree);
+            let geom = &mesh.classes[0].geoms[0];
+            assert_eq!(geom.name, "");
+            assert_eq!(geom.providers[0].config[""], "");
+            assert_eq!(geom.consumers[0].provider, None);
+
+            let gident = Gident {
+                ident:   NonNull::new(ident).unwrap(),
+                phantom: PhantomData,
+            };
+            assert!(matches!(gident.name(), Err(GidentError::Malformed)));
+            assert_eq!(gident.rank(), None);
+            assert!(gident.consumer().is_none());
+            assert_eq!(gident.class(), None);
+        }
+    }
+
+    mod devname {
+        use super::*;
+
+        fn mkdevstat(name: &[u8], unit_number: i32) -> devstat {
+            let mut ds: devstat = unsafe { mem::zeroed() };
+            for (i, b) in name.iter().enumerate() {
+                ds.device_name[i] = *b as _;
+            }
+            ds.unit_number = unit_number;
+            ds
+        }
+
+        #[test]
+        fn devname() {
+            let inner = mkdevstat(b"pass", 3);
+            let ds = Devstat {
+                devstat: NonNull::from(&inner),
+                phantom: PhantomData,
+            };
+            assert_eq!(ds.device_name().to_bytes(), b"pass");
+            assert_eq!(ds.unit_number(), 3);
+            assert_eq!(ds.devname(), "pass3");
+        }
+
+        #[test]
+        fn creation_time() {
+            let mut inner = mkdevstat(b"da", 0);
+            inner.creation_time = bintime {
+                sec:  42,
+                frac: 1 << 63,
+            };
+            let ds = Devstat {
+                devstat: NonNull::from(&inner),
+                phantom: PhantomData,
+            };
+            assert_eq!(ds.creation_time(), 42.5);
+        }
+
+        /// device_name should always be NUL-terminated, but we mustn't read
+        /// beyond its end if it isn't.
+        #[test]
+        fn device_name_unterminated() {
+            let inner = mkdevstat(&[b'x'; 16], 0);
+            let ds = Devstat {
+                devstat: NonNull::from(&inner),
+                phantom: PhantomData,
+            };
+            assert_eq!(ds.device_name().to_bytes(), b"");
+        }
+    }
+
+    mod error {
+        use super::*;
+
+        #[test]
+        fn display() {
+            let e =
+                Error::TreeFailed(io::Error::from_raw_os_error(libc::EPERM));
+            assert!(e.to_string().starts_with("Cannot get GEOM tree: "));
+        }
+
+        /// Callers that return io::Error should keep the original kind
+        #[test]
+        fn into_io_error() {
+            let e = Error::StatsOpenFailed(io::Error::from(
+                io::ErrorKind::PermissionDenied,
+            ));
+            let e = io::Error::from(e);
+            assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+            assert!(e.to_string().starts_with("Cannot open devstat"));
+        }
+    }
+
+    mod counters {
+        use super::*;
+
+        #[test]
+        fn transfer_flag_conversions() {
+            for flag in TransferFlag::ALL {
+                let raw = devstat_trans_flags::from(flag);
+                assert_eq!(TransferFlag::try_from(raw), Ok(flag));
+            }
+            assert_eq!(
+                devstat_trans_flags::from(TransferFlag::Write),
+                devstat_trans_flags_DEVSTAT_WRITE
+            );
+            assert_eq!(
+                TransferFlag::try_from(99),
+                Err(UnknownTransferFlag(99))
+            );
+        }
+
+        #[test]
+        fn per_flag() {
+            let mut inner: devstat = unsafe { mem::zeroed() };
+            for (i, flag) in [
+                devstat_trans_flags_DEVSTAT_NO_DATA,
+                devstat_trans_flags_DEVSTAT_READ,
+                devstat_trans_flags_DEVSTAT_WRITE,
+                devstat_trans_flags_DEVSTAT_FREE,
+            ]
+            .into_iter()
+            .enumerate()
+            {
+                inner.operations[flag as usize] = 10 * i as u64;
+                inner.bytes[flag as usize] = 100 * i as u64;
+                inner.duration[flag as usize] = bintime {
+                    sec:  i as _,
+                    frac: 1 << 62,
+                };
+            }
+            let ds = Devstat {
+                devstat: NonNull::from(&inner),
+                phantom: PhantomData,
+            };
+            for (i, flag) in TransferFlag::ALL.into_iter().enumerate() {
+                assert_eq!(ds.operations(flag), 10 * i as u64);
+                assert_eq!(ds.bytes(flag), 100 * i as u64);
+                assert_eq!(ds.duration(flag), i as f64 + 0.25);
+            }
+        }
+
+        #[test]
+        fn busy_time() {
+            let mut inner: devstat = unsafe { mem::zeroed() };
+            inner.busy_time = bintime {
+                sec:  7,
+                frac: 1 << 63,
+            };
+            let ds = Devstat {
+                devstat: NonNull::from(&inner),
+                phantom: PhantomData,
+            };
+            assert_eq!(ds.busy_time(), 7.5);
+            assert_eq!(Statistics::compute(ds, None, 1.0).busy_time(), 7.5);
+        }
+    }
+
+    mod statistics {
+        use super::*;
+
+        const KB: u64 = 1 << 10;
+
+        /// A devstat with the given operation and byte counts for reads,
+        /// writes, and frees
+        fn mkdevstat(ops: [u64; 3], bytes: [u64; 3]) -> devstat {
+            let mut ds: devstat = unsafe { mem::zeroed() };
+            for (i, flag) in [
+                devstat_trans_flags_DEVSTAT_READ,
+                devstat_trans_flags_DEVSTAT_WRITE,
+                devstat_trans_flags_DEVSTAT_FREE,
+            ]
+            .into_iter()
+            .enumerate()
+            {
+                ds.operations[flag as usize] = ops[i];
+                ds.bytes[flag as usize] = bytes[i];
+            }
+            ds
+        }
+
+        fn wrap(ds: &devstat) -> Devstat<'_> {
+            Devstat {
+                devstat: NonNull::from(ds),
+                phantom: PhantomData,
+            }
+        }
+
+        /// Each operation type's transfer size must only count its own bytes.
+        /// Previously every type divided the total bytes by its own
+        /// operations, so heavy writes would inflate kB/r.
+        #[test]
+        fn kb_per_transfer_mixed() {
+            let prev = mkdevstat([0; 3], [0; 3]);
+            let cur = mkdevstat([10, 5, 2], [40 * KB, 640 * KB, 2048 * KB]);
+            let stats = Statistics::compute(wrap(&cur), Some(wrap(&prev)), 1.0);
+            assert_relative_eq!(stats.kb_per_transfer_read(), 4.0);
+            assert_relative_eq!(stats.kb_per_transfer_write(), 128.0);
+            assert_relative_eq!(stats.kb_per_transfer_free(), 1024.0);
+            assert_relative_eq!(stats.kb_per_transfer(), 2728.0 / 17.0);
+        }
+
+        /// Only the bytes since the previous snapshot count.
+        #[test]
+        fn kb_per_transfer_delta() {
+            let prev = mkdevstat([100, 100, 0], [400 * KB, 12800 * KB, 0]);
+            let cur = mkdevstat([110, 100, 0], [480 * KB, 12800 * KB, 0]);
+            let stats = Statistics::compute(wrap(&cur), Some(wrap(&prev)), 1.0);
+            assert_relative_eq!(stats.kb_per_transfer_read(), 8.0);
+            assert_relative_eq!(stats.kb_per_transfer_write(), 0.0);
+            assert_relative_eq!(stats.mb_per_second_read(), 80.0 / 1024.0);
+        }
+
+        /// Counters that go backwards, as when a device is replaced between
+        /// snapshots, yield zero rather than overflowing.
+        #[test]
+        fn backwards() {
+            let prev = mkdevstat([100, 100, 0], [400 * KB, 400 * KB, 0]);
+            let cur = mkdevstat([10, 200, 0], [40 * KB, 800 * KB, 0]);
+            let stats = Statistics::compute(wrap(&cur), Some(wrap(&prev)), 1.0);
+            assert_eq!(stats.total_transfers_read(), 0);
+            assert_eq!(stats.total_transfers_write(), 100);
+            assert_eq!(stats.total_bytes(), 400 * KB);
+        }
+
+        #[test]
+        fn to_record() {
+            let prev = mkdevstat([100, 100, 0], [400 * KB, 12800 * KB, 0]);
+            let cur = mkdevstat([110, 100, 2], [480 * KB, 12800 * KB, 8 * KB]);
+            let stats = Statistics::compute(wrap(&cur), Some(wrap(&prev)), 2.0);
+            let record = stats.to_record();
+            assert_eq!(record.name, "");
+            assert_eq!(record.etime, 2.0);
+            assert_eq!(record.total_transfers, 12);
+            assert_eq!(record.total_transfers_read, 10);
+            assert_eq!(record.total_bytes_free, 8 * KB);
+            assert_relative_eq!(record.transfers_per_second_read, 5.0);
+            assert_relative_eq!(record.kb_per_transfer_read, 8.0);
+            assert_relative_eq!(record.mb_per_second_read, 40.0 / 1024.0);
+            assert_eq!(record.queue_length, stats.queue_length());
+        }
+
+        #[test]
+        fn checked_ok() {
+            let prev = mkdevstat([100, 100, 0], [400 * KB, 400 * KB, 0]);
+            let cur = mkdevstat([110, 200, 0], [440 * KB, 800 * KB, 0]);
+            let stats =
+                Statistics::compute_checked(wrap(&cur), Some(wrap(&prev)), 1.0)
+                    .unwrap();
+            assert_eq!(stats.total_transfers(), 110);
+            // With no previous snapshot, there's nothing to check
+            assert!(Statistics::compute_checked(wrap(&cur), None, 1.0).is_ok());
+        }
+
+        #[test]
+        fn checked_backwards() {
+            let prev = mkdevstat([100, 100, 0], [400 * KB, 400 * KB, 0]);
+            let cur = mkdevstat([10, 200, 0], [40 * KB, 800 * KB, 0]);
+            let r =
+                Statistics::compute_checked(wrap(&cur), Some(wrap(&prev)), 1.0);
+            assert_eq!(r.err(), Some(DeviceRestarted));
+        }
+
+        /// A device re-created with the same id is detected even if its new
+        /// counters have already passed the old ones.
+        #[test]
+        fn checked_recreated() {
+            let prev = mkdevstat([100, 100, 0], [400 * KB, 400 * KB, 0]);
+            let mut cur = mkdevstat([200, 200, 0], [800 * KB, 800 * KB, 0]);
+            cur.creation_time.sec = 1000;
+            let r =
+                Statistics::compute_checked(wrap(&cur), Some(wrap(&prev)), 1.0);
+            assert_eq!(r.err(), Some(DeviceRestarted));
+        }
+
+        /// A re-created device's statistics cover its entire counters, since
+        /// its creation if the uptime is known.
+        #[test]
+        fn or_since_creation() {
+            let prev = mkdevstat([100, 100, 0], [400 * KB, 400 * KB, 0]);
+            let mut cur = mkdevstat([10, 200, 0], [40 * KB, 800 * KB, 0]);
+            cur.creation_time.sec = 1000;
+            let stats = Statistics::compute_or_since_creation(
+                wrap(&cur),
+                Some(wrap(&prev)),
+                1.0,
+                None,
+            );
+            assert_eq!(stats.total_transfers(), 210);
+            assert_relative_eq!(stats.transfers_per_second(), 210.0);
+            let stats = Statistics::compute_or_since_creation(
+                wrap(&cur),
+                Some(wrap(&prev)),
+                1.0,
+                Some(1010.0),
+            );
+            assert_eq!(stats.total_transfers(), 210);
+            assert_relative_eq!(stats.transfers_per_second(), 21.0);
+
+            // An intact device's statistics cover just the interval
+            let cur = mkdevstat([110, 200, 0], [440 * KB, 800 * KB, 0]);
+            let stats = Statistics::compute_or_since_creation(
+                wrap(&cur),
+                Some(wrap(&prev)),
+                1.0,
+                Some(1010.0),
+            );
+            assert_eq!(stats.total_transfers(), 110);
+        }
+
+        /// Tag counts are independent of the operation types
+        #[test]
+        fn tags() {
+            let mut prev = mkdevstat([0; 3], [0; 3]);
+            let mut cur = mkdevstat([10, 5, 0], [0; 3]);
+            for (i, tag) in [
+                devstat_tag_type_DEVSTAT_TAG_SIMPLE,
+                devstat_tag_type_DEVSTAT_TAG_HEAD,
+                devstat_tag_type_DEVSTAT_TAG_ORDERED,
+            ]
+            .into_iter()
+            .enumerate()
+            {
+                prev.tag_types[tag as usize] = 100 * i as u64;
+                cur.tag_types[tag as usize] = 100 * i as u64 + [12, 2, 1][i];
+            }
+            let stats = Statistics::compute(wrap(&cur), Some(wrap(&prev)), 2.0);
+            assert_eq!(stats.total_tags_simple(), 12);
+            assert_eq!(stats.total_tags_head(), 2);
+            assert_eq!(stats.total_tags_ordered(), 1);
+            assert_relative_eq!(stats.tags_per_second_simple(), 6.0);
+        }
+
+        /// Little's law: 200 transactions per second, each lasting 20 ms,
+        /// average 4 outstanding.
+        #[test]
+        fn avg_queue_depth() {
+            let prev = mkdevstat([0; 3], [0; 3]);
+            let mut cur = mkdevstat([300, 100, 0], [0; 3]);
+            let read = devstat_trans_flags_DEVSTAT_READ as usize;
+            let write = devstat_trans_flags_DEVSTAT_WRITE as usize;
+            // 300 * 20 ms and 100 * 20 ms
+            cur.duration[read] = bintime { sec: 6, frac: 0 };
+            cur.duration[write] = bintime { sec: 2, frac: 0 };
+            let stats = Statistics::compute(wrap(&cur), Some(wrap(&prev)), 2.0);
+            assert_relative_eq!(stats.avg_queue_depth(), 4.0);
+            assert_relative_eq!(
+                stats.avg_queue_depth(),
+                stats.transfers_per_second() * stats.ms_per_transaction()
+                    / 1000.0
+            );
+            let stats = Statistics::compute(wrap(&cur), Some(wrap(&prev)), 0.0);
+            assert_eq!(stats.avg_queue_depth(), 0.0);
+        }
+
+        #[test]
+        fn saturated() {
+            let ds = mkdevstat([u64::MAX; 3], [u64::MAX; 3]);
+            let stats = Statistics::compute(wrap(&ds), None, 1.0);
+            assert_eq!(stats.total_transfers(), u64::MAX);
+            assert_eq!(stats.total_bytes(), u64::MAX);
+        }
+
+        /// start_count and end_count wrap independently
+        #[test]
+        fn queue_length_wrapped() {
+            let mut ds = mkdevstat([0; 3], [0; 3]);
+            ds.start_count = 2;
+            ds.end_count = u32::MAX - 1;
+            let stats = Statistics::compute(wrap(&ds), None, 1.0);
+            assert_eq!(stats.queue_length(), 4);
+        }
+
+        #[test]
+        fn kb_per_transfer_idle() {
+            let ds = mkdevstat([0; 3], [0; 3]);
+            let stats = Statistics::compute(wrap(&ds), Some(wrap(&ds)), 1.0);
+            assert_eq!(stats.kb_per_transfer(), 0.0);
+            assert_eq!(stats.kb_per_transfer_read(), 0.0);
+            assert_eq!(stats.kb_per_transfer_write(), 0.0);
+            assert_eq!(stats.kb_per_transfer_free(), 0.0);
+        }
+    }
 
     mod delta_t {
         use super::*;
@@ -0,0 +1,159 @@
+//! An owned copy of a device's [`Statistics`]
+//!
+//! [`Statistics`] borrows from a snapshot, and computes each metric on
+//! demand.  A [`Record`] computes them all at once and stores them as plain
+//! named fields, so it can be kept, sent between threads, or, with the `serde`
+//! feature, serialized.
+#[cfg(feature = "serde")]
+use serde_derive::{Deserialize, Serialize};
+
+use crate::Statistics;
+
+/// Every statistic of one device over one interval.  Returned by
+/// [`Statistics::to_record`].
+///
+/// Fields have the same names and meanings as [`Statistics`]' methods.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[non_exhaustive]
+pub struct Record {
+    /// The device's name.  [`Statistics`] doesn't know it, so it's empty until
+    /// set by the caller.
+    pub name:  String,
+    /// The length of the interval, in seconds
+    pub etime: f64,
+
+    pub total_bytes:       u64,
+    pub total_bytes_free:  u64,
+    pub total_bytes_read:  u64,
+    pub total_bytes_write: u64,
+
+    pub total_blocks:       u64,
+    pub total_blocks_free:  u64,
+    pub total_blocks_read:  u64,
+    pub total_blocks_write: u64,
+
+    pub total_tags_head:    u64,
+    pub total_tags_ordered: u64,
+    pub total_tags_simple:  u64,
+
+    pub total_transfers:       u64,
+    pub total_transfers_free:  u64,
+    pub total_transfers_other: u64,
+    pub total_transfers_read:  u64,
+    pub total_transfers_write: u64,
+
+    pub total_duration:       f64,
+    pub total_duration_free:  f64,
+    pub total_duration_other: f64,
+    pub total_duration_read:  f64,
+    pub total_duration_write: f64,
+
+    pub blocks_per_second:       f64,
+    pub blocks_per_second_free:  f64,
+    pub blocks_per_second_read:  f64,
+    pub blocks_per_second_write: f64,
+
+    pub kb_per_transfer:       f64,
+    pub kb_per_transfer_free:  f64,
+    pub kb_per_transfer_read:  f64,
+    pub kb_per_transfer_write: f64,
+
+    pub mb_per_second:       f64,
+    pub mb_per_second_free:  f64,
+    pub mb_per_second_read:  f64,
+    pub mb_per_second_write: f64,
+
+    pub ms_per_transaction:       f64,
+    pub ms_per_transaction_free:  f64,
+    pub ms_per_transaction_other: f64,
+    pub ms_per_transaction_read:  f64,
+    pub ms_per_transaction_write: f64,
+
+    pub tags_per_second_head:    f64,
+    pub tags_per_second_ordered: f64,
+    pub tags_per_second_simple:  f64,
+
+    pub transfers_per_second:       f64,
+    pub transfers_per_second_free:  f64,
+    pub transfers_per_second_other: f64,
+    pub transfers_per_second_read:  f64,
+    pub transfers_per_second_write: f64,
+
+    /// The device's cumulative busy time, in seconds
+    pub busy_time:       f64,
+    pub busy_pct:        f64,
+    pub avg_queue_depth: f64,
+    pub queue_length:    u32,
+}
+
+impl From<&Statistics<'_>> for Record {
+    fn from(s: &Statistics<'_>) -> Self {
+        Record {
+            name:  String::new(),
+            etime: s.etime,
+
+            total_bytes:       s.total_bytes(),
+            total_bytes_free:  s.total_bytes_free(),
+            total_bytes_read:  s.total_bytes_read(),
+            total_bytes_write: s.total_bytes_write(),
+
+            total_blocks:       s.total_blocks(),
+            total_blocks_free:  s.total_blocks_free(),
+            total_blocks_read:  s.total_blocks_read(),
+            total_blocks_write: s.total_blocks_write(),
+
+            total_tags_head:    s.total_tags_head(),
+            total_tags_ordered: s.total_tags_ordered(),
+            total_tags_simple:  s.total_tags_simple(),
+
+            total_transfers:       s.total_transfers(),
+            total_transfers_free:  s.total_transfers_free(),
+            total_transfers_other: s.total_transfers_other(),
+            total_transfers_read:  s.total_transfers_read(),
+            total_transfers_write: s.total_transfers_write(),
+
+            total_duration:       s.total_duration(),
+            total_duration_free:  s.total_duration_free(),
+            total_duration_other: s.total_duration_other(),
+            total_duration_read:  s.total_duration_read(),
+            total_duration_write: s.total_duration_write(),
+
+            blocks_per_second:       s.blocks_per_second(),
+            blocks_per_second_free:  s.blocks_per_second_free(),
+            blocks_per_second_read:  s.blocks_per_second_read(),
+            blocks_per_second_write: s.blocks_per_second_write(),
+
+            kb_per_transfer:       s.kb_per_transfer(),
+            kb_per_transfer_free:  s.kb_per_transfer_free(),
+            kb_per_transfer_read:  s.kb_per_transfer_read(),
+            kb_per_transfer_write: s.kb_per_transfer_write(),
+
+            mb_per_second:       s.mb_per_second(),
+            mb_per_second_free:  s.mb_per_second_free(),
+            mb_per_second_read:  s.mb_per_second_read(),
+            mb_per_second_write: s.mb_per_second_write(),
+
+            ms_per_transaction:       s.ms_per_transaction(),
+            ms_per_transaction_free:  s.ms_per_transaction_free(),
+            ms_per_transaction_other: s.ms_per_transaction_other(),
+            ms_per_transaction_read:  s.ms_per_transaction_read(),
+            ms_per_transaction_write: s.ms_per_transaction_write(),
+
+            tags_per_second_head:    s.tags_per_second_head(),
+            tags_per_second_ordered: s.tags_per_second_ordered(),
+            tags_per_second_simple:  s.tags_per_second_simple(),
+
+            transfers_per_second:       s.transfers_per_second(),
+            transfers_per_second_free:  s.transfers_per_second_free(),
+            transfers_per_second_other: s.transfers_per_second_other(),
+            transfers_per_second_read:  s.transfers_per_second_read(),
+            transfers_per_second_write: s.transfers_per_second_write(),
+
+            busy_time:       s.busy_time(),
+            busy_pct:        s.busy_pct(),
+            avg_queue_depth: s.avg_queue_depth(),
+            queue_length:    s.queue_length(),
+        }
+    }
+}
@@ -0,0 +1,332 @@
+//! A versioned binary format for saving [`SnapshotBuf`]s
+//!
+//! A saved snapshot begins with a fixed header, all little-endian:
+//!
+//! | Offset | Size | Field                                          |
+//! |--------|------|------------------------------------------------|
+//! | 0      | 8    | Magic: `b"GEOMSNAP"`                           |
+//! | 8      | 2    | Format version, currently 2                    |
+//! | 10     | 2    | `DEVSTAT_VERSION` of the system that saved it  |
+//! | 12     | 4    | Size of each record, `sizeof(struct devstat)`  |
+//! | 16     | 4    | Number of records                              |
+//! | 20     | 1    | Byte order of the records: 1 little, 2 big     |
+//! | 21     | 1    | Pointer width of the saving system, in bytes   |
+//! | 22     | 2    | Reserved, zero                                 |
+//! | 24     | 8    | Generation number                              |
+//! | 32     | 8    | Timestamp, seconds                             |
+//! | 40     | 8    | Timestamp, nanoseconds                         |
+//!
+//! The records follow, each a `struct devstat` in the saving machine's native
+//! layout.  Past revisions of `struct devstat` reshaped existing fields rather
+//! than only appending new ones, so a record can only be read by a system with
+//! the same `DEVSTAT_VERSION`, record size, byte order, and pointer width.
+//! Snapshots that differ in any of those are rejected rather than misread.
+//! Version 1 of the format didn't record the byte order or pointer width, so
+//! its records are assumed to be native.
+use std::{
+    io::{self, Error, ErrorKind, Read, Write},
+    mem,
+    os::raw::c_long,
+    ptr,
+    slice,
+};
+
+use freebsd_libgeom_sys::*;
+
+use crate::{SnapshotBuf, Timespec};
+
+const MAGIC: &[u8; 8] = b"GEOMSNAP";
+/// The newest format version that this crate can read, and the one it writes
+const VERSION: u16 = 2;
+const HEADER_LEN: usize = 48;
+const LITTLE_ENDIAN: u8 = 1;
+const BIG_ENDIAN: u8 = 2;
+/// This system's byte order, as recorded in the header
+const NATIVE_ENDIAN: u8 = if cfg!(target_endian = "little") {
+    LITTLE_ENDIAN
+} else {
+    BIG_ENDIAN
+};
+/// The other byte order
+const FOREIGN_ENDIAN: u8 = LITTLE_ENDIAN + BIG_ENDIAN - NATIVE_ENDIAN;
+/// This system's pointer width, as recorded in the header
+const NATIVE_PTR_WIDTH: u8 = mem::size_of::<usize>() as u8;
+
+fn invalid(msg: impl Into<String>) -> Error {
+    Error::new(ErrorKind::InvalidData, msg.into())
+}
+
+impl SnapshotBuf {
+    /// Save the most recent capture in a versioned binary format, which
+    /// [`SnapshotBuf::read_from`] can load on this or a later release.
+    // c_long and time_t are only 32 bits wide on some architectures
+    #[allow(clippy::unnecessary_cast)]
+    pub fn write_to<W: Write>(&self, mut w: W) -> io::Result<()> {
+        let devstats = self.devstats();
+        let generation = self.generation().unwrap_or(0);
+        let count = u32::try_from(devstats.len())
+            .map_err(|_| invalid("too many devices"))?;
+        let mut hdr = [0u8; HEADER_LEN];
+        hdr[0..8].copy_from_slice(MAGIC);
+        hdr[8..10].copy_from_slice(&VERSION.to_le_bytes());
+        hdr[10..12].copy_from_slice(&(DEVSTAT_VERSION as u16).to_le_bytes());
+        hdr[12..16]
+            .copy_from_slice(&(mem::size_of::<devstat>() as u32).to_le_bytes());
+        hdr[16..20].copy_from_slice(&count.to_le_bytes());
+        hdr[20] = NATIVE_ENDIAN;
+        hdr[21] = NATIVE_PTR_WIDTH;
+        hdr[24..32].copy_from_slice(&(generation as i64).to_le_bytes());
+        hdr[32..40]
+            .copy_from_slice(&(self.timestamp.0.tv_sec as i64).to_le_bytes());
+        hdr[40..48]
+            .copy_from_slice(&(self.timestamp.0.tv_nsec as i64).to_le_bytes());
+        w.write_all(&hdr)?;
+        let bytes = unsafe {
+            slice::from_raw_parts(
+                devstats.as_ptr().cast::<u8>(),
+                mem::size_of_val(devstats),
+            )
+        };
+        w.write_all(bytes)
+    }
+
+    /// Load a snapshot saved by [`SnapshotBuf::write_to`].
+    ///
+    /// Fails with [`ErrorKind::InvalidData`] if the data isn't a saved
+    /// snapshot, was saved by a newer format version than this crate
+    /// understands, or describes an incompatible devstat ABI: another
+    /// `DEVSTAT_VERSION`, record size, byte order, or pointer width.
+    pub fn read_from<R: Read>(mut r: R) -> io::Result<Self> {
+        let mut hdr = [0u8; HEADER_LEN];
+        r.read_exact(&mut hdr)?;
+        let u16_at = |i: usize| u16::from_le_bytes([hdr[i], hdr[i + 1]]);
+        let u32_at = |i: usize| {
+            u32::from_le_bytes(hdr[i..i + 4].try_into().unwrap()) as usize
+        };
+        let i64_at =
+            |i: usize| i64::from_le_bytes(hdr[i..i + 8].try_into().unwrap());
+        if &hdr[0..8] != MAGIC {
+            return Err(invalid("not a saved GEOM snapshot"));
+        }
+        let version = u16_at(8);
+        if version == 0 || version > VERSION {
+            return Err(invalid(format!(
+                "unsupported snapshot format version {version}"
+            )));
+        }
+        let abi = u16_at(10);
+        if u32::from(abi) != DEVSTAT_VERSION {
+            return Err(invalid(format!(
+                "incompatible devstat version {abi}; expected \
+                 {DEVSTAT_VERSION}"
+            )));
+        }
+        if version >= 2 {
+            match hdr[20] {
+                NATIVE_ENDIAN => (),
+                FOREIGN_ENDIAN => {
+                    return Err(invalid(
+                        "snapshot was saved on a system of the other byte \
+                         order",
+                    ))
+                }
+                e => return Err(invalid(format!("unknown byte order {e}"))),
+            }
+            let width = hdr[21];
+            if width != NATIVE_PTR_WIDTH {
+                return Err(invalid(format!(
+                    "snapshot was saved on a system with {}-bit pointers",
+                    u32::from(width) * 8
+                )));
+            }
+        }
+        let record_len = u32_at(12);
+        let size = mem::size_of::<devstat>();
+        if record_len != size {
+            return Err(invalid(format!(
+                "incompatible devstat record size {record_len}; expected \
+                 {size}"
+            )));
+        }
+        let count = u32_at(16);
+
+        // Don't trust the count for preallocation, in case it's corrupt
+        let mut records = Vec::with_capacity(count.min(1024) * size);
+        for _ in 0..count {
+            let start = records.len();
+            records.resize(start + size, 0);
+            r.read_exact(&mut records[start..])?;
+        }
+
+        let hdr_len = mem::size_of::<c_long>();
+        let len = hdr_len + records.len();
+        let mut sb = SnapshotBuf::new();
+        sb.buf.resize((len + 7) / 8, 0);
+        sb.len = len;
+        sb.timestamp = Timespec(freebsd_libgeom_sys::timespec {
+            tv_sec:  i64_at(32) as _,
+            tv_nsec: i64_at(40) as _,
+        });
+        unsafe {
+            let first = sb.buf.as_mut_ptr().cast::<u8>();
+            *first.cast::<c_long>() = i64_at(24) as c_long;
+            ptr::copy_nonoverlapping(
+                records.as_ptr(),
+                first.add(hdr_len),
+                records.len(),
+            );
+        }
+        Ok(sb)
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use std::os::raw::c_void;
+
+    use super::*;
+
+    fn mkbuf(devstats: &[devstat]) -> SnapshotBuf {
+        let hdr = mem::size_of::<c_long>();
+        let len = hdr + mem::size_of_val(devstats);
+        let mut sb = SnapshotBuf::new();
+        sb.buf.resize((len + 7) / 8, 0);
+        sb.len = len;
+        unsafe { *sb.buf.as_mut_ptr().cast::<c_long>() = 42 };
+        sb.timestamp.0.tv_sec = 1234;
+        sb.timestamp.0.tv_nsec = 5678;
+        unsafe {
+            let first = sb.buf.as_mut_ptr().cast::<u8>().add(hdr);
+            ptr::copy_nonoverlapping(
+                devstats.as_ptr(),
+                first.cast(),
+                devstats.len(),
+            );
+        }
+        sb
+    }
+
+    fn mkdevstat(id: usize, unit_number: i32) -> devstat {
+        let mut ds: devstat = unsafe { mem::zeroed() };
+        ds.id = id as *const c_void;
+        ds.unit_number = unit_number;
+        ds
+    }
+
+    fn units(sb: &SnapshotBuf) -> Vec<i32> {
+        sb.iter().map(|ds| ds.unit_number()).collect()
+    }
+
+    #[test]
+    fn bad_magic() {
+        let mut data = Vec::new();
+        mkbuf(&[]).write_to(&mut data).unwrap();
+        data[0] = b'X';
+        let e = SnapshotBuf::read_from(&data[..]).map(drop).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+    }
+
+    /// Newer format versions may not be readable, so reject them.
+    #[test]
+    fn newer_version() {
+        let mut data = Vec::new();
+        mkbuf(&[]).write_to(&mut data).unwrap();
+        data[8..10].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        let e = SnapshotBuf::read_from(&data[..]).map(drop).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+    }
+
+    /// Records from another byte order can't be read natively.
+    #[test]
+    fn foreign_endian() {
+        let mut data = Vec::new();
+        mkbuf(&[]).write_to(&mut data).unwrap();
+        assert_eq!(data[20], NATIVE_ENDIAN);
+        data[20] = FOREIGN_ENDIAN;
+        let e = SnapshotBuf::read_from(&data[..]).map(drop).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+    }
+
+    /// Records from another pointer width can't be read natively.
+    #[test]
+    fn foreign_pointer_width() {
+        let mut data = Vec::new();
+        mkbuf(&[]).write_to(&mut data).unwrap();
+        assert_eq!(usize::from(data[21]), mem::size_of::<*const c_void>());
+        data[21] = if data[21] == 8 { 4 } else { 8 };
+        let e = SnapshotBuf::read_from(&data[..]).map(drop).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+    }
+
+    /// Version 1 didn't record the byte order or pointer width, so they're
+    /// assumed to be native.
+    #[test]
+    fn format_v1() {
+        let mut data = Vec::new();
+        mkbuf(&[mkdevstat(1, 3)]).write_to(&mut data).unwrap();
+        data[8..10].copy_from_slice(&1u16.to_le_bytes());
+        data[20..22].copy_from_slice(&[0, 0]);
+        let sb = SnapshotBuf::read_from(&data[..]).unwrap();
+        assert_eq!(units(&sb), [3]);
+    }
+
+    /// Snapshots from releases with any other devstat ABI are rejected, since
+    /// their layouts differ.
+    #[test]
+    fn devstat_version() {
+        let mut data = Vec::new();
+        mkbuf(&[mkdevstat(1, 3)]).write_to(&mut data).unwrap();
+        let older = (DEVSTAT_VERSION - 1) as u16;
+        data[10..12].copy_from_slice(&older.to_le_bytes());
+        let e = SnapshotBuf::read_from(&data[..]).map(drop).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        let newer = (DEVSTAT_VERSION + 1) as u16;
+        data[10..12].copy_from_slice(&newer.to_le_bytes());
+        let e = SnapshotBuf::read_from(&data[..]).map(drop).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn roundtrip() {
+        let sb = mkbuf(&[mkdevstat(1, 0), mkdevstat(2, 1)]);
+        let mut data = Vec::new();
+        sb.write_to(&mut data).unwrap();
+        assert_eq!(data.len(), HEADER_LEN + 2 * mem::size_of::<devstat>());
+        let sb2 = SnapshotBuf::read_from(&data[..]).unwrap();
+        assert_eq!(units(&sb2), [0, 1]);
+        assert_eq!(sb2.generation(), Some(42));
+        assert_eq!(sb2.timestamp().0.tv_sec, 1234);
+        assert_eq!(sb2.timestamp().0.tv_nsec, 5678);
+        // Devices are still paired by id
+        let pairs = sb2.iter_pair(Some(&sb)).filter(|(_, p)| p.is_some());
+        assert_eq!(pairs.count(), 2);
+    }
+
+    /// Records of a different size than this system's struct devstat are
+    /// rejected, rather than padded or truncated.
+    #[test]
+    fn short_records() {
+        let sb = mkbuf(&[mkdevstat(1, 7), mkdevstat(2, 8)]);
+        let mut data = Vec::new();
+        sb.write_to(&mut data).unwrap();
+        // Pretend that the last 8 bytes of each record didn't exist
+        let size = mem::size_of::<devstat>();
+        let short = size - 8;
+        let mut old = data[..HEADER_LEN].to_vec();
+        old[12..16].copy_from_slice(&(short as u32).to_le_bytes());
+        for rec in data[HEADER_LEN..].chunks_exact(size) {
+            old.extend_from_slice(&rec[..short]);
+        }
+        let e = SnapshotBuf::read_from(&old[..]).map(drop).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn truncated() {
+        let mut data = Vec::new();
+        mkbuf(&[mkdevstat(1, 0)]).write_to(&mut data).unwrap();
+        data.pop();
+        let e = SnapshotBuf::read_from(&data[..]).map(drop).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::UnexpectedEof);
+    }
+}
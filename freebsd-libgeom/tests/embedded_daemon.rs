@@ -0,0 +1,45 @@
+//! Exercise the embedded_daemon example's sampling pattern
+#[allow(dead_code)]
+#[path = "../examples/embedded_daemon.rs"]
+mod embedded_daemon;
+
+use std::time::{Duration, Instant};
+
+use embedded_daemon::{Consumer, Sampler};
+
+#[test]
+fn sample_and_shutdown() {
+    let sampler = Sampler::spawn(Duration::from_millis(10));
+    let mut consumer = Consumer::new().unwrap();
+    let mut providers = 0;
+    let mut recycled = 0;
+    for _ in 0..3 {
+        let sample = sampler.recv().unwrap().unwrap();
+        let spent = consumer
+            .process(sample, |name, _rank, stats| {
+                assert!(!name.is_empty());
+                assert!(stats.busy_pct() >= 0.0);
+                providers += 1;
+            })
+            .unwrap();
+        if let Some(buf) = spent {
+            sampler.recycle(buf);
+            recycled += 1;
+        }
+    }
+    // The first sample only primes the consumer
+    assert_eq!(recycled, 2);
+    assert!(providers > 0, "No GEOM providers found");
+    sampler.shutdown().unwrap();
+}
+
+/// Dropping the sampler should stop its thread without waiting for the
+/// interval to elapse.
+#[test]
+fn drop_is_prompt() {
+    let sampler = Sampler::spawn(Duration::from_secs(3600));
+    sampler.recv().unwrap().unwrap();
+    let start = Instant::now();
+    drop(sampler);
+    assert!(start.elapsed() < Duration::from_secs(60));
+}
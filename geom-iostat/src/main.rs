@@ -0,0 +1,193 @@
+//! Like "iostat -x", but for GEOM providers.  See iostat(8).
+use std::{
+    error::Error,
+    io::{self, Write},
+    num::NonZeroUsize,
+    thread::sleep,
+    time::Duration,
+};
+
+use clap::Parser;
+use freebsd_libgeom::{DeltaSnapshot, Statistics};
+
+/// Report extended I/O statistics for GEOM providers, like "iostat -x"
+#[derive(Debug, Default, clap::Parser)]
+struct Cli {
+    /// Display this many reports.  The default is one, unless -w is given.
+    #[clap(short = 'c')]
+    count:    Option<NonZeroUsize>,
+    /// Pause this many seconds between reports.  The default is one, if -c
+    /// is given.
+    #[clap(short = 'w', value_parser = Cli::parse_wait)]
+    wait:     Option<Duration>,
+    /// Ignored.  Statistics are always extended, as with iostat -x.
+    #[clap(short = 'x')]
+    extended: bool,
+    /// Only report these devices.  By default, report every physical
+    /// provider (those with rank of 1).
+    devices:  Vec<String>,
+}
+
+impl Cli {
+    fn parse_wait(s: &str) -> Result<Duration, String> {
+        let secs = s.parse::<f64>().map_err(|e| e.to_string())?;
+        if secs.is_finite() && secs > 0.0 {
+            Ok(Duration::from_secs_f64(secs))
+        } else {
+            Err("must be a positive number of seconds".to_owned())
+        }
+    }
+
+    /// How many reports to display, or `None` for no limit, and how long to
+    /// pause between them.  Like iostat, -w without -c repeats forever.
+    fn schedule(&self) -> (Option<usize>, Duration) {
+        let wait = self.wait.unwrap_or(Duration::from_secs(1));
+        let count = match (self.count, self.wait) {
+            (Some(count), _) => Some(count.get()),
+            (None, Some(_)) => None,
+            (None, None) => Some(1),
+        };
+        (count, wait)
+    }
+
+    /// Should `name`, a provider of the given `rank`, be reported?
+    fn wanted(&self, name: &str, rank: u32) -> bool {
+        if self.devices.is_empty() {
+            rank == 1
+        } else {
+            self.devices.iter().any(|d| d == name)
+        }
+    }
+}
+
+fn header() -> String {
+    format!(
+        "{:8}{:>8}{:>8}{:>9}{:>9}{:>6}{:>6}{:>6}{:>6}{:>5}{:>4}",
+        "device",
+        "r/s",
+        "w/s",
+        "kr/s",
+        "kw/s",
+        "ms/r",
+        "ms/w",
+        "ms/o",
+        "ms/t",
+        "qlen",
+        "%b"
+    )
+}
+
+fn row(name: &str, stats: &Statistics) -> String {
+    format!(
+        "{:8} {:>7.0} {:>7.0} {:>8.1} {:>8.1} {:>5.0} {:>5.0} {:>5.0} {:>5.0} \
+         {:>4} {:>3.0}",
+        name,
+        stats.transfers_per_second_read(),
+        stats.transfers_per_second_write(),
+        stats.mb_per_second_read() * 1024.0,
+        stats.mb_per_second_write() * 1024.0,
+        stats.ms_per_transaction_read(),
+        stats.ms_per_transaction_write(),
+        stats.ms_per_transaction_other(),
+        stats.ms_per_transaction(),
+        stats.queue_length(),
+        stats.busy_pct()
+    )
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let cli = Cli::parse();
+    let (count, wait) = cli.schedule();
+    let mut ds = DeltaSnapshot::new()?;
+
+    let mut stdout = io::stdout().lock();
+    let mut buf = String::new();
+    let mut reports = 0;
+    loop {
+        // Like iostat, the first report covers the time since each device
+        // was created.
+        buf.clear();
+        buf.push_str(&header());
+        buf.push('\n');
+        for (name, rank, stats) in ds.refresh()?.providers() {
+            let name = name.to_string_lossy();
+            if cli.wanted(&name, rank) {
+                buf.push_str(&row(&name, &stats));
+                buf.push('\n');
+            }
+        }
+        match stdout
+            .write_all(buf.as_bytes())
+            .and_then(|_| stdout.flush())
+        {
+            // The reader, like head(1), has seen enough
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => break,
+            r => r?,
+        }
+        reports += 1;
+        if count.is_some_and(|c| reports >= c) {
+            break;
+        }
+        sleep(wait);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    mod schedule {
+        use super::*;
+
+        fn parse(args: &[&str]) -> (Option<usize>, Duration) {
+            Cli::try_parse_from(["geom-iostat"].iter().chain(args))
+                .unwrap()
+                .schedule()
+        }
+
+        #[test]
+        fn once() {
+            assert_eq!(parse(&[]), (Some(1), Duration::from_secs(1)));
+        }
+
+        #[test]
+        fn count() {
+            assert_eq!(parse(&["-c", "3"]), (Some(3), Duration::from_secs(1)));
+        }
+
+        #[test]
+        fn forever() {
+            assert_eq!(
+                parse(&["-w", "0.5"]),
+                (None, Duration::from_millis(500))
+            );
+        }
+
+        #[test]
+        fn count_and_wait() {
+            assert_eq!(
+                parse(&["-x", "-w", "2", "-c", "5"]),
+                (Some(5), Duration::from_secs(2))
+            );
+        }
+
+        #[test]
+        fn zero_wait() {
+            Cli::try_parse_from(["geom-iostat", "-w", "0"]).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn wanted() {
+        let cli = Cli::default();
+        assert!(cli.wanted("ada0", 1));
+        assert!(!cli.wanted("ada0p1", 2));
+        let cli =
+            Cli::try_parse_from(["geom-iostat", "ada0p1", "da0"]).unwrap();
+        assert!(!cli.wanted("ada0", 1));
+        assert!(cli.wanted("ada0p1", 2));
+        assert!(cli.wanted("da0", 1));
+    }
+}
@@ -0,0 +1,247 @@
+//! Notify the user when a device's statistics cross a threshold
+use std::{
+    collections::HashMap,
+    fmt,
+    num::NonZeroUsize,
+    str::FromStr,
+    sync::Arc,
+};
+
+use crate::Element;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Op {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Op {
+    fn eval(self, l: f64, r: f64) -> bool {
+        match self {
+            Op::Lt => l < r,
+            Op::Le => l <= r,
+            Op::Gt => l > r,
+            Op::Ge => l >= r,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+        }
+    }
+}
+
+/// A threshold expression like `ms_r>100`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rule {
+    stat:      &'static str,
+    op:        Op,
+    threshold: f64,
+}
+
+impl Rule {
+    fn matches(&self, elem: &Element) -> Option<f64> {
+        elem.stat(self.stat)
+            .filter(|&v| self.op.eval(v, self.threshold))
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}{}", self.stat, self.op.as_str(), self.threshold)
+    }
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let i = s
+            .find(['<', '>'])
+            .ok_or_else(|| format!("{s:?} has no comparison operator"))?;
+        let (stat, rest) = s.split_at(i);
+        let (op, threshold) = match rest.split_at(1) {
+            ("<", t) if t.starts_with('=') => (Op::Le, &t[1..]),
+            (">", t) if t.starts_with('=') => (Op::Ge, &t[1..]),
+            ("<", t) => (Op::Lt, t),
+            (_, t) => (Op::Gt, t),
+        };
+        let stat = Element::STATS
+            .iter()
+            .find(|&&name| name == stat.trim())
+            .ok_or_else(|| {
+                format!(
+                    "unknown statistic {:?}.  Valid statistics are: {}",
+                    stat.trim(),
+                    Element::STATS.join(", ")
+                )
+            })?;
+        let threshold = threshold
+            .trim()
+            .parse::<f64>()
+            .map_err(|e| format!("invalid threshold {threshold:?}: {e}"))?;
+        Ok(Rule {
+            stat,
+            op,
+            threshold,
+        })
+    }
+}
+
+/// Tracks how long each device has been in violation of each rule
+#[derive(Debug)]
+pub struct Alerts {
+    rules:     Vec<Rule>,
+    /// Number of consecutive intervals before a violation is reported
+    intervals: NonZeroUsize,
+    /// Consecutive intervals of violation, by rule index and device name
+    streaks:   HashMap<(usize, Arc<str>), usize>,
+}
+
+impl Alerts {
+    pub fn new(rules: Vec<Rule>, intervals: NonZeroUsize) -> Self {
+        Alerts {
+            rules,
+            intervals,
+            streaks: HashMap::new(),
+        }
+    }
+
+    /// Update the streaks from one interval's statistics.
+    ///
+    /// Returns a message for each device that has just been in violation of
+    /// a rule for the required number of intervals.  It won't be reported
+    /// again until it has recovered for at least one interval.
+    pub fn check(&mut self, items: &[Element]) -> Vec<String> {
+        let mut messages = Vec::new();
+        let mut streaks = HashMap::with_capacity(self.streaks.len());
+        for (i, rule) in self.rules.iter().enumerate() {
+            for elem in items {
+                let Some(value) = rule.matches(elem) else {
+                    continue;
+                };
+                let key = (i, elem.name.clone());
+                let streak = self.streaks.get(&key).copied().unwrap_or(0) + 1;
+                if streak == self.intervals.get() {
+                    messages.push(format!(
+                        "{}: {}={} (alert {}) for {} interval(s)",
+                        elem.name, rule.stat, value, rule, streak
+                    ));
+                }
+                streaks.insert(key, streak);
+            }
+        }
+        self.streaks = streaks;
+        messages
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Does any rule check the statistic `stat`?
+    pub fn uses(&self, stat: &str) -> bool {
+        self.rules.iter().any(|rule| rule.stat == stat)
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use nonzero_ext::nonzero;
+
+    use super::*;
+    use crate::group;
+
+    fn elem(name: &str, ms_r: f64) -> Element {
+        let mut e = group::total(&[]);
+        e.name = name.into();
+        e.ms_r = ms_r;
+        e
+    }
+
+    mod parse {
+        use super::*;
+
+        #[test]
+        fn ops() {
+            for (s, op) in [
+                ("ms_r>100", Op::Gt),
+                ("ms_r>=100", Op::Ge),
+                ("ms_r<100", Op::Lt),
+                ("ms_r <= 100", Op::Le),
+            ] {
+                let rule = s.parse::<Rule>().unwrap();
+                assert_eq!(rule.stat, "ms_r");
+                assert_eq!(rule.op, op);
+                assert_eq!(rule.threshold, 100.0);
+            }
+        }
+
+        #[test]
+        fn display() {
+            let rule = "pct_busy>=99.5".parse::<Rule>().unwrap();
+            assert_eq!(rule.to_string(), "pct_busy>=99.5");
+        }
+
+        #[test]
+        fn no_op() {
+            assert!("ms_r=100".parse::<Rule>().is_err());
+        }
+
+        #[test]
+        fn unknown_stat() {
+            let e = "ms_x>100".parse::<Rule>().unwrap_err();
+            assert!(e.contains("ms_x"));
+        }
+
+        #[test]
+        fn bad_threshold() {
+            assert!("ms_r>".parse::<Rule>().is_err());
+            assert!("ms_r>fast".parse::<Rule>().is_err());
+        }
+    }
+
+    #[test]
+    fn consecutive() {
+        let rule = "ms_r>100".parse().unwrap();
+        let mut alerts = Alerts::new(vec![rule], nonzero!(3usize));
+        let slow = [elem("da0", 150.0), elem("da1", 10.0)];
+        assert!(alerts.check(&slow).is_empty());
+        assert!(alerts.check(&slow).is_empty());
+        let msgs = alerts.check(&slow);
+        assert_eq!(msgs.len(), 1);
+        assert!(msgs[0].starts_with("da0: ms_r=150"));
+        // Don't repeat it while the violation continues
+        assert!(alerts.check(&slow).is_empty());
+    }
+
+    #[test]
+    fn uses() {
+        let rules = vec!["errs>0".parse().unwrap()];
+        let alerts = Alerts::new(rules, nonzero!(1usize));
+        assert!(alerts.uses("errs"));
+        assert!(!alerts.uses("sq"));
+    }
+
+    /// A single good interval resets the streak
+    #[test]
+    fn recovery() {
+        let rule = "ms_r>100".parse().unwrap();
+        let mut alerts = Alerts::new(vec![rule], nonzero!(2usize));
+        let slow = [elem("da0", 150.0)];
+        let fast = [elem("da0", 50.0)];
+        assert!(alerts.check(&slow).is_empty());
+        assert!(alerts.check(&fast).is_empty());
+        assert!(alerts.check(&slow).is_empty());
+        assert_eq!(alerts.check(&slow).len(), 1);
+        assert!(alerts.check(&fast).is_empty());
+        assert!(alerts.check(&slow).is_empty());
+        assert_eq!(alerts.check(&slow).len(), 1);
+    }
+}
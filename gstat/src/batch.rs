@@ -0,0 +1,504 @@
+//! Non-interactive output, for scripts
+//!
+//! In batch mode, gstat prints the enabled columns of every visible device to
+//! stdout once per update interval, in one of several formats, instead of
+//! drawing the table on the terminal.
+use std::{
+    io::{self, Write},
+    num::NonZeroUsize,
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde_json::{Map, Value};
+
+use crate::{
+    cli::{Cli, Format},
+    column::{self, Unit},
+    replay::Marker,
+    Column,
+    Columns,
+    DataSource,
+    Element,
+};
+
+/// One column's value for one element
+#[derive(Debug, PartialEq)]
+pub enum Field<'a> {
+    /// A number, and how many digits to display after the decimal point
+    Num(f64, usize),
+    Text(&'a str),
+    /// The element doesn't report this column
+    Blank,
+}
+
+impl<'a> Field<'a> {
+    pub fn new(elem: &'a Element, k: usize) -> Self {
+        let num = |v: Option<f64>, prec| match v {
+            Some(v) => Field::Num(v, prec),
+            None => Field::Blank,
+        };
+        let text = |s: Option<&'a str>| match s {
+            Some(s) => Field::Text(s),
+            None => Field::Blank,
+        };
+        match column::BUILTIN.get(k) {
+            Some(spec) if spec.unit == Unit::Text => text(elem.text(spec.stat)),
+            Some(spec) => num(elem.stat(spec.stat), spec.unit.precision()),
+            None => {
+                num(elem.custom.get(k - Columns::LEN).copied().flatten(), 1)
+            }
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        match self {
+            Field::Num(v, _) => Value::from(*v),
+            Field::Text(s) => Value::from(*s),
+            Field::Blank => Value::Null,
+        }
+    }
+}
+
+impl std::fmt::Display for Field<'_> {
+    /// Honors the formatter's width.  Numbers are right-aligned, and text
+    /// left-aligned.
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let width = f.width().unwrap_or(0);
+        match self {
+            Field::Num(v, prec) => {
+                let prec = *prec;
+                write!(f, "{v:>width$.prec$}")
+            }
+            Field::Text(s) => write!(f, "{s:<width$}"),
+            Field::Blank => write!(f, "{:width$}", ""),
+        }
+    }
+}
+
+/// Quote a CSV field, if necessary.
+fn csv_escape(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+/// How often batch mode prints the header
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Header {
+    /// Before every this many samples
+    Every(NonZeroUsize),
+    /// Only before the first sample
+    Once,
+    /// Not at all
+    Never,
+}
+
+impl Header {
+    pub fn new(cfg: &Cli) -> Self {
+        match (cfg.no_repeat_header, cfg.header_interval) {
+            (true, _) => Header::Once,
+            (false, None) => Header::Every(NonZeroUsize::MIN),
+            (false, Some(n)) => {
+                NonZeroUsize::new(n).map_or(Header::Never, Header::Every)
+            }
+        }
+    }
+
+    /// Should the header precede sample number `i`, counting from 0?
+    pub fn due(self, i: usize) -> bool {
+        match self {
+            Header::Every(n) => i % n.get() == 0,
+            Header::Once => i == 0,
+            Header::Never => false,
+        }
+    }
+}
+
+/// Writes samples in the chosen format
+struct Printer<W: Write> {
+    out:     W,
+    format:  Format,
+    header:  Header,
+    /// How many samples have been written
+    samples: usize,
+    /// Has the CSV header been written yet?
+    headed:  bool,
+}
+
+impl<W: Write> Printer<W> {
+    fn new(out: W, format: Format, header: Header) -> Self {
+        Printer {
+            out,
+            format,
+            header,
+            samples: 0,
+            headed: false,
+        }
+    }
+
+    /// Print one sample of `items`, taken at `time`
+    fn sample<'a>(
+        &mut self,
+        columns: &Columns,
+        items: impl Iterator<Item = &'a Element>,
+        time: SystemTime,
+    ) -> io::Result<()> {
+        let enabled = columns.enabled().collect::<Vec<_>>();
+        let time = time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        match self.format {
+            Format::Text => {
+                // Like the interactive display, the name column is last and
+                // unpadded.
+                let mut line = String::new();
+                for (k, col) in enabled.iter() {
+                    if *k == Columns::NAME {
+                        line.push_str(col.header);
+                    } else if Columns::is_text(*k) {
+                        let w = usize::from(columns.width(*k)) - 1;
+                        line.push_str(&format!("{:<w$} ", col.header));
+                    } else {
+                        line.push_str(&columns.header(*k, columns.width(*k)));
+                        line.push(' ');
+                    }
+                }
+                if self.header.due(self.samples) {
+                    writeln!(self.out, "{}", line.trim_end())?;
+                }
+                for elem in items {
+                    let mut line = String::new();
+                    for (k, _) in enabled.iter() {
+                        if *k == Columns::NAME {
+                            line.push_str(&elem.display_name());
+                        } else if Columns::is_text(*k) {
+                            // Text columns begin with a space, as they do
+                            // interactively
+                            let w = usize::from(columns.width(*k)) - 2;
+                            line.push_str(&format!(
+                                " {:w$} ",
+                                Field::new(elem, *k)
+                            ));
+                        } else {
+                            // Honors --human, unlike the other formats
+                            let w = usize::from(columns.width(*k)) - 1;
+                            line.push_str(&format!(
+                                "{:>w$} ",
+                                columns.text(elem, *k)
+                            ));
+                        }
+                    }
+                    writeln!(self.out, "{}", line.trim_end())?;
+                }
+            }
+            Format::Csv => {
+                self.csv_header(&enabled)?;
+                for elem in items {
+                    let fields = enabled
+                        .iter()
+                        .map(|(k, _)| {
+                            csv_escape(&Field::new(elem, *k).to_string())
+                        })
+                        .collect::<Vec<_>>();
+                    writeln!(self.out, "{time:.3},{}", fields.join(","))?;
+                }
+            }
+            Format::Json => {
+                let devices = items
+                    .map(|elem| {
+                        enabled
+                            .iter()
+                            .map(|(k, col)| {
+                                let v = Field::new(elem, *k).to_json();
+                                (col.header.trim().to_owned(), v)
+                            })
+                            .collect::<Map<_, _>>()
+                    })
+                    .collect::<Vec<_>>();
+                let sample = serde_json::json!({
+                    "time": time,
+                    "devices": devices,
+                });
+                writeln!(self.out, "{sample}")?;
+            }
+        }
+        self.samples += 1;
+        self.out.flush()
+    }
+
+    /// Print a marker row.  In CSV, the label is in the Name column.
+    fn marker(&mut self, columns: &Columns, marker: &Marker) -> io::Result<()> {
+        let time = marker.time;
+        let label = &marker.label;
+        match self.format {
+            Format::Text => writeln!(self.out, "--- Marker: {label}")?,
+            Format::Csv => {
+                let enabled = columns.enabled().collect::<Vec<_>>();
+                self.csv_header(&enabled)?;
+                let fields = enabled
+                    .iter()
+                    .map(|(k, _)| {
+                        if *k == Columns::NAME {
+                            csv_escape(label)
+                        } else {
+                            String::new()
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                writeln!(self.out, "{time:.3},{}", fields.join(","))?;
+            }
+            Format::Json => {
+                let marker = serde_json::json!({
+                    "time": time,
+                    "marker": label,
+                });
+                writeln!(self.out, "{marker}")?;
+            }
+        }
+        self.out.flush()
+    }
+
+    /// Write the CSV header, unless it's already been written
+    fn csv_header(&mut self, enabled: &[(usize, &Column)]) -> io::Result<()> {
+        if !self.headed && self.header != Header::Never {
+            let header = enabled
+                .iter()
+                .map(|(_, col)| csv_escape(col.header.trim()))
+                .collect::<Vec<_>>();
+            writeln!(self.out, "time,{}", header.join(","))?;
+            self.headed = true;
+        }
+        Ok(())
+    }
+}
+
+/// How many samples to print, or None to print until `--duration` elapses.
+///
+/// With neither limit, that's a single sample.
+pub fn count(cfg: &Cli) -> Option<NonZeroUsize> {
+    match (cfg.count, cfg.duration) {
+        (Some(count), _) => Some(count),
+        (None, Some(_)) => None,
+        (None, None) => Some(NonZeroUsize::MIN),
+    }
+}
+
+/// Print statistics until the count or duration limit is reached.
+///
+/// With neither limit, print a single sample, like gstat(8)'s batch mode.
+pub fn run(
+    cfg: &Cli,
+    mut data: DataSource,
+    columns: &mut Columns,
+    sort_idx: Option<usize>,
+    filter: Option<&Regex>,
+    interval: Duration,
+) -> Result<()> {
+    if cfg.skip_first && cfg.replay.is_none() {
+        thread::sleep(interval);
+        data.refresh()?;
+    }
+    if let Some(path) = cfg.record.as_ref() {
+        data.record(path)?;
+    }
+    let count = count(cfg);
+    let start = Instant::now();
+    let mut printer = Printer::new(
+        io::stdout().lock(),
+        cfg.format.unwrap_or_default(),
+        Header::new(cfg),
+    );
+    let mut samples = 0;
+    loop {
+        data.sort(sort_idx, cfg.reverse);
+        for marker in data.reached.iter() {
+            match printer.marker(columns, marker) {
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                    return Ok(())
+                }
+                r => r.context("writing to stdout")?,
+            }
+        }
+        let items = data
+            .items
+            .iter()
+            .filter(|e| e.visible(cfg, filter))
+            .collect::<Vec<_>>();
+        match printer.sample(columns, items.into_iter(), SystemTime::now()) {
+            // The reader, like head(1), has seen enough
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => break,
+            r => r.context("writing to stdout")?,
+        }
+        samples += 1;
+        if count.is_some_and(|c| samples >= c.get())
+            || cfg.duration.is_some_and(|d| start.elapsed() + interval > d)
+        {
+            break;
+        }
+        thread::sleep(interval);
+        data.refresh()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+    use crate::group;
+
+    fn elem() -> Element {
+        let mut elem = group::total(&[]);
+        elem.name = "ada0".into();
+        elem.class = "DISK".to_owned();
+        elem.rank = 1;
+        elem.r_s = 100.0;
+        elem.ms_r = 0.3;
+        elem
+    }
+
+    fn print(format: Format, header: Header, samples: usize) -> String {
+        let mut cfg = Cli::default();
+        let columns = Columns::new(&mut cfg);
+        let elem = elem();
+        let mut printer = Printer::new(Vec::new(), format, header);
+        for _ in 0..samples {
+            printer
+                .sample(&columns, [&elem].into_iter(), UNIX_EPOCH)
+                .unwrap();
+        }
+        String::from_utf8(printer.out).unwrap()
+    }
+
+    #[test]
+    fn csv() {
+        assert_eq!(
+            print(Format::Csv, Header::new(&Cli::default()), 2),
+            "time,L(q),ops/s,r/s,kB/s r,ms/r,w/s,kB/s \
+             w,ms/w,%busy,Name\n0.000,0,0,100,0,0.3,0,0,0.0,0.0,ada0\n0.000,0,\
+             0,100,0,0.3,0,0,0.0,0.0,ada0\n"
+        );
+    }
+
+    /// The CSV header can be suppressed, but never repeated
+    #[test]
+    fn csv_header() {
+        assert_eq!(
+            print(Format::Csv, Header::Never, 1),
+            "0.000,0,0,100,0,0.3,0,0,0.0,0.0,ada0\n"
+        );
+    }
+
+    #[test]
+    fn csv_marker() {
+        let mut cfg = Cli::default();
+        let columns = Columns::new(&mut cfg);
+        let mut printer = Printer::new(Vec::new(), Format::Csv, Header::Once);
+        let marker = Marker {
+            time:  2.0,
+            label: "fio, run 1".to_owned(),
+        };
+        printer.marker(&columns, &marker).unwrap();
+        assert_eq!(
+            String::from_utf8(printer.out).unwrap(),
+            "time,L(q),ops/s,r/s,kB/s r,ms/r,w/s,kB/s \
+             w,ms/w,%busy,Name\n2.000,,,,,,,,,,\"fio, run 1\"\n"
+        );
+    }
+
+    #[test]
+    fn csv_escape() {
+        assert_eq!(super::csv_escape("ada0"), "ada0");
+        assert_eq!(super::csv_escape("a,b"), "\"a,b\"");
+        assert_eq!(super::csv_escape("a\"b"), "\"a\"\"b\"");
+    }
+
+    #[test]
+    fn header() {
+        let mut cfg = Cli::default();
+        assert_eq!(Header::new(&cfg), Header::Every(NonZeroUsize::MIN));
+        cfg.header_interval = Some(0);
+        assert_eq!(Header::new(&cfg), Header::Never);
+        cfg.header_interval = Some(3);
+        let every3 = Header::new(&cfg);
+        assert_eq!(
+            (0..7).map(|i| every3.due(i)).collect::<Vec<_>>(),
+            [true, false, false, true, false, false, true]
+        );
+        cfg.no_repeat_header = true;
+        assert_eq!(Header::new(&cfg), Header::Once);
+        assert!(Header::Once.due(0));
+        assert!(!Header::Once.due(1));
+    }
+
+    #[test]
+    fn field() {
+        let elem = elem();
+        assert_eq!(
+            Field::new(&elem, column::find("r_s")),
+            Field::Num(100.0, 0)
+        );
+        assert_eq!(Field::new(&elem, Columns::ERRS), Field::Blank);
+        assert_eq!(Field::new(&elem, column::find("rank")), Field::Num(1.0, 0));
+        assert_eq!(
+            Field::new(&elem, column::find("class")),
+            Field::Text("DISK")
+        );
+        assert_eq!(format!("{:6}", Field::Num(0.3, 1)), "   0.3");
+        assert_eq!(format!("{:6}", Field::Text("x")), "x     ");
+        assert_eq!(format!("{:6}", Field::Blank), "      ");
+    }
+
+    #[test]
+    fn json_marker() {
+        let mut cfg = Cli::default();
+        let columns = Columns::new(&mut cfg);
+        let mut printer = Printer::new(Vec::new(), Format::Json, Header::Once);
+        let marker = Marker {
+            time:  2.0,
+            label: "fio start".to_owned(),
+        };
+        printer.marker(&columns, &marker).unwrap();
+        let v: Value = serde_json::from_slice(&printer.out).unwrap();
+        assert_eq!(v["time"], 2.0);
+        assert_eq!(v["marker"], "fio start");
+    }
+
+    #[test]
+    fn json() {
+        let out = print(Format::Json, Header::new(&Cli::default()), 1);
+        let v: Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(v["time"], 0.0);
+        assert_eq!(v["devices"][0]["Name"], "ada0");
+        assert_eq!(v["devices"][0]["r/s"], 100.0);
+    }
+
+    /// Text mode repeats the header for every sample, like gstat(8)
+    #[test]
+    fn text() {
+        assert_eq!(
+            print(Format::Text, Header::new(&Cli::default()), 2),
+            "L(q)  ops/s    r/s  kB/s r   ms/r    w/s  kB/s w   ms/w  %busy \
+             Name\n   0      0    100       0    0.3      0       0    0.0    \
+             0.0 ada0\nL(q)  ops/s    r/s  kB/s r   ms/r    w/s  kB/s w   \
+             ms/w  %busy Name\n   0      0    100       0    0.3      0       \
+             0    0.0    0.0 ada0\n"
+        );
+    }
+
+    /// With -n, only the first sample is preceded by the header
+    #[test]
+    fn text_header_once() {
+        let out = print(Format::Text, Header::Once, 3);
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("L(q)"));
+        assert!(lines[1..].iter().all(|l| l.ends_with("ada0")));
+        assert!(!print(Format::Text, Header::Never, 2).contains("L(q)"));
+    }
+}
@@ -0,0 +1,121 @@
+//! Measure the cost of regenerating and drawing the table for one frame.
+//!
+//! gstat has no library target for a benchmark to link against, so this is an
+//! ignored test instead.  Run it with
+//! "cargo test --release bench -- --ignored --nocapture".  It replays a
+//! recording of 800 providers, and for each frame reloads the statistics, fits
+//! the columns, formats the rows, and draws them to an off-screen buffer.
+//! Besides the time per frame, it reports the number of heap allocations per
+//! frame.
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    env,
+    fs,
+    hint::black_box,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+use ratatui::{
+    buffer::Buffer,
+    layout::{Rect, SegmentSize},
+    widgets::{Table, Widget},
+};
+use serde_json::json;
+
+use crate::{rows::RowCache, Cli, Columns, DataSource};
+
+/// Counts every allocation made by the process
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const ITERATIONS: usize = 1_000;
+
+/// Number of disks.  Each has three partitions.
+const DISKS: usize = 200;
+
+/// Run `f` repeatedly, and print the time and allocations per iteration.
+fn bench<F: FnMut()>(name: &str, mut f: F) {
+    // Warm up
+    f();
+    let allocs = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    let elapsed = start.elapsed();
+    let allocs = ALLOCATIONS.load(Ordering::Relaxed) - allocs;
+    println!(
+        "{:12} {:>8.2} us/frame {:>8.2} allocations/frame",
+        name,
+        elapsed.as_secs_f64() * 1e6 / ITERATIONS as f64,
+        allocs as f64 / ITERATIONS as f64
+    );
+}
+
+/// A recording of one frame, of busy disks and their partitions
+fn recording() -> String {
+    let mut items = Vec::new();
+    for i in 0..DISKS {
+        let x = i as f64;
+        let provider = |name: String, class: &str, rank: u32| {
+            json!({
+                "qd": i % 4, "ops_s": 310.0 + x, "r_s": 200.0 + x,
+                "kb_r": 64.0, "kbs_r": 12800.0 + x, "ms_r": 0.4 + x / 100.0,
+                "w_s": 110.0, "kb_w": 128.0, "kbs_w": 14080.0, "ms_w": 1.2,
+                "d_s": 0.0, "kb_d": 0.0, "kbs_d": 0.0, "ms_d": 0.0,
+                "o_s": 0.5, "ms_o": 3.0, "pct_busy": x / 2.0,
+                "rw_bal": 0.47, "name": name, "class": class, "rank": rank,
+            })
+        };
+        items.push(provider(format!("da{i}"), "DISK", 1));
+        for p in 1..=3 {
+            items.push(provider(format!("da{i}p{p}"), "PART", 2));
+        }
+    }
+    format!("{}\n", json!({ "items": items }))
+}
+
+#[test]
+#[ignore = "a benchmark"]
+fn render() {
+    let path = env::temp_dir().join("gstat-bench-render.jsonl");
+    fs::write(&path, recording()).unwrap();
+    let mut data = DataSource::replay(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    let mut cfg = Cli::default();
+    let columns = Columns::new(&mut cfg);
+    let mut rows = RowCache::default();
+    let area = Rect::new(0, 0, 200, 60);
+    let mut buf = Buffer::empty(area);
+    bench("regen+render", || {
+        data.refresh().unwrap();
+        rows.format(&columns, &data.items);
+        let widths = columns
+            .visible()
+            .map(|(k, _)| columns.constraint(k, rows.width(k)))
+            .collect::<Vec<_>>();
+        let table = Table::new(rows.rows(), &widths)
+            .segment_size(SegmentSize::LastTakesRemainder)
+            .column_spacing(0);
+        buf.reset();
+        table.render(area, &mut buf);
+        black_box(&buf);
+    });
+}
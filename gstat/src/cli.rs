@@ -0,0 +1,612 @@
+//! The command line, which is also the format of the config file
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::OsStr,
+    num::NonZeroUsize,
+    ops::BitOrAssign,
+    path::PathBuf,
+    time::Duration,
+};
+
+use clap::{
+    builder::{PossibleValue, PossibleValuesParser, TypedValueParser},
+    ValueEnum,
+};
+use clap_complete::Shell;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{column, ColumnsEnabled, Expr, Limit, Preset, Rule};
+
+/// Print statistics about GEOM disks
+///
+/// gstat-rs can be used to monitor I/O transactions of geom(4) devices.
+/// Devices that arrive while it is running are displayed, and the names of any
+/// that arrive or depart are briefly displayed at the bottom of the screen.
+///
+/// It can be configured from the command line, or on-line by interactive
+/// commands.  In either case, the configuration will be automatically saved
+/// between invocations.  It is based on the familiar gstat(8) command from
+/// FreeBSD, but with several notable changes.
+#[derive(Debug, Default, Deserialize, Serialize, clap::Parser)]
+pub struct Cli {
+    /// Only display providers that are at least 0.1% busy.
+    #[clap(short = 'a', long = "auto")]
+    pub auto:                bool,
+    /// Also display devices that aren't GEOM providers, like pass(4) and
+    /// ses(4).
+    #[clap(long = "all-devstat")]
+    pub all_devstat:         bool,
+    /// Batch mode.  Print statistics to stdout instead of displaying them
+    /// interactively.
+    ///
+    /// Every other option that selects devices and columns applies, but the
+    /// config file is not updated.  By default, a single sample is printed.
+    #[serde(skip)]
+    #[clap(short = 'b', long = "batch")]
+    pub batch:               bool,
+    /// In batch mode, exit after printing this many samples, one per update
+    /// interval.
+    #[serde(skip)]
+    #[clap(short = 'c', long = "count", requires = "batch")]
+    pub count:               Option<NonZeroUsize>,
+    /// In batch mode, exit after running for this long, like "5m".
+    ///
+    /// If -c is also given, exit at whichever limit is reached first.
+    #[serde(skip)]
+    #[clap(
+        long = "duration",
+        requires = "batch",
+        value_parser = humanize_rs::duration::parse
+    )]
+    pub duration:            Option<Duration>,
+    /// In batch mode, the output format.
+    #[serde(skip)]
+    #[clap(long = "format", requires = "batch", value_enum)]
+    pub format:              Option<Format>,
+    /// In batch mode, print the header only before the first sample, so that
+    /// logs are easier to parse.
+    #[serde(skip)]
+    #[clap(
+        short = 'n',
+        long = "no-repeat-header",
+        requires = "batch",
+        conflicts_with = "header_interval"
+    )]
+    pub no_repeat_header:    bool,
+    /// In batch mode, print the header before every this many samples, or never
+    /// if 0.  The default is 1.
+    ///
+    /// In csv format, the header is never repeated, but 0 still suppresses it.
+    #[serde(skip)]
+    #[clap(
+        long = "header-interval",
+        requires = "batch",
+        value_name = "SAMPLES"
+    )]
+    pub header_interval:     Option<usize>,
+    /// In batch mode, print exactly what FreeBSD's gstat(8) would: its columns,
+    /// headers, spacing, and choice of devices.  The config file is ignored.
+    ///
+    /// This is for scripts that parse gstat(8)'s output.  The columns are
+    /// chosen only by -d, -o, and -s.  Devices are printed in the order that
+    /// the kernel reports them, after waiting for one update interval.  -f and
+    /// -p apply only to providers, and -a hides only devices that are less than
+    /// 0.1% busy.  The configuration file is ignored, as are the options that
+    /// choose columns, sort order, or grouping.
+    ///
+    /// The options themselves are not all compatible.  In particular, -c takes
+    /// a sample count, as it does without --compat, rather than selecting
+    /// consumers as it does for gstat(8).  Scripts that run "gstat -bc" must
+    /// use "gstat -b --compat --consumers" instead.
+    #[serde(skip)]
+    #[clap(long = "compat", requires = "batch")]
+    pub compat:              bool,
+    /// With --compat, also display GEOM consumers, like gstat(8)'s -c.
+    ///
+    /// Consumers are named like "PART/ada0/ada0".
+    #[serde(skip)]
+    #[clap(long = "consumers", requires = "compat")]
+    pub consumers:           bool,
+    /// Only display devices belonging to these GEOM classes, like DISK,PART.
+    ///
+    /// Class names are case-insensitive.  Devices that are not GEOM providers
+    /// belong to a pseudo-class named after their driver, such as pass.
+    #[clap(long = "class", value_delimiter = ',')]
+    pub class:               Option<Vec<String>>,
+    /// Only display devices on these kinds of disk: hdd, ssd, or nvme.
+    ///
+    /// NVMe disks are recognized by their driver, and other disks by the
+    /// rotation rate that they report.  Partitions and other providers share
+    /// the kind of the disk beneath them.  Devices whose kind cannot be
+    /// determined are not displayed.  When the kind is known, the latency
+    /// columns are colored by what is normal for it: magenta above 20 ms for
+    /// hard disks, 2 ms for SSDs, and 0.5 ms for NVMe, and red above 50 ms,
+    /// 10 ms, and 2 ms respectively.
+    #[clap(long = "kind", value_delimiter = ',', value_enum)]
+    pub kind:                Option<Vec<Kind>>,
+    /// Scale the kB columns with unit suffixes, like "4.00G", so large values
+    /// stay readable.
+    ///
+    /// The suffixes are k, M, G, T, and P, in powers of 1024, like
+    /// zpool-iostat(8).  Sorting still uses the unscaled values.  In batch
+    /// mode, this only affects the text format.
+    #[clap(short = 'H', long = "human")]
+    pub human:               bool,
+    /// Display latencies in this unit: ms or us.
+    ///
+    /// NVMe devices' latencies are often too small to show in milliseconds.
+    /// Batch mode's CSV and JSON formats always use milliseconds.
+    #[clap(long = "latency-unit", value_enum, value_name = "UNIT")]
+    pub latency_unit:        Option<LatencyUnit>,
+    /// Display statistics for delete (BIO_DELETE) operations.
+    #[clap(short = 'd', long = "delete")]
+    pub delete:              bool,
+    /// Display one row per GEOM class, aggregating its providers.
+    ///
+    /// Rates, throughputs, queue depths, and error counts are summed over the
+    /// class's providers.  Transaction sizes and latencies are averaged,
+    /// weighted by each provider's operation rate.  %busy is that of the
+    /// busiest provider.  Devices that are not GEOM providers are grouped by
+    /// driver name.
+    #[clap(short = 'G', long = "group-by-class")]
+    pub group:               bool,
+    /// Only display devices with names matching filter, as a regex.
+    ///
+    /// The format of the regular expression is described at
+    /// https://docs.rs/regex.
+    #[clap(short = 'f', long = "filter")]
+    pub filter:              Option<String>,
+    /// Display statistics for other (BIO_FLUSH) operations.
+    #[clap(short = 'o', long = "other")]
+    pub other:               bool,
+    /// Display block size statistics
+    #[clap(short = 's', long = "size")]
+    pub size:                bool,
+    /// Only display physical providers (those with rank of 1).
+    #[clap(short = 'p', long = "physical")]
+    pub physical:            bool,
+    /// Read and save settings in this file, instead of the usual per-user
+    /// config file.
+    ///
+    /// The system-wide config file still supplies the defaults.
+    #[serde(skip)]
+    #[clap(long = "config", value_name = "FILE")]
+    pub config:              Option<PathBuf>,
+    /// Reset the config file to the system-wide defaults
+    ///
+    /// Those are the settings of the system-wide config file, if there is one,
+    /// or else the built-in ones.
+    #[serde(skip)]
+    #[clap(long = "reset-config")]
+    pub reset_config:        bool,
+    /// Reverse the sort
+    #[clap(short = 'r', long = "reverse")]
+    pub reverse:             bool,
+    /// Sort by the named column.  The name should match the column header.
+    #[clap(
+        short = 'S',
+        long = "sort",
+        value_parser = sort_keys(),
+        value_name = "COLUMN"
+    )]
+    pub sort:                Option<String>,
+    /// Display a preset combination of columns and sort order, like
+    /// "ssd-latency" or one saved from the presets dialog.  Not saved in the
+    /// config file.
+    ///
+    /// The built-in presets are ssd-latency, the latency of every kind of
+    /// operation, sorted by write latency; hdd-throughput, transfer sizes and
+    /// throughput, sorted by %busy; and trim-debug, delete (TRIM) statistics
+    /// alongside writes, sorted by deletes per second.  Each sorts the worst
+    /// devices first.  Presets saved from the presets dialog may also be named.
+    /// The preset's columns and sort order are not saved in the configuration
+    /// file.
+    #[serde(skip)]
+    #[clap(long = "preset", value_name = "NAME")]
+    pub preset:              Option<String>,
+    /// Presets saved by the user, by name
+    #[serde(default)]
+    #[clap(skip)]
+    pub presets:             BTreeMap<String, Preset>,
+    /// Bitfield of columns to enable
+    #[serde(default = "default_columns_enabled")]
+    #[clap(skip)]
+    pub columns:             Option<ColumnsEnabled>,
+    /// Display exactly these columns, like "L(q),r/s,w/s,%busy,Name", named by
+    /// their headers.  Not saved in the config file.
+    ///
+    /// Case is ignored, and custom columns from --expr are named by their
+    /// names.  The display order is unaffected.  The choice applies to batch
+    /// mode too.
+    #[serde(skip)]
+    #[clap(long = "columns", value_delimiter = ',', value_name = "COLUMN")]
+    pub only_columns:        Option<Vec<String>>,
+    /// Display update interval, in microseconds or with the specified unit
+    ///
+    /// Adding a suffix of s, ms, or us (the default) indicates that the update
+    /// interval is specified in seconds, milliseconds, or microseconds,
+    /// respectively.
+    #[clap(
+        short = 'I',
+        long = "interval",
+        value_parser = Cli::duration_from_str
+    )]
+    pub interval:            Option<Duration>,
+    /// Display statistics accumulated since gstat started, rather than per
+    /// interval.
+    ///
+    /// This can be useful to measure the totals for a benchmark run.
+    #[clap(short = 'T', long = "cumulative")]
+    pub cumulative:          bool,
+    /// Don't display the first sample, which covers the time since each
+    /// device's creation, but wait one interval before the first display, like
+    /// gstat(8).
+    ///
+    /// It has no effect with --replay.
+    #[clap(long = "skip-first")]
+    pub skip_first:          bool,
+    /// Draw on the terminal's main screen rather than the alternate screen,
+    /// leaving the last display in the scrollback after exiting.
+    #[clap(long = "no-alternate-screen")]
+    pub no_alternate_screen: bool,
+    /// Pin a row of system-wide totals above the table.
+    ///
+    /// The row is followed by a sparkline of the total throughput over recent
+    /// update intervals.  Only physical providers are counted, so that each I/O
+    /// is only counted once.  The totals row is not affected by sorting or
+    /// filtering.
+    #[clap(long = "totals")]
+    pub totals:              bool,
+    /// Display providers indented beneath the ones they're built upon.
+    ///
+    /// This is like "geom -t".  A provider with several parents, such as a
+    /// mirror, is only displayed beneath the first.  When sorting, siblings are
+    /// sorted among themselves.  In this mode, all devices are displayed in a
+    /// single column.
+    #[clap(long = "tree")]
+    pub tree:                bool,
+    /// Display GEOM labels, like gpt/ and diskid/ names, beside the devices
+    /// that they label rather than as separate rows.
+    ///
+    /// The names of the LABEL class's providers are displayed in parentheses.
+    /// The -f filter also matches these names, so "-f gpt/mydisk" displays the
+    /// partition labelled gpt/mydisk.  In tree view, providers built upon a
+    /// label are displayed beneath the labelled provider.
+    #[clap(long = "aliases")]
+    pub aliases:             bool,
+    /// Average the statistics over this many update intervals.
+    ///
+    /// The default is 1.  Unlike most options, it is not saved in the config
+    /// file.
+    #[serde(skip)]
+    #[clap(short = 'w', long = "window")]
+    pub window:              Option<NonZeroUsize>,
+    /// Half-life of the recent peak throughput column's decay, like "30s".  The
+    /// default is 10 seconds.
+    ///
+    /// The "pk kB/s" column displays each device's total throughput decaying
+    /// from its recent peak.  Whenever the throughput rises above the peak, the
+    /// peak rises to match it.  Otherwise, its excess over the current
+    /// throughput halves every DURATION, so that a short burst of activity
+    /// remains visible for a while after it ends.  The default is 10s.  The
+    /// column is blank in cumulative mode.
+    #[clap(
+        long = "peak-decay",
+        value_parser = humanize_rs::duration::parse,
+        value_name = "DURATION"
+    )]
+    pub peak_decay:          Option<Duration>,
+    /// Record each interval's statistics to this file
+    ///
+    /// Latencies that were unusually high are listed in each device's
+    /// "outliers" field, and are underlined again when the session is replayed.
+    #[serde(skip)]
+    #[clap(long = "record", conflicts_with = "replay", value_name = "FILE")]
+    pub record:              Option<PathBuf>,
+    /// Play back a session previously saved with --record
+    ///
+    /// One recorded interval is displayed per update interval.  In batch mode,
+    /// the recording's markers are printed as marker rows, each before the
+    /// interval that followed it.  In csv format, a marker row has the marker's
+    /// time and its label in the Name column.  In json format, it's an object
+    /// with "time" and "marker" fields.
+    #[serde(skip)]
+    #[clap(long = "replay", value_name = "FILE")]
+    pub replay:              Option<PathBuf>,
+    /// Ring the terminal bell when any device's statistic crosses a threshold,
+    /// like "ms_r>100".  May be repeated.
+    ///
+    /// EXPR consists of a statistic, one of the operators <, <=, >, or >=, and
+    /// a number, such as "ms_r>100".  Valid statistics are qd, ops_s, r_s,
+    /// kb_r, kbs_r, ms_r, w_s, kb_w, kbs_w, ms_w, d_s, kb_d, kbs_d, ms_d, o_s,
+    /// ms_o, pct_busy, errs, rank; rw_bal, the fraction of the bytes
+    /// transferred that were read; pct_iops and pct_kbs, the saturation
+    /// columns' percentages of each device's limits; tbw_d, the write
+    /// throughput extrapolated to terabytes per day; sq, the number of requests
+    /// waiting in the cam_iosched(4) queues; ms_sq, the I/O scheduler's moving
+    /// average latency; avg_qd, the number of outstanding transactions averaged
+    /// over the interval; and peak_kbs, the recent peak throughput described
+    /// under --peak-decay.  Unlike pct_busy, which cannot exceed 100% however
+    /// many transactions are outstanding at once, avg_qd reflects the
+    /// parallelism of devices like NVMe drives.
+    ///
+    /// Each device is reported only once per episode; it will not be reported
+    /// again until it has gone at least one interval without crossing the
+    /// threshold.  Alerts are not saved in the configuration file.
+    #[serde(skip)]
+    #[clap(
+        long = "alert",
+        value_parser = str::parse::<Rule>,
+        value_name = "EXPR"
+    )]
+    pub alert:               Vec<Rule>,
+    /// Only alert after the threshold has been crossed for this many
+    /// consecutive intervals.
+    ///
+    /// The default is 1.
+    #[serde(skip)]
+    #[clap(long = "alert-after", value_name = "INTERVALS")]
+    pub alert_after:         Option<NonZeroUsize>,
+    /// Also log alerts with syslog(3).
+    #[serde(skip)]
+    #[clap(long = "alert-syslog")]
+    pub alert_syslog:        bool,
+    /// Add a column computed from other statistics, like
+    /// "rw_ratio = r_s / (w_s + 1)".  May be repeated.
+    ///
+    /// EXPR is an arithmetic expression using +, -, *, /, parentheses, numbers,
+    /// and the statistics listed under --alert.  For example,
+    /// "rw_ratio = r_s / (w_s + 1)" or "kbs = kbs_r + kbs_w".  The column is
+    /// left blank if the result is not a finite number, or if it uses a
+    /// statistic that the device does not report.  When grouping by class,
+    /// custom columns are computed from the class's aggregate statistics.
+    /// Custom columns are displayed just before the Name column, and can be
+    /// sorted with the + and - commands.
+    ///
+    /// Custom columns are not saved in the config file.
+    #[serde(skip)]
+    #[clap(
+        long = "expr",
+        value_parser = str::parse::<Expr>,
+        value_name = "NAME=EXPR"
+    )]
+    pub expr:                Vec<Expr>,
+    /// Rated performance of each device, by name.  Only set in the config file.
+    #[serde(default)]
+    #[clap(skip)]
+    pub limits:              HashMap<String, Limit>,
+    /// The device that was selected when gstat last exited
+    #[serde(default)]
+    #[clap(skip)]
+    pub selected:            Option<String>,
+    /// Whether the display was paused when gstat last exited
+    #[serde(default)]
+    #[clap(skip)]
+    pub paused:              bool,
+    /// How many columns were scrolled off to the left when gstat last exited
+    #[serde(default)]
+    #[clap(skip)]
+    pub scroll:              usize,
+    #[serde(skip)]
+    #[clap(subcommand)]
+    pub command:             Option<Command>,
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum Command {
+    /// Write a bundle of diagnostic information to FILE and exit, for
+    /// attaching to a bug report.
+    ///
+    /// The bundle is a tar archive.  It contains the versions of gstat and the
+    /// kernel, the GEOM configuration, every raw devstat structure from two
+    /// snapshots taken one update interval apart, and the statistics computed
+    /// from them.  Please attach it to bug reports about incorrect
+    /// statistics.  With --sanitize, disk serial numbers and LUN identifiers
+    /// are replaced with placeholders.
+    BugReport {
+        /// The file to write
+        file:     PathBuf,
+        /// Replace disk serial numbers and LUN identifiers with placeholders.
+        #[clap(long = "sanitize")]
+        sanitize: bool,
+    },
+    /// Print a shell completion script to stdout.
+    ///
+    /// The script doesn't complete device names for the -f option.  A
+    /// generated script could only list the devices of the host that generated
+    /// it, and -f takes a regular expression rather than a name.
+    Completions {
+        /// The shell to generate completions for
+        shell: Shell,
+    },
+    /// Print the GEOM topology, every class, geom, provider, and consumer, and
+    /// exit.
+    ///
+    /// Each geom is listed with its class and rank.  Beneath it are its
+    /// consumers, with the providers they're attached to, and its providers,
+    /// with their sizes, sector sizes, and access modes.  Beneath each provider
+    /// are the geoms built upon it, like a disk's partition table.  A geom with
+    /// several consumers, like a mirror, is only listed beneath the provider of
+    /// its first.  With --json, the same information is printed as JSON, along
+    /// with each object's configuration, like that of "sysctl
+    /// kern.geom.confxml".
+    Tree {
+        /// Print JSON instead of an indented tree
+        #[clap(long = "json")]
+        json: bool,
+    },
+}
+
+impl Cli {
+    pub fn duration_from_str(
+        s: &str,
+    ) -> std::result::Result<Duration, humanize_rs::ParseError> {
+        if let Ok(us) = s.parse::<u64>() {
+            // With no units, default to microseconds
+            Ok(Duration::from_micros(us))
+        } else {
+            humanize_rs::duration::parse(s)
+        }
+    }
+}
+
+impl BitOrAssign for Cli {
+    #[allow(clippy::or_fun_call)]
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.auto |= rhs.auto;
+        self.all_devstat |= rhs.all_devstat;
+        self.batch = rhs.batch;
+        self.count = rhs.count;
+        self.duration = rhs.duration;
+        self.format = rhs.format;
+        self.no_repeat_header = rhs.no_repeat_header;
+        self.header_interval = rhs.header_interval;
+        self.compat = rhs.compat;
+        self.consumers = rhs.consumers;
+        self.class = rhs.class.or(self.class.take());
+        self.kind = rhs.kind.or(self.kind.take());
+        self.cumulative |= rhs.cumulative;
+        self.skip_first |= rhs.skip_first;
+        self.no_alternate_screen |= rhs.no_alternate_screen;
+        self.delete |= rhs.delete;
+        self.human |= rhs.human;
+        self.latency_unit = rhs.latency_unit.or(self.latency_unit.take());
+        self.filter = rhs.filter.or(self.filter.take());
+        self.group |= rhs.group;
+        self.other |= rhs.other;
+        self.size |= rhs.size;
+        self.interval = rhs.interval.or(self.interval.take());
+        self.window = rhs.window;
+        self.peak_decay = rhs.peak_decay.or(self.peak_decay.take());
+        self.record = rhs.record.or(self.record.take());
+        self.replay = rhs.replay.or(self.replay.take());
+        self.alert = rhs.alert;
+        self.alert_after = rhs.alert_after;
+        self.alert_syslog = rhs.alert_syslog;
+        self.expr = rhs.expr;
+        self.physical |= rhs.physical;
+        self.reverse |= rhs.reverse;
+        self.sort = rhs.sort.or(self.sort.take());
+        self.totals |= rhs.totals;
+        self.tree |= rhs.tree;
+        self.aliases |= rhs.aliases;
+        self.columns = rhs.columns.or(self.columns.take());
+        self.only_columns = rhs.only_columns;
+        self.preset = rhs.preset;
+        self.presets.extend(rhs.presets);
+        self.limits.extend(rhs.limits);
+        self.selected = rhs.selected.or(self.selected.take());
+        self.paused |= rhs.paused;
+        self.scroll = self.scroll.max(rhs.scroll);
+    }
+}
+
+fn default_columns_enabled() -> Option<ColumnsEnabled> {
+    Some(Default::default())
+}
+
+/// Valid arguments to `--sort`: every built-in column's header.  Padding is
+/// ignored, on both the headers and the argument.
+fn sort_keys() -> SortKeyParser {
+    SortKeyParser(PossibleValuesParser::new(
+        column::BUILTIN.iter().map(|spec| spec.header.trim()),
+    ))
+}
+
+/// Parses `--sort`'s argument, after trimming it, as one of the trimmed column
+/// headers.
+#[derive(Clone)]
+struct SortKeyParser(PossibleValuesParser);
+
+impl TypedValueParser for SortKeyParser {
+    type Value = String;
+
+    fn parse_ref(
+        &self,
+        cmd: &clap::Command,
+        arg: Option<&clap::Arg>,
+        value: &OsStr,
+    ) -> std::result::Result<String, clap::Error> {
+        let value = value.to_str().map_or(value, |s| OsStr::new(s.trim()));
+        self.0.parse_ref(cmd, arg, value)
+    }
+
+    fn possible_values(
+        &self,
+    ) -> Option<Box<dyn Iterator<Item = PossibleValue> + '_>> {
+        self.0.possible_values()
+    }
+}
+
+/// Output formats for batch mode
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    Deserialize,
+    Serialize,
+    Eq,
+    PartialEq,
+    ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// Aligned columns, like gstat(8)'s batch mode, with a header line before
+    /// each sample unless -n or --header-interval says otherwise
+    #[default]
+    Text,
+    /// Comma-separated values, with a single header line.  The first field of
+    /// each line is the time of the sample, in seconds since the epoch.
+    Csv,
+    /// One JSON object per line for each sample, with its time and an array of
+    /// devices.  Each device's fields are named after the column headers, and
+    /// values that a device does not report are null.
+    Json,
+}
+
+/// The kind of storage behind a disk, which determines what latencies are
+/// normal for it
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Deserialize,
+    Serialize,
+    Eq,
+    Ord,
+    PartialEq,
+    PartialOrd,
+    ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    /// Rotating hard disk
+    Hdd,
+    /// Solid-state disk attached by ATA or SCSI
+    Ssd,
+    /// Solid-state disk attached by NVMe
+    Nvme,
+}
+
+/// The unit in which to display the latency columns
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    Deserialize,
+    Serialize,
+    Eq,
+    PartialEq,
+    ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum LatencyUnit {
+    /// Milliseconds, with one decimal place
+    #[default]
+    Ms,
+    /// Microseconds, with one decimal place.  Useful for NVMe devices, whose
+    /// latencies are often well under a millisecond.
+    Us,
+}
@@ -0,0 +1,279 @@
+//! The built-in columns
+//!
+//! Everything that differs from one built-in column to the next is in
+//! [`BUILTIN`], so adding a column means adding a row there and, if it
+//! displays a new statistic, teaching `Element::stat` its name.
+
+/// How a column's values are formatted
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Unit {
+    /// A plain number, with this many digits after the decimal point
+    Num(usize),
+    /// A quantity of kB, scaled by `--human`
+    Kb,
+    /// A latency in ms, displayed in the chosen `LatencyUnit`
+    Latency,
+    /// Left-aligned text
+    Text,
+}
+
+impl Unit {
+    /// Digits to display after the decimal point, when not scaled
+    pub const fn precision(self) -> usize {
+        match self {
+            Unit::Num(prec) => prec,
+            Unit::Latency => 1,
+            Unit::Kb | Unit::Text => 0,
+        }
+    }
+}
+
+/// One built-in column
+#[derive(Debug)]
+pub struct Spec {
+    /// As listed in the column selection dialog
+    pub name:    &'static str,
+    pub header:  &'static str,
+    /// The statistic displayed, as named by `Element::stat`, or for text
+    /// columns by `Element::text`
+    pub stat:    &'static str,
+    /// This column's position in `ColumnsEnabled`.  It may differ from its
+    /// display position, since the Name column must always be last.
+    pub bit:     u32,
+    pub unit:    Unit,
+    /// The most digits expected before the decimal point, or for text the
+    /// most characters.  Columns widen to fit any wider values.
+    pub digits:  u16,
+    /// Displayed unless the user says otherwise
+    pub default: bool,
+}
+
+const fn spec(
+    name: &'static str,
+    header: &'static str,
+    stat: &'static str,
+    bit: u32,
+    unit: Unit,
+    digits: u16,
+    default: bool,
+) -> Spec {
+    Spec {
+        name,
+        header,
+        stat,
+        bit,
+        unit,
+        digits,
+        default,
+    }
+}
+
+/// Every built-in column, in display order, except that Name is always
+/// displayed last
+pub const BUILTIN: [Spec; 31] = [
+    spec("Queue depth", "L(q)", "qd", 0, Unit::Num(0), 4, true),
+    spec("IOPs", " ops/s", "ops_s", 1, Unit::Num(0), 6, true),
+    spec("Read IOPs", "   r/s", "r_s", 2, Unit::Num(0), 6, true),
+    spec("Read size", "kB/r", "kb_r", 3, Unit::Kb, 5, false),
+    spec("Read throughput", "kB/s r", "kbs_r", 4, Unit::Kb, 7, true),
+    spec("Read latency", "  ms/r", "ms_r", 5, Unit::Latency, 4, true),
+    spec("Write IOPs", "   w/s", "w_s", 6, Unit::Num(0), 6, true),
+    spec("Write size", "kB/w", "kb_w", 7, Unit::Kb, 5, false),
+    spec("Write throughput", "kB/s w", "kbs_w", 8, Unit::Kb, 7, true),
+    spec("Write latency", "  ms/w", "ms_w", 9, Unit::Latency, 4, true),
+    spec("Delete IOPs", "   d/s", "d_s", 10, Unit::Num(0), 6, false),
+    spec("Delete size", "kB/d", "kb_d", 11, Unit::Kb, 5, false),
+    spec(
+        "Delete throughput",
+        "kB/s d",
+        "kbs_d",
+        12,
+        Unit::Kb,
+        7,
+        false,
+    ),
+    spec(
+        "Delete latency",
+        "  ms/d",
+        "ms_d",
+        13,
+        Unit::Latency,
+        4,
+        false,
+    ),
+    spec("Other IOPs", "   o/s", "o_s", 14, Unit::Num(0), 6, false),
+    spec(
+        "Other latency",
+        "  ms/o",
+        "ms_o",
+        15,
+        Unit::Latency,
+        4,
+        false,
+    ),
+    spec(
+        "Percent busy",
+        " %busy",
+        "pct_busy",
+        16,
+        Unit::Num(1),
+        3,
+        true,
+    ),
+    // Displayed as a bar, but printed as the fraction of bytes that were read
+    spec(
+        "Read/write balance",
+        "   r/w",
+        "rw_bal",
+        18,
+        Unit::Num(2),
+        1,
+        false,
+    ),
+    spec("Errors", "  errs", "errs", 19, Unit::Num(0), 6, false),
+    spec("GEOM rank", "  rank", "rank", 20, Unit::Num(0), 2, false),
+    spec("GEOM class", " class", "class", 21, Unit::Text, 9, false),
+    spec(
+        "IOPs saturation",
+        " %iops",
+        "pct_iops",
+        22,
+        Unit::Num(1),
+        3,
+        false,
+    ),
+    spec(
+        "Throughput saturation",
+        " %kB/s",
+        "pct_kbs",
+        23,
+        Unit::Num(1),
+        3,
+        false,
+    ),
+    spec(
+        "Serial number",
+        " serial",
+        "serial",
+        24,
+        Unit::Text,
+        19,
+        false,
+    ),
+    spec("Disk kind", " kind", "kind", 25, Unit::Text, 4, false),
+    spec(
+        "Writes per day",
+        " TBW/d",
+        "tbw_d",
+        26,
+        Unit::Num(2),
+        3,
+        false,
+    ),
+    spec(
+        "Scheduler queue depth",
+        "L(sq)",
+        "sq",
+        27,
+        Unit::Num(0),
+        4,
+        false,
+    ),
+    spec(
+        "Scheduler latency",
+        " ms/sq",
+        "ms_sq",
+        28,
+        Unit::Latency,
+        4,
+        false,
+    ),
+    spec(
+        "Average queue depth",
+        "L(avg)",
+        "avg_qd",
+        29,
+        Unit::Num(1),
+        4,
+        false,
+    ),
+    spec(
+        "Peak throughput",
+        "pk kB/s",
+        "peak_kbs",
+        30,
+        Unit::Kb,
+        7,
+        false,
+    ),
+    spec("Name", "Name", "name", 17, Unit::Text, 9, true),
+];
+
+/// The index in [`BUILTIN`] of the column displaying `stat`
+pub const fn find(stat: &str) -> usize {
+    let mut i = 0;
+    while i < BUILTIN.len() {
+        if const_eq(BUILTIN[i].stat, stat) {
+            return i;
+        }
+        i += 1;
+    }
+    panic!("no such column");
+}
+
+/// `str`'s `==` isn't usable in a const fn
+const fn const_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// The bits of the columns that are displayed by default
+pub const fn default_enabled() -> u64 {
+    let mut bits = 0;
+    let mut i = 0;
+    while i < BUILTIN.len() {
+        if BUILTIN[i].default {
+            bits |= 1 << BUILTIN[i].bit;
+        }
+        i += 1;
+    }
+    bits
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    /// Each column must have its own bit, stat, and header
+    #[test]
+    fn unique() {
+        for (i, a) in BUILTIN.iter().enumerate() {
+            for b in &BUILTIN[i + 1..] {
+                assert_ne!(a.bit, b.bit, "{}", a.name);
+                assert_ne!(a.stat, b.stat, "{}", a.name);
+                assert_ne!(a.header.trim(), b.header.trim(), "{}", a.name);
+            }
+        }
+    }
+
+    #[test]
+    fn default_enabled() {
+        assert_eq!(super::default_enabled(), 0x30377);
+    }
+
+    #[test]
+    fn find() {
+        assert_eq!(super::find("qd"), 0);
+        assert_eq!(BUILTIN[super::find("name")].header, "Name");
+    }
+}
@@ -0,0 +1,303 @@
+//! Batch output that matches FreeBSD's gstat(8) exactly
+//!
+//! Scripts that scrape gstat(8)'s batch output find its fields by position.  So
+//! with `--compat`, gstat-rs ignores its own columns, sorting, and config file,
+//! and prints just what gstat(8) would: the same columns in the same order,
+//! padded the same way, for the same devices, in the order that the kernel
+//! reports them.
+use std::{
+    fmt::Write as _,
+    io::{self, Write},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use regex::Regex;
+
+use crate::{batch, Cli, DataSource, Element};
+
+/// Would gstat(8) display this element?
+///
+/// Unlike gstat-rs's own filters, `-f` and `-p` only apply to providers, and
+/// `-a` hides only devices that are less than 0.1% busy.
+fn visible(elem: &Element, cfg: &Cli, filter: Option<&Regex>) -> bool {
+    let idle = cfg.auto && elem.pct_busy < 0.1;
+    if elem.consumer {
+        return cfg.consumers && !idle;
+    }
+    // Devices that aren't GEOM providers have no rank, and gstat(8) never
+    // displays them.
+    elem.rank > 0
+        && (!cfg.physical || elem.rank == 1)
+        && filter.map_or(true, |f| f.is_match(&elem.name))
+        && !idle
+}
+
+/// Append the two header lines, the first of which gives the interval.
+fn header(out: &mut String, cfg: &Cli, dt: f64, interval: Duration) {
+    write!(out, "dT: {dt:5.3}s  w: {:.3}s", interval.as_secs_f64()).unwrap();
+    if let Some(filter) = cfg.filter.as_deref() {
+        write!(out, "  filter: {filter}").unwrap();
+    }
+    out.push('\n');
+    out.push_str(" L(q)  ops/s   ");
+    if cfg.size {
+        out.push_str(" r/s     kB   kBps   ms/r   ");
+        out.push_str(" w/s     kB   kBps   ms/w   ");
+    } else {
+        out.push_str(" r/s   kBps   ms/r   ");
+        out.push_str(" w/s   kBps   ms/w   ");
+    }
+    if cfg.delete {
+        if cfg.size {
+            out.push_str(" d/s     kB   kBps   ms/d   ");
+        } else {
+            out.push_str(" d/s   kBps   ms/d   ");
+        }
+    }
+    if cfg.other {
+        out.push_str(" o/s   ms/o   ");
+    }
+    out.push_str("%busy Name\n");
+}
+
+/// Append a latency, with more decimal places the shorter it is
+fn latency(out: &mut String, ms: f64) {
+    if ms > 1e3 {
+        write!(out, " {ms:6.0}")
+    } else if ms > 1e0 {
+        write!(out, " {ms:6.1}")
+    } else {
+        write!(out, " {ms:6.3}")
+    }
+    .unwrap()
+}
+
+/// Append a count or rate, rounded to an integer
+fn num(out: &mut String, v: f64) {
+    write!(out, " {v:6.0}").unwrap()
+}
+
+/// Append one element's line.
+fn row(out: &mut String, cfg: &Cli, elem: &Element) {
+    write!(out, " {:4}", elem.qd).unwrap();
+    num(out, elem.ops_s);
+    num(out, elem.r_s);
+    if cfg.size {
+        num(out, elem.kb_r);
+    }
+    num(out, elem.kbs_r);
+    latency(out, elem.ms_r);
+    num(out, elem.w_s);
+    if cfg.size {
+        num(out, elem.kb_w);
+    }
+    num(out, elem.kbs_w);
+    latency(out, elem.ms_w);
+    if cfg.delete {
+        num(out, elem.d_s);
+        if cfg.size {
+            num(out, elem.kb_d);
+        }
+        num(out, elem.kbs_d);
+        latency(out, elem.ms_d);
+    }
+    if cfg.other {
+        num(out, elem.o_s);
+        latency(out, elem.ms_o);
+    }
+    // In batch mode, gstat(8) separates %busy from the name with two spaces
+    // rather than a bar.
+    writeln!(out, " {:6.1}  {}", elem.pct_busy, elem.name).unwrap();
+}
+
+/// Append one sample: the headers, if `with_header`, followed by the visible
+/// elements.
+fn sample<'a>(
+    out: &mut String,
+    cfg: &Cli,
+    items: impl Iterator<Item = &'a Element>,
+    dt: f64,
+    interval: Duration,
+    with_header: bool,
+) {
+    if with_header {
+        header(out, cfg, dt, interval);
+    }
+    for elem in items {
+        row(out, cfg, elem);
+    }
+}
+
+/// Print statistics like gstat(8)'s batch mode, until the count or duration
+/// limit is reached.
+pub fn run(
+    cfg: &Cli,
+    mut data: DataSource,
+    filter: Option<&Regex>,
+    interval: Duration,
+) -> Result<()> {
+    // Like gstat(8), wait for one interval, so the first sample covers just
+    // that interval rather than the time since each device's creation.
+    let mut then = Instant::now();
+    if cfg.replay.is_none() {
+        thread::sleep(interval);
+        data.refresh()?;
+    }
+    let count = batch::count(cfg);
+    let header = batch::Header::new(cfg);
+    let start = Instant::now();
+    let mut stdout = io::stdout().lock();
+    let mut buf = String::new();
+    let mut samples = 0;
+    loop {
+        let now = Instant::now();
+        let dt = (now - then).as_secs_f64();
+        then = now;
+        buf.clear();
+        let items = data.items.iter().filter(|e| visible(e, cfg, filter));
+        sample(&mut buf, cfg, items, dt, interval, header.due(samples));
+        match stdout
+            .write_all(buf.as_bytes())
+            .and_then(|_| stdout.flush())
+        {
+            // The reader, like head(1), has seen enough
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => break,
+            r => r.context("writing to stdout")?,
+        }
+        samples += 1;
+        if count.is_some_and(|c| samples >= c.get())
+            || cfg.duration.is_some_and(|d| start.elapsed() + interval > d)
+        {
+            break;
+        }
+        thread::sleep(interval);
+        data.refresh()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+    use crate::group;
+
+    fn elem(name: &str, rank: u32) -> Element {
+        let mut elem = group::total(&[]);
+        elem.name = name.into();
+        elem.rank = rank;
+        elem
+    }
+
+    #[test]
+    fn header() {
+        let mut cfg = Cli::default();
+        let mut out = String::new();
+        super::header(&mut out, &cfg, 1.0014, Duration::from_secs(1));
+        assert_eq!(
+            out,
+            "dT: 1.001s  w: 1.000s\n L(q)  ops/s    r/s   kBps   ms/r    w/s   \
+             kBps   ms/w   %busy Name\n"
+        );
+
+        cfg.delete = true;
+        cfg.other = true;
+        cfg.size = true;
+        cfg.filter = Some("^da".to_owned());
+        out.clear();
+        super::header(&mut out, &cfg, 0.5, Duration::from_millis(500));
+        assert_eq!(
+            out,
+            "dT: 0.500s  w: 0.500s  filter: ^da\n L(q)  ops/s    r/s     kB   \
+             kBps   ms/r    w/s     kB   kBps   ms/w    d/s     kB   kBps   \
+             ms/d    o/s   ms/o   %busy Name\n"
+        );
+    }
+
+    /// Short latencies get more decimal places
+    #[test]
+    fn latency() {
+        let mut out = String::new();
+        for ms in [0.25, 12.34, 2345.6] {
+            super::latency(&mut out, ms);
+        }
+        assert_eq!(out, "  0.250   12.3   2346");
+    }
+
+    /// Each field lines up beneath its header
+    #[test]
+    fn row() {
+        let mut cfg = Cli::default();
+        // As long as the "Name" header, so the lines are the same length
+        let mut ada0 = elem("ada0", 1);
+        ada0.qd = 2;
+        ada0.ops_s = 310.0;
+        ada0.r_s = 200.0;
+        ada0.kbs_r = 12800.0;
+        ada0.ms_r = 0.4;
+        ada0.w_s = 110.0;
+        ada0.kbs_w = 14080.0;
+        ada0.ms_w = 1.3;
+        ada0.pct_busy = 42.0;
+        let mut out = String::new();
+        super::header(&mut out, &cfg, 1.0, Duration::from_secs(1));
+        super::row(&mut out, &cfg, &ada0);
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[2],
+            "    2    310    200  12800  0.400    110  14080    1.3   42.0  \
+             ada0"
+        );
+        assert_eq!(lines[1].len(), lines[2].len());
+
+        cfg.size = true;
+        cfg.delete = true;
+        cfg.other = true;
+        out.clear();
+        super::header(&mut out, &cfg, 1.0, Duration::from_secs(1));
+        super::row(&mut out, &cfg, &ada0);
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines[1].len(), lines[2].len());
+    }
+
+    /// -f and -p only apply to providers, and consumers need --consumers
+    #[test]
+    fn visible() {
+        let mut cfg = Cli::default();
+        let filter = Regex::new("^da").unwrap();
+        let da0 = elem("da0", 1);
+        let da0p1 = elem("da0p1", 2);
+        let ada0 = elem("ada0", 1);
+        let pass0 = elem("pass0", 0);
+        let mut cons = elem("PART/da0/da0", 0);
+        cons.consumer = true;
+
+        assert!(super::visible(&da0p1, &cfg, None));
+        assert!(!super::visible(&pass0, &cfg, None));
+        assert!(!super::visible(&cons, &cfg, None));
+        assert!(!super::visible(&ada0, &cfg, Some(&filter)));
+
+        cfg.consumers = true;
+        cfg.physical = true;
+        assert!(super::visible(&da0, &cfg, Some(&filter)));
+        assert!(!super::visible(&da0p1, &cfg, Some(&filter)));
+        assert!(super::visible(&cons, &cfg, Some(&filter)));
+
+        cfg.auto = true;
+        assert!(!super::visible(&cons, &cfg, None));
+        let mut busy = elem("da1", 1);
+        busy.pct_busy = 0.1;
+        assert!(super::visible(&busy, &cfg, None));
+    }
+
+    /// -I takes the same arguments as gstat(8)'s
+    #[test]
+    fn interval() {
+        let parse = |s| Cli::duration_from_str(s).unwrap();
+        assert_eq!(parse("1000000"), Duration::from_secs(1));
+        assert_eq!(parse("250us"), Duration::from_micros(250));
+        assert_eq!(parse("500ms"), Duration::from_millis(500));
+        assert_eq!(parse("2s"), Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,228 @@
+//! Locating, layering, and saving the configuration file
+//!
+//! Settings come from three layers, each overriding the one before: the
+//! built-in defaults, the system-wide file at [`SYSTEM`], and the user's own
+//! file.  The user's file is chosen by `--config`, or else is in the XDG
+//! configuration directory, honoring `$XDG_CONFIG_HOME`.  Only the user's
+//! file is ever written, and only with the settings that differ from the
+//! layers below it.
+use std::{
+    fs,
+    io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde::{de::DeserializeOwned, Serialize};
+use toml::Value;
+
+/// Shared defaults for every user on the system
+pub const SYSTEM: &str = "/usr/local/etc/gstat-rs.toml";
+
+/// The user's configuration file: `explicit` if given, or else the usual
+/// per-user location.
+pub fn path(explicit: Option<&Path>) -> Result<PathBuf> {
+    match explicit {
+        Some(p) => Ok(p.to_owned()),
+        None => confy::get_configuration_file_path("gstat-rs", None)
+            .context("locating config file"),
+    }
+}
+
+/// Load the settings, layering the system-wide file and then the user's file
+/// at `path` over the defaults.  Either file may be missing, or may contain
+/// only some of the settings.
+pub fn load<T>(path: &Path) -> Result<T>
+where
+    T: Default + DeserializeOwned + Serialize,
+{
+    let mut value = base::<T>()?;
+    if let Some(layer) = read(path)? {
+        merge(&mut value, layer);
+    }
+    value
+        .try_into()
+        .with_context(|| format!("parsing config file {}", path.display()))
+}
+
+/// Load the settings without the user's file, as `--reset-config` does.
+pub fn load_system<T>() -> Result<T>
+where
+    T: Default + DeserializeOwned + Serialize,
+{
+    base::<T>()?
+        .try_into()
+        .with_context(|| format!("parsing config file {SYSTEM}"))
+}
+
+/// Save the settings to the user's file at `path`.  Only those that differ
+/// from the system-wide file, or from the defaults, are written, so that
+/// later changes to the system-wide file still take effect.
+pub fn store<T: Default + Serialize>(path: &Path, cfg: &T) -> Result<()> {
+    let value = Value::try_from(cfg).context("serializing config")?;
+    let value = overrides(value, &base::<T>()?)
+        .unwrap_or_else(|| Value::Table(Default::default()));
+    confy::store_path(path, value)
+        .with_context(|| format!("writing config file {}", path.display()))
+}
+
+/// The defaults, overridden by the system-wide file
+fn base<T: Default + Serialize>() -> Result<Value> {
+    let mut value =
+        Value::try_from(T::default()).context("serializing defaults")?;
+    if let Some(layer) = read(Path::new(SYSTEM))? {
+        merge(&mut value, layer);
+    }
+    Ok(value)
+}
+
+/// Read one layer, if it exists.
+fn read(path: &Path) -> Result<Option<Value>> {
+    match fs::read_to_string(path) {
+        Ok(s) => s
+            .parse::<Value>()
+            .map(Some)
+            .with_context(|| format!("parsing config file {}", path.display())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e)
+            .with_context(|| format!("reading config file {}", path.display())),
+    }
+}
+
+/// Override `base` with `layer`.  Tables, like `presets`, are merged key by
+/// key, so a user may add to them without repeating the system-wide entries.
+fn merge(base: &mut Value, layer: Value) {
+    match (base, layer) {
+        (Value::Table(base), Value::Table(layer)) => {
+            for (k, v) in layer {
+                match base.get_mut(&k) {
+                    Some(b) => merge(b, v),
+                    None => {
+                        base.insert(k, v);
+                    }
+                }
+            }
+        }
+        (base, layer) => *base = layer,
+    }
+}
+
+/// The parts of `value` that differ from `base`, if any.  It's the inverse of
+/// [`merge`]: merging the result into `base` yields `value` again.
+fn overrides(value: Value, base: &Value) -> Option<Value> {
+    match (value, base) {
+        (Value::Table(value), Value::Table(base)) => {
+            let table = value
+                .into_iter()
+                .filter_map(|(k, v)| match base.get(&k) {
+                    Some(b) => overrides(v, b).map(|v| (k, v)),
+                    None => Some((k, v)),
+                })
+                .collect::<toml::map::Map<_, _>>();
+            (!table.is_empty()).then_some(Value::Table(table))
+        }
+        (value, base) => (value != *base).then_some(value),
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    fn toml(s: &str) -> Value {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn merge_scalars() {
+        let mut base = toml("a = 1\nb = true");
+        merge(&mut base, toml("b = false\nc = \"x\""));
+        assert_eq!(base, toml("a = 1\nb = false\nc = \"x\""));
+    }
+
+    #[test]
+    fn merge_tables() {
+        let mut base = toml(
+            "[presets.a]
+             x = 1
+             y = 2
+             [presets.b]
+             x = 3",
+        );
+        let layer = toml(
+            "[presets.a]
+             y = 4
+             [presets.c]
+             x = 5",
+        );
+        merge(&mut base, layer);
+        let expected = toml(
+            "[presets.a]
+             x = 1
+             y = 4
+             [presets.b]
+             x = 3
+             [presets.c]
+             x = 5",
+        );
+        assert_eq!(base, expected);
+    }
+
+    #[test]
+    fn overrides_unchanged() {
+        let base = toml("a = 1\n[presets.a]\nx = 1");
+        assert_eq!(overrides(base.clone(), &base), None);
+    }
+
+    /// Only changed and added settings are kept, down to individual entries
+    /// of tables.
+    #[test]
+    fn overrides_changed() {
+        let base = toml(
+            "a = 1
+             b = [1, 2]
+             [presets.a]
+             x = 1
+             y = 2",
+        );
+        let value = toml(
+            "a = 1
+             b = [1, 3]
+             c = true
+             [presets.a]
+             x = 1
+             y = 4
+             [presets.b]
+             x = 5",
+        );
+        let expected = toml(
+            "b = [1, 3]
+             c = true
+             [presets.a]
+             y = 4
+             [presets.b]
+             x = 5",
+        );
+        let layer = overrides(value.clone(), &base).unwrap();
+        assert_eq!(layer, expected);
+        let mut merged = base;
+        merge(&mut merged, layer);
+        assert_eq!(merged, value);
+    }
+
+    /// The defaults layer must be able to stand alone
+    #[test]
+    fn defaults() {
+        let value = Value::try_from(crate::Cli::default()).unwrap();
+        let cfg: crate::Cli = value.try_into().unwrap();
+        assert!(!cfg.paused);
+        assert!(cfg.columns.is_some());
+    }
+
+    #[test]
+    fn read_missing() {
+        assert!(read(Path::new("/nonexistent/gstat-rs.toml"))
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,110 @@
+//! Identification of physical disks, for finding the right one to replace
+use std::{collections::HashMap, fmt};
+
+use freebsd_libgeom::{Gprovider, Tree};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::cli::Kind;
+
+impl Kind {
+    /// Classify a disk by its provider's name and its GEOM DISK
+    /// `rotationrate`, which is "0" for non-rotating media and "unknown" if
+    /// the drive doesn't report it.
+    fn new(name: &str, rotationrate: Option<&str>) -> Option<Self> {
+        if name.starts_with("nvd") || name.starts_with("nda") {
+            return Some(Kind::Nvme);
+        }
+        match rotationrate?.parse::<u32>().ok()? {
+            0 => Some(Kind::Ssd),
+            _ => Some(Kind::Hdd),
+        }
+    }
+
+    /// Latencies in ms above which an operation is unusually slow, and above
+    /// which it is very slow, for this kind of disk
+    pub fn latency_thresholds(self) -> (f64, f64) {
+        match self {
+            Kind::Hdd => (20.0, 50.0),
+            Kind::Ssd => (2.0, 10.0),
+            Kind::Nvme => (0.5, 2.0),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Kind::Hdd => "HDD",
+            Kind::Ssd => "SSD",
+            Kind::Nvme => "NVMe",
+        }
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+/// A disk's identifying attributes, from its GEOM DISK configuration
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Disk {
+    /// Serial number
+    pub ident: Option<String>,
+    /// Vendor and model
+    pub descr: Option<String>,
+    /// Logical unit's world-wide identifier
+    pub lunid: Option<String>,
+    /// Kind of storage, if it can be determined
+    #[serde(default)]
+    pub kind:  Option<Kind>,
+}
+
+impl Disk {
+    fn new(provider: &Gprovider) -> Self {
+        let attr = |name| {
+            provider
+                .config()
+                .get(name)
+                .map(|v| v.to_string_lossy().trim().to_owned())
+                .filter(|v| !v.is_empty())
+        };
+        let name = provider.name().to_string_lossy();
+        Disk {
+            ident: attr("ident"),
+            descr: attr("descr"),
+            lunid: attr("lunid"),
+            kind:  Kind::new(&name, attr("rotationrate").as_deref()),
+        }
+    }
+}
+
+/// Map the name of each provider of the DISK class to its identification.
+///
+/// It only changes when devices arrive or depart, so the result should be
+/// cached along with the tree.
+pub fn disks(tree: &Tree) -> HashMap<String, Disk> {
+    tree.classes()
+        .filter(|class| class.name().to_bytes() == b"DISK")
+        .flat_map(|class| class.geoms())
+        .flat_map(|geom| geom.providers())
+        .map(|provider| {
+            let name = provider.name().to_string_lossy().into_owned();
+            (name, Disk::new(&provider))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    #[test]
+    fn kind() {
+        assert_eq!(Kind::new("ada0", Some("7200")), Some(Kind::Hdd));
+        assert_eq!(Kind::new("da0", Some("0")), Some(Kind::Ssd));
+        assert_eq!(Kind::new("da0", Some("unknown")), None);
+        assert_eq!(Kind::new("md0", None), None);
+        assert_eq!(Kind::new("nvd0", Some("0")), Some(Kind::Nvme));
+        assert_eq!(Kind::new("nda1", None), Some(Kind::Nvme));
+    }
+}
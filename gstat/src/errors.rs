@@ -0,0 +1,76 @@
+//! Per-device I/O error counts
+//!
+//! devstat(9) doesn't count errors, but cam_iosched(4) does, for CAM disks.
+use std::{collections::HashMap, mem};
+
+use crate::util::sysctl;
+
+/// Tracks each device's cumulative error count across update intervals.
+#[derive(Debug, Default)]
+pub struct ErrorCounts {
+    /// Counts at the start of the current interval
+    prev: HashMap<String, Option<u64>>,
+    /// Counts at the end of the current interval, read lazily
+    cur:  HashMap<String, Option<u64>>,
+}
+
+impl ErrorCounts {
+    /// Begin a new update interval.
+    pub fn rotate(&mut self) {
+        self.prev = mem::take(&mut self.cur);
+    }
+
+    /// The number of errors that `name` reported during the current interval,
+    /// or since boot if this is its first.  Returns `None` if the device
+    /// doesn't report errors.
+    pub fn get(&mut self, name: &str) -> Option<u64> {
+        let cur = *self
+            .cur
+            .entry(name.to_owned())
+            .or_insert_with(|| read(name))
+            .as_ref()?;
+        let prev = self.prev.get(name).copied().flatten().unwrap_or(0);
+        Some(cur.saturating_sub(prev))
+    }
+}
+
+/// Split a device name like "da0" into its driver name and unit number.
+pub fn driver_and_unit(name: &str) -> Option<(&str, &str)> {
+    let split = name.find(|c: char| c.is_ascii_digit())?;
+    let (driver, unit) = name.split_at(split);
+    if driver.is_empty() || !unit.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some((driver, unit))
+}
+
+/// Read a CAM device's cumulative error count, like for "da0".
+fn read(name: &str) -> Option<u64> {
+    let (driver, unit) = driver_and_unit(name)?;
+    let mut total = None;
+    for queue in ["read", "write", "trim"] {
+        let oid = format!("kern.cam.{driver}.{unit}.iosched.{queue}.errs");
+        if let Some(errs) = sysctl::u64_by_name(&oid) {
+            *total.get_or_insert(0) += errs;
+        }
+    }
+    total
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    #[test]
+    fn driver_and_unit_disk() {
+        assert_eq!(driver_and_unit("da0"), Some(("da", "0")));
+        assert_eq!(driver_and_unit("nda12"), Some(("nda", "12")));
+    }
+
+    #[test]
+    fn driver_and_unit_other() {
+        assert_eq!(driver_and_unit("ada0p1"), None);
+        assert_eq!(driver_and_unit("gpt/data"), None);
+        assert_eq!(driver_and_unit("0"), None);
+    }
+}
@@ -0,0 +1,262 @@
+//! User-defined columns computed from the built-in statistics
+use std::{
+    iter::Peekable,
+    str::{CharIndices, FromStr},
+};
+
+use crate::Element;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Node {
+    Num(f64),
+    Stat(&'static str),
+    Neg(Box<Node>),
+    Bin(Box<Node>, BinOp, Box<Node>),
+}
+
+impl Node {
+    fn eval(&self, elem: &Element) -> Option<f64> {
+        match self {
+            Node::Num(x) => Some(*x),
+            Node::Stat(name) => elem.stat(name),
+            Node::Neg(n) => n.eval(elem).map(|x| -x),
+            Node::Bin(l, op, r) => {
+                let (l, r) = (l.eval(elem)?, r.eval(elem)?);
+                Some(match op {
+                    BinOp::Add => l + r,
+                    BinOp::Sub => l - r,
+                    BinOp::Mul => l * r,
+                    BinOp::Div => l / r,
+                })
+            }
+        }
+    }
+}
+
+/// A recursive-descent parser for arithmetic over [`Element::STATS`]
+struct Parser<'a> {
+    s:     &'a str,
+    chars: Peekable<CharIndices<'a>>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(s: &'a str) -> Self {
+        Parser {
+            s,
+            chars: s.char_indices().peekable(),
+        }
+    }
+
+    /// Skip whitespace and return the next character, without consuming it
+    fn peek(&mut self) -> Option<char> {
+        while self.chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+        self.chars.peek().map(|&(_, c)| c)
+    }
+
+    fn parse(mut self) -> Result<Node, String> {
+        let node = self.expr()?;
+        match self.peek() {
+            None => Ok(node),
+            Some(c) => Err(format!("unexpected {c:?} in {:?}", self.s)),
+        }
+    }
+
+    /// expr := term (('+' | '-') term)*
+    fn expr(&mut self) -> Result<Node, String> {
+        let mut node = self.term()?;
+        loop {
+            let op = match self.peek() {
+                Some('+') => BinOp::Add,
+                Some('-') => BinOp::Sub,
+                _ => return Ok(node),
+            };
+            self.chars.next();
+            node = Node::Bin(Box::new(node), op, Box::new(self.term()?));
+        }
+    }
+
+    /// term := factor (('*' | '/') factor)*
+    fn term(&mut self) -> Result<Node, String> {
+        let mut node = self.factor()?;
+        loop {
+            let op = match self.peek() {
+                Some('*') => BinOp::Mul,
+                Some('/') => BinOp::Div,
+                _ => return Ok(node),
+            };
+            self.chars.next();
+            node = Node::Bin(Box::new(node), op, Box::new(self.factor()?));
+        }
+    }
+
+    /// factor := '-' factor | '(' expr ')' | number | statistic
+    fn factor(&mut self) -> Result<Node, String> {
+        match self.peek() {
+            Some('-') => {
+                self.chars.next();
+                Ok(Node::Neg(Box::new(self.factor()?)))
+            }
+            Some('(') => {
+                self.chars.next();
+                let node = self.expr()?;
+                if self.peek() != Some(')') {
+                    return Err(format!(
+                        "unbalanced parentheses in {:?}",
+                        self.s
+                    ));
+                }
+                self.chars.next();
+                Ok(node)
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let word = self.word(|c| c.is_ascii_digit() || c == '.');
+                word.parse::<f64>()
+                    .map(Node::Num)
+                    .map_err(|e| format!("invalid number {word:?}: {e}"))
+            }
+            Some(c) if c.is_ascii_alphabetic() => {
+                let word = self.word(|c| c.is_ascii_alphanumeric() || c == '_');
+                Element::STATS
+                    .iter()
+                    .find(|&&name| name == word)
+                    .map(|name| Node::Stat(name))
+                    .ok_or_else(|| {
+                        format!(
+                            "unknown statistic {word:?}.  Valid statistics \
+                             are: {}",
+                            Element::STATS.join(", ")
+                        )
+                    })
+            }
+            Some(c) => Err(format!("unexpected {c:?} in {:?}", self.s)),
+            None => Err(format!("incomplete expression {:?}", self.s)),
+        }
+    }
+
+    /// Consume the longest run of characters matching `f`
+    fn word<F: Fn(char) -> bool>(&mut self, f: F) -> &'a str {
+        let start = self.chars.peek().map(|&(i, _)| i).unwrap_or(0);
+        let mut end = start;
+        while let Some((i, c)) = self.chars.next_if(|&(_, c)| f(c)) {
+            end = i + c.len_utf8();
+        }
+        &self.s[start..end]
+    }
+}
+
+/// A custom column definition, like `rw_ratio = r_s / (w_s + 1)`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expr {
+    name: String,
+    root: Node,
+}
+
+impl Expr {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Compute this column's value for `elem`.
+    ///
+    /// Returns `None` if a statistic is unavailable, like the error count of a
+    /// device that doesn't report it, or if the result isn't finite.
+    pub fn eval(&self, elem: &Element) -> Option<f64> {
+        self.root.eval(elem).filter(|x| x.is_finite())
+    }
+}
+
+impl FromStr for Expr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, expr) = s
+            .split_once('=')
+            .ok_or_else(|| format!("{s:?} should look like \"name = expr\""))?;
+        let name = name.trim();
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(format!("invalid column name {name:?}"));
+        }
+        let root = Parser::new(expr).parse()?;
+        Ok(Expr {
+            name: name.to_owned(),
+            root,
+        })
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+    use crate::group;
+
+    fn elem() -> Element {
+        let mut e = group::total(&[]);
+        e.r_s = 300.0;
+        e.w_s = 99.0;
+        e.kbs_r = 1000.0;
+        e.kbs_w = 24.0;
+        e
+    }
+
+    fn eval(s: &str) -> Option<f64> {
+        s.parse::<Expr>().unwrap().eval(&elem())
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(eval("x = r_s / (w_s+1)"), Some(3.0));
+        assert_eq!(eval("x = kbs_r + kbs_w"), Some(1024.0));
+        assert_eq!(eval("x=1+2*3"), Some(7.0));
+        assert_eq!(eval("x = (1 + 2) * 3"), Some(9.0));
+        assert_eq!(eval("x = 8 - 4 - 2"), Some(2.0));
+        assert_eq!(eval("x = -r_s * -1"), Some(300.0));
+        assert_eq!(eval("x = 0.5 * 3"), Some(1.5));
+    }
+
+    #[test]
+    fn name() {
+        let expr = " rw_ratio = r_s".parse::<Expr>().unwrap();
+        assert_eq!(expr.name(), "rw_ratio");
+    }
+
+    /// Division by zero yields no value, rather than inf or NaN, so the
+    /// column can still be sorted.
+    #[test]
+    fn div_by_zero() {
+        assert_eq!(eval("x = r_s / d_s"), None);
+        assert_eq!(eval("x = d_s / d_s"), None);
+    }
+
+    /// A statistic that the device doesn't report yields no value.
+    #[test]
+    fn missing_stat() {
+        assert_eq!(eval("x = errs + 1"), None);
+    }
+
+    #[test]
+    fn errors() {
+        for s in [
+            "r_s / w_s",
+            "= r_s",
+            "two words = r_s",
+            "x = ",
+            "x = r_s +",
+            "x = (r_s",
+            "x = r_s)",
+            "x = r_s w_s",
+            "x = bogus",
+            "x = 1.2.3",
+            "x = r_s % 2",
+        ] {
+            assert!(s.parse::<Expr>().is_err(), "{s}");
+        }
+    }
+}
@@ -0,0 +1,238 @@
+//! Aggregate statistics by GEOM class
+use std::collections::BTreeMap;
+
+use crate::Element;
+
+/// Combine `items` into one row per GEOM class.
+///
+/// Rates, throughputs, queue depths, and error counts are summed.  Sizes and
+/// latencies are recomputed from the sums, so they are weighted by each
+/// device's operation count.  %busy is the busiest member's, since summing it
+/// would be meaningless.  Devices that aren't GEOM providers are grouped by
+/// their driver name instead.
+pub fn by_class(items: &[Element]) -> Vec<Element> {
+    let mut groups: BTreeMap<&str, Vec<&Element>> = BTreeMap::new();
+    for elem in items {
+        groups.entry(&elem.class).or_default().push(elem);
+    }
+    groups
+        .into_iter()
+        .map(|(class, members)| aggregate(class, &members))
+        .collect()
+}
+
+/// Combine the physical devices in `items` into a single row of system-wide
+/// totals.  Higher-ranked providers are excluded, lest each I/O be counted once
+/// for every layer of the GEOM stack.
+pub fn total(items: &[Element]) -> Element {
+    let members = items.iter().filter(|e| e.rank == 1).collect::<Vec<_>>();
+    aggregate("total", &members)
+}
+
+/// Weighted average of `f(elem)`, using `w(elem)` as the weight
+fn wavg<F, W>(members: &[&Element], f: F, w: W) -> f64
+where
+    F: Fn(&Element) -> f64,
+    W: Fn(&Element) -> f64,
+{
+    let total: f64 = members.iter().map(|e| w(e)).sum();
+    if total > 0.0 {
+        members.iter().map(|e| f(e) * w(e)).sum::<f64>() / total
+    } else {
+        0.0
+    }
+}
+
+fn aggregate(class: &str, members: &[&Element]) -> Element {
+    // Not Iterator::sum, which yields -0.0 for no members, displayed as "-0"
+    let sum = |f: fn(&Element) -> f64| {
+        members.iter().map(|e| f(e)).fold(0.0, |acc, x| acc + x)
+    };
+    let kbs_r: f64 = sum(|e| e.kbs_r);
+    let kbs_w: f64 = sum(|e| e.kbs_w);
+    let rw_bal = if kbs_r + kbs_w > 0.0 {
+        Some(kbs_r / (kbs_r + kbs_w))
+    } else {
+        None
+    };
+    let errs = members.iter().filter_map(|e| e.errs).reduce(|a, b| a + b);
+    let sq = members.iter().filter_map(|e| e.sq).reduce(|a, b| a + b);
+    let ms_sq = members.iter().filter_map(|e| e.ms_sq).reduce(f64::max);
+    Element {
+        qd: members.iter().map(|e| e.qd).sum(),
+        ops_s: sum(|e| e.ops_s),
+        r_s: sum(|e| e.r_s),
+        kb_r: wavg(members, |e| e.kb_r, |e| e.r_s),
+        kbs_r,
+        ms_r: wavg(members, |e| e.ms_r, |e| e.r_s),
+        w_s: sum(|e| e.w_s),
+        kb_w: wavg(members, |e| e.kb_w, |e| e.w_s),
+        kbs_w,
+        ms_w: wavg(members, |e| e.ms_w, |e| e.w_s),
+        d_s: sum(|e| e.d_s),
+        kb_d: wavg(members, |e| e.kb_d, |e| e.d_s),
+        kbs_d: sum(|e| e.kbs_d),
+        ms_d: wavg(members, |e| e.ms_d, |e| e.d_s),
+        o_s: sum(|e| e.o_s),
+        ms_o: wavg(members, |e| e.ms_o, |e| e.o_s),
+        pct_busy: members.iter().map(|e| e.pct_busy).fold(0.0, f64::max),
+        rw_bal,
+        errs,
+        sq,
+        ms_sq,
+        avg_qd: sum(|e| e.avg_qd),
+        // Tracked separately for each group
+        peak_kbs: None,
+        name: class.into(),
+        class: class.to_owned(),
+        // A class is physical if any of its members are, so --physical will
+        // still show the DISK class.
+        rank: members.iter().map(|e| e.rank).min().unwrap_or(0),
+        pct_iops: None,
+        pct_kbs: None,
+        parent: None,
+        disk: None,
+        kind: None,
+        consumer: false,
+        depth: 0,
+        folded: false,
+        custom: Vec::new(),
+        aliases: Vec::new(),
+        outliers: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    fn elem(name: &str, class: &str, rank: u32) -> Element {
+        Element {
+            qd: 0,
+            ops_s: 0.0,
+            r_s: 0.0,
+            kb_r: 0.0,
+            kbs_r: 0.0,
+            ms_r: 0.0,
+            w_s: 0.0,
+            kb_w: 0.0,
+            kbs_w: 0.0,
+            ms_w: 0.0,
+            d_s: 0.0,
+            kb_d: 0.0,
+            kbs_d: 0.0,
+            ms_d: 0.0,
+            o_s: 0.0,
+            ms_o: 0.0,
+            pct_busy: 0.0,
+            rw_bal: None,
+            errs: None,
+            sq: None,
+            ms_sq: None,
+            avg_qd: 0.0,
+            peak_kbs: None,
+            name: name.into(),
+            class: class.to_owned(),
+            rank,
+            pct_iops: None,
+            pct_kbs: None,
+            parent: None,
+            disk: None,
+            kind: None,
+            consumer: false,
+            depth: 0,
+            folded: false,
+            custom: Vec::new(),
+            aliases: Vec::new(),
+            outliers: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn one_row_per_class() {
+        let items = [
+            elem("ada0", "DISK", 1),
+            elem("ada0p1", "PART", 2),
+            elem("ada1", "DISK", 1),
+            elem("ada1p1", "PART", 2),
+        ];
+        let groups = by_class(&items);
+        let names = groups.iter().map(|e| &*e.name).collect::<Vec<_>>();
+        assert_eq!(names, ["DISK", "PART"]);
+        assert_eq!(groups[0].rank, 1);
+        assert_eq!(groups[1].rank, 2);
+    }
+
+    #[test]
+    fn sums() {
+        let mut a = elem("ada0", "DISK", 1);
+        a.qd = 1;
+        a.r_s = 100.0;
+        a.kbs_r = 400.0;
+        a.kb_r = 4.0;
+        a.ms_r = 1.0;
+        a.pct_busy = 20.0;
+        a.errs = Some(1);
+        a.sq = Some(4);
+        a.ms_sq = Some(2.0);
+        a.avg_qd = 0.5;
+        let mut b = elem("ada1", "DISK", 1);
+        b.qd = 2;
+        b.r_s = 300.0;
+        b.kbs_r = 9600.0;
+        b.kb_r = 32.0;
+        b.ms_r = 5.0;
+        b.pct_busy = 60.0;
+        b.errs = Some(2);
+        b.sq = Some(1);
+        b.ms_sq = Some(7.0);
+        b.avg_qd = 1.5;
+        b.kbs_w = 10000.0;
+        let groups = by_class(&[a, b]);
+        assert_eq!(groups.len(), 1);
+        let g = &groups[0];
+        assert_eq!(g.qd, 3);
+        assert_eq!(g.avg_qd, 2.0);
+        assert_eq!(g.r_s, 400.0);
+        assert_eq!(g.kbs_r, 10000.0);
+        assert_eq!(g.kb_r, 25.0);
+        assert_eq!(g.ms_r, 4.0);
+        assert_eq!(g.pct_busy, 60.0);
+        assert_eq!(g.errs, Some(3));
+        assert_eq!(g.sq, Some(5));
+        assert_eq!(g.ms_sq, Some(7.0));
+        assert_eq!(g.rw_bal, Some(0.5));
+    }
+
+    #[test]
+    fn total_excludes_upper_layers() {
+        let mut a = elem("ada0", "DISK", 1);
+        a.w_s = 100.0;
+        let mut b = elem("ada0p1", "PART", 2);
+        b.w_s = 100.0;
+        let mut c = elem("ada1", "DISK", 1);
+        c.w_s = 50.0;
+        let t = total(&[a, b, c]);
+        assert_eq!(&*t.name, "total");
+        assert_eq!(t.w_s, 150.0);
+    }
+
+    #[test]
+    fn total_empty() {
+        let t = total(&[]);
+        assert_eq!(t.ops_s, 0.0);
+        assert_eq!(t.pct_busy, 0.0);
+    }
+
+    /// With no operations, averages should be 0, not NaN
+    #[test]
+    fn idle() {
+        let items = [elem("da0", "DISK", 1), elem("da1", "DISK", 1)];
+        let g = &by_class(&items)[0];
+        assert_eq!(g.ms_r, 0.0);
+        assert_eq!(g.kb_w, 0.0);
+        assert_eq!(g.rw_bal, None);
+        assert_eq!(g.errs, None);
+        assert_eq!(g.sq, None);
+    }
+}
@@ -0,0 +1,356 @@
+//! The help popup, and the key bindings
+//!
+//! Every key binding is described in [`KEYS`], which also supplies the
+//! interactive loop's dispatch and the INTERACTIVE COMMANDS section of the man
+//! page.
+
+/// A key, as named independently of the terminal library
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Code {
+    Char(char),
+    Down,
+    Up,
+    Left,
+    Right,
+    ShiftLeft,
+    ShiftRight,
+    Delete,
+    Insert,
+    Enter,
+}
+
+/// What a key does, outside of any popup
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Action {
+    Pause,
+    SelectNext,
+    SelectPrevious,
+    /// Collapse the selected provider in tree view, or else scroll left
+    Collapse,
+    /// Expand the selected provider in tree view, or else scroll right
+    Expand,
+    ScrollLeft,
+    ScrollRight,
+    DeleteSortColumn,
+    SelectColumns,
+    ShowDetail,
+    SortNext,
+    SortPrevious,
+    HalveInterval,
+    DoubleInterval,
+    Search,
+    Help,
+    ToggleAuto,
+    ShowBusy,
+    EditFilter,
+    ClearFilters,
+    ToggleGroup,
+    ToggleTree,
+    ToggleAliases,
+    SwitchLatencyUnit,
+    AddMarker,
+    TogglePhysical,
+    SelectPreset,
+    Quit,
+    ToggleReverse,
+    ToggleTotals,
+    ToggleCumulative,
+    ToggleHuman,
+    StepBackward,
+    StepForward,
+}
+
+/// One line of the help, with the keys that it describes
+pub struct Key {
+    pub key:      &'static str,
+    /// Each key described, and what it does
+    pub bindings: &'static [(Code, Action)],
+    /// What it does, in a few words for the help popup
+    pub help:     &'static str,
+    /// Anything more that the man page should say.  The man page is only
+    /// rendered by tests.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub detail:   &'static str,
+}
+
+const fn key(
+    key: &'static str,
+    bindings: &'static [(Code, Action)],
+    help: &'static str,
+    detail: &'static str,
+) -> Key {
+    Key {
+        key,
+        bindings,
+        help,
+        detail,
+    }
+}
+
+/// The binding for a single character
+macro_rules! ch {
+    ($c:literal, $action:ident) => {
+        &[(Code::Char($c), Action::$action)]
+    };
+}
+
+/// Each interactive key and what it does
+pub const KEYS: &[Key] = &[
+    key(
+        "space",
+        ch!(' ', Pause),
+        "Pause/unpause updating the display",
+        "",
+    ),
+    key(
+        "down/up",
+        &[
+            (Code::Down, Action::SelectNext),
+            (Code::Up, Action::SelectPrevious),
+        ],
+        "Select one row of the table",
+        "This is purely for visual focus purposes.  It has no other effect.",
+    ),
+    key(
+        "left/right",
+        &[
+            (Code::Left, Action::Collapse),
+            (Code::Right, Action::Expand),
+        ],
+        "Scroll columns, or collapse/expand in tree view",
+        "Scroll the table horizontally by one column, for terminals too \
+         narrow to display every enabled column.  The Name column is always \
+         displayed.  In tree view, these keys instead collapse or expand the \
+         selected provider.  A collapsed provider is marked with a \"+\".",
+    ),
+    key(
+        "shift-left/right",
+        &[
+            (Code::ShiftLeft, Action::ScrollLeft),
+            (Code::ShiftRight, Action::ScrollRight),
+        ],
+        "Scroll columns, even in tree view",
+        "",
+    ),
+    key(
+        "delete",
+        &[(Code::Delete, Action::DeleteSortColumn)],
+        "Delete the sort column from the display",
+        "",
+    ),
+    key(
+        "insert",
+        &[(Code::Insert, Action::SelectColumns)],
+        "Select columns",
+        "This can be used to control exactly which columns are displayed.",
+    ),
+    key(
+        "enter",
+        &[(Code::Enter, Action::ShowDetail)],
+        "Display the selected device's details",
+        "These include the serial number, description, and LUN ID of physical \
+         disks.  They are also available in the optional \"Serial number\" \
+         column, which helps to identify a disk that must be replaced.",
+    ),
+    key(
+        "+",
+        ch!('+', SortNext),
+        "Sort by the next column to the right",
+        "",
+    ),
+    key(
+        "-",
+        ch!('-', SortPrevious),
+        "Sort by the next column to the left",
+        "",
+    ),
+    key(
+        "<",
+        ch!('<', HalveInterval),
+        "Halve the update interval",
+        "",
+    ),
+    key(
+        ">",
+        ch!('>', DoubleInterval),
+        "Double the update interval",
+        "",
+    ),
+    key(
+        "/",
+        ch!('/', Search),
+        "Search for a device by name",
+        "Each key typed moves the selection to the first device whose name \
+         contains the search text.  Unlike f, this does not hide any devices.  \
+         Press enter or escape to close the prompt.",
+    ),
+    key(
+        "?",
+        ch!('?', Help),
+        "Display this help",
+        "Along with the key bindings, the help displays the current update \
+         interval, filter, and sort order.",
+    ),
+    key(
+        "a",
+        ch!('a', ToggleAuto),
+        "Toggle auto mode",
+        "This has the same effect as the --auto option.",
+    ),
+    key(
+        "b",
+        ch!('b', ShowBusy),
+        "Display the selected device's busy time by operation",
+        "The time is divided among reads, writes, deletes, and other \
+         operations, as a stacked bar and percentages.  Each type's share is \
+         its operation rate multiplied by its latency.",
+    ),
+    key(
+        "f",
+        ch!('f', EditFilter),
+        "Filter devices by a regular expression",
+        "Only devices with names matching the expression are displayed.",
+    ),
+    key(
+        "F",
+        ch!('F', ClearFilters),
+        "Remove the device and class filters",
+        "",
+    ),
+    key(
+        "g",
+        ch!('g', ToggleGroup),
+        "Toggle grouping by GEOM class",
+        "This has the same effect as the --group-by-class option.",
+    ),
+    key(
+        "h",
+        ch!('h', ToggleTree),
+        "Toggle tree view",
+        "This has the same effect as the --tree option.",
+    ),
+    key(
+        "l",
+        ch!('l', ToggleAliases),
+        "Toggle displaying labels as aliases",
+        "This has the same effect as the --aliases option.",
+    ),
+    key(
+        "m",
+        ch!('m', SwitchLatencyUnit),
+        "Switch latencies between ms and µs",
+        "This has the same effect as the --latency-unit option.",
+    ),
+    key(
+        "M",
+        ch!('M', AddMarker),
+        "Drop a labeled marker, like the start of a benchmark",
+        "Markers are drawn as vertical lines through the throughput graph of \
+         the totals row, and are saved in --record recordings.  When a \
+         recording is replayed, each marker's label is displayed as its \
+         interval is reached.",
+    ),
+    key(
+        "p",
+        ch!('p', TogglePhysical),
+        "Toggle physical mode",
+        "This has the same effect as the --physical option.",
+    ),
+    key(
+        "P",
+        ch!('P', SelectPreset),
+        "Select or save a preset",
+        "Press enter to apply the selected preset, or s to save the current \
+         columns and sort order as a new preset.",
+    ),
+    key("q", ch!('q', Quit), "Quit", ""),
+    key(
+        "r",
+        ch!('r', ToggleReverse),
+        "Toggle reverse sort",
+        "This has the same effect as the --reverse option.",
+    ),
+    key(
+        "t",
+        ch!('t', ToggleTotals),
+        "Toggle the totals row",
+        "This has the same effect as the --totals option.",
+    ),
+    key(
+        "T",
+        ch!('T', ToggleCumulative),
+        "Toggle cumulative mode",
+        "This has the same effect as the --cumulative option.",
+    ),
+    key(
+        "u",
+        ch!('u', ToggleHuman),
+        "Toggle scaling with unit suffixes",
+        "This has the same effect as the --human option.",
+    ),
+    key(
+        "[ ]",
+        &[
+            (Code::Char('['), Action::StepBackward),
+            (Code::Char(']'), Action::StepForward),
+        ],
+        "Step backwards or forwards through a replay",
+        "",
+    ),
+];
+
+/// What `code` does, outside of any popup
+pub fn action(code: Code) -> Option<Action> {
+    KEYS.iter()
+        .flat_map(|k| k.bindings)
+        .find(|(c, _)| *c == code)
+        .map(|(_, action)| *action)
+}
+
+/// The help popup's text: the current `settings`, then every key binding.
+pub fn lines(settings: &[(&str, String)]) -> Vec<String> {
+    let width = KEYS
+        .iter()
+        .map(|k| k.key)
+        .chain(settings.iter().map(|(k, _)| *k))
+        .map(|k| k.chars().count())
+        .max()
+        .unwrap_or(0);
+    let row = |k: &str, v: &str| format!("{k:>width$}  {v}");
+    settings
+        .iter()
+        .map(|(k, v)| row(k, v))
+        .chain([String::new()])
+        .chain(KEYS.iter().map(|k| row(k.key, k.help)))
+        .collect()
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    #[test]
+    fn aligned() {
+        let lines = lines(&[("Sort", "Name".to_owned())]);
+        assert_eq!(lines.len(), KEYS.len() + 2);
+        assert_eq!(lines[0], "            Sort  Name");
+        assert_eq!(lines[1], "");
+        assert_eq!(
+            lines.last().unwrap(),
+            "             [ ]  Step backwards or forwards through a replay"
+        );
+    }
+
+    /// No key may be bound twice
+    #[test]
+    fn unique() {
+        let codes = KEYS
+            .iter()
+            .flat_map(|k| k.bindings)
+            .map(|(code, _)| code)
+            .collect::<Vec<_>>();
+        for (i, code) in codes.iter().enumerate() {
+            assert!(!codes[i + 1..].contains(code), "{code:?}");
+        }
+    }
+}
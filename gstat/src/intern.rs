@@ -0,0 +1,56 @@
+//! Shared device names
+//!
+//! Every refresh builds a new [`Element`](crate::Element) for every device.
+//! Interning their names means that, after the first refresh, doing so
+//! allocates no strings at all.
+use std::{collections::HashSet, sync::Arc};
+
+/// A set of names, each allocated only once per session.
+#[derive(Debug, Default)]
+pub struct Interner(HashSet<Arc<str>>);
+
+impl Interner {
+    /// Get the shared copy of `name`, allocating it if this is the first time
+    /// it's been seen.
+    pub fn intern(&mut self, name: &str) -> Arc<str> {
+        if let Some(interned) = self.0.get(name) {
+            Arc::clone(interned)
+        } else {
+            let interned = Arc::<str>::from(name);
+            self.0.insert(Arc::clone(&interned));
+            interned
+        }
+    }
+
+    /// Forget names that nothing else refers to anymore, like those of
+    /// departed devices.
+    pub fn prune(&mut self) {
+        self.0.retain(|name| Arc::strong_count(name) > 1);
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    #[test]
+    fn intern() {
+        let mut interner = Interner::default();
+        let a = interner.intern("ada0");
+        let b = interner.intern("ada0");
+        let c = interner.intern("ada1");
+        assert!(Arc::ptr_eq(&a, &b));
+        assert_eq!(&*c, "ada1");
+        assert!(!Arc::ptr_eq(&a, &c));
+    }
+
+    #[test]
+    fn prune() {
+        let mut interner = Interner::default();
+        let a = interner.intern("ada0");
+        interner.intern("ada1");
+        interner.prune();
+        assert_eq!(interner.0.len(), 1);
+        assert!(Arc::ptr_eq(&a, &interner.intern("ada0")));
+    }
+}
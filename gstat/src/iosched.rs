@@ -0,0 +1,66 @@
+//! Queue statistics from the CAM I/O scheduler
+//!
+//! cam_iosched(4) holds requests for a CAM disk in its own queues before
+//! issuing them to the hardware.  devstat(9) only sees a request once it's
+//! issued, so comparing the two shows whether latency comes from the device
+//! itself or from waiting in the scheduler.
+use crate::{errors::driver_and_unit, util::sysctl};
+
+/// The queues that cam_iosched keeps for each device
+const QUEUES: [&str; 3] = ["read", "write", "trim"];
+
+/// A snapshot of one device's I/O scheduler queues
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Sched {
+    /// Requests waiting in the scheduler, not yet issued to the device
+    pub queued: u32,
+    /// The largest of the queues' moving average latencies, in milliseconds
+    pub ms:     Option<f64>,
+}
+
+/// Read a CAM device's scheduler queues, like for "da0".  Returns `None` if
+/// the device has no I/O scheduler.
+pub fn read(name: &str) -> Option<Sched> {
+    let (driver, unit) = driver_and_unit(name)?;
+    let mut sched = None;
+    for queue in QUEUES {
+        let oid = format!("kern.cam.{driver}.{unit}.iosched.{queue}");
+        let Some(queued) = sysctl::u64_by_name(&format!("{oid}.queued")) else {
+            continue;
+        };
+        let sched = sched.get_or_insert_with(Sched::default);
+        sched.queued = sched
+            .queued
+            .saturating_add(u32::try_from(queued).unwrap_or(u32::MAX));
+        if let Some(ms) = sysctl::string_by_name(&format!("{oid}.ema"))
+            .as_deref()
+            .and_then(parse_us)
+        {
+            sched.ms = Some(sched.ms.map_or(ms, |prev: f64| prev.max(ms)));
+        }
+    }
+    sched
+}
+
+/// Parse a latency, which cam_iosched reports as a string of microseconds,
+/// into milliseconds.
+fn parse_us(s: &str) -> Option<f64> {
+    s.trim().parse::<f64>().ok().map(|us| us / 1000.0)
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    #[test]
+    fn parse_us_ok() {
+        assert_eq!(parse_us("1500"), Some(1.5));
+        assert_eq!(parse_us(" 0\n"), Some(0.0));
+    }
+
+    #[test]
+    fn parse_us_garbage() {
+        assert_eq!(parse_us(""), None);
+        assert_eq!(parse_us("12 ms"), None);
+    }
+}
@@ -1,25 +1,65 @@
+mod alert;
+mod batch;
+#[cfg(test)]
+mod bench;
+mod cli;
+mod column;
+mod compat;
+mod config;
+mod disk;
+mod errors;
+mod expr;
+mod group;
+mod help;
+mod intern;
+mod iosched;
+#[cfg(test)]
+mod man;
+mod outlier;
+mod peak;
+mod preset;
+mod replay;
+mod report;
+mod rows;
+mod topology;
+mod tree;
 mod util;
 
 use std::{
+    borrow::Cow,
     cmp::Ordering,
-    io,
+    collections::{HashMap, HashSet},
+    ffi::CString,
+    fmt::Write as _,
+    io::{self, Write},
     mem,
-    num::NonZeroU16,
-    ops::BitOrAssign,
-    time::Duration,
+    num::{NonZeroU16, NonZeroUsize},
+    panic,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
-use anyhow::{Context, Result};
-use bitfield::bitfield;
-use clap::Parser;
-use crossterm::event::KeyCode;
-use freebsd_libgeom::{Snapshot, Statistics, Tree};
+use anyhow::{bail, Context, Result};
+use clap::{CommandFactory, Parser};
+use clap_complete::Shell;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use freebsd_libgeom::{
+    name_cmp,
+    record::Record,
+    DeviceEvent,
+    DeviceWatcher,
+    Gident,
+    Snapshot,
+    Statistics,
+    TreeCache,
+};
 use nix::time::{clock_gettime, ClockId};
 use ratatui::{
     backend::CrosstermBackend,
     layout::{Constraint, Direction, Layout, Rect, SegmentSize},
     style::{Color, Modifier, Style},
-    text::Text,
+    text::{Line, Span, Text},
     widgets::{
         Block,
         Borders,
@@ -30,6 +70,7 @@ use ratatui::{
         ListState,
         Paragraph,
         Row,
+        Sparkline,
         Table,
         TableState,
     },
@@ -38,7 +79,21 @@ use ratatui::{
 use regex::Regex;
 use serde_derive::{Deserialize, Serialize};
 
-use crate::util::{event::Event, iter::IteratorExt};
+use crate::{
+    alert::{Alerts, Rule},
+    batch::Field,
+    cli::{Cli, Command, Kind, LatencyUnit},
+    column::{Spec, Unit},
+    disk::Disk,
+    errors::ErrorCounts,
+    expr::Expr,
+    help::{Action, Code},
+    intern::Interner,
+    preset::Preset,
+    replay::{Marker, Recorder, Replay},
+    rows::RowCache,
+    util::{event::Event, iter::IteratorExt, window::Window},
+};
 
 /// helper function to create a one-line popup box
 fn popup_layout(x: u16, y: u16, r: Rect) -> Rect {
@@ -67,132 +122,63 @@ fn popup_layout(x: u16, y: u16, r: Rect) -> Rect {
         .split(popup_layout[1])[1]
 }
 
-/// Drop-in replacement for gstat(8)
-#[derive(Debug, Default, Deserialize, Serialize, clap::Parser)]
-struct Cli {
-    /// Only display providers that are at least 0.1% busy
-    #[clap(short = 'a', long = "auto")]
-    auto:         bool,
-    /// Display statistics for delete (BIO_DELETE) operations.
-    #[clap(short = 'd', long = "delete")]
-    delete:       bool,
-    /// Only display devices with names matching filter, as a regex.
-    #[clap(short = 'f', long = "filter")]
-    filter:       Option<String>,
-    /// Display statistics for other (BIO_FLUSH) operations.
-    #[clap(short = 'o', long = "other")]
-    other:        bool,
-    /// Display block size statistics
-    #[clap(short = 's', long = "size")]
-    size:         bool,
-    /// Only display physical providers (those with rank of 1).
-    #[clap(short = 'p', long = "physical")]
-    physical:     bool,
-    /// Reset the config file to defaults
-    #[serde(skip)]
-    #[clap(long = "reset-config")]
-    reset_config: bool,
-    /// Reverse the sort
-    #[clap(short = 'r', long = "reverse")]
-    reverse:      bool,
-    /// Sort by the named column.  The name should match the column header.
-    #[clap(short = 'S', long = "sort")]
-    sort:         Option<String>,
-    /// Bitfield of columns to enable
-    #[serde(default = "default_columns_enabled")]
-    #[clap(skip)]
-    columns:      Option<ColumnsEnabled>,
-    /// Display update interval, in microseconds or with the specified unit
-    #[clap(
-        short = 'I',
-        long = "interval",
-        value_parser = Cli::duration_from_str
-    )]
-    interval:     Option<Duration>,
-}
-
-impl Cli {
-    fn duration_from_str(
-        s: &str,
-    ) -> std::result::Result<Duration, humanize_rs::ParseError> {
-        if let Ok(us) = s.parse::<u64>() {
-            // With no units, default to microseconds
-            Ok(Duration::from_micros(us))
-        } else {
-            humanize_rs::duration::parse(s)
-        }
-    }
-}
-
-impl BitOrAssign for Cli {
-    #[allow(clippy::or_fun_call)]
-    fn bitor_assign(&mut self, rhs: Self) {
-        self.auto |= rhs.auto;
-        self.delete |= rhs.delete;
-        self.filter = rhs.filter.or(self.filter.take());
-        self.other |= rhs.other;
-        self.size |= rhs.size;
-        self.interval = rhs.interval.or(self.interval.take());
-        self.physical |= rhs.physical;
-        self.reverse |= rhs.reverse;
-        self.sort = rhs.sort.or(self.sort.take());
-        self.columns = rhs.columns.or(self.columns.take());
-    }
-}
-
 struct Column {
     name:    &'static str,
     header:  &'static str,
+    /// This column's position in [`ColumnsEnabled`].  It may differ from its
+    /// display position, since the Name column must always be last.  Custom
+    /// columns have none, so their visibility isn't saved.
+    bit:     Option<u32>,
     enabled: bool,
-    width:   Constraint,
 }
 
 impl Column {
-    fn new(
-        name: &'static str,
-        header: &'static str,
-        enabled: bool,
-        width: Constraint,
-    ) -> Self {
+    fn builtin(spec: &'static Spec, cb: ColumnsEnabled) -> Self {
         Column {
-            name,
-            header,
-            enabled,
-            width,
+            name:    spec.name,
+            header:  spec.header,
+            bit:     Some(spec.bit),
+            enabled: cb.0 & (1 << spec.bit) != 0,
         }
     }
 
-    fn min_width(&self) -> u16 {
-        match self.width {
-            Constraint::Min(x) => x,
-            Constraint::Length(x) => x,
-            _ => unreachable!("gstat-rs doesn't create columns like this"),
+    /// A column defined by `--expr`
+    fn custom(expr: &Expr) -> Self {
+        let header = format!("{:>6}", expr.name());
+        // Custom columns live as long as the program, so leaking their names
+        // costs nothing.
+        Column {
+            name:    Box::leak(expr.name().to_owned().into_boxed_str()),
+            header:  Box::leak(header.into_boxed_str()),
+            bit:     None,
+            enabled: true,
         }
     }
 }
 
-bitfield! {
-    #[derive(Clone, Copy, Deserialize, Serialize)]
-    pub struct ColumnsEnabled(u32);
-    impl Debug;
-    u32; qd, set_qd: 0;
-    u32; ops_s, set_ops_s: 1;
-    u32; r_s, set_r_s: 2;
-    u32; kb_r, set_kb_r: 3;
-    u32; kbs_r, set_kbs_r: 4;
-    u32; ms_r, set_ms_r: 5;
-    u32; w_s, set_w_s: 6;
-    u32; kb_w, set_kb_w: 7;
-    u32; kbs_w, set_kbs_w: 8;
-    u32; ms_w, set_ms_w: 9;
-    u32; d_s, set_d_s: 10;
-    u32; kb_d, set_kb_d: 11;
-    u32; kbs_d, set_kbs_d: 12;
-    u32; ms_d, set_ms_d: 13;
-    u32; o_s, set_o_s: 14;
-    u32; ms_o, set_ms_o: 15;
-    u32; pct_busy, set_pct_busy: 16;
-    u32; name, set_name: 17;
+/// Bitfield of the built-in columns to display, by [`Spec::bit`]
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct ColumnsEnabled(u64);
+
+impl ColumnsEnabled {
+    /// The bit of the column displaying `stat`
+    fn mask(stat: &str) -> u64 {
+        let spec = column::BUILTIN.iter().find(|spec| spec.stat == stat);
+        1 << spec.expect("no such column").bit
+    }
+
+    /// Is the column displaying `stat` enabled?
+    pub fn get(self, stat: &str) -> bool {
+        self.0 & Self::mask(stat) != 0
+    }
+
+    pub fn set(&mut self, stat: &str, enabled: bool) {
+        if enabled {
+            self.0 |= Self::mask(stat);
+        } else {
+            self.0 &= !Self::mask(stat);
+        }
+    }
 }
 
 impl Default for ColumnsEnabled {
@@ -201,152 +187,283 @@ impl Default for ColumnsEnabled {
     }
 }
 
-fn default_columns_enabled() -> Option<ColumnsEnabled> {
-    Some(Default::default())
-}
-
 struct Columns {
-    cols:  [Column; Columns::LEN],
-    state: ListState,
+    /// The built-in columns, indexed by the constants below, followed by any
+    /// custom columns
+    cols:    Vec<Column>,
+    state:   ListState,
+    /// How many of the enabled columns are scrolled off to the left
+    scroll:  usize,
+    /// Scale the kB columns' values with unit suffixes
+    human:   bool,
+    latency: LatencyUnit,
 }
 
 impl Columns {
-    const DEFAULT_ENABLED: u32 = 0x30377;
-    const D_S: usize = 10;
-    const KBS_D: usize = 12;
-    const KBS_R: usize = 4;
-    const KBS_W: usize = 8;
-    const KB_D: usize = 11;
-    const KB_R: usize = 3;
-    const KB_W: usize = 7;
-    const LEN: usize = 18;
-    const MS_D: usize = 13;
-    const MS_O: usize = 15;
-    const MS_R: usize = 5;
-    const MS_W: usize = 9;
-    const NAME: usize = 17;
-    const OPS_S: usize = 1;
-    const O_S: usize = 14;
-    const PCT_BUSY: usize = 16;
-    const QD: usize = 0;
-    const R_S: usize = 2;
-    const W_S: usize = 6;
+    /// The most digits expected before the decimal point of custom columns
+    const CUSTOM_DIGITS: u16 = 5;
+    const DEFAULT_ENABLED: u64 = column::default_enabled();
+    const ERRS: usize = column::find("errs");
+    const LEN: usize = column::BUILTIN.len();
+    const MS_D: usize = column::find("ms_d");
+    const MS_O: usize = column::find("ms_o");
+    const MS_R: usize = column::find("ms_r");
+    const MS_SQ: usize = column::find("ms_sq");
+    const MS_W: usize = column::find("ms_w");
+    const NAME: usize = column::find("name");
+    const PCT_BUSY: usize = column::find("pct_busy");
+    const PCT_IOPS: usize = column::find("pct_iops");
+    const PCT_KBS: usize = column::find("pct_kbs");
+    const RW_BAL: usize = column::find("rw_bal");
+    const SQ: usize = column::find("sq");
 
     fn new(cfg: &mut Cli) -> Self {
         let mut cb = match cfg.columns {
             Some(cb) => cb,
             None => {
-                // Can only happen when using --reset-config
+                // Can only happen when using --compat
                 ColumnsEnabled(Self::DEFAULT_ENABLED)
             }
         };
         // Apply the -ods switches, for legacy compatibility
         if cfg.delete {
-            cb.set_d_s(true);
-            cb.set_kbs_d(true);
-            cb.set_ms_d(true);
+            cb.set("d_s", true);
+            cb.set("kbs_d", true);
+            cb.set("ms_d", true);
         }
         if cfg.other {
-            cb.set_o_s(true);
-            cb.set_ms_o(true);
+            cb.set("o_s", true);
+            cb.set("ms_o", true);
         }
         if cfg.delete && cfg.size {
-            cb.set_kb_d(true);
+            cb.set("kb_d", true);
         }
         if cfg.size {
-            cb.set_kb_r(true);
-            cb.set_kb_w(true);
+            cb.set("kb_r", true);
+            cb.set("kb_w", true);
         }
         // Write back any changes we made.
         cfg.columns = Some(cb);
-        let cols = [
-            Column::new("Queue depth", "L(q)", cb.qd(), Constraint::Length(5)),
-            Column::new("IOPs", " ops/s", cb.ops_s(), Constraint::Length(7)),
-            Column::new("Read IOPs", "   r/s", cb.r_s(), Constraint::Length(7)),
-            Column::new("Read size", "kB/r", cb.kb_r(), Constraint::Length(5)),
-            Column::new(
-                "Read throughput",
-                "kB/s r",
-                cb.kbs_r(),
-                Constraint::Length(7),
-            ),
-            Column::new(
-                "Read latency",
-                "  ms/r",
-                cb.ms_r(),
-                Constraint::Length(7),
-            ),
-            Column::new(
-                "Write IOPs",
-                "   w/s",
-                cb.w_s(),
-                Constraint::Length(7),
-            ),
-            Column::new("Write size", "kB/w", cb.kb_w(), Constraint::Length(5)),
-            Column::new(
-                "Write throughput",
-                "kB/s w",
-                cb.kbs_w(),
-                Constraint::Length(7),
-            ),
-            Column::new(
-                "Write latency",
-                "  ms/w",
-                cb.ms_w(),
-                Constraint::Length(7),
-            ),
-            Column::new(
-                "Delete IOPs",
-                "   d/s",
-                cb.d_s(),
-                Constraint::Length(7),
-            ),
-            Column::new(
-                "Delete size",
-                "kB/d",
-                cb.kb_d(),
-                Constraint::Length(5),
-            ),
-            Column::new(
-                "Delete throughput",
-                "kB/s d",
-                cb.kbs_d(),
-                Constraint::Length(7),
-            ),
-            Column::new(
-                "Delete latency",
-                "  ms/d",
-                cb.ms_d(),
-                Constraint::Length(7),
-            ),
-            Column::new(
-                "Other IOPs",
-                "   o/s",
-                cb.o_s(),
-                Constraint::Length(7),
-            ),
-            Column::new(
-                "Other latency",
-                "  ms/o",
-                cb.ms_o(),
-                Constraint::Length(7),
-            ),
-            Column::new(
-                "Percent busy",
-                " %busy",
-                cb.pct_busy(),
-                Constraint::Length(7),
-            ),
-            Column::new("Name", "Name", cb.name(), Constraint::Min(10)),
-        ];
+        let mut cols = column::BUILTIN
+            .iter()
+            .map(|spec| Column::builtin(spec, cb))
+            .collect::<Vec<_>>();
+        cols.extend(cfg.expr.iter().map(Column::custom));
         let mut state = ListState::default();
         state.select(Some(0));
-        Columns { cols, state }
+        Columns {
+            cols,
+            state,
+            scroll: cfg.scroll,
+            human: cfg.human,
+            latency: cfg.latency_unit.unwrap_or_default(),
+        }
+    }
+
+    /// The enabled columns and their indices, in display order.  Custom
+    /// columns are displayed just before Name, which is always last.
+    fn enabled(&self) -> impl Iterator<Item = (usize, &Column)> {
+        (0..Self::NAME)
+            .chain(Self::LEN..self.cols.len())
+            .chain([Self::NAME])
+            .map(move |i| (i, &self.cols[i]))
+            .filter(|(_, col)| col.enabled)
+    }
+
+    /// Does column `k` measure data in kB?  These are scaled by `--human`.
+    fn is_kb(k: usize) -> bool {
+        Self::unit(k) == Some(Unit::Kb)
+    }
+
+    /// How column `k`'s values are formatted, or `None` for custom columns
+    fn unit(k: usize) -> Option<Unit> {
+        column::BUILTIN.get(k).map(|spec| spec.unit)
+    }
+
+    /// The text displayed for `elem` in column `k`, without any padding
+    fn text(&self, elem: &Element, k: usize) -> String {
+        let mut s = String::new();
+        self.write_text(elem, k, 0, &mut s);
+        s
+    }
+
+    /// Append the text displayed for `elem` in column `k` to `out`.  Numbers
+    /// are right-aligned to `width`.
+    fn write_text(
+        &self,
+        elem: &Element,
+        k: usize,
+        width: usize,
+        out: &mut String,
+    ) {
+        let field = Field::new(elem, k);
+        match field {
+            Field::Num(v, _) if self.human && Self::is_kb(k) => {
+                let (v, prec, u) = human_kb_parts(v);
+                let width = width.saturating_sub(u.len());
+                write!(out, "{v:>width$.prec$}{u}")
+            }
+            Field::Num(v, _) if Self::is_latency(k) => {
+                write!(out, "{:>width$.1}", self.latency.scale(v))
+            }
+            _ => write!(out, "{field:width$}"),
+        }
+        .unwrap()
+    }
+
+    /// Is column `k` a latency, displayed in the chosen [`LatencyUnit`]?
+    fn is_latency(k: usize) -> bool {
+        Self::unit(k) == Some(Unit::Latency)
+    }
+
+    /// Column `k`'s header, in the chosen latency unit but without padding
+    fn label(&self, k: usize) -> Cow<'static, str> {
+        let header = self.cols[k].header;
+        if Self::is_latency(k) && self.latency != LatencyUnit::Ms {
+            Cow::Owned(header.replace("ms", self.latency.label()))
+        } else {
+            Cow::Borrowed(header)
+        }
+    }
+
+    /// Format column `k`'s header for a frame in which it is `width` wide.
+    /// Numeric headers are right-aligned over their values.
+    fn header(&self, k: usize, width: u16) -> String {
+        let label = self.label(k);
+        if k == Self::NAME || Self::is_text(k) {
+            label.into_owned()
+        } else {
+            // One character of the column is used as spacing
+            let w = usize::from(width) - 1;
+            format!("{label:>w$}")
+        }
+    }
+
+    /// Is column `k` left-aligned text?
+    fn is_text(k: usize) -> bool {
+        k != Self::NAME && Self::unit(k) == Some(Unit::Text)
+    }
+
+    /// Column `k`'s minimum width, including a space to separate it from its
+    /// neighbor: enough for its header, and for the widest value that its
+    /// format expects in the current settings.  [`RowCache`] widens it further
+    /// for any frame with wider values.
+    fn width(&self, k: usize) -> u16 {
+        let spec = column::BUILTIN.get(k);
+        let value = match spec.map(|spec| (spec.unit, spec.digits)) {
+            Some((Unit::Kb, _)) if self.human => HUMAN_KB_WIDTH,
+            // Text columns begin with a space
+            Some((Unit::Text, chars)) => chars + u16::from(Self::is_text(k)),
+            Some((unit, digits)) => {
+                let digits = if unit == Unit::Latency {
+                    digits + self.latency.extra_digits()
+                } else {
+                    digits
+                };
+                match unit.precision() {
+                    0 => digits,
+                    prec => digits + 1 + prec as u16,
+                }
+            }
+            // Custom columns have one decimal place
+            None => Self::CUSTOM_DIGITS + 2,
+        };
+        let label = u16::try_from(self.label(k).chars().count()).unwrap();
+        label.max(value) + 1
+    }
+
+    /// The layout constraint of column `k`, for a frame in which it is `width`
+    /// wide.  Only Name grows, to fill the screen.
+    fn constraint(&self, k: usize, width: u16) -> Constraint {
+        if k == Self::NAME {
+            Constraint::Min(width)
+        } else {
+            Constraint::Length(width)
+        }
+    }
+
+    /// The columns to display, in display order: the enabled ones, less any
+    /// that are scrolled off to the left.  Name is always displayed.
+    fn visible(&self) -> impl Iterator<Item = (usize, &Column)> {
+        // Always display at least one column besides Name
+        let scroll = self.scroll.min(self.scrollable().saturating_sub(1));
+        self.enabled()
+            .enumerate()
+            .filter(move |(pos, (k, _))| *k == Self::NAME || *pos >= scroll)
+            .map(|(_, c)| c)
+    }
+
+    /// The number of enabled columns that may be scrolled off
+    fn scrollable(&self) -> usize {
+        self.enabled().filter(|(k, _)| *k != Self::NAME).count()
+    }
+
+    fn scroll_left(&mut self) {
+        self.scroll = self.scroll.saturating_sub(1);
+    }
+
+    fn scroll_right(&mut self) {
+        self.scroll =
+            (self.scroll + 1).min(self.scrollable().saturating_sub(1));
+    }
+
+    /// Enable exactly the built-in columns in `cb`.  Custom columns are
+    /// unaffected.
+    fn apply(&mut self, cb: ColumnsEnabled) {
+        for col in self.cols.iter_mut() {
+            if let Some(bit) = col.bit {
+                col.enabled = cb.0 & (1 << bit) != 0;
+            }
+        }
+    }
+
+    /// The built-in columns that are enabled
+    fn enabled_bits(&self) -> ColumnsEnabled {
+        let bits = self
+            .cols
+            .iter()
+            .filter(|col| col.enabled)
+            .filter_map(|col| col.bit)
+            .fold(0, |bits, bit| bits | 1 << bit);
+        ColumnsEnabled(bits)
+    }
+
+    /// Enable exactly the columns with these headers, including custom ones,
+    /// ignoring padding and case.
+    fn apply_headers(&mut self, headers: &[String]) -> Result<()> {
+        let matches = |col: &Column, h: &str| {
+            col.header.trim().eq_ignore_ascii_case(h.trim())
+        };
+        if let Some(h) = headers
+            .iter()
+            .find(|h| !self.cols.iter().any(|col| matches(col, h)))
+        {
+            let valid = self
+                .cols
+                .iter()
+                .map(|col| col.header.trim())
+                .collect::<Vec<_>>();
+            bail!(
+                "unknown column {h:?}.  Valid columns are: {}",
+                valid.join(", ")
+            );
+        }
+        for col in self.cols.iter_mut() {
+            col.enabled = headers.iter().any(|h| matches(col, h));
+        }
+        Ok(())
+    }
+
+    /// The index of the column with this header, ignoring padding
+    fn find(&self, header: &str) -> Option<usize> {
+        self.cols
+            .iter()
+            .position(|col| col.header.trim() == header.trim())
     }
 
     // This value is "defined" by the unit test of the same name.
     pub const fn max_name_width(&self) -> u16 {
-        17
+        21
     }
 
     pub fn next(&mut self) {
@@ -361,8 +478,117 @@ impl Columns {
     }
 }
 
+impl LatencyUnit {
+    fn label(self) -> &'static str {
+        match self {
+            LatencyUnit::Ms => "ms",
+            LatencyUnit::Us => "µs",
+        }
+    }
+
+    /// Convert a latency, given in milliseconds, to this unit
+    fn scale(self, ms: f64) -> f64 {
+        match self {
+            LatencyUnit::Ms => ms,
+            LatencyUnit::Us => ms * 1000.0,
+        }
+    }
+
+    /// How many more digits a latency has in this unit than in ms
+    fn extra_digits(self) -> u16 {
+        match self {
+            LatencyUnit::Ms => 0,
+            LatencyUnit::Us => 3,
+        }
+    }
+
+    fn next(self) -> Self {
+        match self {
+            LatencyUnit::Ms => LatencyUnit::Us,
+            LatencyUnit::Us => LatencyUnit::Ms,
+        }
+    }
+}
+
+/// The most characters that [`human_kb`] returns
+const HUMAN_KB_WIDTH: u16 = 5;
+
+/// Format a quantity of kB with a unit suffix, in at most 5 characters, like
+/// zpool-iostat(8).
+fn human_kb(kb: f64) -> String {
+    let (v, prec, u) = human_kb_parts(kb);
+    format!("{v:.prec$}{u}")
+}
+
+/// Scale a quantity of kB for [`human_kb`].  Returns the scaled value, the
+/// digits to display after its decimal point, and its unit suffix.
+fn human_kb_parts(kb: f64) -> (f64, usize, &'static str) {
+    const UNITS: [&str; 5] = ["k", "M", "G", "T", "P"];
+
+    if kb == 0.0 {
+        return (0.0, 0, "");
+    }
+    let mut v = kb;
+    let mut unit = 0;
+    while v >= 1023.5 && unit < UNITS.len() - 1 {
+        v /= 1024.0;
+        unit += 1;
+    }
+    let prec = if v < 9.995 {
+        2
+    } else if v < 99.95 {
+        1
+    } else {
+        0
+    };
+    (v, prec, UNITS[unit])
+}
+
+/// Color a percentage of some maximum, like %busy, by how close it is.
+fn pct_style(pct: f64) -> Style {
+    const HIGH_THRESH: f64 = 80.0;
+    const MEDIUM_THRESH: f64 = 50.0;
+
+    let color = if pct > HIGH_THRESH {
+        Color::Red
+    } else if pct > MEDIUM_THRESH {
+        Color::Magenta
+    } else {
+        Color::Green
+    };
+    Style::default().fg(color)
+}
+
+/// Color a latency by the thresholds for the kind of disk that it's on, if
+/// known.
+fn latency_style(ms: f64, kind: Option<Kind>) -> Style {
+    let Some(kind) = kind else {
+        return Style::default();
+    };
+    let (medium, high) = kind.latency_thresholds();
+    let color = if ms > high {
+        Color::Red
+    } else if ms > medium {
+        Color::Magenta
+    } else {
+        Color::Green
+    };
+    Style::default().fg(color)
+}
+
+/// A device's rated performance, from the config file
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+struct Limit {
+    /// Maximum operations per second
+    #[serde(default)]
+    iops: Option<f64>,
+    /// Maximum throughput of reads, writes, and deletes in kB/s
+    #[serde(default)]
+    kbs:  Option<f64>,
+}
+
 /// The data for one element in the table, usually a Geom provider
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 struct Element {
     qd:       u32,
     ops_s:    f64,
@@ -381,202 +607,990 @@ struct Element {
     o_s:      f64,
     ms_o:     f64,
     pct_busy: f64,
-    name:     String,
+    /// Fraction of the bytes transferred that were reads rather than writes,
+    /// or None if there were neither.
+    #[serde(default)]
+    rw_bal:   Option<f64>,
+    /// I/O errors during the interval, if the device reports them
+    #[serde(default)]
+    errs:     Option<u64>,
+    /// Requests waiting in the CAM I/O scheduler, if the device has one
+    #[serde(default)]
+    sq:       Option<u32>,
+    /// Moving average latency reported by the CAM I/O scheduler
+    #[serde(default)]
+    ms_sq:    Option<f64>,
+    /// Transactions outstanding, averaged over the interval
+    #[serde(default)]
+    avg_qd:   f64,
+    /// Total throughput, decaying from its recent peak
+    #[serde(default)]
+    peak_kbs: Option<f64>,
+    name:     Arc<str>,
+    /// GEOM class, or driver name for devices that aren't GEOM providers
+    #[serde(default)]
+    class:    String,
+    /// GEOM rank, or 0 for devices that aren't GEOM providers
     rank:     u32,
+    /// IOPs as a percentage of the configured limit, if any
+    #[serde(default)]
+    pct_iops: Option<f64>,
+    /// Throughput as a percentage of the configured limit, if any
+    #[serde(default)]
+    pct_kbs:  Option<f64>,
+    /// Name of the provider beneath this one in the GEOM hierarchy, if any
+    #[serde(default)]
+    parent:   Option<Arc<str>>,
+    /// Identification of the physical disk, for DISK providers
+    #[serde(default)]
+    disk:     Option<Disk>,
+    /// Kind of the disk that this provider is ultimately built upon
+    #[serde(default)]
+    kind:     Option<Kind>,
+    /// A GEOM consumer rather than a provider, named like gstat(8) names
+    /// them: "class/geom/provider"
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    consumer: bool,
+    /// Indentation level in tree view
+    #[serde(skip)]
+    depth:    usize,
+    /// In tree view, this element's descendants are hidden
+    #[serde(skip)]
+    folded:   bool,
+    /// Values of the custom columns, in the order they were defined
+    #[serde(skip)]
+    custom:   Vec<Option<f64>>,
+    /// Names of the GEOM labels of this provider, with --aliases
+    #[serde(skip)]
+    aliases:  Vec<Arc<str>>,
+    /// Names of the statistics that were unusually high this interval
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    outliers: Vec<String>,
 }
 
 impl Element {
-    fn new(name: &str, rank: u32, stats: &Statistics) -> Self {
+    /// Names of the statistics that [`Element::stat`] can look up
+    const STATS: [&'static str; 27] = [
+        "qd", "ops_s", "r_s", "kb_r", "kbs_r", "ms_r", "w_s", "kb_w", "kbs_w",
+        "ms_w", "d_s", "kb_d", "kbs_d", "ms_d", "o_s", "ms_o", "pct_busy",
+        "rw_bal", "errs", "rank", "pct_iops", "pct_kbs", "tbw_d", "sq",
+        "ms_sq", "avg_qd", "peak_kbs",
+    ];
+
+    fn new(name: Arc<str>, rank: u32, stats: &Record) -> Self {
+        let rw_bytes = stats.total_bytes_read + stats.total_bytes_write;
+        let rw_bal = if rw_bytes > 0 {
+            Some(stats.total_bytes_read as f64 / rw_bytes as f64)
+        } else {
+            None
+        };
         Element {
-            qd: stats.queue_length(),
-            ops_s: stats.transfers_per_second(),
-            r_s: stats.transfers_per_second_read(),
-            kb_r: stats.kb_per_transfer_read(),
-            kbs_r: stats.mb_per_second_read() * 1024.0,
-            ms_r: stats.ms_per_transaction_read(),
-            w_s: stats.transfers_per_second_write(),
-            kb_w: stats.kb_per_transfer_write(),
-            kbs_w: stats.mb_per_second_write() * 1024.0,
-            ms_w: stats.ms_per_transaction_write(),
-            d_s: stats.transfers_per_second_free(),
-            kb_d: stats.kb_per_transfer_free(),
-            kbs_d: stats.mb_per_second_free() * 1024.0,
-            ms_d: stats.ms_per_transaction_free(),
-            o_s: stats.transfers_per_second_other(),
-            ms_o: stats.ms_per_transaction_other(),
-            pct_busy: stats.busy_pct(),
-            name: name.to_owned(),
+            qd: stats.queue_length,
+            ops_s: stats.transfers_per_second,
+            r_s: stats.transfers_per_second_read,
+            kb_r: stats.kb_per_transfer_read,
+            kbs_r: stats.mb_per_second_read * 1024.0,
+            ms_r: stats.ms_per_transaction_read,
+            w_s: stats.transfers_per_second_write,
+            kb_w: stats.kb_per_transfer_write,
+            kbs_w: stats.mb_per_second_write * 1024.0,
+            ms_w: stats.ms_per_transaction_write,
+            d_s: stats.transfers_per_second_free,
+            kb_d: stats.kb_per_transfer_free,
+            kbs_d: stats.mb_per_second_free * 1024.0,
+            ms_d: stats.ms_per_transaction_free,
+            o_s: stats.transfers_per_second_other,
+            ms_o: stats.ms_per_transaction_other,
+            pct_busy: stats.busy_pct,
+            rw_bal,
+            errs: None,
+            sq: None,
+            ms_sq: None,
+            avg_qd: stats.avg_queue_depth,
+            peak_kbs: None,
+            name,
+            class: String::new(),
             //fields: f,
             rank,
+            pct_iops: None,
+            pct_kbs: None,
+            parent: None,
+            disk: None,
+            kind: None,
+            consumer: false,
+            depth: 0,
+            folded: false,
+            custom: Vec::new(),
+            aliases: Vec::new(),
+            outliers: Vec::new(),
         }
     }
 
-    /// Like [`std::cmp::PartialOrd::partial_cmp`], but based on the selected
-    /// field.
-    fn partial_cmp_by(&self, k: usize, other: &Self) -> Option<Ordering> {
-        match k {
-            Columns::QD => self.qd.partial_cmp(&other.qd),
-            Columns::OPS_S => self.ops_s.partial_cmp(&other.ops_s),
-            Columns::R_S => self.r_s.partial_cmp(&other.r_s),
-            Columns::KB_R => self.kb_r.partial_cmp(&other.kb_r),
-            Columns::KBS_R => self.kbs_r.partial_cmp(&other.kbs_r),
-            Columns::MS_R => self.ms_r.partial_cmp(&other.ms_r),
-            Columns::W_S => self.w_s.partial_cmp(&other.w_s),
-            Columns::KB_W => self.kb_w.partial_cmp(&other.kb_w),
-            Columns::KBS_W => self.kbs_w.partial_cmp(&other.kbs_w),
-            Columns::MS_W => self.ms_w.partial_cmp(&other.ms_w),
-            Columns::D_S => self.d_s.partial_cmp(&other.d_s),
-            Columns::KB_D => self.kb_d.partial_cmp(&other.kb_d),
-            Columns::KBS_D => self.kbs_d.partial_cmp(&other.kbs_d),
-            Columns::MS_D => self.ms_d.partial_cmp(&other.ms_d),
-            Columns::O_S => self.o_s.partial_cmp(&other.o_s),
-            Columns::MS_O => self.ms_o.partial_cmp(&other.ms_o),
-            Columns::PCT_BUSY => self.pct_busy.partial_cmp(&other.pct_busy),
-            Columns::NAME => self.name.partial_cmp(&other.name),
-            _ => None,
-        }
+    /// The write throughput extrapolated to a day, in terabytes, for budgeting
+    /// an SSD's rated endurance.
+    ///
+    /// Over a single interval it's noisy; it's steadiest in cumulative mode.
+    fn tbw_d(&self) -> f64 {
+        const SECS_PER_DAY: f64 = 86400.0;
+        self.kbs_w * 1024.0 * SECS_PER_DAY / 1e12
     }
 
-    fn row(&self, columns: &Columns) -> Row {
-        let mut cells = Vec::with_capacity(Columns::LEN);
-        if columns.cols[Columns::QD].enabled {
-            cells.push(Cell::from(format!("{:>4}", self.qd)));
-        }
-        if columns.cols[Columns::OPS_S].enabled {
-            cells.push(Cell::from(format!("{:>6.0}", self.ops_s)));
+    /// The disk's serial number, if known
+    fn serial(&self) -> Option<&str> {
+        self.disk.as_ref()?.ident.as_deref()
+    }
+
+    /// Lines describing the element in detail, for the detail popup
+    fn detail(&self) -> Vec<String> {
+        let mut fields = vec![("Class", Some(self.class.clone()))];
+        if self.rank > 0 {
+            fields.push(("Rank", Some(self.rank.to_string())));
         }
-        if columns.cols[Columns::R_S].enabled {
-            cells.push(Cell::from(format!("{:>6.0}", self.r_s)));
+        if let Some(disk) = self.disk.as_ref() {
+            fields.push(("Serial", disk.ident.clone()));
+            fields.push(("Descr", disk.descr.clone()));
+            fields.push(("LUN ID", disk.lunid.clone()));
         }
-        if columns.cols[Columns::KB_R].enabled {
-            cells.push(Cell::from(format!("{:>4.0}", self.kb_r)));
+        fields.push(("Kind", self.kind.map(|k| k.to_string())));
+        fields
+            .into_iter()
+            .filter_map(|(label, value)| Some(format!("{label:7} {}", value?)))
+            .collect()
+    }
+
+    /// Each operation type's share of the total time spent on I/O during the
+    /// interval: read, write, delete, and other.  `None` if there was none.
+    fn busy_shares(&self) -> Option<[f64; 4]> {
+        let ms = [
+            self.r_s * self.ms_r,
+            self.w_s * self.ms_w,
+            self.d_s * self.ms_d,
+            self.o_s * self.ms_o,
+        ];
+        let total: f64 = ms.iter().sum();
+        (total > 0.0).then(|| ms.map(|ms| ms / total))
+    }
+
+    /// The busy time breakdown popup: a stacked bar `width` cells wide, and
+    /// then each operation type's percentage.
+    fn busy_lines(&self, width: usize) -> Vec<Line<'static>> {
+        const TYPES: [(&str, Color); 4] = [
+            ("read", Color::Red),
+            ("write", Color::Blue),
+            ("delete", Color::Yellow),
+            ("other", Color::Magenta),
+        ];
+
+        let Some(shares) = self.busy_shares() else {
+            return vec![Line::from("No I/O during this interval")];
+        };
+        // Round the running total, so the segments always fill the bar
+        let mut bar = Vec::with_capacity(TYPES.len());
+        let mut cum = 0.0;
+        let mut used = 0;
+        for ((_, color), share) in TYPES.iter().zip(shares) {
+            cum += share;
+            let end = ((cum * width as f64).round() as usize).min(width);
+            bar.push(Span::styled(
+                "█".repeat(end - used),
+                Style::default().fg(*color),
+            ));
+            used = end;
         }
-        if columns.cols[Columns::KBS_R].enabled {
-            cells.push(Cell::from(format!("{:>6.0}", self.kbs_r)));
+        let mut lines = vec![Line::from(bar), Line::default()];
+        lines.extend(TYPES.iter().zip(shares).map(
+            |((label, color), share)| {
+                Line::from(vec![
+                    Span::styled("█ ", Style::default().fg(*color)),
+                    Span::raw(format!("{label:6} {:>5.1}%", share * 100.0)),
+                ])
+            },
+        ));
+        lines
+    }
+
+    /// Should this element be displayed, given the current settings?
+    fn visible(&self, cfg: &Cli, filter: Option<&Regex>) -> bool {
+        (!cfg.auto || self.pct_busy > 0.1)
+            && (!cfg.physical || self.rank == 1)
+            && cfg
+                .class
+                .as_ref()
+                .map(|c| c.iter().any(|c| c.eq_ignore_ascii_case(&self.class)))
+                .unwrap_or(true)
+            && cfg
+                .kind
+                .as_ref()
+                .map(|k| self.kind.is_some_and(|kind| k.contains(&kind)))
+                .unwrap_or(true)
+            && filter
+                .map(|f| {
+                    f.is_match(&self.name)
+                        || self.aliases.iter().any(|a| f.is_match(a))
+                })
+                .unwrap_or(true)
+    }
+
+    /// The name, indented by its depth in tree view and followed by any
+    /// aliases
+    fn display_name(&self) -> String {
+        let mut s = String::new();
+        self.write_display_name(&mut s);
+        s
+    }
+
+    /// Append [`Element::display_name`] to `out`
+    fn write_display_name(&self, out: &mut String) {
+        let marker = if self.folded { "+" } else { "" };
+        write!(
+            out,
+            "{:indent$}{marker}{}",
+            "",
+            self.name,
+            indent = 2 * self.depth
+        )
+        .unwrap();
+        for (i, alias) in self.aliases.iter().enumerate() {
+            let sep = if i == 0 { " (" } else { ", " };
+            out.push_str(sep);
+            out.push_str(alias);
         }
-        if columns.cols[Columns::MS_R].enabled {
-            cells.push(Cell::from(format!("{:>6.1}", self.ms_r)));
+        if !self.aliases.is_empty() {
+            out.push(')');
         }
-        if columns.cols[Columns::W_S].enabled {
-            cells.push(Cell::from(format!("{:>6.0}", self.w_s)));
+    }
+
+    /// Look up a numeric statistic by its field name.
+    fn stat(&self, name: &str) -> Option<f64> {
+        match name {
+            "qd" => Some(f64::from(self.qd)),
+            "ops_s" => Some(self.ops_s),
+            "r_s" => Some(self.r_s),
+            "kb_r" => Some(self.kb_r),
+            "kbs_r" => Some(self.kbs_r),
+            "ms_r" => Some(self.ms_r),
+            "w_s" => Some(self.w_s),
+            "kb_w" => Some(self.kb_w),
+            "kbs_w" => Some(self.kbs_w),
+            "ms_w" => Some(self.ms_w),
+            "d_s" => Some(self.d_s),
+            "kb_d" => Some(self.kb_d),
+            "kbs_d" => Some(self.kbs_d),
+            "ms_d" => Some(self.ms_d),
+            "o_s" => Some(self.o_s),
+            "ms_o" => Some(self.ms_o),
+            "pct_busy" => Some(self.pct_busy),
+            "rw_bal" => self.rw_bal,
+            "errs" => self.errs.map(|e| e as f64),
+            // Devices that aren't GEOM providers have none
+            "rank" => Some(f64::from(self.rank)).filter(|r| *r > 0.0),
+            "pct_iops" => self.pct_iops,
+            "pct_kbs" => self.pct_kbs,
+            "tbw_d" => Some(self.tbw_d()),
+            "sq" => self.sq.map(f64::from),
+            "ms_sq" => self.ms_sq,
+            "avg_qd" => Some(self.avg_qd),
+            "peak_kbs" => self.peak_kbs,
+            _ => None,
         }
-        if columns.cols[Columns::KB_W].enabled {
-            cells.push(Cell::from(format!("{:>4.0}", self.kb_w)));
+    }
+
+    /// Look up a textual property by its field name.
+    fn text(&self, name: &str) -> Option<&str> {
+        match name {
+            "name" => Some(&self.name),
+            "class" => Some(&self.class),
+            "serial" => self.serial(),
+            "kind" => self.kind.map(Kind::as_str),
+            _ => None,
         }
-        if columns.cols[Columns::KBS_W].enabled {
-            cells.push(Cell::from(format!("{:>6.0}", self.kbs_w)));
+    }
+
+    /// Like [`std::cmp::PartialOrd::partial_cmp`], but based on the selected
+    /// field.
+    fn partial_cmp_by(&self, k: usize, other: &Self) -> Option<Ordering> {
+        if k == Columns::NAME {
+            return Some(name_cmp(&self.name, &other.name));
         }
-        if columns.cols[Columns::MS_W].enabled {
-            cells.push(Cell::from(format!("{:>6.1}", self.ms_w)));
+        // Blank values sort before any others
+        match (Field::new(self, k), Field::new(other, k)) {
+            (Field::Num(a, _), Field::Num(b, _)) => a.partial_cmp(&b),
+            (Field::Text(a), Field::Text(b)) => a.partial_cmp(b),
+            (Field::Blank, Field::Blank) => Some(Ordering::Equal),
+            (Field::Blank, _) => Some(Ordering::Less),
+            (_, Field::Blank) => Some(Ordering::Greater),
+            _ => None,
         }
-        if columns.cols[Columns::D_S].enabled {
-            cells.push(Cell::from(format!("{:>6.0}", self.d_s)));
+    }
+
+    /// Compare by column `k`, breaking ties by name, so that rows with equal
+    /// values don't trade places between refreshes.
+    fn cmp_by(&self, k: usize, other: &Self, reverse: bool) -> Ordering {
+        let ord = self.partial_cmp_by(k, other).unwrap_or(Ordering::Equal);
+        let ord = if reverse { ord.reverse() } else { ord };
+        ord.then_with(|| name_cmp(&self.name, &other.name))
+    }
+
+    /// Underline the cells of statistics that were unusually high
+    fn outlier_style(&self, stat: &str, style: Style) -> Style {
+        if self.outliers.iter().any(|s| s == stat) {
+            style.add_modifier(Modifier::UNDERLINED)
+        } else {
+            style
         }
-        if columns.cols[Columns::KB_D].enabled {
-            cells.push(Cell::from(format!("{:>4.0}", self.kb_d)));
+    }
+
+    /// The style of this element's cell in column `k`
+    fn style(&self, k: usize) -> Style {
+        let latency =
+            |stat, ms| self.outlier_style(stat, latency_style(ms, self.kind));
+        match k {
+            Columns::MS_R => latency("ms_r", self.ms_r),
+            Columns::MS_W => latency("ms_w", self.ms_w),
+            Columns::MS_D => latency("ms_d", self.ms_d),
+            Columns::MS_O => latency("ms_o", self.ms_o),
+            Columns::PCT_BUSY => pct_style(self.pct_busy),
+            Columns::ERRS if self.errs.is_some_and(|errs| errs > 0) => {
+                Style::default().fg(Color::Red)
+            }
+            // Blank for devices without a configured limit
+            Columns::PCT_IOPS => {
+                self.pct_iops.map(pct_style).unwrap_or_default()
+            }
+            Columns::PCT_KBS => self.pct_kbs.map(pct_style).unwrap_or_default(),
+            _ => Style::default(),
         }
-        if columns.cols[Columns::KBS_D].enabled {
-            cells.push(Cell::from(format!("{:>6.0}", self.kbs_d)));
+    }
+}
+
+/// Live statistics from GEOM
+struct Geom {
+    /// The latest snapshot and its predecessors.  Statistics are computed
+    /// between the oldest and the latest, which yields a moving average.
+    snapshots:   Window<Snapshot>,
+    /// The snapshot taken when gstat started, for cumulative mode
+    start:       Snapshot,
+    /// The system's uptime when `cur` was taken
+    uptime:      f64,
+    tree:        TreeCache,
+    /// Error counts, which come from outside of devstat
+    errors:      ErrorCounts,
+    /// Read the error counts.  Each costs a sysctl per device, so they're
+    /// only read while their column is displayed.
+    read_errors: bool,
+    /// Likewise, read the I/O scheduler queues
+    read_sched:  bool,
+    /// Include devstat entries that aren't GEOM providers
+    all_devstat: bool,
+    /// Include GEOM consumers, like gstat(8)'s -c
+    consumers:   bool,
+    /// Each provider's parent in the GEOM hierarchy
+    parents:     HashMap<String, String>,
+    /// Identification of each disk, which only changes along with `tree`
+    disks:       HashMap<String, Disk>,
+    /// Detects devices arriving and departing whenever `tree` is rebuilt
+    watcher:     DeviceWatcher,
+    /// Device names, shared by every refresh's elements
+    names:       Interner,
+}
+
+impl Geom {
+    fn new(window: NonZeroUsize, all_devstat: bool) -> Result<Self> {
+        let tree = TreeCache::new().context("Error opening GEOM tree")?;
+        let parents = tree::parents(tree.tree());
+        let disks = disk::disks(tree.tree());
+        let mut watcher = DeviceWatcher::default();
+        watcher.update(tree.tree());
+        // XXX difference from gstat: the first display will show stats since
+        // each device's creation, like iostat.
+        let start =
+            Snapshot::new().context("obtaining initial GEOM snapshot")?;
+        let cur = Snapshot::new().context("obtaining initial GEOM snapshot")?;
+        let uptime = uptime()?;
+        Ok(Geom {
+            snapshots: Window::new(cur, window),
+            start,
+            uptime,
+            tree,
+            errors: ErrorCounts::default(),
+            read_errors: true,
+            read_sched: true,
+            all_devstat,
+            consumers: false,
+            parents,
+            disks,
+            watcher,
+            names: Interner::default(),
+        })
+    }
+
+    /// The kind of the disk that a provider is ultimately built upon, found by
+    /// following its parents down to the DISK class.
+    ///
+    /// Takes the maps rather than `&self`, so it can be used while iterating
+    /// over the snapshots.
+    fn kind<'a>(
+        disks: &HashMap<String, Disk>,
+        parents: &'a HashMap<String, String>,
+        mut name: &'a str,
+    ) -> Option<Kind> {
+        // Bound the walk, in case the hierarchy is somehow cyclic
+        for _ in 0..64 {
+            if let Some(disk) = disks.get(name) {
+                return disk.kind;
+            }
+            name = parents.get(name)?;
         }
-        if columns.cols[Columns::MS_D].enabled {
-            cells.push(Cell::from(format!("{:>6.1}", self.ms_d)));
+        None
+    }
+
+    /// Name a consumer the way gstat(8) does: "class/geom/provider"
+    fn consumer_name(gident: &Gident) -> Option<String> {
+        let consumer = gident.consumer()?;
+        let geom = consumer.geom()?;
+        let class = geom.class()?;
+        let provider = consumer.provider()?;
+        Some(format!(
+            "{}/{}/{}",
+            class.name().to_string_lossy(),
+            geom.name().to_string_lossy(),
+            provider.name().to_string_lossy()
+        ))
+    }
+
+    /// Take a new snapshot.  Returns any devices that have arrived or
+    /// departed since the last one.
+    fn refresh(&mut self) -> Result<Vec<DeviceEvent>> {
+        let mut events = Vec::new();
+        if self.tree.refresh().context("Error opening GEOM tree")? {
+            self.parents = tree::parents(self.tree.tree());
+            self.disks = disk::disks(self.tree.tree());
+            events = self.watcher.update(self.tree.tree());
         }
-        if columns.cols[Columns::O_S].enabled {
-            cells.push(Cell::from(format!("{:>6.0}", self.o_s)));
+        let ss = Snapshot::new().context("obtaining GEOM snapshot")?;
+        self.uptime = uptime()?;
+        self.snapshots.push(ss);
+        self.errors.rotate();
+        Ok(events)
+    }
+
+    /// Compute the statistics for every provider into `items`
+    ///
+    /// If `cumulative`, then compute them since gstat started.  Otherwise,
+    /// over the most recent window.
+    fn regen(
+        &mut self,
+        items: &mut Vec<Element>,
+        cumulative: bool,
+    ) -> Result<()> {
+        let (cur, mut prev) = self.snapshots.ends_mut();
+        if cumulative && prev.is_some() {
+            prev = Some(&mut self.start);
         }
-        if columns.cols[Columns::MS_O].enabled {
-            cells.push(Cell::from(format!("{:>6.1}", self.ms_o)));
+        let etime = if let Some(prev) = prev.as_deref() {
+            f64::from(cur.timestamp() - prev.timestamp())
+        } else {
+            self.uptime
+        };
+        items.reserve(cur.len());
+        for (curstat, prevstat) in cur.iter_pair(prev) {
+            match self.tree.lookup(curstat.id()) {
+                Some(gident) => {
+                    if let (Some(rank), Ok(name)) =
+                        (gident.rank(), gident.name())
+                    {
+                        let stats = Statistics::compute_or_since_creation(
+                            curstat,
+                            prevstat,
+                            etime,
+                            Some(self.uptime),
+                        )
+                        .to_record();
+                        let name = self.names.intern(&name.to_string_lossy());
+                        let mut elem =
+                            Element::new(Arc::clone(&name), rank, &stats);
+                        if let Some(class) = gident.class() {
+                            elem.class = class.to_string_lossy().into_owned();
+                        }
+                        elem.parent = self
+                            .parents
+                            .get(&*name)
+                            .map(|parent| self.names.intern(parent));
+                        elem.disk = self.disks.get(&*name).cloned();
+                        elem.kind =
+                            Geom::kind(&self.disks, &self.parents, &name);
+                        if rank == 1 {
+                            if self.read_errors {
+                                elem.errs = self.errors.get(&name);
+                            }
+                            if let Some(sched) = self
+                                .read_sched
+                                .then(|| iosched::read(&name))
+                                .flatten()
+                            {
+                                elem.sq = Some(sched.queued);
+                                elem.ms_sq = sched.ms;
+                            }
+                        }
+                        items.push(elem);
+                    } else if let Some(name) = self
+                        .consumers
+                        .then(|| Geom::consumer_name(&gident))
+                        .flatten()
+                    {
+                        let stats = Statistics::compute_or_since_creation(
+                            curstat,
+                            prevstat,
+                            etime,
+                            Some(self.uptime),
+                        )
+                        .to_record();
+                        let name = self.names.intern(&name);
+                        let mut elem = Element::new(name, 0, &stats);
+                        if let Some(class) = gident.class() {
+                            elem.class = class.to_string_lossy().into_owned();
+                        }
+                        elem.consumer = true;
+                        items.push(elem);
+                    }
+                }
+                None if self.all_devstat => {
+                    let stats = Statistics::compute_or_since_creation(
+                        curstat,
+                        prevstat,
+                        etime,
+                        Some(self.uptime),
+                    )
+                    .to_record();
+                    let name = self.names.intern(&curstat.devname());
+                    let mut elem = Element::new(name, 0, &stats);
+                    elem.class =
+                        curstat.device_name().to_string_lossy().into_owned();
+                    items.push(elem);
+                }
+                None => {}
+            }
         }
-        if columns.cols[Columns::PCT_BUSY].enabled {
-            const BUSY_HIGH_THRESH: f64 = 80.0;
-            const BUSY_MEDIUM_THRESH: f64 = 50.0;
+        // The previous refresh's elements are gone by now, so any name that
+        // isn't in `items` belongs to a departed device.
+        self.names.prune();
+        Ok(())
+    }
+}
 
-            let color = if self.pct_busy > BUSY_HIGH_THRESH {
-                Color::Red
-            } else if self.pct_busy > BUSY_MEDIUM_THRESH {
-                Color::Magenta
-            } else {
-                Color::Green
-            };
-            let style = Style::default().fg(color);
-            let s = format!("{:>6.1}", self.pct_busy);
-            let cell = Cell::from(s).style(style);
-            cells.push(cell);
-        }
-        if columns.cols[Columns::NAME].enabled {
-            cells.push(Cell::from(self.name.clone()));
+enum Source {
+    // Boxed, because a Geom is much larger than a Replay
+    Geom(Box<Geom>),
+    Replay(Replay),
+}
+
+/// The settings that decide which elements are visible, as of
+/// [`DataSource::update_visible`]
+#[derive(Debug)]
+struct Filters {
+    regex:    Option<String>,
+    auto:     bool,
+    physical: bool,
+    class:    Option<Vec<String>>,
+    kind:     Option<Vec<Kind>>,
+}
+
+impl Filters {
+    fn new(cfg: &Cli) -> Self {
+        Filters {
+            regex:    cfg.filter.clone(),
+            auto:     cfg.auto,
+            physical: cfg.physical,
+            class:    cfg.class.clone(),
+            kind:     cfg.kind.clone(),
         }
-        Row::new(cells)
+    }
+
+    /// Are these still `cfg`'s settings?
+    fn matches(&self, cfg: &Cli) -> bool {
+        self.regex == cfg.filter
+            && self.auto == cfg.auto
+            && self.physical == cfg.physical
+            && self.class == cfg.class
+            && self.kind == cfg.kind
     }
 }
 
 struct DataSource {
-    prev:  Option<Snapshot>,
-    cur:   Snapshot,
-    tree:  Tree,
-    items: Vec<Element>,
+    source:     Source,
+    items:      Vec<Element>,
+    /// Indices into `items` of the visible elements
+    visible:    Vec<usize>,
+    /// The filters that `visible` was computed with, or None if `items` have
+    /// been loaded or reordered since
+    filtered:   Option<Filters>,
+    /// The column and direction that `items` are sorted by, or None if they
+    /// haven't been sorted since they were loaded
+    sorted:     Option<(Option<usize>, bool)>,
+    recorder:   Option<Recorder>,
+    /// Show statistics since gstat started, rather than per interval
+    cumulative: bool,
+    /// Aggregate the statistics by GEOM class
+    group:      bool,
+    /// System-wide totals of the physical devices
+    total:      Element,
+    /// Total throughput in kB/s at each refresh, oldest first
+    throughput: Vec<u64>,
+    /// How many throughput samples have ever been taken
+    samples:    usize,
+    /// Every marker of the session, and how many throughput samples had been
+    /// taken before it
+    markers:    Vec<(usize, Marker)>,
+    /// When replaying, the markers reached at the most recent refresh or seek
+    reached:    Vec<Marker>,
+    /// Arrange the elements according to the GEOM hierarchy
+    tree:       bool,
+    /// In tree view, hide the descendants of these elements
+    collapsed:  HashSet<String>,
+    /// Definitions of the custom columns
+    exprs:      Vec<Expr>,
+    /// Fold GEOM labels into the providers that they label
+    aliases:    bool,
+    /// Rated performance of each device, by name
+    limits:     HashMap<String, Limit>,
+    /// Devices that arrived or departed at the most recent refresh
+    events:     Vec<DeviceEvent>,
+    /// Flags unusual intervals of live data.  Recordings already contain
+    /// their flags.
+    outliers:   Option<outlier::Detector>,
+    /// Tracks each device's recent peak throughput from live data.
+    /// Recordings already contain their peaks.
+    peaks:      Option<peak::Tracker>,
 }
 
 impl DataSource {
-    fn new() -> Result<DataSource> {
-        let tree = Tree::new().context("Error opening GEOM tree")?;
-        let prev = None;
-        // XXX difference from gstat: the first display will show stats since
-        // boot, like iostat.
-        let cur = Snapshot::new().context("obtaining initial GEOM snapshot")?;
+    /// Enough throughput history for the sparkline to span a wide terminal
+    const MAX_THROUGHPUT_HISTORY: usize = 512;
+
+    fn new(
+        window: NonZeroUsize,
+        all_devstat: bool,
+        half_life: Duration,
+    ) -> Result<DataSource> {
+        let geom = Geom::new(window, all_devstat)?;
+        let mut ds = DataSource::with_source(Source::Geom(Box::new(geom)))?;
+        ds.outliers = Some(outlier::Detector::default());
+        ds.peaks = Some(peak::Tracker::new(half_life.as_secs_f64()));
+        Ok(ds)
+    }
+
+    /// Play back a session previously saved with `--record`
+    fn replay(path: &Path) -> Result<DataSource> {
+        let replay = Replay::open(path)?;
+        let mut ds = DataSource::with_source(Source::Replay(replay))?;
+        ds.reach_markers();
+        Ok(ds)
+    }
+
+    fn with_source(source: Source) -> Result<DataSource> {
         let items = Default::default();
         let mut ds = DataSource {
-            prev,
-            cur,
-            tree,
+            source,
             items,
+            visible: Vec::new(),
+            filtered: None,
+            sorted: None,
+            recorder: None,
+            cumulative: false,
+            group: false,
+            total: group::total(&[]),
+            throughput: Vec::new(),
+            samples: 0,
+            markers: Vec::new(),
+            reached: Vec::new(),
+            tree: false,
+            collapsed: HashSet::new(),
+            exprs: Vec::new(),
+            aliases: false,
+            limits: HashMap::new(),
+            events: Vec::new(),
+            outliers: None,
+            peaks: None,
         };
         ds.regen()?;
         Ok(ds)
     }
 
-    pub fn refresh(&mut self) -> Result<()> {
-        let ss = Snapshot::new().context("obtaining GEOM snapshot")?;
-        self.prev = Some(mem::replace(&mut self.cur, ss));
-        self.regen()?;
+    /// Record every interval's statistics, starting with the current one.
+    fn record(&mut self, path: &Path) -> Result<()> {
+        let mut recorder = Recorder::create(path)?;
+        recorder.write(&self.items)?;
+        self.recorder = Some(recorder);
         Ok(())
     }
 
-    /// Regenerate the data from geom
-    fn regen(&mut self) -> Result<()> {
-        let etime = if let Some(prev) = self.prev.as_mut() {
-            f64::from(self.cur.timestamp() - prev.timestamp())
-        } else {
-            let boottime = clock_gettime(ClockId::CLOCK_UPTIME)
-                .context("clock_gettime")?;
-            boottime.tv_sec() as f64 + boottime.tv_nsec() as f64 * 1e-9
+    pub fn refresh(&mut self) -> Result<()> {
+        // At the end of a replay, the same frame is shown again, and its
+        // markers mustn't be reached again.
+        let mut advanced = true;
+        self.events = match &mut self.source {
+            Source::Geom(geom) => geom.refresh()?,
+            Source::Replay(replay) => {
+                advanced = replay.seek(1);
+                Vec::new()
+            }
         };
-        self.items.clear();
-        for (curstat, prevstat) in self.cur.iter_pair(self.prev.as_mut()) {
-            if let Some(gident) = self.tree.lookup(curstat.id()) {
-                if let Some(rank) = gident.rank() {
-                    let stats = Statistics::compute(curstat, prevstat, etime);
-                    let name = gident.name().unwrap().to_string_lossy();
-                    let elem = Element::new(&name, rank, &stats);
-                    self.items.push(elem);
-                }
+        self.load()?;
+        // Cumulative statistics change too slowly to have outliers or peaks
+        if !self.cumulative {
+            if let Some(detector) = self.outliers.as_mut() {
+                detector.observe(&self.items);
             }
+            if let Some(peaks) = self.peaks.as_mut() {
+                peaks.observe(&self.items);
+            }
+        }
+        self.annotate();
+        if self.throughput.len() >= Self::MAX_THROUGHPUT_HISTORY {
+            self.throughput.remove(0);
+        }
+        if advanced {
+            self.reach_markers();
+        } else {
+            self.reached.clear();
+        }
+        let t = &self.total;
+        self.throughput.push((t.kbs_r + t.kbs_w + t.kbs_d) as u64);
+        self.samples += 1;
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.write(&self.items)?;
         }
         Ok(())
     }
 
-    fn sort(&mut self, sort_idx: Option<usize>, reverse: bool) {
-        if let Some(k) = sort_idx {
-            self.items.sort_by(|l, r| {
-                if reverse {
-                    r.partial_cmp_by(k, l)
-                } else {
-                    l.partial_cmp_by(k, r)
-                }
-                .unwrap()
-            });
+    /// Move forwards or backwards through a replayed session.  Does nothing
+    /// for live data.
+    pub fn seek(&mut self, delta: isize) -> Result<()> {
+        if let Source::Replay(replay) = &mut self.source {
+            replay.seek(delta);
+            self.regen()?;
+            self.reached = replay_markers(&self.source);
+        }
+        Ok(())
+    }
+
+    /// Drop a marker now, and record it if recording.
+    pub fn mark(&mut self, label: String) -> Result<()> {
+        let marker = Marker::now(label);
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.mark(&marker)?;
+        }
+        self.markers.push((self.samples, marker));
+        Ok(())
+    }
+
+    /// When replaying, note the markers recorded before the current frame.
+    fn reach_markers(&mut self) {
+        self.reached = replay_markers(&self.source);
+        self.markers
+            .extend(self.reached.iter().map(|m| (self.samples, m.clone())));
+    }
+
+    /// The offset within the sparkline of each marker that's visible, when
+    /// it displays the most recent `width` samples.
+    fn marker_offsets(&self, width: usize) -> Vec<usize> {
+        let first = self.samples - self.throughput.len().min(width);
+        marker_offsets(&self.markers, first, width)
+    }
+
+    /// Switch between cumulative and per-interval statistics.  Only applies
+    /// to live data.
+    pub fn set_cumulative(&mut self, cumulative: bool) -> Result<()> {
+        self.cumulative = cumulative;
+        self.regen()
+    }
+
+    /// Switch between per-device and per-class statistics.
+    pub fn set_group(&mut self, group: bool) -> Result<()> {
+        self.group = group;
+        self.regen()
+    }
+
+    /// Switch between a flat list and a tree view.
+    pub fn set_tree(&mut self, tree: bool) -> Result<()> {
+        self.tree = tree;
+        self.regen()
+    }
+
+    /// In tree view, hide or show the descendants of the named element.
+    pub fn set_collapsed(&mut self, name: &str, collapse: bool) -> Result<()> {
+        if collapse {
+            self.collapsed.insert(name.to_owned());
+        } else {
+            self.collapsed.remove(name);
+        }
+        self.regen()
+    }
+
+    /// Switch between displaying GEOM labels as rows or as aliases.
+    pub fn set_aliases(&mut self, aliases: bool) -> Result<()> {
+        self.aliases = aliases;
+        self.regen()
+    }
+
+    /// Include GEOM consumers as well as providers.  Recordings keep whatever
+    /// they were recorded with.
+    pub fn set_consumers(&mut self, consumers: bool) -> Result<()> {
+        if let Source::Geom(geom) = &mut self.source {
+            geom.consumers = consumers;
+        }
+        self.regen()
+    }
+
+    /// Only read the statistics that come from outside of devstat if their
+    /// columns are enabled, or alerts need them.  Recordings keep whatever
+    /// they were recorded with.
+    pub fn set_columns(
+        &mut self,
+        columns: &Columns,
+        alerts: &Alerts,
+    ) -> Result<()> {
+        let Source::Geom(geom) = &mut self.source else {
+            return Ok(());
+        };
+        let wanted =
+            |k: usize, stat| columns.cols[k].enabled || alerts.uses(stat);
+        let read_errors = wanted(Columns::ERRS, "errs");
+        let read_sched =
+            wanted(Columns::SQ, "sq") || wanted(Columns::MS_SQ, "ms_sq");
+        if (geom.read_errors, geom.read_sched) == (read_errors, read_sched) {
+            return Ok(());
+        }
+        geom.read_errors = read_errors;
+        geom.read_sched = read_sched;
+        self.regen()
+    }
+
+    /// Set the rated performance of each device, for the saturation columns.
+    pub fn set_limits(&mut self, limits: HashMap<String, Limit>) -> Result<()> {
+        self.limits = limits;
+        self.regen()
+    }
+
+    /// Set the definitions of the custom columns.
+    pub fn set_exprs(&mut self, exprs: Vec<Expr>) -> Result<()> {
+        self.exprs = exprs;
+        self.regen()
+    }
+
+    /// Regenerate the data from the source
+    fn regen(&mut self) -> Result<()> {
+        self.load()?;
+        self.annotate();
+        Ok(())
+    }
+
+    /// Load the elements from the source, aggregated as configured
+    fn load(&mut self) -> Result<()> {
+        self.items.clear();
+        self.filtered = None;
+        self.sorted = None;
+        match &mut self.source {
+            Source::Geom(geom) => {
+                geom.regen(&mut self.items, self.cumulative)?
+            }
+            Source::Replay(replay) => {
+                self.items.extend_from_slice(replay.items())
+            }
+        }
+        self.total = group::total(&self.items);
+        if self.aliases {
+            tree::fold_labels(&mut self.items);
+        }
+        if self.group {
+            self.items = group::by_class(&self.items);
+        }
+        Ok(())
+    }
+
+    /// Fill in the elements' derived fields: peaks, custom columns,
+    /// saturation, and outliers
+    fn annotate(&mut self) {
+        if let Some(peaks) = self.peaks.as_ref().filter(|_| !self.cumulative) {
+            peaks.mark(&mut self.items);
+        }
+        // Evaluate after grouping, so custom columns are computed from the
+        // aggregate statistics.
+        for elem in self.items.iter_mut().chain([&mut self.total]) {
+            elem.custom = self.exprs.iter().map(|e| e.eval(elem)).collect();
+            let limit =
+                self.limits.get(&*elem.name).copied().unwrap_or_default();
+            let kbs = elem.kbs_r + elem.kbs_w + elem.kbs_d;
+            elem.pct_iops = limit.iops.map(|max| elem.ops_s / max * 100.0);
+            elem.pct_kbs = limit.kbs.map(|max| kbs / max * 100.0);
+        }
+        if let Some(detector) =
+            self.outliers.as_ref().filter(|_| !self.cumulative)
+        {
+            detector.mark(&mut self.items);
         }
     }
+
+    /// Sort the elements, unless they're already sorted this way.
+    fn sort(&mut self, sort_idx: Option<usize>, reverse: bool) {
+        if self.sorted == Some((sort_idx, reverse)) {
+            return;
+        }
+        if let Some(k) = sort_idx {
+            self.items.sort_by(|l, r| l.cmp_by(k, r, reverse));
+        }
+        if self.tree {
+            let items = mem::take(&mut self.items);
+            self.items = tree::order(items, &self.collapsed);
+        }
+        self.sorted = Some((sort_idx, reverse));
+        self.filtered = None;
+    }
+
+    /// Find the elements that pass `cfg`'s filters, unless neither they nor
+    /// the elements have changed since last time.
+    fn update_visible(&mut self, cfg: &Cli, filter: Option<&Regex>) {
+        if self.filtered.as_ref().is_some_and(|f| f.matches(cfg)) {
+            return;
+        }
+        self.visible.clear();
+        self.visible.extend(
+            self.items
+                .iter()
+                .enumerate()
+                .filter(|(_, elem)| elem.visible(cfg, filter))
+                .map(|(i, _)| i),
+        );
+        self.filtered = Some(Filters::new(cfg));
+    }
+
+    /// The visible elements, in display order, as of the last
+    /// [`DataSource::update_visible`]
+    fn visible(&self) -> impl Iterator<Item = &Element> + Clone {
+        self.visible.iter().map(|&i| &self.items[i])
+    }
+}
+
+/// Which popup, if any, is receiving keystrokes
+enum Mode {
+    Normal,
+    /// Typing a new filter regex
+    EditingRegex(String),
+    /// Typing into the search prompt.  `found` says whether the text matched
+    /// anything.
+    Searching {
+        text:  String,
+        found: bool,
+    },
+    /// Typing the name of a new preset
+    NamingPreset(String),
+    /// Typing the label of a new marker
+    NamingMarker(String),
+    SelectingPreset,
+    ShowingDetail,
+    ShowingBusy,
+    ShowingHelp,
+    SelectingColumns,
 }
 
 #[derive(Default)]
@@ -620,6 +1634,40 @@ impl StatefulTable {
         self.state.select(s);
     }
 
+    /// The name of the selected row, given the rows' elements
+    fn selected_name<'a, I>(&self, rows: I) -> Option<Arc<str>>
+    where
+        I: IntoIterator<Item = &'a Element>,
+    {
+        let i = self.state.selected()?;
+        rows.into_iter().nth(i).map(|elem| elem.name.clone())
+    }
+
+    /// Select the row with the given name, wherever it has moved to.  If it's
+    /// gone, select nothing.
+    fn select_name<'a, I>(&mut self, rows: I, name: Option<&str>)
+    where
+        I: IntoIterator<Item = &'a Element>,
+    {
+        let i = name.and_then(|name| {
+            rows.into_iter().position(|elem| &*elem.name == name)
+        });
+        self.state.select(i);
+    }
+
+    /// Select the first row whose name contains `text`.  If there is none,
+    /// leave the selection alone and return false.
+    fn search<'a, I>(&mut self, rows: I, text: &str) -> bool
+    where
+        I: IntoIterator<Item = &'a Element>,
+    {
+        let i = rows.into_iter().position(|elem| elem.name.contains(text));
+        if i.is_some() {
+            self.state.select(i);
+        }
+        i.is_some()
+    }
+
     pub fn table<'a>(
         &mut self,
         header: Row<'a>,
@@ -637,6 +1685,207 @@ impl StatefulTable {
     }
 }
 
+/// Print a shell completion script for `shell` to stdout.
+fn print_completions(shell: Shell) -> Result<()> {
+    let mut cmd = Cli::command();
+    let bin_name = cmd.get_name().to_owned();
+    clap_complete::generate(shell, &mut cmd, bin_name, &mut io::stdout());
+    Ok(())
+}
+
+/// Ring the bell once for any number of alert messages, and optionally log
+/// them.
+fn alert<W: io::Write>(
+    terminal: &mut Terminal<CrosstermBackend<W>>,
+    msgs: &[String],
+    syslog: bool,
+) -> Result<()> {
+    if msgs.is_empty() {
+        return Ok(());
+    }
+    let backend = terminal.backend_mut();
+    backend.write_all(b"\x07").context("ringing bell")?;
+    backend.flush().context("ringing bell")?;
+    if syslog {
+        for msg in msgs {
+            // Interior NULs are impossible in our own messages
+            let msg = CString::new(msg.as_str()).unwrap();
+            unsafe {
+                libc::syslog(
+                    libc::LOG_WARNING,
+                    b"%s\0".as_ptr().cast(),
+                    msg.as_ptr(),
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The time since boot, in seconds
+fn uptime() -> Result<f64> {
+    let ts = clock_gettime(ClockId::CLOCK_UPTIME).context("clock_gettime")?;
+    Ok(ts.tv_sec() as f64 + ts.tv_nsec() as f64 * 1e-9)
+}
+
+/// Summarize device arrivals and departures for the status line.
+fn describe_events(events: &[DeviceEvent]) -> String {
+    let names = |arrived: bool| {
+        events
+            .iter()
+            .filter(|e| matches!(e, DeviceEvent::Arrived(_)) == arrived)
+            .map(DeviceEvent::name)
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let mut parts = Vec::new();
+    for (label, names) in [("Arrived", names(true)), ("Departed", names(false))]
+    {
+        if !names.is_empty() {
+            parts.push(format!("{label}: {names}"));
+        }
+    }
+    parts.join("  ")
+}
+
+/// The offset of each marker from sample number `first`, for those within
+/// `width` samples of it.  `markers` holds each marker's sample number.
+fn marker_offsets(
+    markers: &[(usize, Marker)],
+    first: usize,
+    width: usize,
+) -> Vec<usize> {
+    markers
+        .iter()
+        .filter_map(|(i, _)| i.checked_sub(first))
+        .filter(|x| *x < width)
+        .collect()
+}
+
+/// The markers that a replay recorded before its current frame
+fn replay_markers(source: &Source) -> Vec<Marker> {
+    match source {
+        Source::Replay(replay) => replay.markers().cloned().collect(),
+        Source::Geom(_) => Vec::new(),
+    }
+}
+
+fn describe_markers(markers: &[Marker]) -> String {
+    let labels = markers.iter().map(|m| m.label.as_str()).collect::<Vec<_>>();
+    format!("Marker: {}", labels.join(", "))
+}
+
+/// Keeps the terminal in raw mode while it lives.  Restores it when dropped,
+/// so that returning early with an error doesn't leave the shell unusable.
+struct RawMode(());
+
+impl RawMode {
+    fn enable() -> Result<Self> {
+        crossterm::terminal::enable_raw_mode().context("Enabling raw mode")?;
+        Ok(RawMode(()))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = crossterm::terminal::disable_raw_mode();
+    }
+}
+
+/// If `e` was caused by lacking permission to read devstat(9), explain how to
+/// grant it.  /dev/devstat is world-readable by default, but devfs rules,
+/// especially a jail's, may hide it or restrict its mode.
+fn explain_permissions(e: anyhow::Error) -> anyhow::Error {
+    let denied = e.chain().any(|cause| {
+        cause
+            .downcast_ref::<freebsd_libgeom::Error>()
+            .is_some_and(|ge| {
+                ge.io_error().kind() == io::ErrorKind::PermissionDenied
+            })
+    });
+    if denied {
+        e.context(
+            "Permission denied reading /dev/devstat.  Run as root, or make it \
+             readable with a devfs.rules(5) entry like \"add path 'devstat' \
+             unhide mode 0444\", applied to the system or jail with \
+             devfs_system_ruleset or devfs_ruleset.",
+        )
+    } else {
+        e
+    }
+}
+
+/// Name `kev` as the [`help::KEYS`] table does, if it names it at all
+fn code(kev: &KeyEvent) -> Option<Code> {
+    let shift = kev.modifiers.contains(KeyModifiers::SHIFT);
+    match kev.code {
+        KeyCode::Char(c) => Some(Code::Char(c)),
+        KeyCode::Down => Some(Code::Down),
+        KeyCode::Up => Some(Code::Up),
+        KeyCode::Left if shift => Some(Code::ShiftLeft),
+        KeyCode::Right if shift => Some(Code::ShiftRight),
+        KeyCode::Left => Some(Code::Left),
+        KeyCode::Right => Some(Code::Right),
+        KeyCode::Delete => Some(Code::Delete),
+        KeyCode::Insert => Some(Code::Insert),
+        KeyCode::Enter => Some(Code::Enter),
+        _ => None,
+    }
+}
+
+/// How many side-by-side tables fit in `width` columns, if each is `twidth`
+/// wide.  Always at least one.
+fn table_count(width: u16, twidth: u16, tree: bool) -> NonZeroU16 {
+    // Splitting the tree across columns would obscure it.
+    width
+        .checked_div(twidth)
+        .and_then(NonZeroU16::new)
+        .filter(|_| !tree)
+        .unwrap_or(NonZeroU16::MIN)
+}
+
+/// Draws on the terminal's alternate screen while it lives, so the shell's
+/// scrollback is untouched.  Returns to the main screen when dropped.
+struct AlternateScreen(());
+
+impl AlternateScreen {
+    fn enter() -> Result<Self> {
+        crossterm::execute!(
+            io::stdout(),
+            crossterm::terminal::EnterAlternateScreen
+        )
+        .context("Entering alternate screen")?;
+        Ok(AlternateScreen(()))
+    }
+}
+
+impl Drop for AlternateScreen {
+    fn drop(&mut self) {
+        let _ = crossterm::execute!(
+            io::stdout(),
+            crossterm::terminal::LeaveAlternateScreen
+        );
+    }
+}
+
+/// Restore the terminal before printing a panic's message, so the message is
+/// legible and the shell is usable afterwards.  `alternate` says whether the
+/// alternate screen must be left, too.
+fn restore_terminal_on_panic(alternate: bool) {
+    let default = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if alternate {
+            let _ = crossterm::execute!(
+                io::stdout(),
+                crossterm::terminal::LeaveAlternateScreen
+            );
+        }
+        let _ = crossterm::terminal::disable_raw_mode();
+        let _ = crossterm::execute!(io::stdout(), crossterm::cursor::Show);
+        default(info);
+    }));
+}
+
 fn cleanup_terminal<B>(terminal: &mut Terminal<B>) -> Result<()>
 where
     B: ratatui::prelude::Backend,
@@ -652,140 +1901,443 @@ where
 // https://github.com/rust-lang/rust-clippy/issues/7483
 #[allow(clippy::or_fun_call)]
 fn main() -> Result<()> {
-    let cli: Cli = Cli::parse();
-    let mut cfg = if cli.reset_config {
+    let mut cli: Cli = Cli::parse();
+    let bug_report = match cli.command.take() {
+        Some(Command::BugReport { file, sanitize }) => Some((file, sanitize)),
+        Some(Command::Completions { shell }) => {
+            return print_completions(shell)
+        }
+        Some(Command::Tree { json }) => return topology::print(json),
+        None => None,
+    };
+    let config_path = config::path(cli.config.as_deref())?;
+    // gstat(8) has no config file, so --compat mustn't be affected by one.
+    let mut cfg = if cli.compat {
         cli
+    } else if cli.reset_config {
+        let mut cfg: Cli = config::load_system()?;
+        cfg |= cli;
+        cfg
     } else {
-        let mut cfg: Cli =
-            confy::load("gstat-rs", None).context("opening config file")?;
+        let mut cfg: Cli = config::load(&config_path)?;
         cfg |= cli;
         cfg
     };
-    let mut filter = cfg.filter.as_ref().map(|s| Regex::new(s).unwrap());
+    if let Some((path, sanitize)) = bug_report {
+        let interval = cfg.interval.unwrap_or(Duration::from_secs(1));
+        return report::create(&path, interval, sanitize)
+            .map_err(explain_permissions);
+    }
+    let mut filter = cfg
+        .filter
+        .as_deref()
+        .map(Regex::new)
+        .transpose()
+        .context("compiling --filter regex")?;
     let mut tick_rate = cfg.interval.unwrap_or(Duration::from_secs(1));
-    let mut editting_regex = false;
-    let mut new_regex = String::new();
-    let mut paused = false;
-    let mut selecting_columns = false;
+    let mut mode = Mode::Normal;
+    let mut paused = cfg.paused;
+    let mut presets_state = ListState::default();
 
     let mut columns = Columns::new(&mut cfg);
+    // The columns chosen by --columns or --preset aren't saved, and neither is
+    // --preset's sort order, which is restored from here.
+    let mut unsaved_columns = false;
+    let mut unsaved_sort = None;
+    if let Some(name) = cfg.preset.take() {
+        let Some(p) = preset::lookup(&cfg.presets, &name) else {
+            bail!(
+                "unknown preset {name:?}.  Valid presets are: {}",
+                preset::names(&cfg.presets).join(", ")
+            );
+        };
+        columns.apply(p.columns);
+        unsaved_columns = true;
+        unsaved_sort = Some((
+            mem::replace(&mut cfg.sort, p.sort),
+            mem::replace(&mut cfg.reverse, p.reverse),
+        ));
+    }
+    if let Some(headers) = cfg.only_columns.as_ref() {
+        columns.apply_headers(headers)?;
+        unsaved_columns = true;
+    }
 
-    let mut sort_idx: Option<usize> = cfg.sort.as_ref().and_then(|name| {
-        columns
-            .cols
-            .iter()
-            .enumerate()
-            .find(|(_i, col)| col.header.trim() == name.trim())
-            .map(|(i, _col)| i)
-    });
+    let mut sort_idx: Option<usize> =
+        cfg.sort.as_ref().and_then(|name| columns.find(name));
+
+    let mut data = if let Some(path) = cfg.replay.as_ref() {
+        DataSource::replay(path)?
+    } else {
+        let window = cfg.window.unwrap_or(NonZeroUsize::MIN);
+        let half_life = cfg.peak_decay.unwrap_or(Duration::from_secs(10));
+        // Must happen before the terminal is initialized, so that errors
+        // like missing permissions are legible.
+        DataSource::new(window, cfg.all_devstat, half_life)
+            .map_err(explain_permissions)?
+    };
+    if cfg.compat {
+        data.set_consumers(cfg.consumers)?;
+        return compat::run(&cfg, data, filter.as_ref(), tick_rate);
+    }
+    data.set_cumulative(cfg.cumulative)?;
+    data.set_group(cfg.group)?;
+    data.set_tree(cfg.tree)?;
+    data.set_aliases(cfg.aliases)?;
+    data.set_exprs(mem::take(&mut cfg.expr))?;
+    data.set_limits(cfg.limits.clone())?;
+    let mut alerts = Alerts::new(
+        mem::take(&mut cfg.alert),
+        cfg.alert_after.unwrap_or(NonZeroUsize::MIN),
+    );
+    data.set_columns(&columns, &alerts)?;
+
+    if cfg.batch {
+        // Batch mode is for scripts, so it doesn't save the config file.
+        return batch::run(
+            &cfg,
+            data,
+            &mut columns,
+            sort_idx,
+            filter.as_ref(),
+            tick_rate,
+        );
+    }
 
     // Terminal initialization
     let stdout = io::stdout();
-    crossterm::terminal::enable_raw_mode().unwrap();
+    restore_terminal_on_panic(!cfg.no_alternate_screen);
+    let _raw_mode = RawMode::enable()?;
+    // Dropped before _raw_mode, so the main screen is restored first
+    let _alternate_screen = if cfg.no_alternate_screen {
+        None
+    } else {
+        Some(AlternateScreen::enter()?)
+    };
     let backend = CrosstermBackend::new(stdout);
     let mut terminal =
         Terminal::new(backend).context("Error opening terminal")?;
 
-    let mut data = DataSource::new()?;
+    // With --skip-first, the event loop silently collects the first interval,
+    // and starts recording after it.
+    let mut skipping = cfg.skip_first && cfg.replay.is_none();
+    if let Some(path) = cfg.record.as_ref().filter(|_| !skipping) {
+        data.record(path)?;
+    }
     let mut table = StatefulTable::default();
     data.sort(sort_idx, cfg.reverse);
+    data.update_visible(&cfg, filter.as_ref());
+    // Select the same device as last time, if it's still displayed
+    if cfg.selected.is_some() {
+        table.select_name(data.visible(), cfg.selected.as_deref());
+    }
 
     let normal_style = Style::default().bg(Color::Blue);
+    // How long to display a status message, like a device's arrival
+    const STATUS_DURATION: Duration = Duration::from_secs(5);
+    let mut status: Option<(String, Instant)> = None;
+    // Consecutive failures to draw.  Give up after too many.
+    const MAX_DRAW_ERRORS: usize = 10;
+    let mut draw_errors = 0;
+
+    // The formatted table, and its totals row
+    let mut rows = RowCache::default();
+    let mut total_row = RowCache::default();
 
     terminal.clear().context("clearing terminal")?;
     loop {
-        terminal
-            .draw(|f| {
-                let header_cells = columns
-                    .cols
-                    .iter()
-                    .enumerate()
-                    .filter(|(_i, col)| col.enabled)
-                    .map(|(i, col)| {
-                        let style = Style::default()
-                            .fg(Color::LightYellow)
-                            .add_modifier(Modifier::BOLD);
-                        let style = if sort_idx == Some(i) {
-                            style.add_modifier(Modifier::REVERSED)
-                        } else {
-                            style
-                        };
-                        Cell::from(col.header).style(style)
-                    });
-                let header = Row::new(header_cells).style(normal_style);
-                let widths = columns
-                    .cols
-                    .iter()
-                    .filter(|col| col.enabled)
-                    .map(|col| col.width)
-                    .collect::<Vec<_>>();
-                let max_name_width = data
-                    .items
-                    .iter()
-                    .filter(|elem| !cfg.auto || elem.pct_busy > 0.1)
-                    .filter(|elem| !cfg.physical || elem.rank == 1)
-                    .filter(|elem| {
-                        filter
-                            .as_ref()
-                            .map(|f| f.is_match(&elem.name))
-                            .unwrap_or(true)
-                    })
-                    .map(|elem| elem.name.len() as u16)
-                    .max()
-                    .unwrap_or(0);
-                let twidth: u16 = columns
-                    .cols
-                    .iter()
-                    .filter(|col| col.enabled)
-                    .map(|col| {
-                        if col.name == "Name" {
-                            max_name_width.max(col.min_width())
-                        } else {
-                            col.min_width()
-                        }
-                    })
-                    .sum();
-                let ntables = NonZeroU16::new(f.size().width / twidth)
-                    .unwrap_or_else(|| NonZeroU16::new(1).unwrap());
-                let rects = Layout::default()
-                    .direction(Direction::Horizontal)
-                    .margin(0)
-                    .constraints(
-                        (0..ntables.into())
-                            .map(|_| {
-                                Constraint::Percentage(100 / u16::from(ntables))
-                            })
-                            .collect::<Vec<_>>(),
-                    )
+        // Columns may have been enabled or disabled by the last key
+        data.set_columns(&columns, &alerts)?;
+        data.update_visible(&cfg, filter.as_ref());
+        let visible = data.visible();
+        if cfg.totals {
+            total_row.format(&columns, [&data.total]);
+        }
+        rows.format(&columns, visible);
+        if cfg.totals {
+            rows.align(&mut total_row);
+        }
+        let drawn = terminal.draw(|f| {
+            if skipping {
+                // Leave the screen blank until the first interval is collected
+                return;
+            }
+            let header_cells = columns.visible().map(|(i, _)| {
+                let style = Style::default()
+                    .fg(Color::LightYellow)
+                    .add_modifier(Modifier::BOLD);
+                let style = if sort_idx == Some(i) {
+                    style.add_modifier(Modifier::REVERSED)
+                } else {
+                    style
+                };
+                Cell::from(columns.header(i, rows.width(i))).style(style)
+            });
+            let header = Row::new(header_cells).style(normal_style);
+            let widths = columns
+                .visible()
+                .map(|(k, _)| columns.constraint(k, rows.width(k)))
+                .collect::<Vec<_>>();
+            let max_name_width =
+                u16::try_from(rows.name_width()).unwrap_or(u16::MAX);
+            let twidth: u16 = columns
+                .visible()
+                .map(|(k, _)| {
+                    if k == Columns::NAME {
+                        max_name_width.max(rows.width(k))
+                    } else {
+                        rows.width(k)
+                    }
+                })
+                .sum();
+            let ntables = table_count(f.size().width, twidth, cfg.tree);
+            let (totals_area, body) = if cfg.totals {
+                let chunks = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints([Constraint::Length(1), Constraint::Min(0)])
                     .split(f.size());
-                let multirows = data
-                    .items
-                    .iter()
-                    .filter(|elem| !cfg.auto || elem.pct_busy > 0.1)
-                    .filter(|elem| !cfg.physical || elem.rank == 1)
-                    .filter(|elem| {
-                        filter
-                            .as_ref()
-                            .map(|f| f.is_match(&elem.name))
-                            .unwrap_or(true)
-                    })
-                    .map(|elem| elem.row(&columns))
-                    .deinterleave::<Vec<_>>(ntables.into());
-                for (i, rows) in multirows.into_iter().enumerate() {
-                    let t = table.table(header.clone(), rows, &widths);
-                    f.render_stateful_widget(t, rects[i], &mut table.state);
+                (Some(chunks[0]), chunks[1])
+            } else {
+                (None, f.size())
+            };
+            let rects = Layout::default()
+                .direction(Direction::Horizontal)
+                .margin(0)
+                .constraints(
+                    (0..ntables.into())
+                        .map(|_| {
+                            Constraint::Percentage(100 / u16::from(ntables))
+                        })
+                        .collect::<Vec<_>>(),
+                )
+                .split(body);
+            let multirows = rows.rows().deinterleave::<Vec<_>>(ntables.into());
+            for (i, rows) in multirows.into_iter().enumerate() {
+                let t = table.table(header.clone(), rows, &widths);
+                f.render_stateful_widget(t, rects[i], &mut table.state);
+            }
+            if let Some(area) = totals_area {
+                // Leave at least this much room for the sparkline
+                const SPARKLINE_MIN_WIDTH: u16 = 16;
+
+                let pane = rects[0]
+                    .width
+                    .min(area.width.saturating_sub(SPARKLINE_MIN_WIDTH));
+                let chunks = Layout::default()
+                    .direction(Direction::Horizontal)
+                    .constraints([Constraint::Length(pane), Constraint::Min(0)])
+                    .split(area);
+                let style = Style::default().add_modifier(Modifier::BOLD);
+                let row = total_row.rows().map(|row| row.style(style));
+                let t = Table::new(row, &widths)
+                    .segment_size(SegmentSize::LastTakesRemainder)
+                    .column_spacing(0);
+                f.render_widget(t, chunks[0]);
+                let start = data
+                    .throughput
+                    .len()
+                    .saturating_sub(usize::from(chunks[1].width));
+                let sparkline = Sparkline::default()
+                    .data(&data.throughput[start..])
+                    .style(Style::default().fg(Color::Cyan));
+                f.render_widget(sparkline, chunks[1]);
+                // Draw each marker as a vertical line through the sparkline
+                for x in data.marker_offsets(usize::from(chunks[1].width)) {
+                    f.buffer_mut()
+                        .get_mut(chunks[1].x + x as u16, chunks[1].y)
+                        .set_symbol("│")
+                        .set_fg(Color::Magenta);
                 }
+            }
+
+            if let Some((msg, _)) = status.as_ref() {
+                let size = f.size();
+                let area = Rect::new(
+                    size.x,
+                    size.bottom().saturating_sub(1),
+                    size.width,
+                    size.height.min(1),
+                );
+                let style = Style::default().add_modifier(Modifier::REVERSED);
+                f.render_widget(Clear, area);
+                f.render_widget(
+                    Paragraph::new(msg.as_str()).style(style),
+                    area,
+                );
+            }
 
-                if editting_regex {
+            match &mode {
+                Mode::Normal => (),
+                Mode::EditingRegex(text) => {
                     let area = popup_layout(40, 3, f.size());
-                    let popup_box = Paragraph::new(new_regex.as_str()).block(
+                    let popup_box = Paragraph::new(text.as_str()).block(
                         Block::default()
                             .borders(Borders::ALL)
                             .title("Filter regex"),
                     );
                     f.render_widget(Clear, area);
                     f.render_widget(popup_box, area);
-                } else if selecting_columns {
+                }
+                Mode::Searching { text, found } => {
+                    let area = popup_layout(40, 3, f.size());
+                    let title = if *found {
+                        "Search"
+                    } else {
+                        "Search (no match)"
+                    };
+                    let popup_box = Paragraph::new(text.as_str()).block(
+                        Block::default().borders(Borders::ALL).title(title),
+                    );
+                    f.render_widget(Clear, area);
+                    f.render_widget(popup_box, area);
+                }
+                Mode::NamingPreset(name) => {
+                    let area = popup_layout(40, 3, f.size());
+                    let popup_box = Paragraph::new(name.as_str()).block(
+                        Block::default()
+                            .borders(Borders::ALL)
+                            .title("Save preset as"),
+                    );
+                    f.render_widget(Clear, area);
+                    f.render_widget(popup_box, area);
+                }
+                Mode::NamingMarker(label) => {
+                    let area = popup_layout(40, 3, f.size());
+                    let popup_box = Paragraph::new(label.as_str()).block(
+                        Block::default()
+                            .borders(Borders::ALL)
+                            .title("Marker label"),
+                    );
+                    f.render_widget(Clear, area);
+                    f.render_widget(popup_box, area);
+                }
+                Mode::SelectingPreset => {
+                    let names = preset::names(&cfg.presets);
+                    let boxwidth = names
+                        .iter()
+                        .map(|name| name.len() as u16)
+                        .max()
+                        .unwrap_or(0)
+                        .max(22)
+                        + 2;
+                    let area = popup_layout(
+                        boxwidth,
+                        names.len() as u16 + 2,
+                        f.size(),
+                    );
+                    f.render_widget(Clear, area);
+                    let items = names
+                        .into_iter()
+                        .map(|name| ListItem::new(Text::from(name)))
+                        .collect::<Vec<_>>();
+                    let list = List::new(items)
+                        .block(
+                            Block::default()
+                                .borders(Borders::ALL)
+                                .title("Presets (s to save)"),
+                        )
+                        .highlight_style(
+                            Style::default().add_modifier(Modifier::REVERSED),
+                        );
+                    f.render_stateful_widget(list, area, &mut presets_state);
+                }
+                Mode::ShowingDetail => {
+                    let selected = table
+                        .state
+                        .selected()
+                        .and_then(|i| data.visible().nth(i));
+                    if let Some(elem) = selected {
+                        let lines = elem.detail();
+                        let boxwidth = lines
+                            .iter()
+                            .map(|l| l.len() as u16)
+                            .max()
+                            .unwrap_or(0)
+                            .max(22)
+                            + 2;
+                        let area = popup_layout(
+                            boxwidth,
+                            lines.len() as u16 + 2,
+                            f.size(),
+                        );
+                        let text = lines
+                            .into_iter()
+                            .map(Line::from)
+                            .collect::<Vec<_>>();
+                        let popup_box = Paragraph::new(text).block(
+                            Block::default()
+                                .borders(Borders::ALL)
+                                .title(&*elem.name),
+                        );
+                        f.render_widget(Clear, area);
+                        f.render_widget(popup_box, area);
+                    }
+                }
+                Mode::ShowingBusy => {
+                    let selected = table
+                        .state
+                        .selected()
+                        .and_then(|i| data.visible().nth(i));
+                    if let Some(elem) = selected {
+                        const BAR_WIDTH: u16 = 40;
+                        let lines = elem.busy_lines(BAR_WIDTH.into());
+                        let area = popup_layout(
+                            BAR_WIDTH + 2,
+                            lines.len() as u16 + 2,
+                            f.size(),
+                        );
+                        let popup_box = Paragraph::new(lines).block(
+                            Block::default()
+                                .borders(Borders::ALL)
+                                .title(format!("{}: busy time", elem.name)),
+                        );
+                        f.render_widget(Clear, area);
+                        f.render_widget(popup_box, area);
+                    }
+                }
+                Mode::ShowingHelp => {
+                    let filter = match (cfg.filter.as_deref(), &cfg.class) {
+                        (None, None) => "none".to_owned(),
+                        (Some(f), None) => f.to_owned(),
+                        (None, Some(c)) => format!("class {}", c.join(",")),
+                        (Some(f), Some(c)) => {
+                            format!("{f}, class {}", c.join(","))
+                        }
+                    };
+                    let sort = match sort_idx {
+                        Some(i) if cfg.reverse => {
+                            format!("{} (reversed)", columns.cols[i].name)
+                        }
+                        Some(i) => columns.cols[i].name.to_owned(),
+                        None => "none".to_owned(),
+                    };
+                    let lines = help::lines(&[
+                        ("Interval", format!("{tick_rate:?}")),
+                        ("Filter", filter),
+                        ("Sort", sort),
+                    ]);
+                    let boxwidth = lines
+                        .iter()
+                        .map(|l| l.chars().count() as u16)
+                        .max()
+                        .unwrap_or(0)
+                        + 2;
+                    let area = popup_layout(
+                        boxwidth,
+                        lines.len() as u16 + 2,
+                        f.size(),
+                    );
+                    let text =
+                        lines.into_iter().map(Line::from).collect::<Vec<_>>();
+                    let popup_box = Paragraph::new(text).block(
+                        Block::default()
+                            .borders(Borders::ALL)
+                            .title("Help (Esc to close)"),
+                    );
+                    f.render_widget(Clear, area);
+                    f.render_widget(popup_box, area);
+                }
+                Mode::SelectingColumns => {
                     let boxwidth = columns.max_name_width() + 6;
                     let area = popup_layout(boxwidth, 20, f.size());
                     f.render_widget(Clear, area);
@@ -813,24 +2365,76 @@ fn main() -> Result<()> {
                         );
                     f.render_stateful_widget(list, area, &mut columns.state);
                 }
-            })
-            .unwrap();
+            }
+        });
+        match drawn {
+            Ok(_) => draw_errors = 0,
+            // Like when a signal arrives during a resize.  The next frame
+            // will try again.
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => {
+                draw_errors += 1;
+                if draw_errors >= MAX_DRAW_ERRORS {
+                    return Err(e).context("drawing the display");
+                }
+                // Start the next frame from scratch, in case the terminal's
+                // contents no longer match what ratatui believes
+                let _ = terminal.clear();
+            }
+        }
 
         match util::event::poll(&tick_rate)? {
+            Some(Event::Tick) if skipping => {
+                skipping = false;
+                let selected = table.selected_name(data.visible());
+                data.refresh()?;
+                if let Some(path) = cfg.record.as_ref() {
+                    data.record(path)?;
+                }
+                data.sort(sort_idx, cfg.reverse);
+                data.update_visible(&cfg, filter.as_ref());
+                table.select_name(data.visible(), selected.as_deref());
+            }
             Some(Event::Tick) => {
+                if status
+                    .as_ref()
+                    .is_some_and(|(_, t)| t.elapsed() >= STATUS_DURATION)
+                {
+                    status = None;
+                }
                 if !paused {
+                    // Keep the same device selected, even if it moves
+                    let selected = table.selected_name(data.visible());
                     data.refresh()?;
+                    if !data.events.is_empty() {
+                        status = Some((
+                            describe_events(&data.events),
+                            Instant::now(),
+                        ));
+                    }
+                    if !data.reached.is_empty() {
+                        status = Some((
+                            describe_markers(&data.reached),
+                            Instant::now(),
+                        ));
+                    }
                     data.sort(sort_idx, cfg.reverse);
+                    data.update_visible(&cfg, filter.as_ref());
+                    table.select_name(data.visible(), selected.as_deref());
+                    if !alerts.is_empty() {
+                        let msgs = alerts.check(&data.items);
+                        alert(&mut terminal, &msgs, cfg.alert_syslog)?;
+                    }
                 }
             }
             Some(Event::Key(kev)) => {
-                if editting_regex {
-                    match kev.code {
-                        KeyCode::Enter => match Regex::new(&new_regex) {
+                match &mut mode {
+                    Mode::EditingRegex(text) => match kev.code {
+                        KeyCode::Enter => match Regex::new(text) {
                             Ok(regex) => {
-                                editting_regex = false;
                                 filter = Some(regex);
-                                cfg.filter = Some(new_regex.split_off(0));
+                                cfg.filter = Some(mem::take(text));
+                                mode = Mode::Normal;
                             }
                             Err(e) => {
                                 cleanup_terminal(&mut terminal)?;
@@ -838,23 +2442,150 @@ fn main() -> Result<()> {
                             }
                         },
                         KeyCode::Char(c) => {
-                            new_regex.push(c);
+                            text.push(c);
+                        }
+                        KeyCode::Backspace => {
+                            text.pop();
+                        }
+                        KeyCode::Esc => {
+                            mode = Mode::Normal;
+                        }
+                        _ => {}
+                    },
+                    Mode::Searching { text, found } => match kev.code {
+                        KeyCode::Enter | KeyCode::Esc => {
+                            mode = Mode::Normal;
+                        }
+                        KeyCode::Char(c) => {
+                            text.push(c);
+                            *found = table.search(data.visible(), text);
+                        }
+                        KeyCode::Backspace => {
+                            text.pop();
+                            *found = table.search(data.visible(), text);
+                        }
+                        _ => {}
+                    },
+                    Mode::NamingPreset(name) => match kev.code {
+                        KeyCode::Enter => {
+                            if !name.is_empty() {
+                                let p = Preset {
+                                    columns: columns.enabled_bits(),
+                                    sort:    cfg.sort.clone(),
+                                    reverse: cfg.reverse,
+                                };
+                                cfg.presets.insert(mem::take(name), p);
+                            }
+                            mode = Mode::Normal;
+                        }
+                        KeyCode::Char(c) => {
+                            name.push(c);
                         }
                         KeyCode::Backspace => {
-                            new_regex.pop();
+                            name.pop();
                         }
                         KeyCode::Esc => {
-                            editting_regex = false;
+                            mode = Mode::Normal;
                         }
                         _ => {}
+                    },
+                    Mode::NamingMarker(label) => match kev.code {
+                        KeyCode::Enter => {
+                            let label = mem::take(label);
+                            status = Some((
+                                format!("Marker: {label}"),
+                                Instant::now(),
+                            ));
+                            data.mark(label)?;
+                            mode = Mode::Normal;
+                        }
+                        KeyCode::Char(c) => {
+                            label.push(c);
+                        }
+                        KeyCode::Backspace => {
+                            label.pop();
+                        }
+                        KeyCode::Esc => {
+                            mode = Mode::Normal;
+                        }
+                        _ => {}
+                    },
+                    Mode::SelectingPreset => {
+                        let names = preset::names(&cfg.presets);
+                        match kev.code {
+                            KeyCode::Enter => {
+                                let p =
+                                    presets_state.selected().and_then(|i| {
+                                        preset::lookup(&cfg.presets, &names[i])
+                                    });
+                                if let Some(p) = p {
+                                    columns.apply(p.columns);
+                                    // Now these are the user's choice
+                                    unsaved_columns = false;
+                                    unsaved_sort = None;
+                                    sort_idx = p
+                                        .sort
+                                        .as_deref()
+                                        .and_then(|s| columns.find(s));
+                                    cfg.sort = p.sort;
+                                    cfg.reverse = p.reverse;
+                                    data.sort(sort_idx, cfg.reverse);
+                                }
+                                mode = Mode::Normal;
+                            }
+                            KeyCode::Char('s') => {
+                                mode = Mode::NamingPreset(String::new());
+                            }
+                            KeyCode::Char('q') => {
+                                break;
+                            }
+                            KeyCode::Down => {
+                                let i = presets_state.selected().unwrap_or(0);
+                                presets_state
+                                    .select(Some((i + 1) % names.len()));
+                            }
+                            KeyCode::Up => {
+                                let i = presets_state.selected().unwrap_or(0);
+                                let i =
+                                    i.checked_sub(1).unwrap_or(names.len() - 1);
+                                presets_state.select(Some(i));
+                            }
+                            KeyCode::Esc => {
+                                mode = Mode::Normal;
+                            }
+                            _ => {}
+                        }
                     }
-                } else if selecting_columns {
-                    match kev.code {
+                    Mode::ShowingDetail => match kev.code {
+                        KeyCode::Char('q') => {
+                            break;
+                        }
+                        KeyCode::Enter | KeyCode::Esc => {
+                            mode = Mode::Normal;
+                        }
+                        _ => {}
+                    },
+                    Mode::ShowingBusy => match kev.code {
+                        KeyCode::Char('q') => {
+                            break;
+                        }
+                        KeyCode::Char('b') | KeyCode::Enter | KeyCode::Esc => {
+                            mode = Mode::Normal;
+                        }
+                        _ => {}
+                    },
+                    Mode::ShowingHelp => match kev.code {
+                        KeyCode::Char('q') => {
+                            break;
+                        }
+                        KeyCode::Char('?') | KeyCode::Enter | KeyCode::Esc => {
+                            mode = Mode::Normal;
+                        }
+                        _ => {}
+                    },
+                    Mode::SelectingColumns => match kev.code {
                         KeyCode::Char(' ') => {
                             if let Some(i) = columns.state.selected() {
-                                // unwrapping is safe; the default value should
-                                // always be set by this point.
-                                cfg.columns.as_mut().unwrap().0 ^= 1 << i;
                                 columns.cols[i].enabled ^= true;
                             }
                         }
@@ -868,118 +2599,218 @@ fn main() -> Result<()> {
                             columns.previous();
                         }
                         KeyCode::Esc => {
-                            selecting_columns = false;
+                            mode = Mode::Normal;
                         }
                         _ => {}
-                    }
-                } else {
-                    match kev.code {
-                        KeyCode::Char(' ') => {
-                            paused ^= true;
-                            if !paused {
-                                // Refresh immediately after unpause.
-                                data.refresh()?;
-                                data.sort(sort_idx, cfg.reverse);
+                    },
+                    Mode::Normal => {
+                        match code(&kev).and_then(help::action) {
+                            Some(Action::Pause) => {
+                                paused ^= true;
+                                if !paused {
+                                    // Refresh immediately after unpause.
+                                    data.refresh()?;
+                                    data.sort(sort_idx, cfg.reverse);
+                                }
                             }
-                        }
-                        KeyCode::Char('+') => {
-                            loop {
-                                match sort_idx {
-                                    Some(idx) => {
-                                        sort_idx = Some(idx + 1);
+                            Some(Action::SortNext) => {
+                                loop {
+                                    match sort_idx {
+                                        Some(idx) => {
+                                            sort_idx = Some(idx + 1);
+                                        }
+                                        None => {
+                                            sort_idx = Some(0);
+                                        }
                                     }
-                                    None => {
-                                        sort_idx = Some(0);
+                                    let idx = sort_idx.unwrap();
+                                    if idx >= columns.cols.len() {
+                                        sort_idx = None;
+                                        break;
+                                    }
+                                    if columns.cols[idx].enabled {
+                                        sort_idx = Some(idx);
+                                        break;
                                     }
                                 }
-                                let idx = sort_idx.unwrap();
-                                if idx >= columns.cols.len() {
-                                    sort_idx = None;
-                                    break;
-                                }
-                                if columns.cols[idx].enabled {
-                                    sort_idx = Some(idx);
-                                    break;
-                                }
+                                let sort_key = sort_idx
+                                    .map(|idx| columns.cols[idx].header.trim());
+                                cfg.sort = sort_key.map(str::to_owned);
+                                data.sort(sort_idx, cfg.reverse);
                             }
-                            let sort_key =
-                                sort_idx.map(|idx| columns.cols[idx].header);
-                            cfg.sort = sort_key.map(str::to_owned);
-                            data.sort(sort_idx, cfg.reverse);
-                        }
-                        KeyCode::Char('-') => {
-                            loop {
-                                match sort_idx {
-                                    Some(idx) => {
-                                        sort_idx = idx.checked_sub(1);
+                            Some(Action::SortPrevious) => {
+                                loop {
+                                    match sort_idx {
+                                        Some(idx) => {
+                                            sort_idx = idx.checked_sub(1);
+                                        }
+                                        None => {
+                                            sort_idx =
+                                                Some(columns.cols.len() - 1);
+                                        }
                                     }
-                                    None => {
-                                        sort_idx = Some(columns.cols.len() - 1);
+                                    if sort_idx.is_none() {
+                                        break;
+                                    }
+                                    if columns.cols[sort_idx.unwrap()].enabled {
+                                        break;
                                     }
                                 }
-                                if sort_idx.is_none() {
-                                    break;
+                                let sort_key = sort_idx
+                                    .map(|idx| columns.cols[idx].header.trim());
+                                cfg.sort = sort_key.map(str::to_owned);
+                                data.sort(sort_idx, cfg.reverse);
+                            }
+                            Some(Action::HalveInterval) => {
+                                tick_rate /= 2;
+                                cfg.interval = Some(tick_rate);
+                            }
+                            Some(Action::DoubleInterval) => {
+                                tick_rate *= 2;
+                                cfg.interval = Some(tick_rate);
+                            }
+                            Some(Action::ShowDetail)
+                                if table.state.selected().is_some() =>
+                            {
+                                mode = Mode::ShowingDetail;
+                            }
+                            Some(Action::Help) => {
+                                mode = Mode::ShowingHelp;
+                            }
+                            Some(Action::Search) => {
+                                mode = Mode::Searching {
+                                    text:  String::new(),
+                                    found: true,
+                                };
+                            }
+                            Some(Action::ClearFilters) => {
+                                cfg.filter = None;
+                                cfg.class = None;
+                                filter = None;
+                            }
+                            Some(Action::ToggleAuto) => {
+                                cfg.auto ^= true;
+                            }
+                            Some(Action::ShowBusy)
+                                if table.state.selected().is_some() =>
+                            {
+                                mode = Mode::ShowingBusy;
+                            }
+                            Some(Action::EditFilter) => {
+                                mode = Mode::EditingRegex(String::new());
+                            }
+                            Some(Action::ToggleGroup) => {
+                                cfg.group ^= true;
+                                data.set_group(cfg.group)?;
+                                data.sort(sort_idx, cfg.reverse);
+                            }
+                            Some(Action::ToggleTree) => {
+                                cfg.tree ^= true;
+                                data.set_tree(cfg.tree)?;
+                                data.sort(sort_idx, cfg.reverse);
+                            }
+                            Some(Action::ToggleAliases) => {
+                                cfg.aliases ^= true;
+                                data.set_aliases(cfg.aliases)?;
+                                data.sort(sort_idx, cfg.reverse);
+                            }
+                            Some(Action::TogglePhysical) => {
+                                cfg.physical ^= true;
+                            }
+                            Some(Action::SelectPreset) => {
+                                mode = Mode::SelectingPreset;
+                                presets_state.select(Some(0));
+                            }
+                            Some(Action::Quit) => {
+                                break;
+                            }
+                            Some(Action::ToggleReverse) => {
+                                cfg.reverse ^= true;
+                                data.sort(sort_idx, cfg.reverse);
+                            }
+                            Some(Action::ToggleTotals) => {
+                                cfg.totals ^= true;
+                            }
+                            Some(Action::ToggleCumulative) => {
+                                cfg.cumulative ^= true;
+                                data.set_cumulative(cfg.cumulative)?;
+                                data.sort(sort_idx, cfg.reverse);
+                            }
+                            Some(Action::ToggleHuman) => {
+                                cfg.human ^= true;
+                                columns.human = cfg.human;
+                            }
+                            Some(Action::SwitchLatencyUnit) => {
+                                columns.latency = columns.latency.next();
+                                cfg.latency_unit = Some(columns.latency);
+                            }
+                            Some(Action::AddMarker) => {
+                                mode = Mode::NamingMarker(String::new());
+                            }
+                            Some(
+                                action @ (Action::StepBackward
+                                | Action::StepForward),
+                            ) => {
+                                let delta = if action == Action::StepBackward {
+                                    -1
+                                } else {
+                                    1
+                                };
+                                data.seek(delta)?;
+                                data.sort(sort_idx, cfg.reverse);
+                                if !data.reached.is_empty() {
+                                    status = Some((
+                                        describe_markers(&data.reached),
+                                        Instant::now(),
+                                    ));
                                 }
-                                if columns.cols[sort_idx.unwrap()].enabled {
-                                    break;
+                            }
+                            Some(Action::SelectNext) => {
+                                table.next();
+                            }
+                            Some(Action::SelectPrevious) => {
+                                table.previous();
+                            }
+                            Some(
+                                action @ (Action::Collapse | Action::Expand),
+                            ) if cfg.tree => {
+                                let selected =
+                                    table.selected_name(data.visible());
+                                if let Some(name) = selected {
+                                    let collapse = action == Action::Collapse;
+                                    data.set_collapsed(&name, collapse)?;
+                                    data.sort(sort_idx, cfg.reverse);
                                 }
                             }
-                            let sort_key =
-                                sort_idx.map(|idx| columns.cols[idx].header);
-                            cfg.sort = sort_key.map(str::to_owned);
-                            data.sort(sort_idx, cfg.reverse);
-                        }
-                        KeyCode::Char('<') => {
-                            tick_rate /= 2;
-                            cfg.interval = Some(tick_rate);
-                        }
-                        KeyCode::Char('>') => {
-                            tick_rate *= 2;
-                            cfg.interval = Some(tick_rate);
-                        }
-                        KeyCode::Char('F') => {
-                            cfg.filter = None;
-                            filter = None;
-                        }
-                        KeyCode::Char('a') => {
-                            cfg.auto ^= true;
-                        }
-                        KeyCode::Char('f') => {
-                            editting_regex = true;
-                            new_regex = String::new();
-                        }
-                        KeyCode::Char('p') => {
-                            cfg.physical ^= true;
-                        }
-                        KeyCode::Char('q') => {
-                            break;
-                        }
-                        KeyCode::Char('r') => {
-                            cfg.reverse ^= true;
-                            data.sort(sort_idx, cfg.reverse);
-                        }
-                        KeyCode::Down => {
-                            table.next();
-                        }
-                        KeyCode::Up => {
-                            table.previous();
-                        }
-                        KeyCode::Delete => {
-                            if let Some(i) = sort_idx {
-                                cfg.columns.as_mut().unwrap().0 ^= 1 << i;
-                                columns.cols[i].enabled ^= true;
+                            Some(Action::Collapse | Action::ScrollLeft) => {
+                                columns.scroll_left();
                             }
+                            Some(Action::Expand | Action::ScrollRight) => {
+                                columns.scroll_right();
+                            }
+                            Some(Action::DeleteSortColumn) => {
+                                if let Some(i) = sort_idx {
+                                    columns.cols[i].enabled ^= true;
+                                }
+                            }
+                            Some(Action::SelectColumns) => {
+                                mode = Mode::SelectingColumns;
+                            }
+                            _ => {}
                         }
-                        KeyCode::Insert => {
-                            selecting_columns = true;
-                        }
-                        _ => {}
                     }
                 }
             }
             Some(Event::Mouse(_mev)) => {
                 // ignore for now
             }
+            Some(Event::Resize) => {
+                // Resize the buffers now, rather than on the next draw, so
+                // the whole screen is repainted.  The loop then redraws
+                // immediately, recomputing the table count and name width
+                // for the new size, instead of waiting for the next tick.
+                terminal.autoresize().context("resizing the display")?;
+            }
             None => {
                 // stdin closed for some reason
                 break;
@@ -989,8 +2820,21 @@ fn main() -> Result<()> {
             }
         };
     }
-    if let Err(e) = confy::store("gstat-rs", None, &cfg) {
-        eprintln!("Warning: failed to save config file: {e}");
+    data.update_visible(&cfg, filter.as_ref());
+    cfg.selected = table
+        .selected_name(data.visible())
+        .map(|name| name.to_string());
+    cfg.paused = paused;
+    cfg.scroll = columns.scroll;
+    if !unsaved_columns {
+        cfg.columns = Some(columns.enabled_bits());
+    }
+    if let Some((sort, reverse)) = unsaved_sort {
+        cfg.sort = sort;
+        cfg.reverse = reverse;
+    }
+    if let Err(e) = config::store(&config_path, &cfg) {
+        eprintln!("Warning: failed to save config file: {e:#}");
     }
     cleanup_terminal(&mut terminal)?;
 
@@ -1001,6 +2845,235 @@ fn main() -> Result<()> {
 mod t {
     use super::*;
 
+    #[test]
+    fn cli() {
+        Cli::command().debug_assert();
+    }
+
+    /// --sort ignores padding, on both the headers and its argument
+    #[test]
+    fn sort_key() {
+        for arg in ["ops/s", " ops/s", "ops/s  "] {
+            let cli = Cli::try_parse_from(["gstat", "--sort", arg]).unwrap();
+            assert_eq!(cli.sort.as_deref(), Some("ops/s"));
+        }
+        Cli::try_parse_from(["gstat", "--sort", "ops"]).unwrap_err();
+    }
+
+    #[test]
+    fn describe_events() {
+        let events = [
+            DeviceEvent::Departed("da0".to_owned()),
+            DeviceEvent::Arrived("da1".to_owned()),
+            DeviceEvent::Arrived("da1p1".to_owned()),
+        ];
+        assert_eq!(
+            super::describe_events(&events),
+            "Arrived: da1, da1p1  Departed: da0"
+        );
+        assert_eq!(super::describe_events(&events[..1]), "Departed: da0");
+    }
+
+    #[test]
+    fn describe_markers() {
+        let markers = ["fio start", "fio stop"].map(|l| Marker {
+            time:  0.0,
+            label: l.to_owned(),
+        });
+        assert_eq!(
+            super::describe_markers(&markers),
+            "Marker: fio start, fio stop"
+        );
+    }
+
+    #[test]
+    fn marker_offsets() {
+        let markers = [3, 10, 12, 20].map(|i| {
+            let marker = Marker {
+                time:  0.0,
+                label: String::new(),
+            };
+            (i, marker)
+        });
+        // Markers that have scrolled off the left, or that are beyond the
+        // right edge, aren't displayed.
+        assert_eq!(super::marker_offsets(&markers, 5, 10), [5, 7]);
+        assert_eq!(super::marker_offsets(&markers, 0, 100), [3, 10, 12, 20]);
+    }
+
+    /// Missing disk attributes are omitted from the detail popup
+    #[test]
+    fn detail() {
+        let mut elem = group::total(&[]);
+        elem.class = "DISK".to_owned();
+        elem.rank = 1;
+        elem.disk = Some(Disk {
+            ident: Some("Z1234567".to_owned()),
+            descr: None,
+            lunid: Some("5000c500".to_owned()),
+            kind:  Some(Kind::Hdd),
+        });
+        elem.kind = Some(Kind::Hdd);
+        assert_eq!(
+            elem.detail(),
+            [
+                "Class   DISK",
+                "Rank    1",
+                "Serial  Z1234567",
+                "LUN ID  5000c500",
+                "Kind    HDD"
+            ]
+        );
+    }
+
+    #[test]
+    fn busy_shares() {
+        let mut elem = group::total(&[]);
+        assert_eq!(elem.busy_shares(), None);
+        // 30 ms of reads and 10 ms of writes per second
+        elem.r_s = 10.0;
+        elem.ms_r = 3.0;
+        elem.w_s = 2.0;
+        elem.ms_w = 5.0;
+        assert_eq!(elem.busy_shares(), Some([0.75, 0.25, 0.0, 0.0]));
+    }
+
+    /// The bar's segments should always add up to its full width
+    #[test]
+    fn busy_lines() {
+        let mut elem = group::total(&[]);
+        for (r_s, w_s) in [(1.0, 2.0), (1.0, 1.0), (5.0, 0.0)] {
+            elem.r_s = r_s;
+            elem.ms_r = 1.0;
+            elem.w_s = w_s;
+            elem.ms_w = 1.0;
+            let lines = elem.busy_lines(40);
+            assert_eq!(lines[0].width(), 40);
+            assert_eq!(lines.len(), 6);
+        }
+    }
+
+    #[test]
+    fn tbw_d() {
+        let mut elem = group::total(&[]);
+        // 1 TB per day
+        elem.kbs_w = 1e12 / 1024.0 / 86400.0;
+        assert!((elem.tbw_d() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn columns_enabled() {
+        let mut cb = ColumnsEnabled::default();
+        assert!(cb.get("name"));
+        assert!(!cb.get("peak_kbs"));
+        cb.set("peak_kbs", true);
+        cb.set("name", false);
+        assert!(cb.get("peak_kbs"));
+        assert!(!cb.get("name"));
+        assert_eq!(cb.0, Columns::DEFAULT_ENABLED & !(1 << 17) | 1 << 30);
+    }
+
+    /// Every name in Element::STATS must be recognized by Element::stat
+    #[test]
+    fn stats() {
+        let mut elem = group::total(&[]);
+        elem.errs = Some(0);
+        elem.sq = Some(0);
+        elem.ms_sq = Some(0.0);
+        elem.peak_kbs = Some(0.0);
+        elem.rw_bal = Some(0.5);
+        elem.rank = 1;
+        elem.pct_iops = Some(0.0);
+        elem.pct_kbs = Some(0.0);
+        for name in Element::STATS {
+            assert!(elem.stat(name).is_some(), "{name}");
+        }
+    }
+
+    /// Every built-in column must display something that Element knows
+    #[test]
+    fn column_stats() {
+        for spec in column::BUILTIN.iter() {
+            let known = if spec.unit == Unit::Text {
+                ["name", "class", "serial", "kind"].contains(&spec.stat)
+            } else {
+                Element::STATS.contains(&spec.stat)
+            };
+            assert!(known, "{}", spec.name);
+        }
+    }
+
+    /// Ties are broken by name, in either direction, so rows don't jump
+    /// around between refreshes.
+    #[test]
+    fn sort_ties() {
+        let mut items = [("da10", 5.0), ("da2", 5.0), ("ada0", 9.0)]
+            .into_iter()
+            .map(|(name, busy)| {
+                let mut elem = group::total(&[]);
+                elem.name = name.into();
+                elem.pct_busy = busy;
+                elem
+            })
+            .collect::<Vec<_>>();
+        let names = |items: &[Element]| {
+            items.iter().map(|e| e.name.to_string()).collect::<Vec<_>>()
+        };
+        let k = Columns::PCT_BUSY;
+        items.sort_by(|l, r| l.cmp_by(k, r, false));
+        assert_eq!(names(&items), ["da2", "da10", "ada0"]);
+        items.sort_by(|l, r| l.cmp_by(k, r, true));
+        assert_eq!(names(&items), ["ada0", "da2", "da10"]);
+    }
+
+    #[test]
+    fn human_kb() {
+        assert_eq!(super::human_kb(0.0), "0");
+        assert_eq!(super::human_kb(0.5), "0.50k");
+        assert_eq!(super::human_kb(512.0), "512k");
+        assert_eq!(super::human_kb(1023.0), "1023k");
+        assert_eq!(super::human_kb(1024.0), "1.00M");
+        assert_eq!(super::human_kb(44712.0), "43.7M");
+        assert_eq!(super::human_kb(4.0 * 1024.0 * 1024.0), "4.00G");
+        assert_eq!(super::human_kb(300.0 * 1024.0 * 1024.0), "300G");
+        assert_eq!(super::human_kb(2e13), "18.2P");
+    }
+
+    #[test]
+    fn table_count() {
+        assert_eq!(super::table_count(80, 100, false).get(), 1);
+        assert_eq!(super::table_count(200, 100, false).get(), 2);
+        assert_eq!(super::table_count(299, 100, false).get(), 2);
+        assert_eq!(super::table_count(300, 100, true).get(), 1);
+        assert_eq!(super::table_count(300, 0, false).get(), 1);
+    }
+
+    mod explain_permissions {
+        use super::*;
+
+        fn open_failed(kind: io::ErrorKind) -> anyhow::Error {
+            anyhow::Error::from(freebsd_libgeom::Error::StatsOpenFailed(
+                io::Error::from(kind),
+            ))
+            .context("obtaining initial GEOM snapshot")
+        }
+
+        #[test]
+        fn denied() {
+            let e = explain_permissions(open_failed(
+                io::ErrorKind::PermissionDenied,
+            ));
+            assert!(e.to_string().contains("devfs.rules"));
+            assert_eq!(e.chain().count(), 3);
+        }
+
+        #[test]
+        fn other() {
+            let e = explain_permissions(open_failed(io::ErrorKind::NotFound));
+            assert_eq!(e.to_string(), "obtaining initial GEOM snapshot");
+        }
+    }
+
     mod columns {
         use super::*;
 
@@ -1013,6 +3086,124 @@ mod t {
             assert_eq!(expected, usize::from(columns.max_name_width()));
         }
 
+        #[test]
+        fn latency_unit() {
+            let mut cfg = Cli {
+                latency_unit: Some(LatencyUnit::Us),
+                ..Default::default()
+            };
+            let columns = Columns::new(&mut cfg);
+            let mut elem = group::total(&[]);
+            elem.ms_r = 0.045;
+            assert_eq!(columns.text(&elem, Columns::MS_R), "45.0");
+            assert_eq!(columns.label(Columns::MS_R), "  µs/r");
+            // Only the latency columns are affected
+            assert_eq!(columns.label(column::find("r_s")), "   r/s");
+        }
+
+        #[test]
+        fn human() {
+            let mut cfg = Cli {
+                human: true,
+                ..Default::default()
+            };
+            let mut columns = Columns::new(&mut cfg);
+            let mut elem = group::total(&[]);
+            elem.kbs_r = 4.0 * 1024.0 * 1024.0;
+            elem.r_s = 4096.0;
+            assert_eq!(columns.text(&elem, column::find("kbs_r")), "4.00G");
+            // Only the kB columns are scaled
+            assert_eq!(columns.text(&elem, column::find("r_s")), "4096");
+            columns.human = false;
+            assert_eq!(columns.text(&elem, column::find("kbs_r")), "4194304");
+        }
+
+        /// Columns are at least as wide as their headers and their formats
+        /// require, whatever the values.
+        #[test]
+        fn width() {
+            let mut cfg = Cli::default();
+            let mut columns = Columns::new(&mut cfg);
+            let k = column::find("kbs_r");
+            assert_eq!(columns.width(k), 8);
+            assert_eq!(columns.header(k, columns.width(k)), " kB/s r");
+            assert_eq!(columns.width(column::find("r_s")), 7);
+            assert_eq!(columns.width(Columns::PCT_BUSY), 7);
+            assert_eq!(columns.width(Columns::MS_R), 7);
+            assert_eq!(columns.width(column::find("class")), 11);
+            assert_eq!(
+                columns.constraint(Columns::NAME, 10),
+                Constraint::Min(10)
+            );
+            columns.human = true;
+            assert_eq!(columns.width(k), 7);
+            columns.latency = LatencyUnit::Us;
+            assert_eq!(columns.width(Columns::MS_R), 10);
+        }
+
+        #[test]
+        fn apply_headers() {
+            let mut cfg = Cli::default();
+            let mut columns = Columns::new(&mut cfg);
+            let headers =
+                ["l(q)", " r/s", "w/s", "%busy", "name"].map(str::to_owned);
+            columns.apply_headers(&headers).unwrap();
+            let enabled = columns.enabled().map(|(k, _)| k).collect::<Vec<_>>();
+            assert_eq!(
+                enabled,
+                [
+                    column::find("qd"),
+                    column::find("r_s"),
+                    column::find("w_s"),
+                    Columns::PCT_BUSY,
+                    Columns::NAME
+                ]
+            );
+            let e = columns
+                .apply_headers(&["r/s".to_owned(), "bogus".to_owned()])
+                .unwrap_err();
+            assert!(e.to_string().starts_with("unknown column \"bogus\""));
+            // Nothing changed
+            assert_eq!(columns.enabled().count(), enabled.len());
+        }
+
+        /// The displayed columns are what get saved
+        #[test]
+        fn enabled_bits() {
+            let mut cfg = Cli::default();
+            let mut columns = Columns::new(&mut cfg);
+            assert_eq!(columns.enabled_bits().0, Columns::DEFAULT_ENABLED);
+            columns.apply_headers(&["r/s".to_owned()]).unwrap();
+            let cb = columns.enabled_bits();
+            assert!(cb.get("r_s"));
+            assert_eq!(cb.0.count_ones(), 1);
+        }
+
+        /// Scrolling hides columns on the left, but never Name nor the
+        /// last data column.
+        #[test]
+        fn scroll() {
+            let mut cfg = Cli::default();
+            let mut columns = Columns::new(&mut cfg);
+            let enabled = columns.enabled().map(|(k, _)| k).collect::<Vec<_>>();
+            let n = enabled.len() - 1;
+            let first = |c: &Columns| c.visible().next().unwrap().0;
+            columns.scroll_left();
+            assert_eq!(columns.scroll, 0);
+            assert_eq!(first(&columns), enabled[0]);
+            columns.scroll_right();
+            assert!(!columns.visible().any(|(k, _)| k == enabled[0]));
+            assert_eq!(first(&columns), enabled[1]);
+            for _ in 0..2 * n {
+                columns.scroll_right();
+            }
+            assert_eq!(columns.scroll, n - 1);
+            let visible = columns.visible().map(|(k, _)| k).collect::<Vec<_>>();
+            assert_eq!(visible, [enabled[n - 1], Columns::NAME]);
+            columns.scroll_left();
+            assert_eq!(first(&columns), enabled[n - 2]);
+        }
+
         /// Unlike TableState, it makes no sense for the ColumnSelector to have
         /// no row selected.  So wrap from end to beginning, skipping None.
         #[test]
@@ -1042,6 +3233,102 @@ mod t {
         }
     }
 
+    mod data_source {
+        use super::*;
+
+        /// Replay a single frame of these devices, with increasing r/s
+        fn data_source(names: &[&str]) -> DataSource {
+            let items = names
+                .iter()
+                .enumerate()
+                .map(|(i, name)| {
+                    let mut elem = group::total(&[]);
+                    elem.name = Arc::from(*name);
+                    elem.rank = if name.contains('p') { 2 } else { 1 };
+                    elem.r_s = i as f64;
+                    elem
+                })
+                .collect::<Vec<_>>();
+            let line = serde_json::json!({ "items": items }).to_string();
+            let replay = Replay::read(line.as_bytes(), Path::new("t")).unwrap();
+            DataSource::with_source(Source::Replay(replay)).unwrap()
+        }
+
+        fn visible(data: &DataSource) -> Vec<&str> {
+            data.visible().map(|elem| &*elem.name).collect()
+        }
+
+        /// The visible elements are only recomputed when the filters change,
+        /// or the elements are loaded or reordered.
+        #[test]
+        fn update_visible() {
+            let mut data = data_source(&["da0", "da0p1", "da1"]);
+            let mut cfg = Cli::default();
+            data.update_visible(&cfg, None);
+            assert_eq!(visible(&data), ["da0", "da0p1", "da1"]);
+            cfg.physical = true;
+            data.update_visible(&cfg, None);
+            assert_eq!(visible(&data), ["da0", "da1"]);
+
+            data.items[1].rank = 1;
+            data.update_visible(&cfg, None);
+            assert_eq!(visible(&data), ["da0", "da1"]);
+            data.sort(Some(column::find("r_s")), true);
+            data.update_visible(&cfg, None);
+            assert_eq!(visible(&data), ["da1", "da0p1", "da0"]);
+
+            data.regen().unwrap();
+            data.update_visible(&cfg, None);
+            assert_eq!(visible(&data), ["da0", "da1"]);
+        }
+
+        /// Sorting the same way twice does nothing, until new elements are
+        /// loaded.
+        #[test]
+        fn sort() {
+            let mut data = data_source(&["da0", "da1", "da2"]);
+            data.sort(Some(column::find("r_s")), true);
+            data.items[2].r_s = 10.0;
+            data.sort(Some(column::find("r_s")), true);
+            let names = |data: &DataSource| {
+                data.items
+                    .iter()
+                    .map(|e| e.name.to_string())
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(names(&data), ["da2", "da1", "da0"]);
+            data.sort(Some(column::find("r_s")), false);
+            assert_eq!(names(&data), ["da1", "da2", "da0"]);
+            data.regen().unwrap();
+            data.sort(Some(column::find("r_s")), false);
+            assert_eq!(names(&data), ["da0", "da1", "da2"]);
+        }
+
+        /// A replay's markers are reached once, even when refreshing past the
+        /// end of it.
+        #[test]
+        fn end_of_replay() {
+            let frame = serde_json::json!({ "items": [] }).to_string();
+            let marker = serde_json::json!({
+                "marker": { "time": 1.5, "label": "fio start" }
+            })
+            .to_string();
+            let recording = format!("{frame}\n{marker}\n{frame}\n");
+            let replay =
+                Replay::read(recording.as_bytes(), Path::new("t")).unwrap();
+            let mut data =
+                DataSource::with_source(Source::Replay(replay)).unwrap();
+            data.refresh().unwrap();
+            assert_eq!(data.reached.len(), 1);
+            assert_eq!(data.markers.len(), 1);
+            for _ in 0..3 {
+                data.refresh().unwrap();
+                assert!(data.reached.is_empty());
+                assert_eq!(data.markers.len(), 1);
+            }
+        }
+    }
+
     mod stateful_table {
         use super::*;
 
@@ -1068,6 +3355,46 @@ mod t {
             assert_eq!(t.state.selected(), None);
         }
 
+        fn named(names: &[&str]) -> Vec<Element> {
+            names
+                .iter()
+                .map(|name| {
+                    let mut e = group::total(&[]);
+                    e.name = (*name).into();
+                    e
+                })
+                .collect()
+        }
+
+        /// The selection follows the device, not the row number
+        #[test]
+        fn select_name() {
+            let mut t = StatefulTable::default();
+            t.state.select(Some(1));
+            let before = named(&["da0", "da1", "da2"]);
+            let name = t.selected_name(&before);
+            assert_eq!(name.as_deref(), Some("da1"));
+            let after = named(&["da1", "da2", "da0"]);
+            t.select_name(&after, name.as_deref());
+            assert_eq!(t.state.selected(), Some(0));
+            // If it departs, nothing is selected
+            t.select_name(&named(&["da0"]), Some("da1"));
+            assert_eq!(t.state.selected(), None);
+        }
+
+        #[test]
+        fn search() {
+            let mut t = StatefulTable::default();
+            let rows = named(&["da0", "da1", "ada0", "ada0p1"]);
+            assert!(t.search(&rows, "ada"));
+            assert_eq!(t.state.selected(), Some(2));
+            assert!(t.search(&rows, "p1"));
+            assert_eq!(t.state.selected(), Some(3));
+            // Without a match, the selection doesn't move
+            assert!(!t.search(&rows, "nvd"));
+            assert_eq!(t.state.selected(), Some(3));
+        }
+
         #[test]
         fn previous_empty() {
             let mut t = StatefulTable::default();
@@ -0,0 +1,307 @@
+//! The man page, gstat.8
+//!
+//! It's rendered from the command line's help text, the key bindings in
+//! [`crate::help::KEYS`], and the sections below that clap can't describe, and
+//! checked in as doc/gstat.8.  The `up_to_date` test fails if that copy is
+//! stale.  Run it with `GSTAT_UPDATE_MAN=1` to regenerate the copy.
+use std::io;
+
+use clap_mangen::{
+    roff::{bold, italic, roman, Roff},
+    Man,
+};
+
+use crate::help::KEYS;
+
+/// How gstat-rs differs from FreeBSD's gstat(8)
+const DIFFERENCES: &[&str] = &[
+    "It can sort the devices.",
+    "It can display or hide arbitrary columns of data.",
+    "If the screen has enough space, it will display multiple devices \
+     side-by-side.",
+    "It can pause the display temporarily.",
+    "It underlines any latency that is unusually high compared with the same \
+     device's recent history.  A latency is unusual if it lies more than 3.5 \
+     times the median absolute deviation above the median of the last 30 \
+     intervals with such operations, after scaling the deviation by 0.6745.  \
+     Cumulative statistics are never underlined.",
+    "Its settings are automatically persisted to a configuration file, along \
+     with the selected device, horizontal scroll position, and whether the \
+     display is paused.",
+    "Its batch mode can emit CSV or JSON, and can be limited by a sample \
+     count or duration.",
+    "It does not display GEOM consumers, only producers, except with --compat \
+     and --consumers.  Its -c option takes a sample count instead.",
+];
+
+const ENVIRONMENT: &[(&str, &str)] = &[
+    (
+        "NO_COLOR",
+        "Output will be rendered in black-and-white only, without color.",
+    ),
+    (
+        "XDG_CONFIG_HOME",
+        "The directory containing the per-user configuration file, instead of \
+         ~/.config.",
+    ),
+];
+
+/// Each file, and its description as paragraphs.  Paragraphs beginning with
+/// a space are examples, displayed verbatim.
+const FILES: &[(&str, &[&str])] = &[
+    (
+        "/dev/devstat",
+        &[
+            "The kernel's device statistics, which must be readable.  It is \
+             readable by all users by default, but devfs.rules(5) may hide it \
+             or restrict its mode, especially within a jail.  A rule like",
+            " add path 'devstat' unhide mode 0444",
+            "makes it readable again.",
+        ],
+    ),
+    (
+        "/usr/local/etc/gstat-rs.toml",
+        &[
+            "The system-wide configuration file, in the same format as the \
+             per-user one.  It supplies the defaults for every user, so it \
+             need only contain the settings that differ from the built-in \
+             defaults.  It is never written by gstat.",
+        ],
+    ),
+    (
+        "~/.config/gstat-rs/default-config.toml",
+        &[
+            "The per-user configuration file, in TOML format.  Its settings \
+             override those of the system-wide file.  When gstat saves it, \
+             only the settings that differ from the system-wide file are \
+             written, so later changes to that file still apply.  Tables, \
+             like presets and limits, are merged entry by entry.  Besides the \
+             persisted settings, it may contain a limits table giving the \
+             rated performance of individual devices, such as",
+            " [limits.nda0]\n iops = 500000\n kbs = 3000000",
+            "iops is the maximum operations per second, and kbs the maximum \
+             combined read, write, and delete throughput in kB/s.  Either may \
+             be omitted.  The optional \"IOPs saturation\" and \"Throughput \
+             saturation\" columns display each device's current activity as a \
+             percentage of these limits, and are blank for devices without \
+             one.  When grouping by class, limits may be given for class \
+             names instead.",
+            "Presets saved from the presets dialog are stored in the presets \
+             table, such as",
+            " [presets.mine]\n columns = 196663\n sort = \"ms/r\"\n reverse = \
+             true",
+            "where columns is a bitfield of the columns to display, in the \
+             same format as the top-level columns setting.  A saved preset \
+             with the same name as a built-in one replaces it.",
+        ],
+    ),
+];
+
+const SEE_ALSO: &[&str] = &[
+    "systat(1)",
+    "syslog(3)",
+    "geom(4)",
+    "devfs.rules(5)",
+    "iostat(8)",
+    "vmstat(8)",
+];
+
+/// Write the man page for `cmd`, which should be [`crate::Cli`]'s.
+pub fn render(cmd: clap::Command, w: &mut dyn io::Write) -> io::Result<()> {
+    let man = Man::new(cmd.clone())
+        .title("gstat-rs")
+        .section("8")
+        .source("gstat-rs");
+    man.render_title(w)?;
+    man.render_name_section(w)?;
+    man.render_synopsis_section(w)?;
+    man.render_description_section(w)?;
+    differences().to_writer(w)?;
+    man.render_options_section(w)?;
+    subcommands(&cmd).to_writer(w)?;
+    keys().to_writer(w)?;
+    trailer().to_writer(w)
+}
+
+fn differences() -> Roff {
+    let mut roff = Roff::new();
+    roff.control("PP", [])
+        .text([roman("Compared with gstat(8):")]);
+    for d in DIFFERENCES {
+        roff.control("IP", ["\\(bu", "2"]).text([roman(*d)]);
+    }
+    roff
+}
+
+/// Like [`Man::render_subcommands_section`], but describing the subcommands
+/// here, rather than referring to pages of their own.
+fn subcommands(cmd: &clap::Command) -> Roff {
+    let mut roff = Roff::new();
+    roff.control("SH", ["SUBCOMMANDS"]);
+    for sub in cmd.get_subcommands().filter(|s| !s.is_hide_set()) {
+        let mut usage =
+            vec![bold(format!("{} {}", cmd.get_name(), sub.get_name()))];
+        for arg in sub.get_arguments().filter(|a| !a.is_hide_set()) {
+            let name = arg.get_id().as_str().to_uppercase();
+            usage.push(roman(" "));
+            match arg.get_long() {
+                Some(long) => usage.extend([
+                    roman("["),
+                    bold(format!("--{long}")),
+                    roman("]"),
+                ]),
+                None => usage.push(italic(name)),
+            }
+        }
+        roff.control("TP", []).text(usage);
+        let about = sub.get_long_about().or_else(|| sub.get_about());
+        for (i, para) in about
+            .map(ToString::to_string)
+            .unwrap_or_default()
+            .split("\n\n")
+            .enumerate()
+        {
+            if i > 0 {
+                roff.control("IP", []);
+            }
+            roff.text([roman(para)]);
+        }
+        for arg in sub.get_positionals() {
+            let values = arg
+                .get_possible_values()
+                .into_iter()
+                .filter(|v| !v.is_hide_set())
+                .map(|v| v.get_name().to_owned())
+                .collect::<Vec<_>>();
+            if let Some((last, rest)) = values.split_last() {
+                let values = if rest.is_empty() {
+                    last.clone()
+                } else {
+                    format!("{}, or {last}", rest.join(", "))
+                };
+                roff.text([
+                    italic(arg.get_id().as_str().to_uppercase()),
+                    roman(format!(" may be {values}.")),
+                ]);
+            }
+        }
+    }
+    roff
+}
+
+fn keys() -> Roff {
+    let mut roff = Roff::new();
+    roff.control("SH", ["INTERACTIVE COMMANDS"]);
+    for key in KEYS {
+        let desc = if key.detail.is_empty() {
+            format!("{}.", key.help)
+        } else {
+            format!("{}.  {}", key.help, key.detail)
+        };
+        roff.control("TP", [])
+            .text([bold(key.key)])
+            .text([roman(desc)]);
+    }
+    roff
+}
+
+/// Every section after INTERACTIVE COMMANDS
+fn trailer() -> Roff {
+    let mut roff = Roff::new();
+    roff.control("SH", ["ENVIRONMENT"]);
+    for (var, desc) in ENVIRONMENT {
+        roff.control("TP", [])
+            .text([bold(*var)])
+            .text([roman(*desc)]);
+    }
+    roff.control("SH", ["FILES"]);
+    for (path, paras) in FILES {
+        roff.control("TP", []).text([italic(*path)]);
+        for (i, para) in paras.iter().enumerate() {
+            if let Some(example) = para.strip_prefix(' ') {
+                roff.control("IP", [])
+                    .control("nf", [])
+                    .text([roman(example.replace("\n ", "\n"))])
+                    .control("fi", []);
+            } else {
+                if i > 0 {
+                    roff.control("IP", []);
+                }
+                roff.text([roman(*para)]);
+            }
+        }
+    }
+    roff.control("SH", ["EXIT STATUS"]);
+    roff.text([roman(
+        "The gstat utility exits 0 on success, and >0 if an error occurs.",
+    )]);
+    roff.control("SH", ["SEE ALSO"]);
+    roff.text([roman(SEE_ALSO.join(", "))]);
+    roff.control("SH", ["HISTORY"]);
+    roff.text([roman(
+        "A gstat utility appeared in FreeBSD 5.0.  It was rewritten in Rust \
+         during 2021 by Alan Somers <asomers@FreeBSD.org> under the \
+         sponsorship of Axcient.",
+    )]);
+    roff
+}
+
+#[cfg(test)]
+mod t {
+    use std::{env, fs, path::Path};
+
+    use clap::CommandFactory;
+
+    use super::*;
+    use crate::Cli;
+
+    fn page() -> String {
+        let mut buf = Vec::new();
+        render(Cli::command(), &mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    /// The checked-in man page must match the command line and key bindings
+    #[test]
+    fn up_to_date() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("doc/gstat.8");
+        let page = page();
+        if env::var_os("GSTAT_UPDATE_MAN").is_some() {
+            fs::write(&path, &page).unwrap();
+        }
+        let checked_in = fs::read_to_string(&path).unwrap_or_default();
+        assert!(
+            checked_in == page,
+            "doc/gstat.8 is stale.  Regenerate it with \"GSTAT_UPDATE_MAN=1 \
+             cargo test -p gstat up_to_date\"."
+        );
+    }
+
+    /// The man page should describe every key
+    #[test]
+    fn keys() {
+        let roff = super::keys().to_roff();
+        let n = roff.lines().filter(|l| *l == ".TP").count();
+        assert_eq!(n, KEYS.len());
+        assert!(roff.contains("Toggle tree view.  This has the same effect"));
+    }
+
+    /// Options' long help, not just their short help, belongs in the man page
+    #[test]
+    fn long_help() {
+        let page = page();
+        assert!(
+            page.contains("The format of the regular expression is described")
+        );
+        assert!(page.contains(".SH OPTIONS"));
+    }
+
+    #[test]
+    fn subcommands() {
+        let roff = super::subcommands(&Cli::command()).to_roff();
+        assert!(roff.contains("\\fBgstat completions\\fR \\fISHELL\\fR"));
+        assert!(roff.contains("\\fBgstat tree\\fR [\\fB\\-\\-json\\fR]"));
+        assert!(roff.contains(", powershell, or zsh."));
+        assert!(!roff.contains("gstat\\-tree(8)"));
+    }
+}
@@ -0,0 +1,201 @@
+//! Detecting statistically unusual intervals
+//!
+//! Averages hide rare latency excursions.  For each device, the recent
+//! history of each latency is kept, and an interval is flagged when its
+//! latency lies far above the history's median, as measured by the median
+//! absolute deviation (MAD).  Unlike the mean and standard deviation, both are
+//! robust to the very excursions being sought.
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::Arc,
+};
+
+use crate::Element;
+
+/// Each statistic that's checked, and the rate of the operations it
+/// describes.  An interval without any such operations is ignored.
+const STATS: [(&str, &str); 4] = [
+    ("ms_r", "r_s"),
+    ("ms_w", "w_s"),
+    ("ms_d", "d_s"),
+    ("ms_o", "o_s"),
+];
+
+/// How many of a device's recent intervals to compare against
+const HISTORY: usize = 30;
+
+/// Don't flag anything until this many intervals have been observed
+const MIN_HISTORY: usize = 10;
+
+/// The modified z-score above which a value is an outlier.  3.5 is the
+/// customary choice, from Iglewicz and Hoaglin.
+const THRESHOLD: f64 = 3.5;
+
+/// Keeps each device's recent history, and flags outliers
+#[derive(Debug, Default)]
+pub struct Detector {
+    history: HashMap<Arc<str>, [VecDeque<f64>; STATS.len()]>,
+    /// The statistics flagged at the most recent observation, by device
+    flagged: HashMap<Arc<str>, Vec<String>>,
+}
+
+impl Detector {
+    /// Check a new interval's statistics against each device's history, and
+    /// then add them to it.  Forgets devices that are no longer present.
+    pub fn observe(&mut self, items: &[Element]) {
+        self.flagged.clear();
+        for elem in items {
+            let history = self.history.entry(elem.name.clone()).or_default();
+            for (h, (stat, rate)) in history.iter_mut().zip(STATS) {
+                let (Some(v), Some(r)) = (elem.stat(stat), elem.stat(rate))
+                else {
+                    continue;
+                };
+                if r <= 0.0 {
+                    continue;
+                }
+                if is_outlier(h, v) {
+                    self.flagged
+                        .entry(elem.name.clone())
+                        .or_default()
+                        .push(stat.to_owned());
+                }
+                if h.len() >= HISTORY {
+                    h.pop_front();
+                }
+                h.push_back(v);
+            }
+        }
+        let present =
+            items.iter().map(|elem| &*elem.name).collect::<HashSet<_>>();
+        self.history.retain(|name, _| present.contains(&**name));
+    }
+
+    /// Annotate each element with the statistics flagged at the most recent
+    /// observation.
+    pub fn mark(&self, items: &mut [Element]) {
+        for elem in items {
+            elem.outliers =
+                self.flagged.get(&elem.name).cloned().unwrap_or_default();
+        }
+    }
+}
+
+/// Is `v` unusually high, compared to `history`?
+fn is_outlier(history: &VecDeque<f64>, v: f64) -> bool {
+    if history.len() < MIN_HISTORY {
+        return false;
+    }
+    let mut values = history.iter().copied().collect::<Vec<_>>();
+    let med = median(&mut values);
+    for x in values.iter_mut() {
+        *x = (*x - med).abs();
+    }
+    let mad = median(&mut values);
+    // With no variation at all, there's no scale to judge by
+    mad > 0.0 && 0.6745 * (v - med) / mad > THRESHOLD
+}
+
+fn median(values: &mut [f64]) -> f64 {
+    values.sort_by(f64::total_cmp);
+    let n = values.len();
+    if n % 2 == 0 {
+        (values[n / 2 - 1] + values[n / 2]) / 2.0
+    } else {
+        values[n / 2]
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+    use crate::group;
+
+    fn elem(name: &str, ms_r: f64) -> Element {
+        let mut e = group::total(&[]);
+        e.name = name.into();
+        e.r_s = 100.0;
+        e.ms_r = ms_r;
+        e
+    }
+
+    /// Observe a steady, slightly noisy latency for `n` intervals
+    fn warm(d: &mut Detector, name: &str, n: usize) {
+        for i in 0..n {
+            d.observe(&[elem(name, 1.0 + (i % 3) as f64 * 0.1)]);
+        }
+    }
+
+    #[test]
+    fn excursion() {
+        let mut d = Detector::default();
+        warm(&mut d, "da0", HISTORY);
+        let mut items = [elem("da0", 5.0)];
+        d.observe(&items);
+        d.mark(&mut items);
+        assert_eq!(items[0].outliers, ["ms_r"]);
+        // The flag lasts only until the next observation
+        let mut items = [elem("da0", 1.1)];
+        d.observe(&items);
+        d.mark(&mut items);
+        assert!(items[0].outliers.is_empty());
+    }
+
+    #[test]
+    fn ordinary() {
+        let mut d = Detector::default();
+        warm(&mut d, "da0", HISTORY);
+        let mut items = [elem("da0", 1.2)];
+        d.observe(&items);
+        d.mark(&mut items);
+        assert!(items[0].outliers.is_empty());
+    }
+
+    /// Unusually low latency isn't worth flagging
+    #[test]
+    fn low() {
+        let mut d = Detector::default();
+        warm(&mut d, "da0", HISTORY);
+        let mut items = [elem("da0", 0.0)];
+        d.observe(&items);
+        d.mark(&mut items);
+        assert!(items[0].outliers.is_empty());
+    }
+
+    #[test]
+    fn too_little_history() {
+        let mut d = Detector::default();
+        warm(&mut d, "da0", MIN_HISTORY - 1);
+        let mut items = [elem("da0", 100.0)];
+        d.observe(&items);
+        d.mark(&mut items);
+        assert!(items[0].outliers.is_empty());
+    }
+
+    /// Intervals without any reads don't count towards the read history
+    #[test]
+    fn idle() {
+        let mut d = Detector::default();
+        let mut idle = elem("da0", 0.0);
+        idle.r_s = 0.0;
+        for _ in 0..HISTORY {
+            d.observe(&[idle.clone()]);
+        }
+        assert!(d.history["da0"][0].is_empty());
+    }
+
+    #[test]
+    fn departed() {
+        let mut d = Detector::default();
+        warm(&mut d, "da0", HISTORY);
+        d.observe(&[elem("da1", 1.0)]);
+        assert!(!d.history.contains_key("da0"));
+        assert!(d.history.contains_key("da1"));
+    }
+
+    #[test]
+    fn median() {
+        assert_eq!(super::median(&mut [3.0, 1.0, 2.0]), 2.0);
+        assert_eq!(super::median(&mut [4.0, 1.0, 2.0, 3.0]), 2.5);
+    }
+}
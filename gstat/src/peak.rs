@@ -0,0 +1,117 @@
+//! Decaying peaks of each device's throughput
+//!
+//! A short burst of I/O is easy to miss when glancing at the screen.  So each
+//! device's peak throughput is kept, and it decays exponentially toward the
+//! current throughput.  A burst remains visible, fading, for several
+//! half-lives after it ends.
+use std::{collections::HashMap, sync::Arc, time::Instant};
+
+use crate::Element;
+
+/// Keeps each device's recent peak
+#[derive(Debug)]
+pub struct Tracker {
+    /// Time for a peak's excess over the current throughput to halve, in
+    /// seconds
+    half_life: f64,
+    peaks:     HashMap<Arc<str>, f64>,
+    /// When the most recent interval was observed
+    last:      Option<Instant>,
+}
+
+impl Tracker {
+    pub fn new(half_life: f64) -> Self {
+        Tracker {
+            half_life,
+            peaks: HashMap::new(),
+            last: None,
+        }
+    }
+
+    /// Decay each device's peak by the time since the previous observation,
+    /// and raise it to the new interval's throughput.  Forgets devices that
+    /// are no longer present.
+    pub fn observe(&mut self, items: &[Element]) {
+        let now = Instant::now();
+        let elapsed = self
+            .last
+            .replace(now)
+            .map_or(0.0, |last| (now - last).as_secs_f64());
+        self.observe_after(items, elapsed);
+    }
+
+    fn observe_after(&mut self, items: &[Element], elapsed: f64) {
+        let factor = if self.half_life > 0.0 {
+            0.5f64.powf(elapsed / self.half_life)
+        } else {
+            0.0
+        };
+        let mut peaks = HashMap::with_capacity(items.len());
+        for elem in items {
+            let kbs = elem.kbs_r + elem.kbs_w + elem.kbs_d;
+            let peak = match self.peaks.get(&elem.name) {
+                Some(&old) if old > kbs => kbs + (old - kbs) * factor,
+                _ => kbs,
+            };
+            peaks.insert(elem.name.clone(), peak);
+        }
+        self.peaks = peaks;
+    }
+
+    /// Annotate each element with its peak as of the most recent observation
+    pub fn mark(&self, items: &mut [Element]) {
+        for elem in items {
+            elem.peak_kbs = self.peaks.get(&elem.name).copied();
+        }
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+    use crate::group;
+
+    fn elem(name: &str, kbs_r: f64) -> Element {
+        let mut e = group::total(&[]);
+        e.name = name.into();
+        e.kbs_r = kbs_r;
+        e
+    }
+
+    fn peak(tracker: &Tracker, name: &str) -> Option<f64> {
+        let mut items = [elem(name, 0.0)];
+        tracker.mark(&mut items);
+        items[0].peak_kbs
+    }
+
+    /// After a burst ends, its excess over the current throughput should halve
+    /// every half-life.
+    #[test]
+    fn decays() {
+        let mut tracker = Tracker::new(10.0);
+        tracker.observe_after(&[elem("ada0", 1000.0)], 0.0);
+        assert_eq!(peak(&tracker, "ada0"), Some(1000.0));
+        tracker.observe_after(&[elem("ada0", 200.0)], 10.0);
+        assert_eq!(peak(&tracker, "ada0"), Some(600.0));
+        tracker.observe_after(&[elem("ada0", 200.0)], 20.0);
+        assert_eq!(peak(&tracker, "ada0"), Some(300.0));
+    }
+
+    /// A new burst should raise the peak immediately.
+    #[test]
+    fn rises() {
+        let mut tracker = Tracker::new(10.0);
+        tracker.observe_after(&[elem("ada0", 100.0)], 0.0);
+        tracker.observe_after(&[elem("ada0", 5000.0)], 1.0);
+        assert_eq!(peak(&tracker, "ada0"), Some(5000.0));
+    }
+
+    #[test]
+    fn forgets_departed() {
+        let mut tracker = Tracker::new(10.0);
+        tracker.observe_after(&[elem("ada0", 100.0), elem("da0", 100.0)], 0.0);
+        tracker.observe_after(&[elem("ada0", 100.0)], 1.0);
+        assert_eq!(peak(&tracker, "ada0"), Some(100.0));
+        assert_eq!(peak(&tracker, "da0"), None);
+    }
+}
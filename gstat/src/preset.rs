@@ -0,0 +1,132 @@
+//! Named combinations of columns and sort order
+use std::collections::BTreeMap;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::ColumnsEnabled;
+
+/// A saved set of columns and sort order
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Preset {
+    /// Bitfield of columns to enable
+    pub columns: ColumnsEnabled,
+    /// Sort by the column with this header
+    #[serde(default)]
+    pub sort:    Option<String>,
+    #[serde(default)]
+    pub reverse: bool,
+}
+
+impl Preset {
+    /// Display the columns of these statistics, as named by `Element::stat`
+    fn builtin(stats: &[&str], sort: &str) -> Self {
+        let mut columns = ColumnsEnabled(0);
+        for stat in stats {
+            columns.set(stat, true);
+        }
+        Preset {
+            columns,
+            sort: Some(sort.to_owned()),
+            // Show the worst devices first
+            reverse: true,
+        }
+    }
+}
+
+/// The presets shipped with gstat, in display order
+pub fn builtins() -> [(&'static str, Preset); 3] {
+    [
+        // Latency of every kind of operation, including TRIM and flush
+        (
+            "ssd-latency",
+            Preset::builtin(
+                &[
+                    "qd", "ops_s", "r_s", "ms_r", "w_s", "ms_w", "ms_d",
+                    "ms_o", "pct_busy", "name",
+                ],
+                "ms/w",
+            ),
+        ),
+        (
+            "hdd-throughput",
+            Preset::builtin(
+                &[
+                    "qd", "ops_s", "r_s", "kb_r", "kbs_r", "w_s", "kb_w",
+                    "kbs_w", "pct_busy", "pct_kbs", "name",
+                ],
+                "%busy",
+            ),
+        ),
+        (
+            "trim-debug",
+            Preset::builtin(
+                &[
+                    "qd", "ops_s", "w_s", "ms_w", "d_s", "kb_d", "kbs_d",
+                    "ms_d", "pct_busy", "name",
+                ],
+                "d/s",
+            ),
+        ),
+    ]
+}
+
+/// Every preset's name: the built-in ones, then the user's own.  A user
+/// preset with the same name as a built-in one replaces it.
+pub fn names(user: &BTreeMap<String, Preset>) -> Vec<String> {
+    let builtins = builtins().map(|(name, _)| name.to_owned());
+    let user = user.keys().filter(|name| !builtins.contains(name)).cloned();
+    builtins.iter().cloned().chain(user).collect()
+}
+
+/// Find a preset by name, preferring the user's own.
+pub fn lookup(user: &BTreeMap<String, Preset>, name: &str) -> Option<Preset> {
+    user.get(name).cloned().or_else(|| {
+        builtins()
+            .into_iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, preset)| preset)
+    })
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+    use crate::{Cli, Columns};
+
+    /// Every built-in preset must sort by a column that it displays
+    #[test]
+    fn builtin_sort_keys() {
+        let mut cfg = Cli::default();
+        let columns = Columns::new(&mut cfg);
+        for (name, preset) in builtins() {
+            let sort = preset.sort.as_deref().unwrap();
+            let i = columns.find(sort).unwrap_or_else(|| panic!("{name}"));
+            let bit = columns.cols[i].bit.unwrap();
+            assert!(preset.columns.0 & (1 << bit) != 0, "{name}");
+            assert!(preset.columns.get("name"), "{name}");
+        }
+    }
+
+    #[test]
+    fn user_overrides_builtin() {
+        let mut columns = ColumnsEnabled(0);
+        columns.set("name", true);
+        let mine = Preset {
+            columns,
+            sort: None,
+            reverse: false,
+        };
+        let user = [
+            ("ssd-latency".to_owned(), mine.clone()),
+            ("mine".to_owned(), mine),
+        ]
+        .into();
+        assert_eq!(
+            names(&user),
+            ["ssd-latency", "hdd-throughput", "trim-debug", "mine"]
+        );
+        assert_eq!(lookup(&user, "ssd-latency").unwrap().columns.0, columns.0);
+        assert!(lookup(&user, "trim-debug").unwrap().reverse);
+        assert!(lookup(&user, "bogus").is_none());
+    }
+}
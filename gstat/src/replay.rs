@@ -0,0 +1,191 @@
+//! Recording and playback of gstat sessions
+//!
+//! A recording is a file of newline-delimited JSON objects, one per update
+//! interval, each containing the statistics that were displayed for every
+//! device.  Markers dropped during the session are interleaved, each before
+//! the interval that followed it.
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    mem,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::Element;
+
+/// A labeled point in a session, like the start of a benchmark run
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Marker {
+    /// When it was dropped, in seconds since the epoch
+    pub time:  f64,
+    pub label: String,
+}
+
+impl Marker {
+    pub fn now(label: String) -> Self {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        Marker { time, label }
+    }
+}
+
+/// One line of a recording
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum Record<T> {
+    /// One update interval's worth of statistics
+    Frame {
+        items: T,
+    },
+    Marker {
+        marker: Marker,
+    },
+}
+
+/// Saves each interval's statistics to a file
+pub struct Recorder(BufWriter<File>);
+
+impl Recorder {
+    pub fn create(path: &Path) -> Result<Self> {
+        let f = File::create(path)
+            .with_context(|| format!("creating {}", path.display()))?;
+        Ok(Recorder(BufWriter::new(f)))
+    }
+
+    pub fn write(&mut self, items: &[Element]) -> Result<()> {
+        self.write_record(&Record::Frame { items })
+    }
+
+    pub fn mark(&mut self, marker: &Marker) -> Result<()> {
+        self.write_record(&Record::<()>::Marker {
+            marker: marker.clone(),
+        })
+    }
+
+    fn write_record<T: serde::Serialize>(
+        &mut self,
+        record: &Record<T>,
+    ) -> Result<()> {
+        serde_json::to_writer(&mut self.0, record)
+            .context("writing recording")?;
+        self.0.write_all(b"\n").context("writing recording")?;
+        // Flush every frame, so the recording is complete even if gstat is
+        // killed.
+        self.0.flush().context("writing recording")?;
+        Ok(())
+    }
+}
+
+/// A previously recorded session
+pub struct Replay {
+    frames:  Vec<Vec<Element>>,
+    /// Each marker, and the index of the frame that followed it
+    markers: Vec<(usize, Marker)>,
+    pos:     usize,
+}
+
+impl Replay {
+    pub fn open(path: &Path) -> Result<Self> {
+        let f = File::open(path)
+            .with_context(|| format!("opening {}", path.display()))?;
+        Self::read(BufReader::new(f), path)
+    }
+
+    /// Read a recording from `r`.  `path` is only for error messages.
+    pub fn read(r: impl BufRead, path: &Path) -> Result<Self> {
+        let mut frames = Vec::new();
+        let mut markers = Vec::new();
+        for (i, line) in r.lines().enumerate() {
+            let line = line.context("reading recording")?;
+            let record: Record<Vec<Element>> = serde_json::from_str(&line)
+                .with_context(|| {
+                    format!("parsing {} line {}", path.display(), i + 1)
+                })?;
+            match record {
+                Record::Frame { items } => frames.push(items),
+                Record::Marker { marker } => {
+                    markers.push((frames.len(), marker))
+                }
+            }
+        }
+        anyhow::ensure!(!frames.is_empty(), "{} is empty", path.display());
+        Ok(Replay {
+            frames,
+            markers,
+            pos: 0,
+        })
+    }
+
+    /// The statistics for the current frame
+    pub fn items(&self) -> &[Element] {
+        &self.frames[self.pos]
+    }
+
+    /// The markers dropped just before the current frame
+    pub fn markers(&self) -> impl Iterator<Item = &Marker> {
+        self.markers
+            .iter()
+            .filter(|(i, _)| *i == self.pos)
+            .map(|(_, m)| m)
+    }
+
+    /// Move `delta` frames forwards or backwards, stopping at either end.
+    /// Returns whether the position changed.
+    pub fn seek(&mut self, delta: isize) -> bool {
+        let last = self.frames.len() - 1;
+        let pos = self.pos.saturating_add_signed(delta).min(last);
+        mem::replace(&mut self.pos, pos) != pos
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    #[test]
+    fn seek() {
+        let mut replay = Replay {
+            frames:  vec![Vec::new(); 3],
+            markers: Vec::new(),
+            pos:     0,
+        };
+        assert!(!replay.seek(-1));
+        assert_eq!(replay.pos, 0);
+        assert!(replay.seek(2));
+        assert_eq!(replay.pos, 2);
+        assert!(!replay.seek(1));
+        assert_eq!(replay.pos, 2);
+        assert!(replay.seek(-1));
+        assert_eq!(replay.pos, 1);
+    }
+
+    /// Markers survive a round trip through a recording, and are reported at
+    /// the frame that followed them.
+    #[test]
+    fn markers() {
+        let start = Marker {
+            time:  1.5,
+            label: "fio start".to_owned(),
+        };
+        let frame =
+            serde_json::to_string(&Record::Frame { items: [0u8; 0] }).unwrap();
+        let marker = serde_json::to_string(&Record::<()>::Marker {
+            marker: start.clone(),
+        })
+        .unwrap();
+        let recording = format!("{frame}\n{marker}\n{frame}\n");
+
+        let mut replay =
+            Replay::read(recording.as_bytes(), Path::new("session")).unwrap();
+        assert_eq!(replay.frames.len(), 2);
+        assert_eq!(replay.markers().count(), 0);
+        replay.seek(1);
+        assert_eq!(replay.markers().collect::<Vec<_>>(), [&start]);
+    }
+}
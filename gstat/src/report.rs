@@ -0,0 +1,256 @@
+//! Bundle everything that a maintainer needs to reproduce a problem
+//!
+//! The bundle is an uncompressed tar archive containing:
+//! * `versions.txt`: gstat's version and the running kernel's
+//! * `confxml.xml`: the GEOM configuration, as in `sysctl kern.geom.confxml`
+//! * `snapshot0.txt` and `snapshot1.txt`: every raw devstat structure, from two
+//!   snapshots taken one interval apart
+//! * `statistics.json`: the statistics that gstat computed from those
+//!   snapshots
+use std::{
+    ffi::CStr,
+    fmt::Write as _,
+    fs::File,
+    io::{self, BufWriter, Write},
+    mem,
+    path::Path,
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use freebsd_libgeom::{getxml, Snapshot, Statistics, Tree};
+
+use crate::Element;
+
+/// Directory within the archive that holds every file
+const PREFIX: &str = "gstat-bug-report";
+
+/// Configuration attributes that may identify a particular device
+const SERIALS: [&str; 3] = ["ident", "lunid", "lunname"];
+
+/// Collect serial numbers and similar identifiers from `tree`, longest first.
+fn serials(tree: &Tree) -> Vec<String> {
+    let mut serials = Vec::new();
+    for class in tree.classes() {
+        for geom in class.geoms() {
+            for provider in geom.providers() {
+                for attr in SERIALS {
+                    if let Some(v) = provider.config().get(attr) {
+                        let v = v.to_string_lossy().trim().to_owned();
+                        if !v.is_empty() && !serials.contains(&v) {
+                            serials.push(v);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    // Replace longer identifiers first, in case one contains another
+    serials.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+    serials
+}
+
+/// Replace every occurrence of each of `serials` with a placeholder.
+///
+/// Each serial gets a distinct placeholder, so devices can still be told apart.
+fn redact(text: &str, serials: &[String]) -> String {
+    let mut text = text.to_owned();
+    for (i, serial) in serials.iter().enumerate() {
+        text = text.replace(serial.as_str(), &format!("SERIAL{i}"));
+    }
+    text
+}
+
+/// Describe the running kernel, like `uname -a`
+fn kernel_version() -> Result<String> {
+    let mut uts = unsafe { mem::zeroed::<libc::utsname>() };
+    if unsafe { libc::uname(&mut uts) } != 0 {
+        return Err(io::Error::last_os_error()).context("uname");
+    }
+    let field = |f: &[libc::c_char]| {
+        unsafe { CStr::from_ptr(f.as_ptr()) }
+            .to_string_lossy()
+            .into_owned()
+    };
+    Ok(format!(
+        "{} {} {} {}",
+        field(&uts.sysname),
+        field(&uts.release),
+        field(&uts.version),
+        field(&uts.machine)
+    ))
+}
+
+/// Format every raw devstat structure in `snapshot`, labelled by name
+fn dump(snapshot: &mut Snapshot, tree: &mut Tree) -> String {
+    let mut s = String::new();
+    for devstat in snapshot.iter() {
+        let name = tree
+            .lookup(devstat.id())
+            .and_then(|gident| gident.name().ok())
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| devstat.devname());
+        writeln!(s, "# {name}\n{devstat:#?}").unwrap();
+    }
+    s
+}
+
+/// Compute statistics between two snapshots, for every devstat entry
+fn statistics(
+    cur: &mut Snapshot,
+    prev: &mut Snapshot,
+    tree: &mut Tree,
+) -> Vec<Element> {
+    let etime = f64::from(cur.timestamp() - prev.timestamp());
+    let mut items = Vec::new();
+    for (curstat, prevstat) in cur.iter_pair(Some(prev)) {
+        let stats = Statistics::compute_or_since_creation(
+            curstat, prevstat, etime, None,
+        )
+        .to_record();
+        let elem = match tree.lookup(curstat.id()) {
+            Some(gident) => {
+                let name = match gident.name() {
+                    Ok(name) => name.to_string_lossy(),
+                    // Consumers have no name of their own
+                    Err(_) => continue,
+                };
+                let mut elem = Element::new(
+                    name.into(),
+                    gident.rank().unwrap_or(0),
+                    &stats,
+                );
+                if let Some(class) = gident.class() {
+                    elem.class = class.to_string_lossy().into_owned();
+                }
+                elem
+            }
+            None => {
+                let mut elem =
+                    Element::new(curstat.devname().into(), 0, &stats);
+                elem.class =
+                    curstat.device_name().to_string_lossy().into_owned();
+                elem
+            }
+        };
+        items.push(elem);
+    }
+    items
+}
+
+/// Write each of `files` into a tar archive under [`PREFIX`], and return the
+/// inner writer.
+fn archive<W: Write>(
+    w: W,
+    mtime: u64,
+    files: &[(&str, String)],
+) -> io::Result<W> {
+    let mut tar = tar::Builder::new(w);
+    for (name, contents) in files {
+        let mut header = tar::Header::new_ustar();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(mtime);
+        tar.append_data(
+            &mut header,
+            format!("{PREFIX}/{name}"),
+            contents.as_bytes(),
+        )?;
+    }
+    let mut w = tar.into_inner()?;
+    w.flush()?;
+    Ok(w)
+}
+
+/// Write a bug report bundle to `path`.
+///
+/// The two snapshots are taken `interval` apart.  If `sanitize`, then disk
+/// serial numbers and LUN identifiers are replaced with placeholders.
+pub fn create(path: &Path, interval: Duration, sanitize: bool) -> Result<()> {
+    let mut tree = Tree::new().context("Error opening GEOM tree")?;
+    let confxml = getxml().context("reading GEOM configuration")?;
+    let mut prev = Snapshot::new().context("obtaining GEOM snapshot")?;
+    thread::sleep(interval);
+    let mut cur = Snapshot::new().context("obtaining GEOM snapshot")?;
+    let snapshot0 = dump(&mut prev, &mut tree);
+    let snapshot1 = dump(&mut cur, &mut tree);
+    let items = statistics(&mut cur, &mut prev, &mut tree);
+    let statistics = serde_json::to_string_pretty(&items)
+        .context("serializing statistics")?;
+    let versions = format!(
+        "gstat {}\n{}\n",
+        env!("CARGO_PKG_VERSION"),
+        kernel_version()?
+    );
+
+    let serials = if sanitize { serials(&tree) } else { Vec::new() };
+    let mtime = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let f = File::create(path)
+        .with_context(|| format!("creating {}", path.display()))?;
+    let files = [
+        ("versions.txt", versions),
+        ("confxml.xml", confxml),
+        ("snapshot0.txt", snapshot0),
+        ("snapshot1.txt", snapshot1),
+        ("statistics.json", statistics),
+    ]
+    .map(|(name, contents)| (name, redact(&contents, &serials)));
+    archive(BufWriter::new(f), mtime, &files)
+        .with_context(|| format!("writing {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod t {
+    use std::io::Read;
+
+    use super::*;
+
+    #[test]
+    fn redact_serials() {
+        let serials = ["Z1234567".to_owned(), "5000c500".to_owned()];
+        let xml = "<ident>Z1234567</ident><lunid>5000c500</lunid><name>diskid/\
+                   DISK-Z1234567</name>";
+        assert_eq!(
+            redact(xml, &serials),
+            "<ident>SERIAL0</ident><lunid>SERIAL1</lunid><name>diskid/\
+             DISK-SERIAL0</name>"
+        );
+    }
+
+    #[test]
+    fn redact_nothing() {
+        assert_eq!(redact("<ident>Z1</ident>", &[]), "<ident>Z1</ident>");
+    }
+
+    /// Every file should be readable from the archive, beneath the prefix
+    #[test]
+    fn archive() {
+        let files = [("a.txt", "hello\n".to_owned()), ("empty", String::new())];
+        let buf = super::archive(Vec::new(), 1_700_000_000, &files).unwrap();
+        let mut ar = tar::Archive::new(buf.as_slice());
+        let entries = ar
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                assert_eq!(entry.header().mtime().unwrap(), 1_700_000_000);
+                let path = entry.path().unwrap().display().to_string();
+                let mut contents = String::new();
+                entry.read_to_string(&mut contents).unwrap();
+                (path, contents)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            [
+                ("gstat-bug-report/a.txt".to_owned(), "hello\n".to_owned()),
+                ("gstat-bug-report/empty".to_owned(), String::new()),
+            ]
+        );
+    }
+}
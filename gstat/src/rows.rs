@@ -0,0 +1,276 @@
+//! The table's rows, formatted once per frame
+//!
+//! With hundreds of devices, allocating a new `String` for every cell of every
+//! frame costs more CPU than gathering the statistics does.  So each cell's
+//! text is written into a buffer that is kept from frame to frame, and the rows
+//! given to ratatui borrow it.
+use ratatui::{
+    style::{Color, Style},
+    text::{Line, Span},
+    widgets::{Cell, Row},
+};
+
+use crate::{Columns, Element};
+
+/// One cell's padded text, and how to display it
+#[derive(Debug, Default)]
+struct Formatted {
+    /// The cell's column
+    k:     usize,
+    text:  String,
+    style: Style,
+}
+
+/// One element's formatted cells
+#[derive(Debug, Default)]
+struct Cells {
+    /// One for each visible column, in display order
+    cells:  Vec<Formatted>,
+    /// Drawn as a bar rather than as text
+    rw_bal: Option<f64>,
+}
+
+/// Formatted rows, whose buffers are reused from frame to frame
+#[derive(Debug, Default)]
+pub struct RowCache {
+    rows:       Vec<Cells>,
+    /// How many of `rows` belong to the current frame
+    len:        usize,
+    /// Length of the longest name in the current frame
+    name_width: usize,
+    /// Each column's width in the current frame, indexed like
+    /// [`Columns::cols`]
+    widths:     Vec<u16>,
+}
+
+impl RowCache {
+    /// Format the visible columns of `elems`, widening any column whose values
+    /// don't fit its usual width.
+    pub fn format<'a, I>(&mut self, columns: &Columns, elems: I)
+    where
+        I: IntoIterator<Item = &'a Element>,
+    {
+        self.len = 0;
+        self.name_width = 0;
+        self.widths.clear();
+        self.widths
+            .extend((0..columns.cols.len()).map(|k| columns.width(k)));
+        let mut grown = false;
+        for elem in elems {
+            if self.len == self.rows.len() {
+                self.rows.push(Cells::default());
+            }
+            let row = &mut self.rows[self.len];
+            self.len += 1;
+            row.rw_bal = elem.rw_bal;
+            let mut n = 0;
+            for (k, _) in columns.visible() {
+                if n == row.cells.len() {
+                    row.cells.push(Formatted::default());
+                }
+                let cell = &mut row.cells[n];
+                n += 1;
+                cell.k = k;
+                cell.style = elem.style(k);
+                cell.text.clear();
+                if k == Columns::NAME {
+                    elem.write_display_name(&mut cell.text);
+                    self.name_width = self.name_width.max(cell.text.len());
+                } else if Columns::is_text(k) {
+                    cell.text.push(' ');
+                    columns.write_text(elem, k, 0, &mut cell.text);
+                } else if k != Columns::RW_BAL {
+                    // One character of each column is used as spacing
+                    let width = usize::from(columns.width(k)) - 1;
+                    columns.write_text(elem, k, width, &mut cell.text);
+                }
+                if k != Columns::NAME && k != Columns::RW_BAL {
+                    let w = cell.text.chars().count() + 1;
+                    let w = u16::try_from(w).unwrap_or(u16::MAX);
+                    if w > self.widths[k] {
+                        self.widths[k] = w;
+                        grown = true;
+                    }
+                }
+            }
+            row.cells.truncate(n);
+        }
+        if grown {
+            self.pad();
+        }
+    }
+
+    /// Widen the columns of both `self` and `other` to the wider of their two
+    /// widths, so that their tables line up.
+    pub fn align(&mut self, other: &mut RowCache) {
+        let mut grown = false;
+        for (a, b) in self.widths.iter_mut().zip(other.widths.iter_mut()) {
+            grown |= a != b;
+            *a = (*a).max(*b);
+            *b = *a;
+        }
+        if grown {
+            self.pad();
+            other.pad();
+        }
+    }
+
+    /// Right-align the numeric cells of the current frame to their columns'
+    /// widths, if those have grown since they were formatted.
+    fn pad(&mut self) {
+        for cell in self.rows[..self.len].iter_mut().flat_map(|r| &mut r.cells)
+        {
+            let k = cell.k;
+            if k == Columns::NAME || k == Columns::RW_BAL || Columns::is_text(k)
+            {
+                continue;
+            }
+            // One character of each column is used as spacing
+            let width = usize::from(self.widths[k]) - 1;
+            let len = cell.text.chars().count();
+            if len < width {
+                cell.text.insert_str(0, &" ".repeat(width - len));
+            }
+        }
+    }
+
+    /// Column `k`'s width in the current frame, including a space to separate
+    /// it from its neighbor
+    pub fn width(&self, k: usize) -> u16 {
+        self.widths[k]
+    }
+
+    /// Length of the longest name in the current frame
+    pub fn name_width(&self) -> usize {
+        self.name_width
+    }
+
+    /// The current frame's rows, borrowing their text from the cache
+    pub fn rows(&self) -> impl Iterator<Item = Row<'_>> {
+        self.rows[..self.len].iter().map(|row| {
+            Row::new(row.cells.iter().map(|cell| {
+                if cell.k == Columns::RW_BAL {
+                    Cell::from(balance_bar(row.rw_bal))
+                } else {
+                    Cell::from(cell.text.as_str()).style(cell.style)
+                }
+            }))
+        })
+    }
+}
+
+/// Draw the fraction of bytes that were read in red, and written in blue.
+fn balance_bar(rw_bal: Option<f64>) -> Line<'static> {
+    // One character of the column is used as spacing
+    const BAR_WIDTH: usize = 6;
+    const BAR: &str = "██████";
+
+    let Some(rw_bal) = rw_bal else {
+        return Line::default();
+    };
+    let reads = (rw_bal * BAR_WIDTH as f64).round() as usize;
+    let writes = BAR_WIDTH - reads;
+    let len = '█'.len_utf8();
+    Line::from(vec![
+        Span::raw(" "),
+        Span::styled(&BAR[..reads * len], Style::default().fg(Color::Red)),
+        Span::styled(&BAR[..writes * len], Style::default().fg(Color::Blue)),
+    ])
+}
+
+#[cfg(test)]
+mod t {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{column, group, Cli};
+
+    fn texts(cache: &RowCache) -> Vec<Vec<&str>> {
+        cache.rows[..cache.len]
+            .iter()
+            .map(|row| row.cells.iter().map(|c| c.text.as_str()).collect())
+            .collect()
+    }
+
+    /// Cells are padded to their columns' widths, and a smaller frame
+    /// reuses the buffers of a larger one.
+    #[test]
+    fn format() {
+        let mut cfg = Cli::default();
+        let mut columns = Columns::new(&mut cfg);
+        columns
+            .apply_headers(&["r/s".to_owned(), "Name".to_owned()])
+            .unwrap();
+        let mut da0 = group::total(&[]);
+        da0.name = Arc::from("da0");
+        da0.r_s = 1234.0;
+        let mut da1 = group::total(&[]);
+        da1.name = Arc::from("da1");
+        da1.aliases = vec![Arc::from("gpt/boot")];
+        let mut cache = RowCache::default();
+
+        cache.format(&columns, [&da1, &da0]);
+        let w = usize::from(columns.width(column::find("r_s"))) - 1;
+        assert_eq!(
+            texts(&cache),
+            [
+                [format!("{:>w$}", "0").as_str(), "da1 (gpt/boot)"],
+                [format!("{:>w$}", "1234").as_str(), "da0"],
+            ]
+        );
+        assert_eq!(cache.name_width(), 14);
+        let buf = cache.rows[0].cells[1].text.as_ptr();
+
+        cache.format(&columns, [&da0]);
+        assert_eq!(
+            texts(&cache),
+            [[format!("{:>w$}", "1234").as_str(), "da0"]]
+        );
+        assert_eq!(cache.name_width(), 3);
+        assert_eq!(cache.rows[0].cells[1].text.as_ptr(), buf);
+    }
+
+    /// A value too wide for its column's usual width widens the column for
+    /// that frame only, and the other values and the totals are right-aligned
+    /// to match.
+    #[test]
+    fn widen() {
+        let mut cfg = Cli::default();
+        let mut columns = Columns::new(&mut cfg);
+        columns
+            .apply_headers(&["kB/s r".to_owned(), "Name".to_owned()])
+            .unwrap();
+        let k = column::find("kbs_r");
+        let mut da0 = group::total(&[]);
+        da0.name = Arc::from("da0");
+        da0.kbs_r = 123_456_789.0;
+        let mut da1 = group::total(&[]);
+        da1.name = Arc::from("da1");
+        da1.kbs_r = 1.0;
+        let total = group::total(&[]);
+        let mut cache = RowCache::default();
+        let mut total_row = RowCache::default();
+
+        total_row.format(&columns, [&total]);
+        cache.format(&columns, [&da0, &da1]);
+        cache.align(&mut total_row);
+        assert_eq!(cache.width(k), 10);
+        assert_eq!(total_row.width(k), 10);
+        assert_eq!(columns.header(k, cache.width(k)), "   kB/s r");
+        assert_eq!(texts(&cache), [["123456789", "da0"], ["        1", "da1"]]);
+        assert_eq!(texts(&total_row)[0][0], "        0");
+
+        cache.format(&columns, [&da1]);
+        assert_eq!(cache.width(k), columns.width(k));
+        assert_eq!(texts(&cache), [["      1", "da1"]]);
+    }
+
+    #[test]
+    fn balance_bar() {
+        assert_eq!(super::balance_bar(None).width(), 0);
+        let line = super::balance_bar(Some(0.5));
+        assert_eq!(line.width(), 7);
+        assert_eq!(line.spans[1].content, "███");
+        assert_eq!(line.spans[2].content, "███");
+    }
+}
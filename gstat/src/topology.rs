@@ -0,0 +1,269 @@
+//! The `tree` subcommand: a one-shot report of the GEOM topology
+//!
+//! It's a quick look at how the devices are stacked, before watching their
+//! I/O.
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write as _,
+    io::{self, Write},
+};
+
+use anyhow::{Context, Result};
+use freebsd_libgeom::conf::{Geom, Mesh};
+
+use crate::human_kb;
+
+/// Print every class, geom, provider, and consumer to stdout, as an indented
+/// tree or as JSON.
+pub fn print(json: bool) -> Result<()> {
+    let mesh = Mesh::new().context("Error opening GEOM tree")?;
+    let mut stdout = io::stdout().lock();
+    if json {
+        serde_json::to_writer_pretty(&mut stdout, &mesh)
+            .context("writing JSON")?;
+        writeln!(stdout).context("writing JSON")?;
+    } else {
+        stdout
+            .write_all(render(&mesh).as_bytes())
+            .context("writing tree")?;
+    }
+    Ok(())
+}
+
+/// Render `mesh` as an indented tree, like `tree`'s view of the devices.
+///
+/// Each geom is nested beneath the provider that its first consumer is
+/// attached to, so a geom with several consumers, like a mirror, is only shown
+/// beneath the first.  Geoms without any, like disks, are at the top level.
+fn render(mesh: &Mesh) -> String {
+    let geoms = mesh
+        .classes
+        .iter()
+        .flat_map(|class| class.geoms.iter().map(move |g| (&*class.name, g)))
+        .collect::<Vec<_>>();
+    let providers = geoms
+        .iter()
+        .flat_map(|(_, g)| g.providers.iter().map(|p| &*p.name))
+        .collect::<HashSet<_>>();
+    let mut roots = Vec::new();
+    let mut children: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, (_, geom)) in geoms.iter().enumerate() {
+        match geom
+            .consumers
+            .iter()
+            .find_map(|c| c.provider.as_deref())
+            .filter(|p| providers.contains(p))
+        {
+            Some(parent) => children.entry(parent).or_default().push(i),
+            None => roots.push(i),
+        }
+    }
+    let mut out = String::new();
+    let mut rendered = vec![false; geoms.len()];
+    let mut tree = Nest {
+        geoms:    &geoms,
+        children: &children,
+        rendered: &mut rendered,
+        out:      &mut out,
+    };
+    for i in roots {
+        tree.geom(i, 0);
+    }
+    // Only a cycle, which GEOM shouldn't allow, would leave any unrendered
+    for i in 0..geoms.len() {
+        if !tree.rendered[i] {
+            tree.geom(i, 0);
+        }
+    }
+    out
+}
+
+/// State of [`render`]'s walk through the mesh
+struct Nest<'a> {
+    /// Every geom, with its class's name
+    geoms:    &'a [(&'a str, &'a Geom)],
+    /// The indices of the geoms attached to each provider
+    children: &'a HashMap<&'a str, Vec<usize>>,
+    rendered: &'a mut [bool],
+    out:      &'a mut String,
+}
+
+impl Nest<'_> {
+    /// Render geom `i` and everything built upon it, indented by `depth`
+    /// levels.
+    fn geom(&mut self, i: usize, depth: usize) {
+        if self.rendered[i] {
+            return;
+        }
+        self.rendered[i] = true;
+        let (class, geom) = self.geoms[i];
+        let indent = "  ".repeat(2 * depth);
+        writeln!(
+            self.out,
+            "{indent}{class} {} (rank {})",
+            geom.name, geom.rank
+        )
+        .unwrap();
+        for consumer in &geom.consumers {
+            let provider = consumer.provider.as_deref().unwrap_or("(none)");
+            writeln!(
+                self.out,
+                "{indent}  consumer of {provider}, {}",
+                consumer.mode
+            )
+            .unwrap();
+        }
+        for provider in &geom.providers {
+            writeln!(
+                self.out,
+                "{indent}  provider {}, {}, {}-byte sectors, {}",
+                provider.name,
+                human_kb(provider.mediasize as f64 / 1024.0),
+                provider.sectorsize,
+                provider.mode
+            )
+            .unwrap();
+            for &child in
+                self.children.get(&*provider.name).into_iter().flatten()
+            {
+                self.geom(child, depth + 1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use freebsd_libgeom::conf::{Class, Consumer, Provider};
+
+    use super::*;
+
+    fn provider(name: &str, mediasize: i64) -> Provider {
+        Provider {
+            name: name.to_owned(),
+            mode: "r1w1e1".to_owned(),
+            mediasize,
+            sectorsize: 512,
+            ..Default::default()
+        }
+    }
+
+    fn geom(
+        name: &str,
+        rank: u32,
+        consumes: &[&str],
+        provides: &[(&str, i64)],
+    ) -> Geom {
+        Geom {
+            name: name.to_owned(),
+            rank,
+            providers: provides
+                .iter()
+                .map(|&(name, size)| provider(name, size))
+                .collect(),
+            consumers: consumes
+                .iter()
+                .map(|&p| Consumer {
+                    provider: Some(p.to_owned()),
+                    mode: "r1w1e1".to_owned(),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn class(name: &str, geoms: Vec<Geom>) -> Class {
+        Class {
+            name: name.to_owned(),
+            geoms,
+            ..Default::default()
+        }
+    }
+
+    /// Geoms are nested beneath the providers they consume, through several
+    /// levels, regardless of the order of their classes.
+    #[test]
+    fn render() {
+        let mesh = Mesh {
+            classes: vec![
+                class(
+                    "ELI",
+                    vec![geom(
+                        "mirror/gm0.eli",
+                        4,
+                        &["mirror/gm0"],
+                        &[("mirror/gm0.eli", 1 << 30)],
+                    )],
+                ),
+                class("MD", vec![]),
+                class(
+                    "PART",
+                    vec![geom(
+                        "ada0",
+                        2,
+                        &["ada0"],
+                        &[("ada0p1", 512 << 20), ("ada0p2", 1 << 30)],
+                    )],
+                ),
+                class(
+                    "DISK",
+                    vec![
+                        geom("ada0", 1, &[], &[("ada0", 1 << 40)]),
+                        geom("ada1", 1, &[], &[("ada1", 1 << 30)]),
+                    ],
+                ),
+                class(
+                    "MIRROR",
+                    vec![geom(
+                        "gm0",
+                        3,
+                        &["ada0p2", "ada1"],
+                        &[("mirror/gm0", 1 << 30)],
+                    )],
+                ),
+            ],
+        };
+        let rendered = super::render(&mesh);
+        assert_eq!(
+            rendered.lines().collect::<Vec<_>>(),
+            [
+                "DISK ada0 (rank 1)",
+                "  provider ada0, 1.00T, 512-byte sectors, r1w1e1",
+                "    PART ada0 (rank 2)",
+                "      consumer of ada0, r1w1e1",
+                "      provider ada0p1, 512M, 512-byte sectors, r1w1e1",
+                "      provider ada0p2, 1.00G, 512-byte sectors, r1w1e1",
+                "        MIRROR gm0 (rank 3)",
+                "          consumer of ada0p2, r1w1e1",
+                "          consumer of ada1, r1w1e1",
+                "          provider mirror/gm0, 1.00G, 512-byte sectors, \
+                 r1w1e1",
+                "            ELI mirror/gm0.eli (rank 4)",
+                "              consumer of mirror/gm0, r1w1e1",
+                "              provider mirror/gm0.eli, 1.00G, 512-byte \
+                 sectors, r1w1e1",
+                "DISK ada1 (rank 1)",
+                "  provider ada1, 1.00G, 512-byte sectors, r1w1e1",
+            ]
+        );
+    }
+
+    /// A consumer that isn't attached to anything leaves its geom at the top
+    /// level.
+    #[test]
+    fn render_detached() {
+        let mut detached = geom("gm1", 2, &[], &[]);
+        detached.consumers.push(Consumer {
+            mode: "r0w0e0".to_owned(),
+            ..Default::default()
+        });
+        let mesh = Mesh {
+            classes: vec![class("MIRROR", vec![detached])],
+        };
+        assert_eq!(
+            super::render(&mesh),
+            "MIRROR gm1 (rank 2)\n  consumer of (none), r0w0e0\n"
+        );
+    }
+}
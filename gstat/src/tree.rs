@@ -0,0 +1,200 @@
+//! Arrange elements according to the GEOM hierarchy
+use std::collections::{HashMap, HashSet};
+
+use freebsd_libgeom::Tree;
+
+use crate::Element;
+
+/// Map each provider's name to the name of the first provider beneath it.
+///
+/// Providers with several parents, like a mirror's, are only shown beneath
+/// the first.
+pub fn parents(tree: &Tree) -> HashMap<String, String> {
+    let mut parents = HashMap::new();
+    for class in tree.classes() {
+        for geom in class.geoms() {
+            let Some(parent) = geom.consumers().find_map(|c| c.provider())
+            else {
+                continue;
+            };
+            let parent = parent.name().to_string_lossy();
+            for provider in geom.providers() {
+                let name = provider.name().to_string_lossy().into_owned();
+                parents.insert(name, parent.clone().into_owned());
+            }
+        }
+    }
+    parents
+}
+
+/// Replace the providers of the LABEL class, like `gpt/boot` or `diskid/X`,
+/// with aliases on the providers that they label.
+///
+/// A label's children are reparented onto the labelled provider, so the tree
+/// view stays connected.
+pub fn fold_labels(items: &mut Vec<Element>) {
+    let mut labels = HashMap::new();
+    items.retain(|elem| {
+        if elem.class == "LABEL" {
+            labels.insert(elem.name.clone(), elem.parent.clone());
+            false
+        } else {
+            true
+        }
+    });
+    for elem in items.iter_mut() {
+        if let Some(parent) = elem.parent.as_ref().and_then(|p| labels.get(p)) {
+            elem.parent = parent.clone();
+        }
+    }
+    for (label, parent) in labels {
+        if let Some(elem) =
+            items.iter_mut().find(|e| Some(&e.name) == parent.as_ref())
+        {
+            elem.aliases.push(label);
+        }
+    }
+    for elem in items.iter_mut() {
+        elem.aliases.sort();
+    }
+}
+
+/// Reorder `items` so that every element immediately follows its parent, and
+/// set each one's depth.
+///
+/// Siblings retain their relative order, so `items` should be sorted first.
+/// The descendants of any element named in `collapsed` are removed.
+pub fn order(items: Vec<Element>, collapsed: &HashSet<String>) -> Vec<Element> {
+    let index = items
+        .iter()
+        .enumerate()
+        .map(|(i, elem)| (elem.name.clone(), i))
+        .collect::<HashMap<_, _>>();
+    let mut roots = Vec::new();
+    let mut children: Vec<Vec<usize>> = vec![Vec::new(); items.len()];
+    for (i, elem) in items.iter().enumerate() {
+        match elem.parent.as_ref().and_then(|p| index.get(p)) {
+            Some(&p) => children[p].push(i),
+            None => roots.push(i),
+        }
+    }
+
+    let mut slots = items.into_iter().map(Some).collect::<Vec<_>>();
+    let mut ordered = Vec::with_capacity(slots.len());
+    // Depth-first, so push the roots in reverse
+    let mut stack = roots.into_iter().rev().map(|i| (i, 0)).collect::<Vec<_>>();
+    while let Some((i, depth)) = stack.pop() {
+        let Some(mut elem) = slots[i].take() else {
+            continue;
+        };
+        elem.depth = depth;
+        elem.folded =
+            !children[i].is_empty() && collapsed.contains(&*elem.name);
+        if !elem.folded {
+            stack.extend(children[i].iter().rev().map(|&c| (c, depth + 1)));
+        }
+        ordered.push(elem);
+    }
+    ordered
+}
+
+#[cfg(test)]
+mod t {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::group;
+
+    fn elem(name: &str, parent: Option<&str>) -> Element {
+        let mut e = group::total(&[]);
+        e.name = name.into();
+        e.parent = parent.map(Arc::from);
+        e
+    }
+
+    fn label(name: &str, parent: &str) -> Element {
+        let mut e = elem(name, Some(parent));
+        e.class = "LABEL".to_owned();
+        e
+    }
+
+    fn names(items: &[Element]) -> Vec<(&str, usize)> {
+        items.iter().map(|e| (&*e.name, e.depth)).collect()
+    }
+
+    #[test]
+    fn flat() {
+        let items = vec![elem("ada0", None), elem("ada1", None)];
+        let ordered = order(items, &HashSet::new());
+        assert_eq!(names(&ordered), [("ada0", 0), ("ada1", 0)]);
+    }
+
+    #[test]
+    fn nested() {
+        let items = vec![
+            elem("ada0p1", Some("ada0")),
+            elem("ada1", None),
+            elem("gpt/boot", Some("ada0p1")),
+            elem("ada0", None),
+            elem("ada0p2", Some("ada0")),
+        ];
+        let ordered = order(items, &HashSet::new());
+        assert_eq!(
+            names(&ordered),
+            [
+                ("ada1", 0),
+                ("ada0", 0),
+                ("ada0p1", 1),
+                ("gpt/boot", 2),
+                ("ada0p2", 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn collapsed() {
+        let items = vec![
+            elem("ada0", None),
+            elem("ada0p1", Some("ada0")),
+            elem("gpt/boot", Some("ada0p1")),
+            elem("ada1", None),
+        ];
+        let collapsed = ["ada0".to_owned(), "ada1".to_owned()].into();
+        let ordered = order(items, &collapsed);
+        assert_eq!(names(&ordered), [("ada0", 0), ("ada1", 0)]);
+        assert!(ordered[0].folded);
+        // ada1 has no children, so there's nothing to fold
+        assert!(!ordered[1].folded);
+    }
+
+    #[test]
+    fn labels() {
+        let mut items = vec![
+            elem("ada0", None),
+            label("diskid/DISK-1234", "ada0"),
+            elem("ada0p1", Some("ada0")),
+            label("gptid/5678", "ada0p1"),
+            label("gpt/boot", "ada0p1"),
+            elem("gpt/boot.eli", Some("gpt/boot")),
+        ];
+        fold_labels(&mut items);
+        let names = items.iter().map(|e| &*e.name).collect::<Vec<_>>();
+        assert_eq!(names, ["ada0", "ada0p1", "gpt/boot.eli"]);
+        assert_eq!(items[0].aliases, [Arc::from("diskid/DISK-1234")]);
+        assert_eq!(
+            items[1].aliases,
+            [Arc::from("gpt/boot"), Arc::from("gptid/5678")]
+        );
+        assert!(items[2].aliases.is_empty());
+        // The label's child now hangs from the labelled provider
+        assert_eq!(items[2].parent.as_deref(), Some("ada0p1"));
+    }
+
+    /// If a parent is filtered out or missing, its children become roots.
+    #[test]
+    fn orphan() {
+        let items = vec![elem("ada0p1", Some("ada0"))];
+        let ordered = order(items, &HashSet::new());
+        assert_eq!(names(&ordered), [("ada0p1", 0)]);
+    }
+}
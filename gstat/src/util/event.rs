@@ -7,6 +7,8 @@ use crossterm::event;
 pub enum Event {
     Key(event::KeyEvent),
     Mouse(event::MouseEvent),
+    /// The terminal changed size
+    Resize,
     Tick,
     Other,
 }
@@ -18,6 +20,7 @@ pub fn poll(tick_rate: &Duration) -> Result<Option<Event>> {
         match event::read() {
             Ok(event::Event::Key(key)) => Ok(Some(Event::Key(key))),
             Ok(event::Event::Mouse(mev)) => Ok(Some(Event::Mouse(mev))),
+            Ok(event::Event::Resize(..)) => Ok(Some(Event::Resize)),
             Ok(_) => Ok(Some(Event::Other)),
             e => panic!("Unhandled error {:?}", e),
         }
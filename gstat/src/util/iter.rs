@@ -33,7 +33,7 @@ mod t {
     fn deinterleave_1_0() {
         let r: Vec<Vec<i32>> =
             iter::empty::<i32>().deinterleave(nonzero!(1usize));
-        assert_eq!(r, vec![vec![]]);
+        assert_eq!(r, vec![Vec::<i32>::new()]);
     }
 
     #[test]
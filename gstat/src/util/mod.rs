@@ -1,2 +1,4 @@
 pub mod event;
 pub mod iter;
+pub mod sysctl;
+pub mod window;
@@ -0,0 +1,49 @@
+use std::{ffi::CString, mem, ptr};
+
+/// Read an integer sysctl by name, returning `None` if it doesn't exist.
+///
+/// Both 32 and 64-bit integers are supported.
+pub fn u64_by_name(name: &str) -> Option<u64> {
+    let cname = CString::new(name).ok()?;
+    let mut buf = [0u8; mem::size_of::<u64>()];
+    let mut len = buf.len();
+    let r = unsafe {
+        libc::sysctlbyname(
+            cname.as_ptr(),
+            buf.as_mut_ptr().cast(),
+            &mut len,
+            ptr::null(),
+            0,
+        )
+    };
+    if r != 0 {
+        return None;
+    }
+    match len {
+        4 => Some(u32::from_ne_bytes(buf[..4].try_into().unwrap()).into()),
+        8 => Some(u64::from_ne_bytes(buf)),
+        _ => None,
+    }
+}
+
+/// Read a string sysctl by name, returning `None` if it doesn't exist.
+pub fn string_by_name(name: &str) -> Option<String> {
+    let cname = CString::new(name).ok()?;
+    let mut buf = [0u8; 64];
+    let mut len = buf.len();
+    let r = unsafe {
+        libc::sysctlbyname(
+            cname.as_ptr(),
+            buf.as_mut_ptr().cast(),
+            &mut len,
+            ptr::null(),
+            0,
+        )
+    };
+    if r != 0 {
+        return None;
+    }
+    let s = &buf[..len];
+    let s = s.split(|b| *b == 0).next().unwrap_or(s);
+    Some(String::from_utf8_lossy(s).into_owned())
+}
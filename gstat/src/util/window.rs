@@ -0,0 +1,77 @@
+//! A moving window over the most recent samples
+use std::{collections::VecDeque, mem, num::NonZeroUsize};
+
+/// The current sample, plus up to `len` previous ones.  Statistics computed
+/// between the oldest previous sample and the current one are a moving average
+/// over the window.
+pub struct Window<T> {
+    /// Previous samples, oldest first
+    history: VecDeque<T>,
+    cur:     T,
+    len:     NonZeroUsize,
+}
+
+impl<T> Window<T> {
+    pub fn new(cur: T, len: NonZeroUsize) -> Self {
+        let history = VecDeque::with_capacity(len.get());
+        Window { history, cur, len }
+    }
+
+    /// Make `sample` the current sample, retiring the oldest previous one if
+    /// the window is full.
+    pub fn push(&mut self, sample: T) {
+        if self.history.len() >= self.len.get() {
+            self.history.pop_front();
+        }
+        self.history.push_back(mem::replace(&mut self.cur, sample));
+    }
+
+    /// The current sample, and the oldest previous one, if any
+    pub fn ends_mut(&mut self) -> (&mut T, Option<&mut T>) {
+        (&mut self.cur, self.history.front_mut())
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use nonzero_ext::nonzero;
+
+    use super::*;
+
+    fn ends(w: &mut Window<u32>) -> (u32, Option<u32>) {
+        let (cur, oldest) = w.ends_mut();
+        (*cur, oldest.copied())
+    }
+
+    #[test]
+    fn empty() {
+        let mut w = Window::new(0, nonzero!(3usize));
+        assert_eq!(ends(&mut w), (0, None));
+    }
+
+    /// A window of 1 spans just the latest interval
+    #[test]
+    fn one() {
+        let mut w = Window::new(0, nonzero!(1usize));
+        for i in 1..5 {
+            w.push(i);
+            assert_eq!(ends(&mut w), (i, Some(i - 1)));
+        }
+    }
+
+    /// Until the window fills, it spans every interval so far.  Afterwards,
+    /// it spans the latest `len` intervals.
+    #[test]
+    fn rolling() {
+        let mut w = Window::new(0, nonzero!(3usize));
+        w.push(1);
+        assert_eq!(ends(&mut w), (1, Some(0)));
+        w.push(2);
+        w.push(3);
+        assert_eq!(ends(&mut w), (3, Some(0)));
+        w.push(4);
+        assert_eq!(ends(&mut w), (4, Some(1)));
+        w.push(5);
+        assert_eq!(ends(&mut w), (5, Some(2)));
+    }
+}